@@ -0,0 +1,70 @@
+//! Translations per second through the NAT, with the table already holding 1K, 10K and 60K
+//! mappings. Every lookup scans the table, so the bigger it is the slower it gets.
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use networking::nat_v4::{MappingState, NatEntry, NatTableV4, NatTimeouts, Protocol};
+use networking::packet::ipv4::Ipv4Header;
+use networking::packet::layered::{LayeredPacket, Transport};
+use networking::packet::udp::UdpHeader;
+use networking::packet::IPPROTO_UDP;
+
+const SIZES: [u32; 3] = [1_000, 10_000, 60_000];
+
+// Filling the table through translate_outgoing would itself take ages at 60K (every new
+// mapping searches for a free port), so the entries are put in directly.
+fn filled_table(mappings: u32) -> NatTableV4 {
+    let mut nat = NatTableV4::new("bench", Ipv4Addr::new(203, 0, 113, 1))
+        .with_timeouts(NatTimeouts { udp : Duration::from_secs(3600), ..NatTimeouts::default() });
+    let now = Instant::now();
+    for k in 0..mappings {
+        nat.table.push(NatEntry {
+            source_ip : Ipv4Addr::from(0x0a00_0000 | k),
+            source_port : 5000,
+            protocol : Protocol::Udp,
+            computer : 0,
+            mangled_port : k as u16,
+            mapped_on_time : now,
+            lifetime : Duration::from_secs(3600),
+            remotes : vec![],
+            state : MappingState::Transient,
+            explicit : false,
+        });
+    }
+    nat
+}
+
+fn udp_packet(source: (Ipv4Addr, u16), destination: (Ipv4Addr, u16)) -> LayeredPacket {
+    let network = Ipv4Header::new(source.0, destination.0, IPPROTO_UDP, 0);
+    LayeredPacket::new(network, Transport::Udp(UdpHeader::new(source.1, destination.1, 0)), vec![])
+}
+
+// a packet of the mapping in the middle of the table, the average lookup
+fn outgoing_packet(mappings: u32) -> LayeredPacket {
+    udp_packet((Ipv4Addr::from(0x0a00_0000 | (mappings / 2)), 5000), (Ipv4Addr::new(198, 51, 100, 1), 53))
+}
+
+fn incoming_packet(mappings: u32) -> LayeredPacket {
+    udp_packet((Ipv4Addr::new(198, 51, 100, 1), 53), (Ipv4Addr::new(203, 0, 113, 1), (mappings / 2) as u16))
+}
+
+fn translations(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nat_translation");
+    group.throughput(Throughput::Elements(1));
+    for mappings in SIZES {
+        let mut nat = filled_table(mappings);
+        let packet = outgoing_packet(mappings);
+        group.bench_with_input(BenchmarkId::new("outgoing", mappings), &packet, |b, packet| {
+            b.iter(|| nat.translate_outgoing(black_box(packet.clone()), 0).unwrap())
+        });
+        let packet = incoming_packet(mappings);
+        group.bench_with_input(BenchmarkId::new("incoming", mappings), &packet, |b, packet| {
+            b.iter(|| nat.translate_incoming(black_box(packet.clone())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, translations);
+criterion_main!(benches);
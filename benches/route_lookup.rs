@@ -0,0 +1,103 @@
+//! Lookups per second with 10K, 100K and 1M routes, in the RoutingTable (one check per route)
+//! and in the RouteTrie (one step per bit of the address). The IPv6 routes are /32 to /48
+//! chunks of a few thousand allocations, the IPv4 ones roughly as long as in the real
+//! Internet table: more than half /24, then /22, /23 and the rest between /16 and /21.
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use networking::route_trie::RouteTrie;
+use networking::routing::{Route, RouteAddress, RoutingTable};
+use networking::traffic::SimpleRng;
+
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+// how many different addresses the lookups go through, so it isn't one cached answer
+const ADDRESSES: usize = 1024;
+
+// (prefix_len, weight), picked proportionally to the weight
+const V6_LENGTHS: [(u8, u64); 5] = [(32, 15), (36, 5), (40, 10), (44, 15), (48, 55)];
+const V4_LENGTHS: [(u8, u64); 9] = [(16, 2), (17, 1), (18, 2), (19, 3), (20, 5), (21, 5), (22, 11), (23, 10), (24, 61)];
+
+fn prefix_len(rng: &mut SimpleRng, lengths: &[(u8, u64)]) -> u8 {
+    let total: u64 = lengths.iter().map(|&(_, weight)| weight).sum();
+    let mut pick = rng.between(0, total - 1);
+    for &(prefix_len, weight) in lengths {
+        if pick < weight {
+            return prefix_len;
+        }
+        pick -= weight;
+    }
+    unreachable!()
+}
+
+// Every IPv6 prefix is inside one of 4096 /32s out of 2a00::/12, like the addresses the
+// registries hand out, so the routes share their first bits as real ones do (and the trie
+// stays small enough to fit in memory at a million routes).
+fn v6_routes(count: usize) -> Vec<Route<Ipv6Addr, Ipv6Addr>> {
+    let mut rng = SimpleRng::new(6);
+    let gateway = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    (0..count)
+        .map(|_| {
+            let allocation = (0x2a00_0000 | rng.between(0, 4095) << 8) as u128;
+            let bits = allocation << 96 | (rng.next_u64() as u128) << 32;
+            let prefix_len = prefix_len(&mut rng, &V6_LENGTHS);
+            Route::with_prefix(Ipv6Addr::from_bits(bits & (u128::MAX << (128 - prefix_len))), prefix_len, gateway).unwrap()
+        })
+        .collect()
+}
+
+// anywhere in 1.0.0.0 to 223.255.255.255, the unicast addresses
+fn v4_routes(count: usize) -> Vec<Route<Ipv4Addr, Ipv4Addr>> {
+    let mut rng = SimpleRng::new(4);
+    let gateway = Ipv4Addr::new(192, 0, 2, 1);
+    (0..count)
+        .map(|_| {
+            let bits = rng.between(0x0100_0000, 0xdfff_ffff) as u32;
+            let prefix_len = prefix_len(&mut rng, &V4_LENGTHS);
+            Route::with_prefix(Ipv4Addr::from_bits(bits & (u32::MAX << (32 - prefix_len))), prefix_len, gateway).unwrap()
+        })
+        .collect()
+}
+
+// addresses inside routes spread over the whole table, so every lookup finds something
+fn addresses<A: RouteAddress, H>(routes: &[Route<A, H>]) -> Vec<A> {
+    let step = (routes.len() / ADDRESSES).max(1);
+    routes.iter().step_by(step).take(ADDRESSES).map(|route| route.destination).collect()
+}
+
+// Filling the table through add_route would check every route against all the others, so
+// they are put in directly.
+fn lookups<A: RouteAddress, H: Clone + std::fmt::Debug>(c: &mut Criterion, name: &str, routes: fn(usize) -> Vec<Route<A, H>>) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(1));
+    // a lookup in the table takes milliseconds at 1M routes
+    group.sample_size(10);
+    for size in SIZES {
+        let table = RoutingTable::default().with_routes(routes(size));
+        let addresses = addresses(table.routes());
+        let mut next = 0;
+        group.bench_with_input(BenchmarkId::new("table", size), &addresses, |b, addresses| {
+            b.iter(|| {
+                next = (next + 1) % addresses.len();
+                table.find_next_hop(black_box(addresses[next])).unwrap()
+            })
+        });
+        let trie = RouteTrie::from_table(&table);
+        drop(table);
+        group.bench_with_input(BenchmarkId::new("trie", size), &addresses, |b, addresses| {
+            b.iter(|| {
+                next = (next + 1) % addresses.len();
+                trie.find_next_hop(black_box(addresses[next])).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn route_lookups(c: &mut Criterion) {
+    lookups(c, "route_lookup_v6", v6_routes);
+    lookups(c, "route_lookup_v4", v4_routes);
+}
+
+criterion_group!(benches, route_lookups);
+criterion_main!(benches);
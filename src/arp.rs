@@ -0,0 +1,148 @@
+//! ARP (RFC 826): how an IPv4 host finds the MAC address of a neighbor before it can send it a
+//! frame. It broadcasts a request "who has 192.168.1.1? tell 192.168.1.10", and the one with
+//! that address answers with a reply straight back. Both sides remember the other in their
+//! ARP cache, so it only happens again once the entry gets too old.
+//!
+//! ```text
+//! | hardware type | protocol type | hlen | plen | operation |
+//! | sender MAC | sender IP | target MAC | target IP |
+//! ```
+//!
+//! 28 bytes for Ethernet and IPv4; the target MAC of a request is zeros, since that is the question.
+use std::net::Ipv4Addr;
+use std::time::Instant;
+
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use crate::neighbor::{ArpCache, Resolution};
+use crate::packet::{need, PacketError};
+
+pub const MESSAGE_LEN: usize = 28;
+const HARDWARE_ETHERNET: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOperation {
+    Request = 1,
+    Reply = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpMessage {
+    pub operation : ArpOperation,
+    pub sender_mac : MacAddr,
+    pub sender_ip : Ipv4Addr,
+    pub target_mac : MacAddr,
+    pub target_ip : Ipv4Addr,
+}
+
+impl ArpMessage {
+    pub fn request(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        ArpMessage { operation : ArpOperation::Request, sender_mac, sender_ip, target_mac : MacAddr::default(), target_ip }
+    }
+
+    // the answer of whoever has the address asked for
+    pub fn reply(&self, mac: MacAddr) -> Self {
+        ArpMessage {
+            operation : ArpOperation::Reply,
+            sender_mac : mac,
+            sender_ip : self.target_ip,
+            target_mac : self.sender_mac,
+            target_ip : self.sender_ip,
+        }
+    }
+
+    // a request goes to everyone, a reply only to the one who asked
+    pub fn to_frame(&self) -> EthernetFrame {
+        let destination = match self.operation {
+            ArpOperation::Request => MacAddr::BROADCAST,
+            ArpOperation::Reply => self.target_mac,
+        };
+        EthernetFrame::new(destination, self.sender_mac, ETHERTYPE_ARP, self.to_bytes())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = HARDWARE_ETHERNET.to_be_bytes().to_vec();
+        bytes.extend(ETHERTYPE_IPV4.to_be_bytes());
+        bytes.extend([6, 4]);
+        bytes.extend((self.operation as u16).to_be_bytes());
+        bytes.extend(self.sender_mac.0);
+        bytes.extend(self.sender_ip.octets());
+        bytes.extend(self.target_mac.0);
+        bytes.extend(self.target_ip.octets());
+        bytes
+    }
+
+    // only Ethernet and IPv4 are understood
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, MESSAGE_LEN)?;
+        if bytes[..6] != [0, 1, 0x08, 0x00, 6, 4] {
+            return Err(PacketError::UnknownType(bytes[1]));
+        }
+        let operation = match u16::from_be_bytes([bytes[6], bytes[7]]) {
+            1 => ArpOperation::Request,
+            2 => ArpOperation::Reply,
+            other => return Err(PacketError::UnknownType(other as u8)),
+        };
+        let mac = |at: usize| MacAddr(bytes[at..at + 6].try_into().unwrap());
+        let ip = |at: usize| Ipv4Addr::new(bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]);
+        Ok(ArpMessage { operation, sender_mac : mac(8), sender_ip : ip(14), target_mac : mac(18), target_ip : ip(24) })
+    }
+}
+
+impl ArpCache {
+    // The MAC address to send a frame for this next hop to, from the cache if it is still fresh.
+    // `own` is the (MAC, IP) of the interface asking.
+    pub fn resolve(&mut self, ip: Ipv4Addr, now: Instant, own: (MacAddr, Ipv4Addr)) -> Resolution {
+        self.expire(now);
+        match self.lookup(ip) {
+            Some(mac) => Resolution::Known(mac),
+            None => Resolution::Ask(ArpMessage::request(own.0, own.1, ip).to_frame()),
+        }
+    }
+
+    // Like RFC 826 says: a sender already in the cache is updated, and one asking for us is
+    // added too, since we are about to talk to it. Gives back the reply if the request is for us.
+    pub fn hear(&mut self, message: &ArpMessage, now: Instant, own: (MacAddr, Ipv4Addr)) -> Option<ArpMessage> {
+        let for_us = message.target_ip == own.1;
+        if for_us || self.lookup(message.sender_ip).is_some() {
+            // a cache that is full just doesn't remember this one
+            let _ = self.learn(message.sender_ip, message.sender_mac, now);
+        }
+        (for_us && message.operation == ArpOperation::Request).then(|| message.reply(own.0))
+    }
+}
+
+#[test]
+fn stale_entries_are_asked_for_again() {
+    use std::time::Duration;
+
+    let host = ("02:00:00:00:00:0a".parse().unwrap(), Ipv4Addr::new(192, 168, 1, 10));
+    let gateway = ("02:00:00:00:00:01".parse().unwrap(), Ipv4Addr::new(192, 168, 1, 1));
+    let mut host_cache = ArpCache::new("host eth0").with_max_age(Duration::from_secs(60));
+    let mut gateway_cache = ArpCache::new("gateway lan");
+    let start = Instant::now();
+
+    // nothing known yet: a request to everyone
+    let Resolution::Ask(frame) = host_cache.resolve(gateway.1, start, host) else {
+        panic!("the cache should be empty");
+    };
+    assert_eq!((frame.destination, frame.ethertype), (MacAddr::BROADCAST, ETHERTYPE_ARP));
+    let request = ArpMessage::from_bytes(&EthernetFrame::from_bytes(&frame.to_bytes()).unwrap().payload).unwrap();
+    assert_eq!(request, ArpMessage::request(host.0, host.1, gateway.1));
+
+    // the gateway learns the host from the question, and answers it directly
+    let reply = gateway_cache.hear(&request, start, gateway).unwrap();
+    assert_eq!(gateway_cache.lookup(host.1), Some(host.0));
+    assert_eq!(reply.to_frame().destination, host.0);
+    assert_eq!(host_cache.hear(&reply, start, host), None);
+    assert_eq!(host_cache.resolve(gateway.1, start + Duration::from_secs(59), host), Resolution::Known(gateway.0));
+
+    // a minute later the entry is gone, and the host asks again
+    let later = start + Duration::from_secs(61);
+    assert!(matches!(host_cache.resolve(gateway.1, later, host), Resolution::Ask(frame) if frame.destination.is_broadcast()));
+    assert!(host_cache.entries.is_empty());
+
+    // someone else's question teaches nothing about an address not already known
+    let other = ArpMessage::request("02:00:00:00:00:0b".parse().unwrap(), Ipv4Addr::new(192, 168, 1, 11), Ipv4Addr::new(192, 168, 1, 12));
+    assert_eq!(host_cache.hear(&other, later, host), None);
+    assert!(host_cache.entries.is_empty());
+}
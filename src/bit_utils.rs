@@ -9,6 +9,41 @@ where T : Copy + std::cmp::PartialEq + From<u8> + std::ops::Sub
     }
     i
 }
+// FNV-1a, a small hash that gives the same number on every run and every machine
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+// The Internet checksum of RFC 1071, used by IPv4, ICMP, UDP and TCP: the bytes as 16 bit
+// numbers (an odd last byte padded with a zero) added up with every carry wrapped around to
+// the bottom, and then all the bits flipped. The order of the words doesn't matter.
+pub fn internet_checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32).sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+// with the right checksum in them, the bytes add up to all ones, so checksumming them again gives zero
+pub fn verify_internet_checksum(bytes: &[u8]) -> bool {
+    internet_checksum(bytes) == 0
+}
+#[test]
+fn rfc_1071_checksums() {
+    // the example of section 3: the sum is ddf2 with the carries wrapped around
+    let bytes = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+    assert_eq!(internet_checksum(&bytes), !0xddf2);
+    // in any order of the words
+    assert_eq!(internet_checksum(&[0xf4, 0xf5, 0xf6, 0xf7, 0x00, 0x01, 0xf2, 0x03]), !0xddf2);
+    // the checksum put after them makes them verify
+    let mut checked = bytes.to_vec();
+    checked.extend((!0xddf2u16).to_be_bytes());
+    assert!(verify_internet_checksum(&checked));
+    checked[0] = 0x80;
+    assert!(!verify_internet_checksum(&checked));
+    // an odd byte counts as the high half of a word
+    assert_eq!(internet_checksum(&[0x01]), !0x0100);
+    assert_eq!(internet_checksum(&[]), 0xffff);
+}
 #[test]
 fn test_count(){
     let a = 7;
@@ -0,0 +1,220 @@
+//! Time for the simulation.
+//! The `VirtualClock` only moves when we tell it to, and only forwards (monotonic), so runs are
+//! reproducible. A `DeviceClock` is what one device *thinks* the time is: normally the same as
+//! the virtual clock, but with a fault switched on it can suddenly jump forward or backward,
+//! like a wall clock being corrected by NTP. Anything that keeps timers with the wrong clock
+//! (NAT expiry, DHCP leases, neighbor caches, multicast groups, TCP's TIME-WAIT) then
+//! misbehaves: things die early or never die. A clock can't jump back to before the virtual
+//! clock started; it stops there.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+// the real clock of the computer running the simulation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// Clones share the same time, so every device can hold one
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    start : Instant,
+    elapsed_nanos : Arc<AtomicU64>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock { start : Instant::now(), elapsed_nanos : Arc::new(AtomicU64::new(0)) }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed_nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+// The fault to inject in a device clock: once the virtual time reaches `at`, the clock jumps `by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockFault {
+    #[default]
+    None,
+    JumpForward { at : Duration, by : Duration },
+    JumpBackward { at : Duration, by : Duration },
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceClock {
+    pub reference : VirtualClock,
+    pub fault : ClockFault,
+}
+
+impl DeviceClock {
+    pub fn new(reference: VirtualClock) -> Self {
+        DeviceClock { reference, fault : ClockFault::None }
+    }
+
+    pub fn with_fault(mut self, fault: ClockFault) -> Self {
+        self.fault = fault;
+        self
+    }
+}
+
+impl Clock for DeviceClock {
+    fn now(&self) -> Instant {
+        let now = self.reference.now();
+        let elapsed = self.reference.elapsed();
+        match self.fault {
+            ClockFault::JumpForward { at, by } if elapsed >= at => now + by,
+            ClockFault::JumpBackward { at, by } if elapsed >= at => self.reference.start + elapsed.saturating_sub(by),
+            _ => now,
+        }
+    }
+}
+
+#[cfg(test)]
+fn nat_with_one_mapping(clock: &impl Clock) -> crate::nat_v4::NatTable {
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+
+    let packet = PacketBuilder::ipv4().src("10.100.1.1".parse().unwrap()).dst("192.168.1.1".parse().unwrap()).udp().sport(8090).dport(80).payload(b"K xa bro, haal khabar?").build();
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    nat.translate_outgoing_at(packet, 12, clock.now()).unwrap();
+    nat
+}
+
+#[test]
+fn clock_jumps_break_nat_expiry() {
+    let reference = VirtualClock::new();
+    let forward = DeviceClock::new(reference.clone())
+        .with_fault(ClockFault::JumpForward { at : Duration::from_secs(10), by : Duration::from_secs(3600) });
+    let backward = DeviceClock::new(reference.clone())
+        .with_fault(ClockFault::JumpBackward { at : Duration::from_secs(10), by : Duration::from_secs(3600) });
+
+    let mut monotonic_nat = nat_with_one_mapping(&reference);
+    let mut forward_nat = nat_with_one_mapping(&forward);
+    let mut backward_nat = nat_with_one_mapping(&backward);
+
+    // 15s in, the 30s mapping should still be alive, but the forward jump already killed it
+    reference.advance(Duration::from_secs(15));
+    monotonic_nat.prune_unnecessary_ports_at(reference.now());
+    forward_nat.prune_unnecessary_ports_at(forward.now());
+    assert_eq!(monotonic_nat.table.len(), 1);
+    assert_eq!(forward_nat.table.len(), 0);
+
+    // 60s in, it should be gone, but after the backward jump it looks like it was never old
+    reference.advance(Duration::from_secs(45));
+    monotonic_nat.prune_unnecessary_ports_at(reference.now());
+    backward_nat.prune_unnecessary_ports_at(backward.now());
+    assert_eq!(monotonic_nat.table.len(), 0);
+    assert_eq!(backward_nat.table.len(), 1);
+}
+
+// The other things that keep timers: a DHCP lease, an ARP entry, a multicast group a router
+// keeps forwarding to, and a TCP connection in TIME-WAIT. Whether each is still there at the
+// device's idea of `now`.
+#[cfg(test)]
+struct Timers {
+    dhcp : crate::dhcp::DhcpServer,
+    arp : crate::neighbor::ArpCache,
+    igmp : crate::multicast::MulticastRouter,
+    tcp : crate::tcp_endpoint::TcpEndpoint,
+}
+
+#[cfg(test)]
+impl Timers {
+    const LAPTOP: crate::link::MacAddr = crate::link::MacAddr([2, 0, 0, 0, 0, 10]);
+    const GROUP: std::net::Ipv4Addr = std::net::Ipv4Addr::new(239, 1, 1, 1);
+
+    fn started(clock: &impl Clock) -> Self {
+        use crate::dhcp::{DhcpClient, DhcpServer};
+        use crate::multicast::MulticastRouter;
+        use crate::neighbor::ArpCache;
+        use crate::tcp_endpoint::TcpEndpoint;
+        use std::net::Ipv4Addr;
+
+        let now = clock.now();
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        let mut dhcp = DhcpServer::new(gateway, Ipv4Addr::new(192, 168, 1, 100), Ipv4Addr::new(192, 168, 1, 199), 24)
+            .with_lease_time(Duration::from_secs(60));
+        let mut client = DhcpClient::new(Self::LAPTOP, 1);
+        let offer = dhcp.handle(&client.discover(), now).unwrap();
+        let request = client.receive(&offer).unwrap();
+        dhcp.handle(&request, now).unwrap();
+        let mut arp = ArpCache::new("gateway lan").with_max_age(Duration::from_secs(60));
+        arp.learn(Ipv4Addr::new(192, 168, 1, 100), Self::LAPTOP, now).unwrap();
+        let mut igmp = MulticastRouter::new(gateway);
+        igmp.hear_report(Self::GROUP, now);
+        // a connection the laptop closed first, so it waits 240s
+        let mut tcp = TcpEndpoint::new(40000, 1000);
+        let mut server = TcpEndpoint::new(80, 5000);
+        server.listen();
+        let syn_ack = server.receive(&tcp.connect(80).unwrap(), now).unwrap();
+        server.receive(&tcp.receive(&syn_ack, now).unwrap(), now);
+        let ack = server.receive(&tcp.close().unwrap(), now).unwrap();
+        tcp.receive(&ack, now);
+        tcp.receive(&server.close().unwrap(), now);
+        Timers { dhcp, arp, igmp, tcp }
+    }
+
+    // (lease, ARP entry, group, TIME-WAIT) still there
+    fn alive(&mut self, clock: &impl Clock) -> (bool, bool, bool, bool) {
+        let now = clock.now();
+        self.dhcp.expire(now);
+        self.arp.expire(now);
+        self.igmp.expire_groups(now);
+        self.tcp.expire(now);
+        (self.dhcp.lease_of(Self::LAPTOP).is_some(), !self.arp.entries.is_empty(), self.igmp.has_members(Self::GROUP), self.tcp.state.current == "TIME-WAIT")
+    }
+}
+
+#[test]
+fn clock_jumps_break_leases_and_routers() {
+    let reference = VirtualClock::new();
+    let forward = DeviceClock::new(reference.clone())
+        .with_fault(ClockFault::JumpForward { at : Duration::from_secs(10), by : Duration::from_secs(3600) });
+    let backward = DeviceClock::new(reference.clone())
+        .with_fault(ClockFault::JumpBackward { at : Duration::from_secs(10), by : Duration::from_secs(3600) });
+    let mut monotonic_timers = Timers::started(&reference);
+    let mut forward_timers = Timers::started(&forward);
+    let mut backward_timers = Timers::started(&backward);
+
+    // 30s in, a minute long lease and ARP entry (and a group, good for 260s, and TIME-WAIT) should
+    // be there; after the forward jump the laptop lost its address and the router its group, and
+    // the same ports could already be given to a new connection
+    reference.advance(Duration::from_secs(30));
+    assert_eq!(monotonic_timers.alive(&reference), (true, true, true, true));
+    assert_eq!(forward_timers.alive(&forward), (false, false, false, false));
+
+    // 5 minutes in they are all long gone, except for the device whose clock went back an hour:
+    // the address can't be given to anybody else, the group is forwarded to nobody, and the
+    // connection never leaves TIME-WAIT
+    reference.advance(Duration::from_secs(270));
+    assert_eq!(monotonic_timers.alive(&reference), (false, false, false, false));
+    assert_eq!(backward_timers.alive(&backward), (true, true, true, true));
+    // it went back as far as it could: to when the virtual clock started
+    assert_eq!(backward.now(), reference.start);
+}
@@ -0,0 +1,497 @@
+//! DHCP (RFC 2131): how a host that just came up gets an address, instead of somebody typing
+//! one in. It has no address yet, so everything goes by broadcast, and the client is known by
+//! its MAC address and a transaction id it picks. Four messages, DORA:
+//!
+//! ```text
+//! client                                   server
+//!   DISCOVER  0.0.0.0:68 → 255.255.255.255:67      anybody there?
+//!   OFFER     ←  you could have 192.168.1.100 for a day
+//!   REQUEST   → 192.168.1.100 please, from that server (the others take back their offers)
+//!   ACK       ←  it's yours: /24, gateway 192.168.1.1, DNS 192.168.1.1
+//! ```
+//!
+//! The messages are still BOOTP ones, a fixed 236 bytes (most of it empty names), then a magic
+//! cookie and the options, each as type, length, value. The message type itself is option 53.
+//!
+//! The lease runs out unless the client asks again, at half of it (T1) to its server, or at 7/8
+//! (T2) to anybody; the client side is state_machine::StateMachine::dhcp_client().
+use std::fmt::{self, Display};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use crate::link::MacAddr;
+use crate::packet::builder::PacketBuilder;
+use crate::packet::layered::{LayeredPacket, Transport};
+use crate::packet::{need, PacketError};
+use crate::router::DEFAULT_HOP_LIMIT;
+use crate::state_machine::StateMachine;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+pub const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+// the BOOTP part, up to the cookie
+pub const FIXED_LEN: usize = 236;
+
+// the options used here
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+// how long an offer is held for the client before the address can go to someone else
+pub const OFFER_HOLD: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+}
+
+impl DhcpMessageType {
+    pub fn from_code(code: u8) -> Option<Self> {
+        use DhcpMessageType::*;
+        [Discover, Offer, Request, Decline, Ack, Nak, Release].into_iter().find(|message_type| *message_type as u8 == code)
+    }
+
+    // what the client sends is a BOOTREQUEST (1), what the server sends a BOOTREPLY (2)
+    pub fn from_server(&self) -> bool {
+        matches!(self, DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak)
+    }
+}
+
+impl Display for DhcpMessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{self:?}").to_uppercase();
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub message_type : DhcpMessageType,
+    pub transaction_id : u32,
+    pub client_mac : MacAddr,
+    // the address the client already has (ciaddr), when renewing or releasing it
+    pub client_ip : Ipv4Addr,
+    // the address offered or given (yiaddr)
+    pub your_ip : Ipv4Addr,
+    pub server_id : Option<Ipv4Addr>,
+    pub requested_ip : Option<Ipv4Addr>,
+    pub subnet_mask : Option<Ipv4Addr>,
+    pub router : Option<Ipv4Addr>,
+    pub dns_servers : Vec<Ipv4Addr>,
+    // in seconds
+    pub lease_time : Option<u32>,
+}
+
+impl DhcpMessage {
+    pub fn new(message_type: DhcpMessageType, transaction_id: u32, client_mac: MacAddr) -> Self {
+        DhcpMessage {
+            message_type,
+            transaction_id,
+            client_mac,
+            client_ip : Ipv4Addr::UNSPECIFIED,
+            your_ip : Ipv4Addr::UNSPECIFIED,
+            server_id : None,
+            requested_ip : None,
+            subnet_mask : None,
+            router : None,
+            dns_servers : vec![],
+            lease_time : None,
+        }
+    }
+
+    // the answer of a server, about the same client and transaction
+    pub fn reply(&self, message_type: DhcpMessageType, server_id: Ipv4Addr) -> Self {
+        DhcpMessage { server_id : Some(server_id), ..DhcpMessage::new(message_type, self.transaction_id, self.client_mac) }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let op = if self.message_type.from_server() { 2 } else { 1 };
+        // htype Ethernet, hlen 6, hops 0
+        let mut bytes = vec![op, 1, 6, 0];
+        bytes.extend(self.transaction_id.to_be_bytes());
+        // secs, and flags with the broadcast bit: the client can't take unicast to an address it doesn't have yet
+        bytes.extend([0, 0, 0x80, 0]);
+        // ciaddr, yiaddr, siaddr, giaddr
+        for address in [self.client_ip, self.your_ip, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED] {
+            bytes.extend(address.octets());
+        }
+        bytes.extend(self.client_mac.0);
+        // the rest of chaddr, then sname and file
+        bytes.resize(FIXED_LEN, 0);
+        bytes.extend(MAGIC_COOKIE);
+
+        let mut option = |code: u8, value: &[u8]| {
+            bytes.extend([code, value.len() as u8]);
+            bytes.extend(value);
+        };
+        option(OPTION_MESSAGE_TYPE, &[self.message_type as u8]);
+        for (code, address) in [(OPTION_SERVER_ID, self.server_id), (OPTION_REQUESTED_IP, self.requested_ip), (OPTION_SUBNET_MASK, self.subnet_mask), (OPTION_ROUTER, self.router)] {
+            if let Some(address) = address {
+                option(code, &address.octets());
+            }
+        }
+        if !self.dns_servers.is_empty() {
+            option(OPTION_DNS, &self.dns_servers.iter().flat_map(|server| server.octets()).collect::<Vec<u8>>());
+        }
+        if let Some(lease_time) = self.lease_time {
+            option(OPTION_LEASE_TIME, &lease_time.to_be_bytes());
+        }
+        bytes.push(OPTION_END);
+        bytes
+    }
+
+    // Options this crate doesn't know are skipped; one without a message type is plain BOOTP,
+    // which isn't served here.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, FIXED_LEN + MAGIC_COOKIE.len())?;
+        if bytes[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE {
+            return Err(PacketError::BadLength(FIXED_LEN));
+        }
+        let address = |value: &[u8]| Ipv4Addr::new(value[0], value[1], value[2], value[3]);
+        let mut message = DhcpMessage::new(DhcpMessageType::Discover, u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), MacAddr(bytes[28..34].try_into().unwrap()));
+        message.client_ip = address(&bytes[12..]);
+        message.your_ip = address(&bytes[16..]);
+
+        let mut message_type = None;
+        let mut at = FIXED_LEN + MAGIC_COOKIE.len();
+        while let Some(&code) = bytes.get(at) {
+            if code == OPTION_END {
+                break;
+            }
+            if code == OPTION_PAD {
+                at += 1;
+                continue;
+            }
+            need(bytes, at + 2)?;
+            let len = bytes[at + 1] as usize;
+            need(bytes, at + 2 + len)?;
+            let value = &bytes[at + 2..at + 2 + len];
+            let four = || if len == 4 { Ok(address(value)) } else { Err(PacketError::BadLength(len)) };
+            match code {
+                OPTION_MESSAGE_TYPE => message_type = value.first().copied(),
+                OPTION_SERVER_ID => message.server_id = Some(four()?),
+                OPTION_REQUESTED_IP => message.requested_ip = Some(four()?),
+                OPTION_SUBNET_MASK => message.subnet_mask = Some(four()?),
+                // more than one router can be listed; the first is the one used
+                OPTION_ROUTER => message.router = value.chunks_exact(4).next().map(address),
+                OPTION_DNS => message.dns_servers = value.chunks_exact(4).map(address).collect(),
+                OPTION_LEASE_TIME => message.lease_time = Some(u32::from_be_bytes(four()?.octets())),
+                _ => {}
+            }
+            at += 2 + len;
+        }
+        let code = message_type.ok_or(PacketError::UnknownType(0))?;
+        message.message_type = DhcpMessageType::from_code(code).ok_or(PacketError::UnknownType(code))?;
+        Ok(message)
+    }
+
+    // A client sends from 0.0.0.0 (or its address, once it has one) to everybody; a server
+    // answers from its own address, to everybody too.
+    pub fn to_packet(&self, source_ip: Ipv4Addr) -> LayeredPacket {
+        let (source_port, destination_port) = if self.message_type.from_server() { (SERVER_PORT, CLIENT_PORT) } else { (CLIENT_PORT, SERVER_PORT) };
+        PacketBuilder::ipv4()
+            .src(source_ip)
+            .dst(Ipv4Addr::BROADCAST)
+            .hop_limit(DEFAULT_HOP_LIMIT)
+            .udp()
+            .sport(source_port)
+            .dport(destination_port)
+            .payload(self.to_bytes())
+            .build()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseState {
+    // held for the client until it asks for it, or OFFER_HOLD passes
+    Offered,
+    Bound,
+    // a client found somebody else already using it (DECLINE), so nobody gets it for a while
+    Declined,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub mac : MacAddr,
+    pub address : Ipv4Addr,
+    pub state : LeaseState,
+    pub expires : Instant,
+}
+
+// A DHCP server for one subnet: it hands out the addresses from start to end, one per client
+#[derive(Debug, Clone)]
+pub struct DhcpServer {
+    pub address : Ipv4Addr,
+    pub pool_start : Ipv4Addr,
+    pub pool_end : Ipv4Addr,
+    pub prefix_len : u8,
+    pub router : Option<Ipv4Addr>,
+    pub dns_servers : Vec<Ipv4Addr>,
+    pub lease_time : Duration,
+    pub leases : Vec<Lease>,
+}
+
+impl DhcpServer {
+    // a day, like most home routers
+    pub fn new(address: Ipv4Addr, pool_start: Ipv4Addr, pool_end: Ipv4Addr, prefix_len: u8) -> Self {
+        DhcpServer { address, pool_start, pool_end, prefix_len, router : None, dns_servers : vec![], lease_time : Duration::from_secs(24 * 60 * 60), leases : vec![] }
+    }
+
+    pub fn with_router(mut self, router: Ipv4Addr) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    pub fn with_dns(mut self, server: Ipv4Addr) -> Self {
+        self.dns_servers.push(server);
+        self
+    }
+
+    pub fn with_lease_time(mut self, lease_time: Duration) -> Self {
+        self.lease_time = lease_time;
+        self
+    }
+
+    pub fn subnet_mask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0))
+    }
+
+    pub fn lease_of(&self, mac: MacAddr) -> Option<&Lease> {
+        self.leases.iter().find(|lease| lease.mac == mac && lease.state != LeaseState::Declined)
+    }
+
+    fn in_pool(&self, address: Ipv4Addr) -> bool {
+        (self.pool_start..=self.pool_end).contains(&address) && address != self.address
+    }
+
+    // free for this client: in the pool, and not leased to anybody else
+    fn free_for(&self, address: Ipv4Addr, mac: MacAddr) -> bool {
+        self.in_pool(address) && self.leases.iter().all(|lease| lease.address != address || lease.mac == mac && lease.state != LeaseState::Declined)
+    }
+
+    // its own address again if it has one, the one it asks for if that's free, or the first free one
+    fn pick(&self, mac: MacAddr, requested: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if let Some(lease) = self.lease_of(mac) {
+            return Some(lease.address);
+        }
+        if let Some(requested) = requested.filter(|&requested| self.free_for(requested, mac)) {
+            return Some(requested);
+        }
+        (u32::from(self.pool_start)..=u32::from(self.pool_end)).map(Ipv4Addr::from).find(|&address| self.free_for(address, mac))
+    }
+
+    fn hold(&mut self, mac: MacAddr, address: Ipv4Addr, state: LeaseState, expires: Instant) {
+        self.leases.retain(|lease| lease.mac != mac && lease.address != address);
+        self.leases.push(Lease { mac, address, state, expires });
+    }
+
+    // with everything the client needs to set itself up
+    fn configured(&self, mut reply: DhcpMessage, address: Ipv4Addr) -> DhcpMessage {
+        reply.your_ip = address;
+        reply.subnet_mask = Some(self.subnet_mask());
+        reply.router = self.router;
+        reply.dns_servers = self.dns_servers.clone();
+        reply.lease_time = Some(self.lease_time.as_secs() as u32);
+        reply
+    }
+
+    pub fn expire(&mut self, now: Instant) -> Vec<Lease> {
+        let (expired, kept) = self.leases.iter().partition(|lease| lease.expires <= now);
+        self.leases = kept;
+        expired
+    }
+
+    // What the server says to a client message, if anything. An empty pool means no OFFER.
+    pub fn handle(&mut self, message: &DhcpMessage, now: Instant) -> Option<DhcpMessage> {
+        self.expire(now);
+        let mac = message.client_mac;
+        match message.message_type {
+            DhcpMessageType::Discover => {
+                let address = self.pick(mac, message.requested_ip)?;
+                if self.lease_of(mac).is_none_or(|lease| lease.state == LeaseState::Offered) {
+                    self.hold(mac, address, LeaseState::Offered, now + OFFER_HOLD);
+                }
+                Some(self.configured(message.reply(DhcpMessageType::Offer, self.address), address))
+            }
+            DhcpMessageType::Request => {
+                // the client chose another server's offer, so ours is free again
+                if message.server_id.is_some_and(|server| server != self.address) {
+                    self.leases.retain(|lease| lease.mac != mac || lease.state != LeaseState::Offered);
+                    return None;
+                }
+                // a renewing client has the address already, and says so in ciaddr
+                let wanted = message.requested_ip.unwrap_or(message.client_ip);
+                if !self.free_for(wanted, mac) {
+                    return Some(message.reply(DhcpMessageType::Nak, self.address));
+                }
+                self.hold(mac, wanted, LeaseState::Bound, now + self.lease_time);
+                Some(self.configured(message.reply(DhcpMessageType::Ack, self.address), wanted))
+            }
+            DhcpMessageType::Decline => {
+                let address = message.requested_ip?;
+                self.hold(MacAddr::default(), address, LeaseState::Declined, now + self.lease_time);
+                None
+            }
+            DhcpMessageType::Release => {
+                self.leases.retain(|lease| lease.mac != mac || lease.address != message.client_ip);
+                None
+            }
+            DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak => None,
+        }
+    }
+
+    // The server on port 67: the reply packet to a client packet
+    pub fn serve(&mut self, packet: &LayeredPacket, now: Instant) -> Option<LayeredPacket> {
+        let Transport::Udp(udp) = &packet.transport else {
+            return None;
+        };
+        if udp.destination_port != SERVER_PORT {
+            return None;
+        }
+        let message = DhcpMessage::from_bytes(&packet.payload).ok()?;
+        Some(self.handle(&message, now)?.to_packet(self.address))
+    }
+}
+
+// What a client ends up with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpConfig {
+    pub address : Ipv4Addr,
+    pub prefix_len : u8,
+    pub router : Option<Ipv4Addr>,
+    pub dns_servers : Vec<Ipv4Addr>,
+    pub server : Ipv4Addr,
+    pub lease_time : Duration,
+}
+
+// The client side, for a host without an address; `state` is the RFC's machine
+#[derive(Debug, Clone)]
+pub struct DhcpClient {
+    pub mac : MacAddr,
+    pub transaction_id : u32,
+    pub state : StateMachine,
+    pub config : Option<DhcpConfig>,
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddr, transaction_id: u32) -> Self {
+        DhcpClient { mac, transaction_id, state : StateMachine::dhcp_client(), config : None }
+    }
+
+    pub fn discover(&mut self) -> DhcpMessage {
+        self.state.reset();
+        self.state.fire("send DISCOVER");
+        DhcpMessage::new(DhcpMessageType::Discover, self.transaction_id, self.mac)
+    }
+
+    // at T1: ask the server it has the lease from to keep it going
+    pub fn renew(&mut self) -> Option<DhcpMessage> {
+        let config = self.config.as_ref()?;
+        self.state.fire("T1 expires, send REQUEST")?;
+        let mut request = DhcpMessage::new(DhcpMessageType::Request, self.transaction_id, self.mac);
+        request.client_ip = config.address;
+        Some(request)
+    }
+
+    // The next message to send, if any. Messages for other clients, or offers after the first
+    // one was taken, are ignored.
+    pub fn receive(&mut self, message: &DhcpMessage) -> Option<DhcpMessage> {
+        if message.transaction_id != self.transaction_id || message.client_mac != self.mac {
+            return None;
+        }
+        match message.message_type {
+            DhcpMessageType::Offer => {
+                self.state.fire("recv OFFER, send REQUEST")?;
+                let mut request = DhcpMessage::new(DhcpMessageType::Request, self.transaction_id, self.mac);
+                request.server_id = message.server_id;
+                request.requested_ip = Some(message.your_ip);
+                Some(request)
+            }
+            DhcpMessageType::Ack => {
+                self.state.fire("recv ACK")?;
+                let prefix_len = message.subnet_mask.map_or(32, |mask| u32::from(mask).leading_ones() as u8);
+                self.config = Some(DhcpConfig {
+                    address : message.your_ip,
+                    prefix_len,
+                    router : message.router,
+                    dns_servers : message.dns_servers.clone(),
+                    server : message.server_id?,
+                    lease_time : Duration::from_secs(message.lease_time.unwrap_or(0) as u64),
+                });
+                None
+            }
+            DhcpMessageType::Nak => {
+                self.state.fire("recv NAK");
+                self.config = None;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn hosts_get_addresses_with_dora() {
+    use crate::packet::Packet;
+
+    let now = Instant::now();
+    let gateway = Ipv4Addr::new(192, 168, 1, 1);
+    let mut server = DhcpServer::new(gateway, Ipv4Addr::new(192, 168, 1, 100), Ipv4Addr::new(192, 168, 1, 101), 24)
+        .with_router(gateway)
+        .with_dns(gateway)
+        .with_lease_time(Duration::from_secs(3600));
+    let laptop: MacAddr = "02:00:00:00:00:0a".parse().unwrap();
+    let mut client = DhcpClient::new(laptop, 0x3903f326);
+
+    // the whole exchange as packets, broadcast both ways
+    let discover = client.discover().to_packet(Ipv4Addr::UNSPECIFIED);
+    assert_eq!((discover.destination_ip(), discover.source_port(), discover.destination_port()), (Ipv4Addr::BROADCAST, 68, 67));
+    assert_eq!((discover.payload[0], discover.payload[10], &discover.payload[236..242]), (1, 0x80, &[99, 130, 83, 99, 53, 1][..]));
+    let offer = server.serve(&discover, now).unwrap();
+    assert_eq!((offer.source_ip(), offer.destination_port()), (gateway, CLIENT_PORT));
+    let offer = DhcpMessage::from_bytes(&offer.payload).unwrap();
+    assert_eq!((offer.message_type, offer.your_ip), (DhcpMessageType::Offer, Ipv4Addr::new(192, 168, 1, 100)));
+    let request = client.receive(&offer).unwrap().to_packet(Ipv4Addr::UNSPECIFIED);
+    let ack = server.serve(&request, now).unwrap();
+    assert_eq!(client.receive(&DhcpMessage::from_bytes(&ack.payload).unwrap()), None);
+    assert_eq!(client.state.current, "BOUND");
+    assert_eq!(client.config, Some(DhcpConfig {
+        address : Ipv4Addr::new(192, 168, 1, 100),
+        prefix_len : 24,
+        router : Some(gateway),
+        dns_servers : vec![gateway],
+        server : gateway,
+        lease_time : Duration::from_secs(3600),
+    }));
+
+    // the next client gets the next address, and the one after that nothing
+    let phone = DhcpMessage::new(DhcpMessageType::Discover, 2, "02:00:00:00:00:0b".parse().unwrap());
+    assert_eq!(server.handle(&phone, now).map(|offer| offer.your_ip), Some(Ipv4Addr::new(192, 168, 1, 101)));
+    let tablet = DhcpMessage::new(DhcpMessageType::Discover, 3, "02:00:00:00:00:0c".parse().unwrap());
+    assert_eq!(server.handle(&tablet, now), None);
+    // asking for the laptop's address is a NAK
+    let mut greedy = DhcpMessage::new(DhcpMessageType::Request, 3, tablet.client_mac);
+    greedy.requested_ip = Some(Ipv4Addr::new(192, 168, 1, 100));
+    assert_eq!(server.handle(&greedy, now).map(|reply| reply.message_type), Some(DhcpMessageType::Nak));
+
+    // renewing at T1 keeps the lease going past its first hour
+    let renew = client.renew().unwrap();
+    let later = now + Duration::from_secs(1800);
+    client.receive(&server.handle(&renew, later).unwrap());
+    assert_eq!(client.state.current, "BOUND");
+    assert_eq!(server.lease_of(laptop).map(|lease| lease.expires), Some(later + Duration::from_secs(3600)));
+    // the phone never asked for its offer, so after a while the tablet can have it
+    assert_eq!(server.handle(&tablet, later).map(|offer| offer.your_ip), Some(Ipv4Addr::new(192, 168, 1, 101)));
+}
@@ -0,0 +1,386 @@
+//! A small authoritative DNS server and service discovery through it.
+//! A and AAAA records name hosts; SRV records (RFC 2782) name services: `_http._tcp.example.com`
+//! lists the hosts running it, the port on each, a priority (lower is tried first, the others
+//! are backups) and a weight (how the load is shared between records of the same priority).
+//! TXT records carry free text, which service discovery (DNS-SD) uses for key=value settings.
+//!
+//! The zone can also answer differently depending on where the client is (GeoDNS): records
+//! for a client subnet, like with the EDNS Client Subnet option (RFC 7871), win over the plain
+//! ones, the longest matching subnet first, just like routes. The subnet can be an IPv4 or an
+//! IPv6 one, and either can have A or AAAA answers: what the client asks for doesn't depend on
+//! how it reached the server.
+//!
+//! So a client doesn't need to know where a service runs, only its name: the balancer below
+//! asks for the SRV records, picks a target by priority and weight, and looks up its address.
+//!
+//! The zone answers real queries too (see dns_message for how they look on the wire): a CNAME
+//! record says a name is another name, so the answer has the CNAME and then the records of the
+//! name it points to, as far as the chain goes.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::dns_message::{DnsMessage, DNS_PORT, NAME_ERROR, NOT_IMPLEMENTED};
+use crate::packet::layered::{IpHeader, LayeredPacket, Transport};
+use crate::packet::Packet;
+use crate::routing::{Route, RouteAddress, RouteError};
+
+// how many CNAMEs in a row an answer follows
+pub const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority : u16,
+    pub weight : u16,
+    pub port : u16,
+    pub target : String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    // the name is an alias for this one
+    Cname(String),
+    Srv(SrvRecord),
+    Txt(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Srv,
+    Txt,
+}
+
+impl RecordType {
+    // the number for it in a DNS message
+    pub fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Cname => 5,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Srv => 33,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        [RecordType::A, RecordType::Aaaa, RecordType::Cname, RecordType::Srv, RecordType::Txt].into_iter().find(|record_type| record_type.code() == code)
+    }
+}
+
+impl Record {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            Record::A(_) => RecordType::A,
+            Record::Aaaa(_) => RecordType::Aaaa,
+            Record::Cname(_) => RecordType::Cname,
+            Record::Srv(_) => RecordType::Srv,
+            Record::Txt(_) => RecordType::Txt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Zone {
+    pub records : Vec<(String, Record)>,
+    // (name, route to the client subnet with the answer for it as the next hop)
+    pub client_subnet_records : Vec<(String, Route<Ipv4Addr, Record>)>,
+    pub client_subnet_records_v6 : Vec<(String, Route<Ipv6Addr, Record>)>,
+}
+
+// the records for the most specific of the subnets the client is in, none if it is in none
+fn most_specific<'a, A: RouteAddress>(records: &'a [(String, Route<A, Record>)], name: &str, record_type: RecordType, client: A) -> Vec<&'a Record> {
+    let matching = records
+        .iter()
+        .filter(|(record_name, route)| record_name == name && route.next_hop.record_type() == record_type && route.matches(client));
+    let Some(longest) = matching.clone().map(|(_, route)| route.prefix_len).max() else {
+        return vec![];
+    };
+    matching.filter(|(_, route)| route.prefix_len == longest).map(|(_, route)| &route.next_hop).collect()
+}
+
+impl Zone {
+    pub fn new() -> Self {
+        Zone::default()
+    }
+
+    pub fn with_record(mut self, name: &str, record: Record) -> Self {
+        self.records.push((name.to_string(), record));
+        self
+    }
+
+    pub fn with_srv(self, service: &str, priority: u16, weight: u16, port: u16, target: &str) -> Self {
+        self.with_record(service, Record::Srv(SrvRecord { priority, weight, port, target : target.to_string() }))
+    }
+
+    // the answer for clients in subnet/prefix_len
+    pub fn with_client_subnet_record(mut self, subnet: IpAddr, prefix_len: u8, name: &str, record: Record) -> Result<Self, RouteError> {
+        match subnet {
+            IpAddr::V4(subnet) => self.client_subnet_records.push((name.to_string(), Route::with_prefix(subnet, prefix_len, record)?)),
+            IpAddr::V6(subnet) => self.client_subnet_records_v6.push((name.to_string(), Route::with_prefix(subnet, prefix_len, record)?)),
+        }
+        Ok(self)
+    }
+
+    // every record of this type for the name, in the order they were added
+    pub fn resolve(&self, name: &str, record_type: RecordType) -> Vec<&Record> {
+        self.records
+            .iter()
+            .filter(|(record_name, record)| record_name == name && record.record_type() == record_type)
+            .map(|(_, record)| record)
+            .collect()
+    }
+
+    pub fn resolve_a(&self, name: &str) -> Option<Ipv4Addr> {
+        self.resolve(name, RecordType::A).into_iter().find_map(|record| match record {
+            Record::A(ip) => Some(*ip),
+            _ => None,
+        })
+    }
+
+    // The records of the most specific subnet the client is in, or the plain ones when there
+    // is none. A resolver would pass the client's subnet along; here it is the client itself.
+    pub fn resolve_for_client(&self, name: &str, record_type: RecordType, client: IpAddr) -> Vec<&Record> {
+        let records = match client {
+            IpAddr::V4(client) => most_specific(&self.client_subnet_records, name, record_type, client),
+            IpAddr::V6(client) => most_specific(&self.client_subnet_records_v6, name, record_type, client),
+        };
+        if records.is_empty() {
+            return self.resolve(name, record_type);
+        }
+        records
+    }
+
+    pub fn resolve_a_for_client(&self, name: &str, client: IpAddr) -> Option<Ipv4Addr> {
+        self.resolve_for_client(name, RecordType::A, client).into_iter().find_map(|record| match record {
+            Record::A(ip) => Some(*ip),
+            _ => None,
+        })
+    }
+
+    pub fn resolve_aaaa_for_client(&self, name: &str, client: IpAddr) -> Option<Ipv6Addr> {
+        self.resolve_for_client(name, RecordType::Aaaa, client).into_iter().find_map(|record| match record {
+            Record::Aaaa(ip) => Some(*ip),
+            _ => None,
+        })
+    }
+
+    pub fn resolve_srv(&self, service: &str) -> Vec<&SrvRecord> {
+        self.resolve(service, RecordType::Srv).into_iter().filter_map(|record| match record {
+            Record::Srv(srv) => Some(srv),
+            _ => None,
+        }).collect()
+    }
+
+    // the key=value pairs in the name's TXT records, like `path=/api` or `version=2`
+    pub fn txt_settings(&self, name: &str) -> Vec<(String, String)> {
+        self.resolve(name, RecordType::Txt).into_iter().filter_map(|record| match record {
+            Record::Txt(text) => text.split_once('=').map(|(key, value)| (key.to_string(), value.to_string())),
+            _ => None,
+        }).collect()
+    }
+
+    // The response to a query, with the CNAMEs on the way to the records asked for. A name
+    // with no records at all is NXDOMAIN; one with only other types is an empty answer.
+    pub fn answer(&self, query: &DnsMessage) -> DnsMessage {
+        let mut response = DnsMessage::response_to(query);
+        if query.header.opcode != 0 {
+            response.header.rcode = NOT_IMPLEMENTED;
+            return response;
+        }
+        for question in &query.questions {
+            let mut name = question.name.clone();
+            // a loop of CNAMEs would otherwise go on forever
+            for _ in 0..MAX_CNAME_CHAIN {
+                let records = self.resolve(&name, question.record_type);
+                if !records.is_empty() {
+                    for record in records {
+                        response = response.with_answer(&name, record.clone());
+                    }
+                    break;
+                }
+                let Some(Record::Cname(target)) = self.resolve(&name, RecordType::Cname).first().copied() else {
+                    if !self.records.iter().any(|(record_name, _)| *record_name == name) {
+                        response.header.rcode = NAME_ERROR;
+                    }
+                    break;
+                };
+                response = response.with_answer(&name, Record::Cname(target.clone()));
+                name = target.clone();
+            }
+        }
+        response
+    }
+
+    // A DNS server on port 53: the response packet to a query packet. Nothing for anything
+    // else, or for a query too broken to answer.
+    pub fn serve<H: IpHeader>(&self, packet: &LayeredPacket<H>) -> Option<LayeredPacket<H>> {
+        if !matches!(packet.transport, Transport::Udp(_)) || packet.destination_port() != DNS_PORT {
+            return None;
+        }
+        let query = DnsMessage::from_bytes(&packet.payload).ok().filter(|query| !query.header.response)?;
+        let mut reply = packet.clone();
+        reply.set_source(packet.destination_ip(), packet.destination_port());
+        reply.set_destination(packet.source_ip(), packet.source_port());
+        reply.payload = self.answer(&query).to_bytes();
+        Some(reply)
+    }
+}
+
+// Where a service was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceEndpoint {
+    pub target : String,
+    pub address : Ipv4Addr,
+    pub port : u16,
+}
+
+// A client side load balancer for one service, fed by its SRV records.
+// Within the best priority that still has healthy targets, the load is shared by weight with
+// smooth weighted round robin (what nginx does): deterministic, and it spreads the picks out
+// instead of sending a target all its turns in a row.
+#[derive(Debug, Clone)]
+pub struct ServiceBalancer {
+    pub service : String,
+    // targets taken out, say by a failed health check
+    pub down : Vec<String>,
+    // (target, current weight) for the round robin
+    current : Vec<(String, i64)>,
+}
+
+impl ServiceBalancer {
+    pub fn new(service: &str) -> Self {
+        ServiceBalancer { service : service.to_string(), down : vec![], current : vec![] }
+    }
+
+    pub fn mark_down(&mut self, target: &str) {
+        if !self.down.iter().any(|down| down == target) {
+            self.down.push(target.to_string());
+        }
+    }
+
+    pub fn mark_up(&mut self, target: &str) {
+        self.down.retain(|down| down != target);
+    }
+
+    pub fn pick(&mut self, zone: &Zone) -> Option<ServiceEndpoint> {
+        let records = zone.resolve_srv(&self.service);
+        let healthy: Vec<&SrvRecord> = records
+            .into_iter()
+            .filter(|srv| !self.down.contains(&srv.target) && zone.resolve_a(&srv.target).is_some())
+            .collect();
+        let best = healthy.iter().map(|srv| srv.priority).min()?;
+        let group: Vec<&SrvRecord> = healthy.into_iter().filter(|srv| srv.priority == best).collect();
+        // a weight of 0 means "only if there is nothing else", so all zeros share equally
+        let all_zero = group.iter().all(|srv| srv.weight == 0);
+        let weight = |srv: &SrvRecord| if all_zero { 1 } else { srv.weight as i64 };
+        let total: i64 = group.iter().map(|srv| weight(srv)).sum();
+
+        let mut chosen: Option<(&SrvRecord, i64)> = None;
+        for srv in group {
+            let current = match self.current.iter_mut().find(|(target, _)| *target == srv.target) {
+                Some((_, current)) => current,
+                None => {
+                    self.current.push((srv.target.clone(), 0));
+                    &mut self.current.last_mut().unwrap().1
+                }
+            };
+            *current += weight(srv);
+            if chosen.is_none_or(|(_, best)| *current > best) {
+                chosen = Some((srv, *current));
+            }
+        }
+        let (srv, _) = chosen?;
+        let (_, current) = self.current.iter_mut().find(|(target, _)| *target == srv.target)?;
+        *current -= total;
+        Some(ServiceEndpoint { target : srv.target.clone(), address : zone.resolve_a(&srv.target)?, port : srv.port })
+    }
+
+    // what an application does before connecting: the packet goes to wherever the service is now
+    pub fn address<P: Packet<Address = Ipv4Addr>>(&mut self, zone: &Zone, mut packet: P) -> Option<P> {
+        let endpoint = self.pick(zone)?;
+        packet.set_destination(endpoint.address, endpoint.port);
+        Some(packet)
+    }
+}
+
+#[test]
+fn services_found_through_srv_records() {
+    use crate::packet::builder::PacketBuilder;
+
+    let zone = Zone::new()
+        .with_srv("_http._tcp.shop.example", 10, 3, 8080, "web1.shop.example")
+        .with_srv("_http._tcp.shop.example", 10, 1, 8081, "web2.shop.example")
+        // only used when both of the others are down
+        .with_srv("_http._tcp.shop.example", 20, 1, 80, "backup.shop.example")
+        .with_record("web1.shop.example", Record::A(Ipv4Addr::new(192, 0, 2, 11)))
+        .with_record("web2.shop.example", Record::A(Ipv4Addr::new(192, 0, 2, 12)))
+        .with_record("backup.shop.example", Record::A(Ipv4Addr::new(198, 51, 100, 7)))
+        .with_record("_http._tcp.shop.example", Record::Txt("path=/api".into()))
+        .with_record("_http._tcp.shop.example", Record::Txt("version=2".into()));
+
+    assert_eq!(zone.txt_settings("_http._tcp.shop.example"), [("path".into(), "/api".into()), ("version".into(), "2".into())]);
+
+    // weights 3 and 1: web1 gets three of every four, spread out
+    let mut balancer = ServiceBalancer::new("_http._tcp.shop.example");
+    let picks: Vec<String> = (0..8).map(|_| balancer.pick(&zone).unwrap().target).collect();
+    assert_eq!(picks.iter().filter(|target| *target == "web1.shop.example").count(), 6);
+    assert_eq!(&picks[..4], ["web1.shop.example", "web1.shop.example", "web2.shop.example", "web1.shop.example"]);
+
+    balancer.mark_down("web1.shop.example");
+    assert_eq!(balancer.pick(&zone).unwrap().target, "web2.shop.example");
+    balancer.mark_down("web2.shop.example");
+    assert_eq!(balancer.pick(&zone), Some(ServiceEndpoint { target : "backup.shop.example".into(), address : Ipv4Addr::new(198, 51, 100, 7), port : 80 }));
+
+    // the application only knows the service name
+    balancer.mark_up("web2.shop.example");
+    let request = PacketBuilder::ipv4().src(Ipv4Addr::new(10, 0, 0, 2)).dst(Ipv4Addr::UNSPECIFIED).tcp().sport(50000).payload(b"GET /api/cart").build();
+    let request = balancer.address(&zone, request).unwrap();
+    assert_eq!((request.destination_ip(), request.destination_port()), (Ipv4Addr::new(192, 0, 2, 12), 8081));
+}
+
+#[test]
+fn zone_answers_queries_on_the_wire() {
+    use crate::dns_message::NO_ERROR;
+    use crate::packet::builder::PacketBuilder;
+
+    let zone = Zone::new()
+        .with_record("www.example.com", Record::Cname("web.example.com".into()))
+        .with_record("web.example.com", Record::Cname("example.com".into()))
+        .with_record("example.com", Record::A(Ipv4Addr::new(93, 184, 216, 34)))
+        .with_record("example.com", Record::Aaaa("2606:2800:220:1::248".parse().unwrap()))
+        .with_record("loop.example.com", Record::Cname("loop.example.com".into()));
+
+    // a stub resolver asks, and the server answers in the same packet turned around
+    let query = |port: u16| {
+        PacketBuilder::ipv4()
+            .src(Ipv4Addr::new(10, 0, 0, 2))
+            .dst(Ipv4Addr::new(10, 0, 0, 53))
+            .udp()
+            .sport(33333)
+            .dport(port)
+            .payload(DnsMessage::query(0xbeef, "www.example.com", RecordType::A).to_bytes())
+            .build()
+    };
+    let reply = zone.serve(&query(DNS_PORT)).unwrap();
+    assert_eq!((reply.source_ip(), reply.destination_port()), (Ipv4Addr::new(10, 0, 0, 53), 33333));
+    let response = DnsMessage::from_bytes(&reply.payload).unwrap();
+    assert_eq!((response.header.id, response.header.response, response.header.rcode), (0xbeef, true, NO_ERROR));
+    // both CNAMEs, then the address of where they lead
+    let names: Vec<&str> = response.answers.iter().map(|answer| answer.name.as_str()).collect();
+    assert_eq!(names, ["www.example.com", "web.example.com", "example.com"]);
+    assert_eq!(response.addresses(), [std::net::IpAddr::from(Ipv4Addr::new(93, 184, 216, 34))]);
+
+    // no such name, a name without that type, and a chain that goes round
+    let missing = zone.answer(&DnsMessage::query(1, "mail.example.com", RecordType::A));
+    assert_eq!((missing.header.rcode, missing.answers.len()), (NAME_ERROR, 0));
+    let no_txt = zone.answer(&DnsMessage::query(2, "example.com", RecordType::Txt));
+    assert_eq!((no_txt.header.rcode, no_txt.answers.len()), (NO_ERROR, 0));
+    assert_eq!(zone.answer(&DnsMessage::query(3, "loop.example.com", RecordType::A)).answers.len(), MAX_CNAME_CHAIN);
+
+    // responses and other ports are left alone
+    assert_eq!(zone.serve(&reply), None);
+    assert_eq!(zone.serve(&query(5353)), None);
+}
@@ -0,0 +1,370 @@
+//! DNS messages as they go over UDP port 53 (RFC 1035). A query and its response are the same
+//! kind of message: a 12 byte header, then the questions, then the answers, the authority and
+//! the additional records, each section as long as its count in the header says.
+//!
+//! ```text
+//! | id (16) | QR | opcode (4) | AA | TC | RD | RA | zero (3) | rcode (4) |
+//! | questions (16) | answers (16) | authority (16) | additional (16) |
+//! ```
+//!
+//! A name is written as its labels, each with its length in front, and a zero at the end:
+//! `7 example 3 com 0`. Since the same names come up again and again in one message, the end of
+//! a name can instead be a pointer (two bytes starting with the bits 11) to where the same labels
+//! were already written, so an answer for example.com is mostly `c0 0c`, the offset of the name
+//! in the question. A pointer only ever points back, so reading one can't go round in circles.
+use std::fmt::{self, Display};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::dns::{Record, RecordType, SrvRecord};
+use crate::packet::{need, PacketError};
+
+pub const DNS_PORT: u16 = 53;
+pub const HEADER_LEN: usize = 12;
+pub const CLASS_IN: u16 = 1;
+pub const MAX_LABEL_LEN: usize = 63;
+pub const MAX_NAME_LEN: usize = 255;
+// the answers of a zone, which doesn't say
+pub const DEFAULT_TTL: u32 = 300;
+
+// the response codes
+pub const NO_ERROR: u8 = 0;
+pub const FORMAT_ERROR: u8 = 1;
+pub const SERVER_FAILURE: u8 = 2;
+pub const NAME_ERROR: u8 = 3;
+pub const NOT_IMPLEMENTED: u8 = 4;
+pub const REFUSED: u8 = 5;
+
+const POINTER: u8 = 0xc0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    Packet(PacketError),
+    // a label too long, or with the reserved bits 01 or 10 in front of its length
+    BadName,
+    // a pointer to this offset, which isn't before it
+    BadPointer(usize),
+    // a question for a type this crate doesn't know
+    UnknownType(u16),
+}
+
+impl Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::Packet(error) => write!(f, "{error}"),
+            DnsError::BadName => write!(f, "malformed name"),
+            DnsError::BadPointer(at) => write!(f, "name pointer to {at} doesn't point back"),
+            DnsError::UnknownType(code) => write!(f, "unknown record type {code}"),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+impl From<PacketError> for DnsError {
+    fn from(error: PacketError) -> Self {
+        DnsError::Packet(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DnsHeader {
+    pub id : u16,
+    pub response : bool,
+    pub opcode : u8,
+    pub authoritative : bool,
+    pub truncated : bool,
+    pub recursion_desired : bool,
+    pub recursion_available : bool,
+    pub rcode : u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name : String,
+    pub record_type : RecordType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceRecord {
+    pub name : String,
+    pub ttl : u32,
+    pub record : Record,
+}
+
+// The counts of the header are the lengths of the sections, so they are never out of step
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub header : DnsHeader,
+    pub questions : Vec<Question>,
+    pub answers : Vec<ResourceRecord>,
+    pub authority : Vec<ResourceRecord>,
+    pub additional : Vec<ResourceRecord>,
+}
+
+impl DnsMessage {
+    // one question, asking for recursion like a stub resolver does
+    pub fn query(id: u16, name: &str, record_type: RecordType) -> Self {
+        DnsMessage {
+            header : DnsHeader { id, recursion_desired : true, ..DnsHeader::default() },
+            questions : vec![Question { name : name.to_string(), record_type }],
+            ..DnsMessage::default()
+        }
+    }
+
+    // an empty answer to the query, with its id and questions
+    pub fn response_to(query: &DnsMessage) -> Self {
+        DnsMessage {
+            header : DnsHeader { response : true, authoritative : true, ..query.header },
+            questions : query.questions.clone(),
+            ..DnsMessage::default()
+        }
+    }
+
+    pub fn with_answer(mut self, name: &str, record: Record) -> Self {
+        self.answers.push(ResourceRecord { name : name.to_string(), ttl : DEFAULT_TTL, record });
+        self
+    }
+
+    // the addresses among the answers, wherever the CNAMEs led
+    pub fn addresses(&self) -> Vec<std::net::IpAddr> {
+        self.answers.iter().filter_map(|answer| match answer.record {
+            Record::A(ip) => Some(ip.into()),
+            Record::Aaaa(ip) => Some(ip.into()),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = &self.header;
+        let flags = [
+            (header.response as u8) << 7 | (header.opcode & 0xf) << 3 | (header.authoritative as u8) << 2 | (header.truncated as u8) << 1 | header.recursion_desired as u8,
+            (header.recursion_available as u8) << 7 | header.rcode & 0xf,
+        ];
+        let mut writer = Writer::default();
+        writer.bytes.extend(header.id.to_be_bytes());
+        writer.bytes.extend(flags);
+        for count in [self.questions.len(), self.answers.len(), self.authority.len(), self.additional.len()] {
+            writer.bytes.extend((count as u16).to_be_bytes());
+        }
+        for question in &self.questions {
+            writer.name(&question.name, true);
+            writer.bytes.extend(question.record_type.code().to_be_bytes());
+            writer.bytes.extend(CLASS_IN.to_be_bytes());
+        }
+        for record in self.answers.iter().chain(&self.authority).chain(&self.additional) {
+            writer.record(record);
+        }
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DnsError> {
+        need(bytes, HEADER_LEN)?;
+        let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let header = DnsHeader {
+            id : word(0),
+            response : bytes[2] & 0x80 != 0,
+            opcode : bytes[2] >> 3 & 0xf,
+            authoritative : bytes[2] & 0x04 != 0,
+            truncated : bytes[2] & 0x02 != 0,
+            recursion_desired : bytes[2] & 0x01 != 0,
+            recursion_available : bytes[3] & 0x80 != 0,
+            rcode : bytes[3] & 0xf,
+        };
+        let mut message = DnsMessage { header, ..DnsMessage::default() };
+        let mut at = HEADER_LEN;
+        for _ in 0..word(4) {
+            let (name, end) = read_name(bytes, at)?;
+            need(bytes, end + 4)?;
+            let code = u16::from_be_bytes([bytes[end], bytes[end + 1]]);
+            let record_type = RecordType::from_code(code).ok_or(DnsError::UnknownType(code))?;
+            message.questions.push(Question { name, record_type });
+            at = end + 4;
+        }
+        for (section, count) in [(0, word(6)), (1, word(8)), (2, word(10))] {
+            for _ in 0..count {
+                let (record, end) = read_record(bytes, at)?;
+                at = end;
+                // what this crate can't hold, like DNSSEC signatures, is skipped
+                if let Some(record) = record {
+                    [&mut message.answers, &mut message.authority, &mut message.additional][section].push(record);
+                }
+            }
+        }
+        Ok(message)
+    }
+}
+
+// Keeps where every name (and the rest of it after each label) was written, to point back to
+#[derive(Default)]
+struct Writer {
+    bytes : Vec<u8>,
+    names : Vec<(String, u16)>,
+}
+
+impl Writer {
+    // a label longer than 63 bytes is cut
+    fn name(&mut self, name: &str, compress: bool) {
+        let labels: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+        for (index, label) in labels.iter().enumerate() {
+            let rest = labels[index..].join(".").to_ascii_lowercase();
+            if let Some(&(_, at)) = self.names.iter().find(|(written, _)| *written == rest).filter(|_| compress) {
+                self.bytes.extend((u16::from(POINTER) << 8 | at).to_be_bytes());
+                return;
+            }
+            // only the first 16K can be pointed to
+            if self.bytes.len() < 0x4000 {
+                self.names.push((rest, self.bytes.len() as u16));
+            }
+            let label = &label.as_bytes()[..label.len().min(MAX_LABEL_LEN)];
+            self.bytes.push(label.len() as u8);
+            self.bytes.extend(label);
+        }
+        self.bytes.push(0);
+    }
+
+    fn record(&mut self, record: &ResourceRecord) {
+        self.name(&record.name, true);
+        self.bytes.extend(record.record.record_type().code().to_be_bytes());
+        self.bytes.extend(CLASS_IN.to_be_bytes());
+        self.bytes.extend(record.ttl.to_be_bytes());
+        // the length goes in front, once the data is written
+        let length_at = self.bytes.len();
+        self.bytes.extend([0, 0]);
+        match &record.record {
+            Record::A(ip) => self.bytes.extend(ip.octets()),
+            Record::Aaaa(ip) => self.bytes.extend(ip.octets()),
+            Record::Cname(target) => self.name(target, true),
+            // RFC 2782 says the target is never compressed
+            Record::Srv(srv) => {
+                for field in [srv.priority, srv.weight, srv.port] {
+                    self.bytes.extend(field.to_be_bytes());
+                }
+                self.name(&srv.target, false);
+            }
+            // strings of up to 255 bytes, each with its length in front
+            Record::Txt(text) => {
+                for chunk in text.as_bytes().chunks(255) {
+                    self.bytes.push(chunk.len() as u8);
+                    self.bytes.extend(chunk);
+                }
+            }
+        }
+        let length = (self.bytes.len() - length_at - 2) as u16;
+        self.bytes[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+    }
+}
+
+// The name at that offset of the whole message, and where what follows it starts
+pub fn read_name(message: &[u8], mut at: usize) -> Result<(String, usize), DnsError> {
+    let mut labels = vec![];
+    let mut length = 0;
+    let mut end = None;
+    loop {
+        need(message, at + 1)?;
+        let label_len = message[at] as usize;
+        match message[at] & POINTER {
+            0 if label_len == 0 => return Ok((labels.join("."), end.unwrap_or(at + 1))),
+            0 => {
+                need(message, at + 1 + label_len)?;
+                length += label_len + 1;
+                if length > MAX_NAME_LEN {
+                    return Err(DnsError::BadName);
+                }
+                labels.push(String::from_utf8_lossy(&message[at + 1..at + 1 + label_len]).into_owned());
+                at += 1 + label_len;
+            }
+            POINTER => {
+                need(message, at + 2)?;
+                let pointer = (u16::from_be_bytes([message[at], message[at + 1]]) & 0x3fff) as usize;
+                if pointer >= at {
+                    return Err(DnsError::BadPointer(pointer));
+                }
+                end.get_or_insert(at + 2);
+                at = pointer;
+            }
+            _ => return Err(DnsError::BadName),
+        }
+    }
+}
+
+// None for a record of a type this crate doesn't know
+fn read_record(message: &[u8], at: usize) -> Result<(Option<ResourceRecord>, usize), DnsError> {
+    let (name, at) = read_name(message, at)?;
+    need(message, at + 10)?;
+    let code = u16::from_be_bytes([message[at], message[at + 1]]);
+    let ttl = u32::from_be_bytes([message[at + 4], message[at + 5], message[at + 6], message[at + 7]]);
+    let length = u16::from_be_bytes([message[at + 8], message[at + 9]]) as usize;
+    let start = at + 10;
+    need(message, start + length)?;
+    let data = &message[start..start + length];
+    let exactly = |expected: usize| if length == expected { Ok(()) } else { Err(PacketError::BadLength(length)) };
+    let record = match RecordType::from_code(code) {
+        None => None,
+        Some(RecordType::A) => {
+            exactly(4)?;
+            Some(Record::A(Ipv4Addr::new(data[0], data[1], data[2], data[3])))
+        }
+        Some(RecordType::Aaaa) => {
+            exactly(16)?;
+            Some(Record::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap())))
+        }
+        // names in the data can point anywhere back in the message
+        Some(RecordType::Cname) => Some(Record::Cname(read_name(message, start)?.0)),
+        Some(RecordType::Srv) => {
+            need(data, 7)?;
+            let field = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+            let target = read_name(message, start + 6)?.0;
+            Some(Record::Srv(SrvRecord { priority : field(0), weight : field(2), port : field(4), target }))
+        }
+        Some(RecordType::Txt) => {
+            let mut text = vec![];
+            let mut rest = data;
+            while let Some((&chunk_len, after)) = rest.split_first() {
+                need(after, chunk_len as usize)?;
+                text.extend(&after[..chunk_len as usize]);
+                rest = &after[chunk_len as usize..];
+            }
+            Some(Record::Txt(String::from_utf8_lossy(&text).into_owned()))
+        }
+    };
+    Ok((record.map(|record| ResourceRecord { name, ttl, record }), start + length))
+}
+
+#[test]
+fn messages_with_compressed_names() {
+    // a query for example.com, as dig sends it
+    let captured = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01";
+    let query = DnsMessage::from_bytes(captured).unwrap();
+    assert_eq!(query, DnsMessage::query(0x1234, "example.com", RecordType::A));
+    assert_eq!(query.to_bytes(), captured);
+
+    // www.example.com is a CNAME for example.com: every name after the question is a pointer
+    let response = DnsMessage::response_to(&DnsMessage::query(0x1234, "www.example.com", RecordType::A))
+        .with_answer("www.example.com", Record::Cname("example.com".into()))
+        .with_answer("example.com", Record::A(Ipv4Addr::new(93, 184, 216, 34)));
+    let bytes = response.to_bytes();
+    // the question's name is at 12, and example.com inside it at 16
+    let cname = HEADER_LEN + 17 + 4;
+    assert_eq!(bytes[cname..cname + 12], [0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0x01, 0x2c, 0, 2]);
+    assert_eq!(bytes[cname + 12..cname + 16], [0xc0, 0x10, 0xc0, 0x10]);
+    assert_eq!(bytes.len(), 12 + 21 + 14 + 16);
+    assert_eq!(DnsMessage::from_bytes(&bytes), Ok(response.clone()));
+    assert_eq!(response.addresses(), [std::net::IpAddr::from(Ipv4Addr::new(93, 184, 216, 34))]);
+
+    // SRV targets are written out in full, long TXT records in pieces
+    let srv = Record::Srv(SrvRecord { priority : 10, weight : 5, port : 5060, target : "sip.example.com".into() });
+    let text = Record::Txt("v=".to_string() + &"x".repeat(300));
+    let records = DnsMessage::response_to(&DnsMessage::query(7, "_sip._udp.example.com", RecordType::Srv)).with_answer("_sip._udp.example.com", srv).with_answer("example.com", text);
+    let bytes = records.to_bytes();
+    assert!(bytes.windows(5).any(|window| window == b"\x03sip\x07"));
+    assert_eq!(DnsMessage::from_bytes(&bytes), Ok(records));
+
+    // a pointer to itself, an unknown question type, and a message cut short
+    let mut looping = captured.to_vec();
+    looping.splice(12..25, [0xc0, 12]);
+    assert_eq!(DnsMessage::from_bytes(&looping), Err(DnsError::BadPointer(12)));
+    let mut unknown = captured.to_vec();
+    unknown[26] = 99;
+    assert_eq!(DnsMessage::from_bytes(&unknown), Err(DnsError::UnknownType(99)));
+    assert_eq!(DnsMessage::from_bytes(&captured[..20]), Err(DnsError::Packet(PacketError::Truncated { needed : 21, got : 20 })));
+}
@@ -0,0 +1,160 @@
+//! A firewall on the computer itself, not on the router.
+//! Even if the router lets a packet through, the host can still drop it, which is usually
+//! the answer to "why can't I reach this computer". Like the firewalls on our laptops, it can
+//! deny all inbound traffic by default and only let in traffic for the applications we allowed.
+use std::net::Ipv4Addr;
+
+use crate::metadata::Tagged;
+use crate::packet::Packet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+// A rule matches when every field that is set matches; None means "anything"
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallRule {
+    pub direction : Direction,
+    pub action : Action,
+    pub remote_ip : Option<Ipv4Addr>,
+    pub remote_port : Option<u16>,
+    pub local_port : Option<u16>,
+}
+
+impl FirewallRule {
+    pub fn new(direction: Direction, action: Action) -> Self {
+        FirewallRule { direction, action, remote_ip : None, remote_port : None, local_port : None }
+    }
+
+    pub fn matches<P: Packet<Address = Ipv4Addr>>(&self, direction: Direction, packet: &P) -> bool {
+        let (remote_ip, remote_port, local_port) = match direction {
+            Direction::Inbound => (packet.source_ip(), packet.source_port(), packet.destination_port()),
+            Direction::Outbound => (packet.destination_ip(), packet.destination_port(), packet.source_port()),
+        };
+        self.direction == direction
+            && self.remote_ip.is_none_or(|ip| ip == remote_ip)
+            && self.remote_port.is_none_or(|port| port == remote_port)
+            && self.local_port.is_none_or(|port| port == local_port)
+    }
+}
+
+#[derive(Debug)]
+pub struct HostFirewall {
+    pub computer : u16,
+    pub default_inbound : Action,
+    pub default_outbound : Action,
+    pub rules : Vec<FirewallRule>,
+    // (application, port) for every port an application is listening on
+    pub bound_ports : Vec<(String, u16)>,
+    // applications allowed to receive traffic on the ports they listen on
+    pub allowed_applications : Vec<String>,
+    // like `iptables -t mangle ... -j MARK --set-mark`: only the match fields of the rule count
+    pub mark_rules : Vec<(FirewallRule, u32)>,
+}
+
+impl HostFirewall {
+    // a firewall that lets everything through, like having none
+    pub fn new(computer: u16) -> Self {
+        HostFirewall {
+            computer,
+            default_inbound : Action::Allow,
+            default_outbound : Action::Allow,
+            rules : vec![],
+            bound_ports : vec![],
+            allowed_applications : vec![],
+            mark_rules : vec![],
+        }
+    }
+
+    pub fn default_deny_inbound(mut self) -> Self {
+        self.default_inbound = Action::Deny;
+        self
+    }
+
+    pub fn add_rule(&mut self, rule: FirewallRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn add_mark_rule(&mut self, rule: FirewallRule, mark: u32) {
+        self.mark_rules.push((rule, mark));
+    }
+
+    // an application started listening on a port
+    pub fn bind(&mut self, application: &str, port: u16) {
+        self.bound_ports.push((application.to_string(), port));
+    }
+
+    pub fn allow_application(&mut self, application: &str) {
+        self.allowed_applications.push(application.to_string());
+    }
+
+    fn application_allowed_on(&self, port: u16) -> bool {
+        self.bound_ports
+            .iter()
+            .any(|(application, bound)| *bound == port && self.allowed_applications.contains(application))
+    }
+
+    // the first matching rule wins, then the application exceptions, then the default
+    pub fn check<P: Packet<Address = Ipv4Addr>>(&self, direction: Direction, packet: &P) -> Action {
+        if let Some(rule) = self.rules.iter().find(|rule| rule.matches(direction, packet)) {
+            return rule.action;
+        }
+        match direction {
+            Direction::Inbound if self.application_allowed_on(packet.destination_port()) => Action::Allow,
+            Direction::Inbound => self.default_inbound,
+            Direction::Outbound => self.default_outbound,
+        }
+    }
+
+    pub fn check_inbound<P: Packet<Address = Ipv4Addr>>(&self, packet: &P) -> Action {
+        self.check(Direction::Inbound, packet)
+    }
+
+    pub fn check_outbound<P: Packet<Address = Ipv4Addr>>(&self, packet: &P) -> Action {
+        self.check(Direction::Outbound, packet)
+    }
+
+    // marking doesn't stop at the first match, so the last matching rule's mark is the one left
+    pub fn mark<P: Packet<Address = Ipv4Addr>>(&self, direction: Direction, packet: &mut Tagged<P>) {
+        for (rule, mark) in &self.mark_rules {
+            if rule.matches(direction, &packet.packet) {
+                packet.meta.mark = *mark;
+            }
+        }
+    }
+}
+
+#[test]
+fn host_firewall_default_deny_with_exceptions() {
+    use crate::packet::builder::PacketBuilder;
+
+    let packet_to = |port: u16| {
+        PacketBuilder::ipv4().src("192.168.1.50".parse().unwrap()).dst("192.168.1.10".parse().unwrap()).udp().sport(40000).dport(port).payload(b"hello?").build()
+    };
+
+    let mut firewall = HostFirewall::new(12).default_deny_inbound();
+    firewall.bind("sshd", 22);
+    firewall.bind("nginx", 80);
+    firewall.allow_application("sshd");
+
+    // nginx listens, but nobody allowed it through the firewall
+    assert_eq!(firewall.check_inbound(&packet_to(22)), Action::Allow);
+    assert_eq!(firewall.check_inbound(&packet_to(80)), Action::Deny);
+    assert_eq!(firewall.check_inbound(&packet_to(8080)), Action::Deny);
+    assert_eq!(firewall.check_outbound(&packet_to(80)), Action::Allow);
+
+    // an explicit rule comes before the application exception
+    firewall.add_rule(FirewallRule {
+        remote_ip : Some("192.168.1.50".parse().unwrap()),
+        ..FirewallRule::new(Direction::Inbound, Action::Deny)
+    });
+    assert_eq!(firewall.check_inbound(&packet_to(22)), Action::Deny);
+}
@@ -0,0 +1,123 @@
+//! Link utilization over time, as a grid: one row per link, one column per time bucket, each
+//! cell the fraction of the bucket the link spent sending. Plotted as a heatmap, the links that
+//! are always busy, and the moments everything is, stand out at a glance.
+//! Exported as CSV (one line per cell) or JSON (one array per link), nothing else needed.
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::traffic::{Link, TrafficError};
+
+#[derive(Debug, Clone)]
+pub struct UtilizationHeatmap {
+    pub bucket : Duration,
+    // (link name, busy time in every bucket)
+    pub links : Vec<(String, Vec<Duration>)>,
+}
+
+impl UtilizationHeatmap {
+    pub fn new(bucket: Duration) -> Result<Self, TrafficError> {
+        if bucket.is_zero() {
+            return Err(TrafficError::ZeroBucket);
+        }
+        Ok(UtilizationHeatmap { bucket, links : vec![] })
+    }
+
+    // the link was sending from start to end; that time is split over the buckets it covers
+    pub fn add_busy(&mut self, link: &str, start: Duration, end: Duration) {
+        let bucket = self.bucket;
+        let index = match self.links.iter().position(|(name, _)| name == link) {
+            Some(index) => index,
+            None => {
+                self.links.push((link.to_string(), vec![]));
+                self.links.len() - 1
+            }
+        };
+        let busy = &mut self.links[index].1;
+        let mut at = start;
+        while at < end {
+            let number = (at.as_nanos() / bucket.as_nanos()) as usize;
+            let bucket_end = bucket * (number as u32 + 1);
+            let until = end.min(bucket_end);
+            if busy.len() <= number {
+                busy.resize(number + 1, Duration::ZERO);
+            }
+            busy[number] += until - at;
+            at = until;
+        }
+    }
+
+    pub fn add_link(&mut self, name: &str, link: &Link) {
+        for &(start, end) in &link.busy_periods {
+            self.add_busy(name, start, end);
+        }
+    }
+
+    fn buckets(&self) -> usize {
+        self.links.iter().map(|(_, busy)| busy.len()).max().unwrap_or(0)
+    }
+
+    // utilization of every link in every bucket, from 0 to 1
+    pub fn utilization(&self) -> Vec<(&str, Vec<f64>)> {
+        let buckets = self.buckets();
+        self.links
+            .iter()
+            .map(|(name, busy)| {
+                let row = (0..buckets)
+                    .map(|number| busy.get(number).map_or(0.0, |busy| busy.as_secs_f64() / self.bucket.as_secs_f64()))
+                    .collect();
+                (name.as_str(), row)
+            })
+            .collect()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("link,bucket_start_ms,utilization\n");
+        for (name, row) in self.utilization() {
+            for (number, utilization) in row.iter().enumerate() {
+                let _ = writeln!(out, "{name},{},{utilization:.3}", (self.bucket * number as u32).as_millis());
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let links: Vec<String> = self.utilization()
+            .iter()
+            .map(|(name, row)| {
+                let cells: Vec<String> = row.iter().map(|utilization| format!("{utilization:.3}")).collect();
+                format!("{{\"link\":\"{name}\",\"utilization\":[{}]}}", cells.join(","))
+            })
+            .collect();
+        format!("{{\"bucket_ms\":{},\"links\":[{}]}}", self.bucket.as_millis(), links.join(","))
+    }
+}
+
+#[test]
+fn busy_time_is_split_into_buckets() {
+    // 1 Mbps: a 1500 byte packet keeps the link busy for 12ms
+    let mut uplink = Link::new(1_000_000, Duration::ZERO, 100_000).unwrap();
+    for _ in 0..2 {
+        uplink.send(1500, Duration::from_millis(5)).unwrap();
+    }
+    let mut backbone = Link::new(1_000_000, Duration::ZERO, 100_000).unwrap();
+    backbone.send(1500, Duration::ZERO).unwrap();
+
+    let mut heatmap = UtilizationHeatmap::new(Duration::from_millis(10)).unwrap();
+    heatmap.add_link("uplink", &uplink);
+    heatmap.add_link("backbone", &backbone);
+    // the uplink sends from 5ms to 29ms: half of the first bucket, all of the second, 90% of the third
+    assert_eq!(heatmap.to_csv(), "\
+link,bucket_start_ms,utilization
+uplink,0,0.500
+uplink,10,1.000
+uplink,20,0.900
+backbone,0,1.000
+backbone,10,0.200
+backbone,20,0.000
+");
+    assert_eq!(heatmap.to_json(), "{\"bucket_ms\":10,\"links\":[\
+{\"link\":\"uplink\",\"utilization\":[0.500,1.000,0.900]},\
+{\"link\":\"backbone\",\"utilization\":[1.000,0.200,0.000]}]}");
+
+    assert_eq!(UtilizationHeatmap::new(Duration::ZERO).err(), Some(TrafficError::ZeroBucket));
+}
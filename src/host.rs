@@ -0,0 +1,378 @@
+//! A host: one network card, the addresses configured on it, a default gateway for each IP
+//! version, and the ARP and neighbor caches to find MAC addresses with. What a laptop or a
+//! server does below its sockets, without the laptop or the server.
+//!
+//! send_to() is like sending from a UDP socket: the host picks the source address, decides if
+//! the destination is on its own link or has to go through the gateway, and finds the MAC
+//! address of that next hop. If the cache doesn't know it yet, the ARP request (or Neighbor
+//! Solicitation) goes out first and the datagram waits for the answer. The frames to put on
+//! the wire come out of transmit(), the frames off the wire go into deliver(), and what was
+//! sent to this host comes out of receive().
+//!
+//! A neighbor that doesn't answer is asked again every second, and after three tries the
+//! datagrams waiting for it are thrown away, like Linux does (see expire()).
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::arp::ArpMessage;
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use crate::ndp::NdpMessage;
+use crate::neighbor::{ArpCache, NdCache, Resolution};
+use crate::packet::builder::PacketBuilder;
+use crate::packet::ipv4::Ipv4Header;
+use crate::packet::ipv6::Ipv6Header;
+use crate::packet::layered::{IpHeader, LayeredPacket, Transport};
+
+// where send_to sends from, the first of the ports left for clients
+pub const DEFAULT_PORT: u16 = 49152;
+// how long to wait for an answer before asking again, and how many times to ask in all
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_TRIES: u32 = 3;
+// the most packets waiting for their next hop, for all the neighbors together; the oldest
+// one makes room for a new one
+pub const MAX_WAITING: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostError {
+    // no address of the destination's IP version
+    NoAddress(IpAddr),
+    // not on the link, and no gateway to send it to
+    NoRoute(IpAddr),
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostError::NoAddress(to) => write!(f, "no address to send to {to} from"),
+            HostError::NoRoute(to) => write!(f, "no route to {to}"),
+        }
+    }
+}
+
+impl std::error::Error for HostError {}
+
+// a neighbor asked about that hasn't answered yet
+#[derive(Debug)]
+struct Asking {
+    next_hop : IpAddr,
+    question : EthernetFrame,
+    asked_at : Instant,
+    tries : u32,
+}
+
+// what came in for one of the host's addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub from : SocketAddr,
+    pub to : SocketAddr,
+    pub data : Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Host {
+    pub name : String,
+    pub mac : MacAddr,
+    pub addresses : Vec<(IpAddr, u8)>,
+    pub gateway_v4 : Option<Ipv4Addr>,
+    pub gateway_v6 : Option<Ipv6Addr>,
+    pub arp : ArpCache,
+    pub nd : NdCache,
+    pub port : u16,
+    // IP packets waiting for the MAC address of their next hop: (next hop, ethertype, packet)
+    waiting : Vec<(IpAddr, u16, Vec<u8>)>,
+    asking : Vec<Asking>,
+    // packets thrown away while waiting: their neighbor never answered, or too many waited
+    pub dropped : usize,
+    outbox : VecDeque<EthernetFrame>,
+    inbox : VecDeque<Datagram>,
+}
+
+// the first prefix_len bits are the same
+fn same_subnet(a: IpAddr, b: IpAddr, prefix_len: u8) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(a) ^ u32::from(b)).checked_shr(32 - prefix_len.min(32) as u32).unwrap_or(0) == 0,
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a) ^ u128::from(b)).checked_shr(128 - prefix_len.min(128) as u32).unwrap_or(0) == 0,
+        _ => false,
+    }
+}
+
+fn udp<H: IpHeader>(from: SocketAddr, to: SocketAddr, source: H::Address, destination: H::Address, payload: &[u8]) -> Vec<u8> {
+    PacketBuilder::<H>::between(source, destination).udp().sport(from.port()).dport(to.port()).payload(payload).bytes()
+}
+
+impl Host {
+    pub fn new(name: &str, mac: MacAddr) -> Self {
+        Host {
+            name : name.to_string(),
+            mac,
+            addresses : vec![],
+            gateway_v4 : None,
+            gateway_v6 : None,
+            arp : ArpCache::new(&format!("{name} arp")),
+            nd : NdCache::new(&format!("{name} nd")),
+            port : DEFAULT_PORT,
+            waiting : vec![],
+            asking : vec![],
+            dropped : 0,
+            outbox : VecDeque::new(),
+            inbox : VecDeque::new(),
+        }
+    }
+
+    pub fn with_address(mut self, address: IpAddr, prefix_len: u8) -> Self {
+        self.addresses.push((address, prefix_len));
+        self
+    }
+
+    // the default gateway of the address's IP version
+    pub fn with_gateway(mut self, gateway: IpAddr) -> Self {
+        match gateway {
+            IpAddr::V4(gateway) => self.gateway_v4 = Some(gateway),
+            IpAddr::V6(gateway) => self.gateway_v6 = Some(gateway),
+        }
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn has_address(&self, address: IpAddr) -> bool {
+        self.addresses.iter().any(|&(own, _)| own == address)
+    }
+
+    // the address to send to this destination from: one on the same subnet if there is one,
+    // else any of the same version
+    pub fn source_for(&self, destination: IpAddr) -> Option<IpAddr> {
+        let same_version = || self.addresses.iter().filter(|(own, _)| own.is_ipv4() == destination.is_ipv4());
+        same_version()
+            .find(|&&(own, prefix_len)| same_subnet(own, destination, prefix_len))
+            .or_else(|| same_version().next())
+            .map(|&(own, _)| own)
+    }
+
+    // who the frame goes to: the destination itself when it is on the link, else the gateway
+    pub fn next_hop(&self, destination: IpAddr) -> Option<IpAddr> {
+        if self.addresses.iter().any(|&(own, prefix_len)| same_subnet(own, destination, prefix_len)) {
+            return Some(destination);
+        }
+        match destination {
+            IpAddr::V4(_) => self.gateway_v4.map(IpAddr::V4),
+            IpAddr::V6(_) => self.gateway_v6.map(IpAddr::V6),
+        }
+    }
+
+    pub fn send_to(&mut self, to: SocketAddr, payload: &[u8], now: Instant) -> Result<(), HostError> {
+        self.expire(now);
+        let source = self.source_for(to.ip()).ok_or(HostError::NoAddress(to.ip()))?;
+        let next_hop = self.next_hop(to.ip()).ok_or(HostError::NoRoute(to.ip()))?;
+        let from = SocketAddr::new(source, self.port);
+        let (ethertype, packet) = match (source, to.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => (ETHERTYPE_IPV4, udp::<Ipv4Header>(from, to, source, destination, payload)),
+            (IpAddr::V6(source), IpAddr::V6(destination)) => (ETHERTYPE_IPV6, udp::<Ipv6Header>(from, to, source, destination, payload)),
+            _ => return Err(HostError::NoAddress(to.ip())),
+        };
+        let resolution = match (source, next_hop) {
+            (IpAddr::V4(source), IpAddr::V4(next_hop)) => self.arp.resolve(next_hop, now, (self.mac, source)),
+            (IpAddr::V6(source), IpAddr::V6(next_hop)) => self.nd.resolve(next_hop, now, (self.mac, source)),
+            _ => return Err(HostError::NoRoute(to.ip())),
+        };
+        match resolution {
+            Resolution::Known(mac) => self.outbox.push_back(EthernetFrame::new(mac, self.mac, ethertype, packet)),
+            Resolution::Ask(question) => {
+                // one question is enough for all the packets waiting on the same neighbor
+                if !self.asking.iter().any(|asking| asking.next_hop == next_hop) {
+                    self.outbox.push_back(question.clone());
+                    self.asking.push(Asking { next_hop, question, asked_at : now, tries : 1 });
+                }
+                if self.waiting.len() >= MAX_WAITING {
+                    self.waiting.remove(0);
+                    self.dropped += 1;
+                }
+                self.waiting.push((next_hop, ethertype, packet));
+            }
+        }
+        Ok(())
+    }
+
+    // the frames to put on the wire, in the order they were made
+    pub fn transmit(&mut self) -> Vec<EthernetFrame> {
+        self.outbox.drain(..).collect()
+    }
+
+    // A frame off the wire. ARP and Neighbor Discovery are answered (and let the waiting
+    // packets go), UDP for one of our addresses is kept for receive(), and anything else,
+    // or for somebody else, is ignored.
+    pub fn deliver(&mut self, frame: &EthernetFrame, now: Instant) {
+        self.expire(now);
+        if frame.destination != self.mac && !frame.destination.is_broadcast() && !frame.destination.is_multicast() {
+            return;
+        }
+        match frame.ethertype {
+            ETHERTYPE_ARP => {
+                let Ok(message) = ArpMessage::from_bytes(&frame.payload) else { return };
+                for own in self.v4_addresses() {
+                    if let Some(reply) = self.arp.hear(&message, now, (self.mac, own)) {
+                        self.outbox.push_back(reply.to_frame());
+                    }
+                }
+            }
+            ETHERTYPE_IPV6 => {
+                if let Ok((header, message)) = NdpMessage::from_frame(frame) {
+                    for own in self.v6_addresses() {
+                        if let Some(answer) = self.nd.hear(header.source, &message, now, (self.mac, own)) {
+                            self.outbox.push_back(answer.to_frame((self.mac, own), (frame.source, header.source)));
+                        }
+                    }
+                } else if let Ok(packet) = LayeredPacket::<Ipv6Header>::from_frame(frame) {
+                    self.keep(packet.network.source.into(), packet.network.destination.into(), &packet.transport, packet.payload);
+                }
+            }
+            ETHERTYPE_IPV4 => {
+                if let Ok(packet) = LayeredPacket::<Ipv4Header>::from_frame(frame) {
+                    self.keep(packet.network.source.into(), packet.network.destination.into(), &packet.transport, packet.payload);
+                }
+            }
+            _ => {}
+        }
+        self.release();
+    }
+
+    pub fn receive(&mut self) -> Option<Datagram> {
+        self.inbox.pop_front()
+    }
+
+    // Asks again about the neighbors that haven't answered for RETRY_INTERVAL, and gives up on
+    // the ones asked MAX_TRIES times already, throwing away the packets waiting for them. Says
+    // how many were thrown away.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut given_up = vec![];
+        for asking in &mut self.asking {
+            if now.saturating_duration_since(asking.asked_at) < RETRY_INTERVAL {
+                continue;
+            }
+            if asking.tries >= MAX_TRIES {
+                given_up.push(asking.next_hop);
+                continue;
+            }
+            asking.tries += 1;
+            asking.asked_at = now;
+            self.outbox.push_back(asking.question.clone());
+        }
+        self.asking.retain(|asking| !given_up.contains(&asking.next_hop));
+        let before = self.waiting.len();
+        self.waiting.retain(|(next_hop, ..)| !given_up.contains(next_hop));
+        let expired = before - self.waiting.len();
+        self.dropped += expired;
+        expired
+    }
+
+    fn v4_addresses(&self) -> Vec<Ipv4Addr> {
+        self.addresses.iter().filter_map(|&(address, _)| match address {
+            IpAddr::V4(address) => Some(address),
+            IpAddr::V6(_) => None,
+        }).collect()
+    }
+
+    fn v6_addresses(&self) -> Vec<Ipv6Addr> {
+        self.addresses.iter().filter_map(|&(address, _)| match address {
+            IpAddr::V6(address) => Some(address),
+            IpAddr::V4(_) => None,
+        }).collect()
+    }
+
+    fn keep(&mut self, source: IpAddr, destination: IpAddr, transport: &Transport, data: Vec<u8>) {
+        if let Transport::Udp(header) = transport {
+            if self.has_address(destination) {
+                let (from, to) = (SocketAddr::new(source, header.source_port), SocketAddr::new(destination, header.destination_port));
+                self.inbox.push_back(Datagram { from, to, data });
+            }
+        }
+    }
+
+    // sends the packets whose next hop the caches know by now
+    fn release(&mut self) {
+        let (arp, nd) = (&self.arp, &self.nd);
+        let lookup = |next_hop: IpAddr| match next_hop {
+            IpAddr::V4(next_hop) => arp.lookup(next_hop),
+            IpAddr::V6(next_hop) => nd.lookup(next_hop),
+        };
+        let mut still_waiting = vec![];
+        for (next_hop, ethertype, packet) in self.waiting.drain(..) {
+            match lookup(next_hop) {
+                Some(mac) => self.outbox.push_back(EthernetFrame::new(mac, self.mac, ethertype, packet)),
+                None => still_waiting.push((next_hop, ethertype, packet)),
+            }
+        }
+        self.waiting = still_waiting;
+        self.asking.retain(|asking| lookup(asking.next_hop).is_none());
+    }
+}
+
+#[test]
+fn hosts_talk_over_a_link() {
+    use std::str::FromStr;
+
+    let ip = |address: &str| IpAddr::from_str(address).unwrap();
+    let mut laptop = Host::new("laptop", "02:00:00:00:00:0a".parse().unwrap())
+        .with_address(ip("192.168.1.10"), 24)
+        .with_address(ip("2001:db8:1::10"), 64)
+        .with_gateway(ip("192.168.1.1"));
+    let mut server = Host::new("server", "02:00:00:00:00:0b".parse().unwrap())
+        .with_address(ip("192.168.1.20"), 24)
+        .with_address(ip("2001:db8:1::20"), 64)
+        .with_port(7);
+    let now = Instant::now();
+    // a cable between the two: what one transmits the other gets
+    let exchange = |laptop: &mut Host, server: &mut Host| {
+        while !laptop.outbox.is_empty() || !server.outbox.is_empty() {
+            laptop.transmit().iter().for_each(|frame| server.deliver(frame, now));
+            server.transmit().iter().for_each(|frame| laptop.deliver(frame, now));
+        }
+    };
+
+    // the first datagram waits for ARP, the second for the same answer without asking again
+    laptop.send_to("192.168.1.20:7".parse().unwrap(), b"hello", now).unwrap();
+    laptop.send_to("192.168.1.20:7".parse().unwrap(), b"again", now).unwrap();
+    assert_eq!(laptop.outbox.len(), 1);
+    assert_eq!(laptop.outbox[0].ethertype, ETHERTYPE_ARP);
+    exchange(&mut laptop, &mut server);
+    let hello = server.receive().unwrap();
+    assert_eq!((hello.from, hello.to, &hello.data[..]), ("192.168.1.10:49152".parse().unwrap(), "192.168.1.20:7".parse().unwrap(), &b"hello"[..]));
+    assert_eq!(server.receive().unwrap().data, b"again");
+
+    // the server learnt the laptop from its question, so the echo goes straight out
+    server.send_to(hello.from, &hello.data, now).unwrap();
+    assert_eq!(server.outbox[0].ethertype, ETHERTYPE_IPV4);
+    exchange(&mut laptop, &mut server);
+    assert_eq!(laptop.receive().map(|echo| echo.data), Some(b"hello".to_vec()));
+
+    // the same over IPv6, through Neighbor Discovery
+    laptop.send_to("[2001:db8:1::20]:7".parse().unwrap(), b"six", now).unwrap();
+    exchange(&mut laptop, &mut server);
+    let six = server.receive().unwrap();
+    assert_eq!((six.from.ip(), &six.data[..]), (ip("2001:db8:1::10"), &b"six"[..]));
+
+    // off the link it goes to the gateway's MAC, which nobody has answered for yet; and there
+    // is no IPv6 gateway at all
+    laptop.send_to("8.8.8.8:53".parse().unwrap(), b"query", now).unwrap();
+    assert_eq!(laptop.waiting[0].0, ip("192.168.1.1"));
+    assert_eq!(laptop.send_to("[2001:4860::8888]:53".parse().unwrap(), b"query", now), Err(HostError::NoRoute(ip("2001:4860::8888"))));
+
+    // the gateway never answers: it is asked again every second, and given up on after three tries
+    laptop.transmit();
+    for second in 1..MAX_TRIES {
+        assert_eq!(laptop.expire(now + RETRY_INTERVAL * second), 0);
+        assert_eq!(laptop.transmit().len(), 1);
+    }
+    assert_eq!(laptop.expire(now + RETRY_INTERVAL * MAX_TRIES), 1);
+    assert!(laptop.waiting.is_empty() && laptop.transmit().is_empty());
+    // and only so many wait at once, the oldest making room
+    for _ in 0..=MAX_WAITING {
+        laptop.send_to("8.8.8.8:53".parse().unwrap(), b"query", now).unwrap();
+    }
+    assert_eq!((laptop.waiting.len(), laptop.dropped), (MAX_WAITING, 2));
+}
@@ -0,0 +1,141 @@
+//! HTTP/1.1, the tiny version: enough to ask for a page and to be told to go somewhere else.
+//! A request is a line like `GET /index.html HTTP/1.1`, then `Name: value` headers, one per
+//! line, then an empty line and the body. A response is the same with a status line like
+//! `HTTP/1.1 302 Found` at the top. Every line ends with "\r\n". No chunked bodies, no
+//! keep-alive, no anything else.
+
+// (name, value), in the order they came
+pub type Headers = Vec<(String, String)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method : String,
+    pub path : String,
+    pub headers : Headers,
+    pub body : Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status : u16,
+    pub reason : String,
+    pub headers : Headers,
+    pub body : Vec<u8>,
+}
+
+// the start line, the headers and the body of a message, None if it isn't one
+fn split(bytes: &[u8]) -> Option<(&str, Headers, Vec<u8>)> {
+    let end = bytes.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&bytes[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let start = lines.next()?;
+    let headers = lines
+        .map(|line| line.split_once(':').map(|(name, value)| (name.trim().to_string(), value.trim().to_string())))
+        .collect::<Option<Vec<_>>>()?;
+    Some((start, headers, bytes[end + 4..].to_vec()))
+}
+
+fn join(start: String, headers: &[(String, String)], body: &[u8]) -> Vec<u8> {
+    let mut bytes = start.into_bytes();
+    bytes.extend_from_slice(b"\r\n");
+    for (name, value) in headers {
+        bytes.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+    }
+    bytes.extend_from_slice(b"\r\n");
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+// header names don't care about case
+fn find<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+impl HttpRequest {
+    pub fn get(host: &str, path: &str) -> Self {
+        HttpRequest { method : "GET".to_string(), path : path.to_string(), headers : vec![("Host".to_string(), host.to_string())], body : vec![] }
+    }
+
+    pub fn post(host: &str, path: &str, body: &[u8]) -> Self {
+        HttpRequest { method : "POST".to_string(), body : body.to_vec(), ..HttpRequest::get(host, path) }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find(&self.headers, name)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (start, headers, body) = split(bytes)?;
+        let mut words = start.split(' ');
+        let (method, path, version) = (words.next()?, words.next()?, words.next()?);
+        if path.is_empty() || !version.starts_with("HTTP/") || words.next().is_some() {
+            return None;
+        }
+        Some(HttpRequest { method : method.to_string(), path : path.to_string(), headers, body })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        join(format!("{} {} HTTP/1.1", self.method, self.path), &self.headers, &self.body)
+    }
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, reason: &str) -> Self {
+        HttpResponse { status, reason : reason.to_string(), headers : vec![], body : vec![] }
+    }
+
+    // go and ask over there instead
+    pub fn redirect(location: &str) -> Self {
+        HttpResponse::new(302, "Found").with_header("Location", location)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find(&self.headers, name)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (start, headers, body) = split(bytes)?;
+        let mut words = start.splitn(3, ' ');
+        let (version, status, reason) = (words.next()?, words.next()?, words.next().unwrap_or(""));
+        if !version.starts_with("HTTP/") {
+            return None;
+        }
+        Some(HttpResponse { status : status.parse().ok()?, reason : reason.to_string(), headers, body })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        join(format!("HTTP/1.1 {} {}", self.status, self.reason), &self.headers, &self.body)
+    }
+}
+
+#[test]
+fn requests_and_redirects_go_through_bytes() {
+    let request = HttpRequest::get("example.com", "/");
+    assert_eq!(request.to_bytes(), b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    assert_eq!(HttpRequest::parse(&request.to_bytes()), Some(request));
+
+    let login = HttpRequest::post("portal", "/login", b"room=214");
+    let parsed = HttpRequest::parse(&login.to_bytes()).unwrap();
+    assert_eq!((parsed.method.as_str(), parsed.body.as_slice(), parsed.header("host")), ("POST", &b"room=214"[..], Some("portal")));
+    // two spaces and no path in between is not a request
+    assert_eq!(HttpRequest::parse(b"POST  HTTP/1.1\r\nHost: portal\r\n\r\n"), None);
+
+    let redirect = HttpResponse::parse(&HttpResponse::redirect("http://portal/login").to_bytes()).unwrap();
+    assert_eq!((redirect.status, redirect.reason.as_str()), (302, "Found"));
+    assert_eq!(redirect.header("location"), Some("http://portal/login"));
+
+    // not HTTP, or not finished yet
+    assert_eq!(HttpRequest::parse(b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+    assert_eq!(HttpRequest::parse(b"GET / HTTP/1.1\r\nHost: example.com\r\n"), None);
+    assert_eq!(HttpResponse::parse(b"HTTP/1.1 abc Found\r\n\r\n"), None);
+}
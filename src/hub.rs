@@ -0,0 +1,99 @@
+//! A hub: a repeater with many ports. It knows nothing and learns nothing; whatever comes in on
+//! one port goes out of all the others, so every host on it sees every frame and throws away
+//! the ones that aren't for it. What a learning switch floods only until it knows better, a hub
+//! floods forever.
+//!
+//! All the ports are one collision domain too: the hub is half duplex, so two hosts talking at
+//! the same moment garble each other on every port, and both have to back off and try again
+//! (CSMA/CD). A switch gives every port a wire of its own, and this never happens.
+use crate::link::EthernetFrame;
+
+#[derive(Debug)]
+pub struct Hub {
+    pub name : String,
+    // named like the switch's, see switch::port_name
+    pub ports : usize,
+    // copies sent out, one per port per frame
+    pub repeated : u64,
+    pub collisions : u64,
+}
+
+impl Hub {
+    pub fn new(name: &str, ports: usize) -> Self {
+        Hub { name : name.to_string(), ports, repeated : 0, collisions : 0 }
+    }
+
+    // out of every other port, no questions asked
+    pub fn receive(&mut self, port: usize, frame: &EthernetFrame) -> Vec<(usize, EthernetFrame)> {
+        if port >= self.ports {
+            return vec![];
+        }
+        let out: Vec<_> = (0..self.ports).filter(|&out| out != port).map(|out| (out, frame.clone())).collect();
+        self.repeated += out.len() as u64;
+        out
+    }
+
+    // Frames that came in at the same moment: one gets repeated, more than one collide and
+    // nothing gets through; the senders will notice the jam and send again later.
+    pub fn receive_together(&mut self, frames: &[(usize, EthernetFrame)]) -> Vec<(usize, EthernetFrame)> {
+        match frames {
+            [] => vec![],
+            [(port, frame)] => self.receive(*port, frame),
+            _ => {
+                self.collisions += 1;
+                vec![]
+            }
+        }
+    }
+}
+
+#[test]
+fn a_hub_floods_what_a_switch_learns_to_keep() {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Instant;
+
+    use crate::host::Host;
+    use crate::link::{MacAddr, ETHERTYPE_IPV4};
+    use crate::switch::{port_name, Switch};
+    use crate::topology::{Device, NodeKind, Topology};
+
+    // the same office twice, around a hub or a switch: a laptop printing, and a desktop that has nothing to do with it
+    let office = |middle: Device| {
+        let mut office = Topology::new();
+        let box_in_the_middle = office.add_node("box", middle.kind());
+        office.attach(box_in_the_middle, middle);
+        for (port, (name, last)) in [("laptop", 10), ("printer", 20), ("desktop", 30)].into_iter().enumerate() {
+            let host = Host::new(name, MacAddr([2, 0, 0, 0, 0, last])).with_address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, last)), 24);
+            let node = office.add_node(name, NodeKind::Host);
+            office.attach(node, Device::Host(Box::new(host)));
+            office.connect(node, "eth0", box_in_the_middle, &port_name(port)).unwrap();
+        }
+        office
+    };
+    // how many frames the desktop's cable carried while the laptop printed ten pages
+    let print = |mut office: Topology| {
+        let now = Instant::now();
+        let [laptop, printer, desktop] = ["laptop", "printer", "desktop"].map(|name| office.find(name).unwrap());
+        for page in 0..10u8 {
+            office.host_mut(laptop).unwrap().send_to("192.168.1.20:9100".parse().unwrap(), &[page; 100], now).unwrap();
+            office.run_frames(now);
+        }
+        let printed = std::iter::from_fn(|| office.host_mut(printer).unwrap().receive()).count();
+        assert_eq!(printed, 10);
+        assert_eq!(office.host_mut(desktop).unwrap().receive(), None);
+        let cable = office.link_at(desktop, "eth0").unwrap();
+        office.link(cable).unwrap().frames
+    };
+
+    // through the hub the desktop sees all of it: the ARP request and reply, and every page
+    assert_eq!(print(office(Device::Hub(Box::new(Hub::new("hub", 4))))), 12);
+    // the switch floods the ARP request, and has learned both ends by the time the reply comes
+    assert_eq!(print(office(Device::Switch(Box::new(Switch::new("switch", 4))))), 1);
+
+    // two hosts on a hub talking at once garble each other
+    let mut hub = Hub::new("hub", 4);
+    let frame = |from: u8| EthernetFrame::new(MacAddr::BROADCAST, MacAddr([2, 0, 0, 0, 0, from]), ETHERTYPE_IPV4, vec![]);
+    assert_eq!(hub.receive_together(&[(0, frame(10))]).len(), 3);
+    assert_eq!(hub.receive_together(&[(0, frame(10)), (2, frame(30))]), vec![]);
+    assert_eq!((hub.repeated, hub.collisions), (3, 1));
+}
@@ -0,0 +1,105 @@
+//! Network interfaces, the things `ip link` and `ip addr` show: a name, an index, an MTU, up or
+//! down, the addresses on it, and for a VLAN subinterface (eth0.100) the VLAN it tags its
+//! frames with. Routes point at them, routers forward through them, and switches have them as
+//! ports.
+use std::fmt::{self, Display};
+use std::net::IpAddr;
+
+use crate::link::MacAddr;
+
+// what Ethernet carries without jumbo frames
+pub const DEFAULT_MTU: u32 = 1500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetInterface {
+    pub index : u64,
+    pub name : String,
+    // all zeros for one without a hardware address, like lo
+    pub mac : MacAddr,
+    pub mtu : u32,
+    pub up : bool,
+    // (address, prefix_len)
+    pub addresses : Vec<(IpAddr, u8)>,
+    // the 802.1Q VLAN id, for a subinterface
+    pub vlan : Option<u16>,
+}
+
+impl NetInterface {
+    // up, with the default MTU and no addresses
+    pub fn new(index: u64, name: &str) -> Self {
+        NetInterface { index, name : name.to_string(), mac : MacAddr::default(), mtu : DEFAULT_MTU, up : true, addresses : vec![], vlan : None }
+    }
+
+    // the number a name like eth3 ends with is taken as the index, 0 if there is none
+    pub fn named(name: &str) -> Self {
+        let number = &name[name.trim_end_matches(|c: char| c.is_ascii_digit()).len()..];
+        Self::new(number.parse().unwrap_or(0), name)
+    }
+
+    pub fn with_mac(mut self, mac: MacAddr) -> Self {
+        self.mac = mac;
+        self
+    }
+
+    pub fn with_mtu(mut self, mtu: u32) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    pub fn with_address(mut self, address: IpAddr, prefix_len: u8) -> Self {
+        self.addresses.push((address, prefix_len));
+        self
+    }
+
+    pub fn down(mut self) -> Self {
+        self.up = false;
+        self
+    }
+
+    // Like `ip link add link eth0 name eth0.100 type vlan id 100`: on top of this one, so it
+    // has its MAC, MTU and state, but its own index and addresses
+    pub fn vlan_subinterface(&self, index: u64, vlan: u16) -> Self {
+        NetInterface {
+            index,
+            name : format!("{}.{vlan}", self.name),
+            mac : self.mac,
+            mtu : self.mtu,
+            up : self.up,
+            addresses : vec![],
+            vlan : Some(vlan),
+        }
+    }
+
+    pub fn has_address(&self, address: IpAddr) -> bool {
+        self.addresses.iter().any(|&(own, _)| own == address)
+    }
+}
+
+// one line of `ip addr`, more or less
+impl Display for NetInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.up { "UP" } else { "DOWN" };
+        write!(f, "{}: {}: mtu {} state {state}", self.index, self.name, self.mtu)?;
+        if self.mac != MacAddr::default() {
+            write!(f, " link/ether {}", self.mac)?;
+        }
+        if let Some(vlan) = self.vlan {
+            write!(f, " vlan {vlan}")?;
+        }
+        for (address, prefix_len) in &self.addresses {
+            write!(f, " inet{} {address}/{prefix_len}", if address.is_ipv6() { "6" } else { "" })?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn interfaces_and_their_vlans() {
+    let eth0 = NetInterface::named("eth0").with_mac("02:00:00:00:00:01".parse().unwrap()).with_mtu(9000).with_address("192.0.2.1".parse().unwrap(), 24);
+    let voice = eth0.vlan_subinterface(5, 100).with_address("10.100.0.1".parse().unwrap(), 24);
+    assert_eq!((voice.name.as_str(), voice.mtu, voice.vlan), ("eth0.100", 9000, Some(100)));
+    assert!(voice.has_address("10.100.0.1".parse().unwrap()));
+    assert!(!voice.has_address("192.0.2.1".parse().unwrap()));
+    assert_eq!((NetInterface::named("enp0s31f6").index, NetInterface::named("lo").index), (6, 0));
+    assert_eq!(voice.down().to_string(), "5: eth0.100: mtu 9000 state DOWN link/ether 02:00:00:00:00:01 vlan 100 inet 10.100.0.1/24");
+}
@@ -0,0 +1,51 @@
+pub mod arp;
+pub mod bit_utils;
+pub mod clock;
+pub mod dhcp;
+pub mod dns;
+pub mod dns_message;
+pub mod firewall;
+pub mod heatmap;
+pub mod host;
+pub mod http;
+pub mod hub;
+pub mod interface;
+pub mod link;
+pub mod metadata;
+pub mod multicast;
+pub mod mpls;
+pub mod namespace;
+#[cfg(feature = "tokio")]
+pub mod nat_expiry;
+pub mod nat_load;
+pub mod nat_v4;
+pub mod ndp;
+pub mod neighbor;
+pub mod networkingv4;
+pub mod packet;
+pub mod pcap;
+pub mod pcapng;
+pub mod policy_routing;
+pub mod protocols;
+pub mod qos;
+pub mod route_cache;
+pub mod route_text;
+pub mod route_trie;
+pub mod route_watch;
+pub mod router;
+pub mod routing;
+pub mod scenarios;
+pub mod shared_nat;
+pub mod slaac;
+pub mod state_machine;
+pub mod storm_control;
+pub mod stun;
+pub mod switch;
+pub mod table_limits;
+pub mod tcp_endpoint;
+pub mod token_bucket;
+pub mod topology;
+pub mod trace;
+pub mod traffic;
+pub mod tunnel;
+pub mod vrf;
@@ -0,0 +1,256 @@
+//! Layer 2: the Ethernet frames that actually go on the wire, and the MAC addresses in them.
+//!
+//! ```text
+//! | destination (6) | source (6) | EtherType (2) | payload (46 to 1500) | FCS (4) |
+//! ```
+//!
+//! The EtherType says what the payload is (IPv4, ARP, IPv6, ...). A payload shorter than 46
+//! bytes is padded with zeros, and the receiver can't tell the padding from the payload, so the
+//! layer above has to know its own length (IPv4's total length, UDP's length). The FCS is
+//! checked and removed by the network card, so captures and these frames don't have it.
+//!
+//! A frame on a trunk has an 802.1Q tag between the source and the EtherType, saying which
+//! VLAN it belongs to:
+//!
+//! ```text
+//! | destination (6) | source (6) | TPID 0x8100 (2) | PCP (3) DEI (1) VID (12) | EtherType (2) | ...
+//! ```
+//!
+//! The TPID is where the EtherType would be, so a card that doesn't know about VLANs sees an
+//! EtherType it doesn't know. A provider can put its own tag (TPID 0x88a8) in front of the
+//! customer's one, which is Q-in-Q (802.1ad), so a frame can have more than one.
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use crate::packet::{need, PacketError};
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+// a packet with MPLS labels in front of it
+pub const ETHERTYPE_MPLS: u16 = 0x8847;
+// the TPIDs of a VLAN tag, and of a provider's outer one
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+pub const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+pub const HEADER_LEN: usize = 14;
+pub const MIN_PAYLOAD: usize = 46;
+pub const TAG_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddr::BROADCAST
+    }
+
+    // the lowest bit of the first byte: a group address, which the broadcast one is too
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    // the second lowest bit: made up by someone (a VM, a container) instead of burnt in the card
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self {
+        MacAddr(octets)
+    }
+}
+
+// like ip link shows them: 02:42:ac:11:00:02
+impl Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidMacAddr;
+
+impl Display for InvalidMacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a MAC address: six hex bytes separated by : or - were expected")
+    }
+}
+
+impl std::error::Error for InvalidMacAddr {}
+
+// with colons, or with dashes the way Windows writes them
+impl FromStr for MacAddr {
+    type Err = InvalidMacAddr;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0; 6];
+        let mut parts = text.split([':', '-']);
+        for octet in &mut octets {
+            let part = parts.next().ok_or(InvalidMacAddr)?;
+            if part.len() != 2 {
+                return Err(InvalidMacAddr);
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| InvalidMacAddr)?;
+        }
+        if parts.next().is_some() {
+            return Err(InvalidMacAddr);
+        }
+        Ok(MacAddr(octets))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlanTag {
+    pub tpid : u16,
+    // the priority, 0 to 7, for the switch's queues
+    pub pcp : u8,
+    // drop eligible: dropped first when there is congestion
+    pub dei : bool,
+    // 1 to 4094; 0 means the tag is only there for the priority
+    pub vid : u16,
+}
+
+impl VlanTag {
+    // a plain 802.1Q tag, at the default priority
+    pub fn new(vid: u16) -> Self {
+        VlanTag { tpid : ETHERTYPE_VLAN, pcp : 0, dei : false, vid }
+    }
+
+    pub fn with_pcp(mut self, pcp: u8) -> Self {
+        self.pcp = pcp;
+        self
+    }
+
+    // the provider's tag of Q-in-Q
+    pub fn outer(vid: u16) -> Self {
+        VlanTag { tpid : ETHERTYPE_QINQ, ..VlanTag::new(vid) }
+    }
+
+    pub fn is_tpid(ethertype: u16) -> bool {
+        matches!(ethertype, ETHERTYPE_VLAN | ETHERTYPE_QINQ)
+    }
+
+    // the tag control information, the two bytes after the TPID
+    pub fn tci(&self) -> u16 {
+        (self.pcp as u16 & 0x7) << 13 | (self.dei as u16) << 12 | self.vid & 0x0fff
+    }
+
+    pub fn from_tci(tpid: u16, tci: u16) -> Self {
+        VlanTag { tpid, pcp : (tci >> 13) as u8, dei : tci & 0x1000 != 0, vid : tci & 0x0fff }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EthernetFrame {
+    pub destination : MacAddr,
+    pub source : MacAddr,
+    // the outermost first; none for an untagged frame
+    pub tags : Vec<VlanTag>,
+    // what the payload is, after the tags
+    pub ethertype : u16,
+    pub payload : Vec<u8>,
+}
+
+impl EthernetFrame {
+    // untagged
+    pub fn new(destination: MacAddr, source: MacAddr, ethertype: u16, payload: Vec<u8>) -> Self {
+        EthernetFrame { destination, source, tags : vec![], ethertype, payload }
+    }
+
+    pub fn with_tag(mut self, tag: VlanTag) -> Self {
+        self.push_tag(tag);
+        self
+    }
+
+    // a switch sending the frame out of a trunk; the new tag goes outside the others
+    pub fn push_tag(&mut self, tag: VlanTag) {
+        self.tags.insert(0, tag);
+    }
+
+    // and taking it off when the frame comes in from one
+    pub fn pop_tag(&mut self) -> Option<VlanTag> {
+        (!self.tags.is_empty()).then(|| self.tags.remove(0))
+    }
+
+    // the VLAN of the outermost tag, the one switches look at
+    pub fn vlan(&self) -> Option<u16> {
+        self.tags.first().map(|tag| tag.vid)
+    }
+
+    // the payload padded to the minimum size, without an FCS
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.destination.0.to_vec();
+        bytes.extend(self.source.0);
+        for tag in &self.tags {
+            bytes.extend(tag.tpid.to_be_bytes());
+            bytes.extend(tag.tci().to_be_bytes());
+        }
+        bytes.extend(self.ethertype.to_be_bytes());
+        bytes.extend(&self.payload);
+        bytes.resize(bytes.len().max(HEADER_LEN + MIN_PAYLOAD), 0);
+        bytes
+    }
+
+    // the payload is everything after the header and the tags, padding included
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, HEADER_LEN)?;
+        let mac = |at: usize| MacAddr(bytes[at..at + 6].try_into().unwrap());
+        let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let mut tags = vec![];
+        let mut at = 12;
+        while VlanTag::is_tpid(word(at)) {
+            need(bytes, at + TAG_LEN + 2)?;
+            tags.push(VlanTag::from_tci(word(at), word(at + 2)));
+            at += TAG_LEN;
+        }
+        Ok(EthernetFrame {
+            destination : mac(0),
+            source : mac(6),
+            tags,
+            ethertype : word(at),
+            payload : bytes[at + 2..].to_vec(),
+        })
+    }
+}
+
+#[test]
+fn frames_and_addresses() {
+    let host: MacAddr = "02:42:ac:11:00:02".parse().unwrap();
+    assert_eq!(host.to_string(), "02:42:ac:11:00:02");
+    assert_eq!("02-42-AC-11-00-02".parse(), Ok(host));
+    assert!(host.is_local() && !host.is_multicast());
+    assert!(MacAddr::BROADCAST.is_broadcast() && MacAddr::BROADCAST.is_multicast());
+    for wrong in ["02:42:ac:11:00", "02:42:ac:11:00:02:03", "2:42:ac:11:00:02", "02:42:ac:11:00:zz", ""] {
+        assert_eq!(wrong.parse::<MacAddr>(), Err(InvalidMacAddr));
+    }
+
+    // an ARP request is shorter than the minimum, and gets padded
+    let frame = EthernetFrame::new(MacAddr::BROADCAST, host, ETHERTYPE_ARP, vec![0x00, 0x01, 0x08, 0x00]);
+    let bytes = frame.to_bytes();
+    assert_eq!(bytes.len(), 60);
+    assert_eq!(bytes[..14], [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x42, 0xac, 0x11, 0x00, 0x02, 0x08, 0x06]);
+    let read = EthernetFrame::from_bytes(&bytes).unwrap();
+    assert_eq!((read.destination, read.source, read.ethertype, read.payload.len()), (MacAddr::BROADCAST, host, ETHERTYPE_ARP, 46));
+    assert_eq!(read.payload[..4], frame.payload);
+    assert_eq!(EthernetFrame::from_bytes(&bytes[..10]), Err(PacketError::Truncated { needed : 14, got : 10 }));
+
+    // a trunk port tags it for VLAN 100, at the voice priority, and the next switch takes it off
+    let mut tagged = frame.clone().with_tag(VlanTag::new(100).with_pcp(5));
+    let bytes = tagged.to_bytes();
+    assert_eq!(bytes[12..18], [0x81, 0x00, 0xa0, 0x64, 0x08, 0x06]);
+    assert_eq!(EthernetFrame::from_bytes(&bytes).unwrap().tags, tagged.tags);
+    // Q-in-Q: the provider's tag goes outside the customer's
+    tagged.push_tag(VlanTag::outer(3000));
+    let read = EthernetFrame::from_bytes(&tagged.to_bytes()).unwrap();
+    assert_eq!((read.vlan(), read.tags.len(), read.ethertype), (Some(3000), 2, ETHERTYPE_ARP));
+    assert_eq!(tagged.pop_tag(), Some(VlanTag::outer(3000)));
+    assert_eq!(tagged.pop_tag().map(|tag| (tag.vid, tag.pcp)), Some((100, 5)));
+    assert_eq!((tagged.pop_tag(), tagged), (None, frame));
+    // a tag cut short
+    assert_eq!(EthernetFrame::from_bytes(&bytes[..16]), Err(PacketError::Truncated { needed : 18, got : 16 }));
+}
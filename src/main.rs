@@ -1,7 +1,5 @@
-mod routing;
-mod nat_v4;
+use networking::{nat_v4, routing};
 
-mod bit_utils;
 fn main() {
     println!("Hello, world!");
     routing::check_routing();
@@ -0,0 +1,146 @@
+//! Things the network stack knows about a packet that are not in the packet itself, like
+//! Linux's sk_buff fields: which interface it came in on, when, the firewall mark and the VRF.
+//! They travel next to the packet so one stage (say the firewall) can leave a note for a later
+//! one (policy routing, QoS) without writing into the headers.
+//!
+//! The sender can also seal a digest of the payload in there, and the receiving application
+//! check it, to be sure no middlebox (NAT, ALG, shaper...) changed the payload on the way.
+//! Only the payload is covered: the headers are supposed to change.
+use std::fmt::{self, Display};
+use std::time::Instant;
+
+use crate::bit_utils::fnv1a;
+use crate::packet::layered::LayeredPacket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PacketMetadata {
+    // None for packets made on this device
+    pub ingress_ifindex : Option<u32>,
+    pub received_at : Option<Instant>,
+    // fwmark, 0 when unmarked
+    pub mark : u32,
+    // 0 is the default VRF
+    pub vrf : u32,
+    // digest of the payload as it was sent, None when the sender didn't seal it
+    pub payload_digest : Option<u64>,
+}
+
+// Packets with a payload that can be checked
+pub trait Payload {
+    fn payload(&self) -> &[u8];
+}
+
+impl<H> Payload for LayeredPacket<H> {
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadCorrupted {
+    pub expected : u64,
+    pub found : u64,
+}
+
+impl Display for PayloadCorrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the payload changed on the way: digest {:016x} instead of {:016x}", self.found, self.expected)
+    }
+}
+
+impl std::error::Error for PayloadCorrupted {}
+
+// A packet together with its metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tagged<P> {
+    pub packet : P,
+    pub meta : PacketMetadata,
+}
+
+impl<P> Tagged<P> {
+    // made locally, no metadata yet
+    pub fn new(packet: P) -> Self {
+        Tagged { packet, meta : PacketMetadata::default() }
+    }
+
+    pub fn received(packet: P, ifindex: u32, at: Instant) -> Self {
+        Tagged { packet, meta : PacketMetadata { ingress_ifindex : Some(ifindex), received_at : Some(at), ..PacketMetadata::default() } }
+    }
+
+    pub fn with_mark(mut self, mark: u32) -> Self {
+        self.meta.mark = mark;
+        self
+    }
+
+    pub fn with_vrf(mut self, vrf: u32) -> Self {
+        self.meta.vrf = vrf;
+        self
+    }
+
+    // a stage changed the packet (NAT, a tunnel...), the metadata stays with it
+    pub fn map<Q>(self, stage: impl FnOnce(P) -> Q) -> Tagged<Q> {
+        Tagged { packet : stage(self.packet), meta : self.meta }
+    }
+
+    pub fn try_map<Q, E>(self, stage: impl FnOnce(P) -> Result<Q, E>) -> Result<Tagged<Q>, E> {
+        Ok(Tagged { packet : stage(self.packet)?, meta : self.meta })
+    }
+}
+
+impl<P: Payload> Tagged<P> {
+    // done by the sender
+    pub fn seal(mut self) -> Self {
+        self.meta.payload_digest = Some(fnv1a(self.packet.payload()));
+        self
+    }
+
+    // done by the receiving application; a packet that was never sealed has nothing to check
+    pub fn verify(&self) -> Result<(), PayloadCorrupted> {
+        let Some(expected) = self.meta.payload_digest else {
+            return Ok(());
+        };
+        let found = fnv1a(self.packet.payload());
+        if found == expected {
+            Ok(())
+        } else {
+            Err(PayloadCorrupted { expected, found })
+        }
+    }
+}
+
+#[test]
+fn metadata_survives_translation() {
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    let at = Instant::now();
+    let packet = PacketBuilder::ipv4().src("10.0.0.2".parse().unwrap()).dst("8.8.8.8".parse().unwrap()).udp().sport(5353).dport(53).payload(b"where is example.com").build();
+    let tagged = Tagged::received(packet, 2, at).with_mark(0x10).with_vrf(7);
+    let translated = tagged.try_map(|packet| nat.translate_outgoing(packet, 1)).unwrap();
+    assert_eq!(translated.packet.source_ip(), "103.5.150.9".parse::<std::net::Ipv4Addr>().unwrap());
+    assert_eq!(translated.meta, PacketMetadata { ingress_ifindex : Some(2), received_at : Some(at), mark : 0x10, vrf : 7, payload_digest : None });
+}
+
+#[test]
+fn middleboxes_must_not_touch_the_payload() {
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    let packet = PacketBuilder::ipv4().src("10.0.0.2".parse().unwrap()).dst("198.51.100.21".parse().unwrap()).tcp().sport(40000).dport(21).payload(b"PORT 10,0,0,2,156,65").build();
+    let sent = Tagged::new(packet).seal();
+
+    // the NAT changes the headers, and that is fine
+    let through_nat = sent.clone().try_map(|packet| nat.translate_outgoing(packet, 1)).unwrap();
+    assert_eq!(through_nat.verify(), Ok(()));
+
+    // an FTP ALG rewriting the address in the payload is exactly what this catches
+    let through_alg = through_nat.map(|packet| LayeredPacket { payload : b"PORT 103,5,150,9,156,65".to_vec(), ..packet });
+    let error = through_alg.verify().unwrap_err();
+    assert_eq!(error.expected, sent.meta.payload_digest.unwrap());
+
+    // nothing to check without a seal
+    assert_eq!(Tagged::new(through_alg.packet).verify(), Ok(()));
+}
@@ -0,0 +1,233 @@
+//! MPLS (RFC 3031, 3032): forwarding on a label in front of the IP header instead of on the
+//! destination address. The first router of a label switched path (LSP) looks up the
+//! destination once and pushes a label for it; every router after that only looks at the
+//! label, swaps it for the one the next router expects and sends the packet on; the router
+//! before the last one pops it, and the last one routes the plain IP packet again. The routers
+//! in the middle don't need a route to the destination at all. Each label entry is 4 bytes:
+//!
+//! ```text
+//! | label (20) | traffic class (3) | bottom of stack (1) | TTL (8) |
+//! ```
+//!
+//! Labels stack: another one can be pushed on top (the outer path) and popped again later, and
+//! only the last one has the bottom-of-stack bit. The TTL starts as the IP hop limit and goes
+//! down by one at every router, like the hop limit would have. Only IPv4 is carried here.
+use std::net::Ipv4Addr;
+
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_MPLS};
+use crate::packet::layered::LayeredPacket;
+use crate::packet::{need, Packet, PacketError};
+use crate::router::Forwarded;
+
+pub const ENTRY_LEN: usize = 4;
+// the labels below 16 are reserved; these are the ones that mean something
+pub const IPV4_EXPLICIT_NULL: u32 = 0;
+pub const IPV6_EXPLICIT_NULL: u32 = 2;
+// never sent: a router asking for this label means "pop it before you send it to me"
+pub const IMPLICIT_NULL: u32 = 3;
+pub const FIRST_UNRESERVED: u32 = 16;
+pub const MAX_LABEL: u32 = 0xf_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MplsLabel {
+    pub label : u32,
+    pub traffic_class : u8,
+    pub bottom : bool,
+    pub ttl : u8,
+}
+
+impl MplsLabel {
+    pub fn new(label: u32, ttl: u8) -> Self {
+        MplsLabel { label : label & MAX_LABEL, traffic_class : 0, bottom : false, ttl }
+    }
+
+    pub fn to_bytes(&self) -> [u8; ENTRY_LEN] {
+        let word = (self.label & MAX_LABEL) << 12 | (self.traffic_class as u32 & 0x7) << 9 | (self.bottom as u32) << 8 | self.ttl as u32;
+        word.to_be_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, ENTRY_LEN)?;
+        let word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Ok(MplsLabel { label : word >> 12, traffic_class : (word >> 9 & 0x7) as u8, bottom : word & 0x100 != 0, ttl : word as u8 })
+    }
+}
+
+// The labels down to the one with the bottom bit, and what is after them
+pub fn read_stack(bytes: &[u8]) -> Result<(Vec<MplsLabel>, &[u8]), PacketError> {
+    let mut labels = vec![];
+    loop {
+        let label = MplsLabel::from_bytes(&bytes[labels.len() * ENTRY_LEN..])?;
+        labels.push(label);
+        if label.bottom {
+            let end = labels.len() * ENTRY_LEN;
+            return Ok((labels, &bytes[end..]));
+        }
+    }
+}
+
+// An IP packet with its labels, the top one first
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabeledPacket {
+    pub labels : Vec<MplsLabel>,
+    pub packet : LayeredPacket,
+}
+
+impl LabeledPacket {
+    // an unlabeled packet, ready to be pushed on
+    pub fn new(packet: LayeredPacket) -> Self {
+        LabeledPacket { labels : vec![], packet }
+    }
+
+    pub fn top(&self) -> Option<&MplsLabel> {
+        self.labels.first()
+    }
+
+    // the TTL comes from the label below, or the IP hop limit for the first one
+    pub fn push(&mut self, label: u32) {
+        let ttl = self.top().map_or(self.packet.hop_limit(), |top| top.ttl);
+        self.labels.insert(0, MplsLabel::new(label, ttl));
+    }
+
+    pub fn swap(&mut self, label: u32) {
+        if let Some(top) = self.labels.first_mut() {
+            top.label = label & MAX_LABEL;
+        }
+    }
+
+    // the TTL goes back down to the label below, or into the IP header for the last one
+    pub fn pop(&mut self) -> Option<MplsLabel> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        let popped = self.labels.remove(0);
+        match self.labels.first_mut() {
+            Some(below) => below.ttl = below.ttl.min(popped.ttl),
+            None => self.packet.set_hop_limit(self.packet.hop_limit().min(popped.ttl)),
+        }
+        Some(popped)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (index, label) in self.labels.iter().enumerate() {
+            bytes.extend(MplsLabel { bottom : index + 1 == self.labels.len(), ..*label }.to_bytes());
+        }
+        bytes.extend(self.packet.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        let (labels, rest) = read_stack(bytes)?;
+        Ok(LabeledPacket { labels, packet : LayeredPacket::from_bytes(rest)? })
+    }
+
+    pub fn to_frame(&self, destination: MacAddr, source: MacAddr) -> EthernetFrame {
+        EthernetFrame::new(destination, source, ETHERTYPE_MPLS, self.to_bytes())
+    }
+}
+
+// What came out of a label switch router
+#[derive(Debug, Clone, PartialEq)]
+pub enum Switched {
+    // still labeled, for the next router on the path
+    Labeled { interface : String, next_hop : Ipv4Addr, packet : LabeledPacket },
+    // a plain IP packet again (or still), and what happened to it as one
+    Routed(Forwarded),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelOperation {
+    Swap(u32),
+    Pop,
+}
+
+// What a router does with a packet that comes in with this label on top
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelEntry {
+    pub in_label : u32,
+    pub operation : LabelOperation,
+    pub interface : String,
+    pub next_hop : Ipv4Addr,
+}
+
+// At the start of a path: the label to push for packets to this prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelBinding {
+    pub prefix : Ipv4Addr,
+    pub prefix_len : u8,
+    pub label : u32,
+    pub interface : String,
+    pub next_hop : Ipv4Addr,
+}
+
+// A router's label forwarding table (the LFIB), and the prefixes it starts paths for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelTable {
+    pub entries : Vec<LabelEntry>,
+    pub bindings : Vec<LabelBinding>,
+}
+
+impl LabelTable {
+    // swapping to implicit null is popping, which is how the next router asks for it
+    pub fn add_entry(&mut self, in_label: u32, operation: LabelOperation, interface: &str, next_hop: Ipv4Addr) {
+        let operation = match operation {
+            LabelOperation::Swap(IMPLICIT_NULL) => LabelOperation::Pop,
+            operation => operation,
+        };
+        self.entries.retain(|entry| entry.in_label != in_label);
+        self.entries.push(LabelEntry { in_label, operation, interface : interface.to_string(), next_hop });
+    }
+
+    pub fn bind(&mut self, prefix: Ipv4Addr, prefix_len: u8, label: u32, interface: &str, next_hop: Ipv4Addr) {
+        self.bindings.push(LabelBinding { prefix, prefix_len, label, interface : interface.to_string(), next_hop });
+    }
+
+    pub fn entry(&self, label: u32) -> Option<&LabelEntry> {
+        self.entries.iter().find(|entry| entry.in_label == label)
+    }
+
+    // the longest prefix bound to a label
+    pub fn binding_for(&self, destination: Ipv4Addr) -> Option<&LabelBinding> {
+        self.bindings
+            .iter()
+            .filter(|binding| {
+                let mask = u32::MAX.checked_shl(32 - binding.prefix_len as u32).unwrap_or(0);
+                u32::from(destination) & mask == u32::from(binding.prefix) & mask
+            })
+            .max_by_key(|binding| binding.prefix_len)
+    }
+}
+
+#[test]
+fn label_stacks_on_the_wire() {
+    use crate::packet::builder::PacketBuilder;
+
+    let packet = PacketBuilder::ipv4().src(Ipv4Addr::new(10, 0, 0, 10)).dst(Ipv4Addr::new(192, 0, 2, 80)).udp().sport(5000).dport(53).payload(b"query").build();
+    let mut labeled = LabeledPacket::new(packet.clone());
+    labeled.push(100);
+    labeled.push(7000);
+    labeled.labels[0].ttl = 60;
+    // label 7000, bottom clear, TTL 60; then label 100 with the bottom bit and TTL 64
+    let bytes = labeled.to_bytes();
+    assert_eq!(bytes[..8], [0x01, 0xb5, 0x80, 60, 0x00, 0x06, 0x41, 64]);
+    assert_eq!(bytes[8] >> 4, 4);
+    let read = LabeledPacket::from_bytes(&bytes).unwrap();
+    assert_eq!((read.labels.len(), read.labels[1].bottom, &read.packet), (2, true, &packet));
+    assert_eq!(LabeledPacket::from_bytes(&bytes[..6]), Err(PacketError::Truncated { needed : 4, got : 2 }));
+
+    // popping carries the lowest TTL down, and into the IP header at the end
+    labeled.swap(7001);
+    assert_eq!(labeled.pop().map(|label| label.label), Some(7001));
+    assert_eq!(labeled.labels[0].ttl, 60);
+    labeled.pop();
+    assert_eq!((labeled.labels.len(), labeled.packet.hop_limit(), labeled.pop()), (0, 60, None));
+
+    let mut table = LabelTable::default();
+    table.add_entry(100, LabelOperation::Swap(IMPLICIT_NULL), "down", Ipv4Addr::new(172, 16, 0, 2));
+    assert_eq!(table.entry(100).map(|entry| entry.operation), Some(LabelOperation::Pop));
+    table.bind(Ipv4Addr::new(192, 0, 2, 0), 24, 100, "down", Ipv4Addr::new(172, 16, 0, 2));
+    table.bind(Ipv4Addr::new(192, 0, 2, 64), 26, 101, "down", Ipv4Addr::new(172, 16, 0, 2));
+    assert_eq!(table.binding_for(Ipv4Addr::new(192, 0, 2, 80)).map(|binding| binding.label), Some(101));
+    assert_eq!(table.binding_for(Ipv4Addr::new(198, 51, 100, 1)), None);
+}
@@ -0,0 +1,365 @@
+//! Multicast routers on a LAN, the IGMPv2 way.
+//! Only one router per LAN sends the membership queries: the querier, which is simply the one
+//! with the lowest IP address. Everyone else stays quiet, but if they stop hearing queries for a
+//! while, they assume the querier died and take over.
+//! Group memberships are soft state: hosts have to keep answering the queries with reports, or
+//! the group times out. A host leaving makes the querier ask "anyone else still in this group?"
+//! a couple of times quickly, and the time that takes is the leave latency.
+//!
+//! Which groups are wanted on which interface of a router is kept by the router itself (see
+//! `router`), from the messages below; IPv6 has the same two as MLD.
+//!
+//! On the wire an IGMPv2 message (RFC 2236) is 8 bytes right after the IPv4 header, protocol 2,
+//! always with a TTL of 1 so it never leaves the link:
+//!
+//! ```text
+//! | type (8) | max response time (8, in 1/10 s) | checksum (16) | group address (32) |
+//! ```
+//!
+//! A report goes to the group itself, so the other members hear it and keep quiet; a leave goes
+//! to all routers (224.0.0.2), and a general query to all systems (224.0.0.1).
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+use crate::bit_utils::{internet_checksum, verify_internet_checksum};
+use crate::packet::{need, PacketError};
+
+pub const MEMBERSHIP_QUERY: u8 = 0x11;
+// IGMPv1's report, which v2 routers still have to take
+pub const V1_MEMBERSHIP_REPORT: u8 = 0x12;
+pub const V2_MEMBERSHIP_REPORT: u8 = 0x16;
+pub const LEAVE_GROUP: u8 = 0x17;
+pub const IGMP_LEN: usize = 8;
+pub const ALL_SYSTEMS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+pub const ALL_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 2);
+
+// What a host says about a group: an IGMP membership report or leave, or MLD's listener report
+// or done for IPv6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupMessage<A = Ipv4Addr> {
+    Report(A),
+    Leave(A),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgmpMessage {
+    // for every group, or only one (after a leave); members answer within max_response
+    Query { max_response : Duration, group : Option<Ipv4Addr> },
+    Report(Ipv4Addr),
+    Leave(Ipv4Addr),
+}
+
+impl IgmpMessage {
+    // what it tells a router about a group, for hear_group_message; a query says nothing
+    pub fn group_message(&self) -> Option<GroupMessage> {
+        match *self {
+            IgmpMessage::Query { .. } => None,
+            IgmpMessage::Report(group) => Some(GroupMessage::Report(group)),
+            IgmpMessage::Leave(group) => Some(GroupMessage::Leave(group)),
+        }
+    }
+
+    // the IPv4 destination it is sent to
+    pub fn destination(&self) -> Ipv4Addr {
+        match *self {
+            IgmpMessage::Query { group : None, .. } => ALL_SYSTEMS,
+            IgmpMessage::Query { group : Some(group), .. } | IgmpMessage::Report(group) => group,
+            IgmpMessage::Leave(_) => ALL_ROUTERS,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, max_response, group) = match *self {
+            IgmpMessage::Query { max_response, group } => (MEMBERSHIP_QUERY, (max_response.as_millis() / 100).min(255) as u8, group.unwrap_or(Ipv4Addr::UNSPECIFIED)),
+            IgmpMessage::Report(group) => (V2_MEMBERSHIP_REPORT, 0, group),
+            IgmpMessage::Leave(group) => (LEAVE_GROUP, 0, group),
+        };
+        let mut bytes = vec![kind, max_response, 0, 0];
+        bytes.extend(group.octets());
+        let sum = internet_checksum(&bytes);
+        bytes[2..4].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, IGMP_LEN)?;
+        if !verify_internet_checksum(&bytes[..IGMP_LEN]) {
+            return Err(PacketError::BadChecksum);
+        }
+        let group = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+        match bytes[0] {
+            MEMBERSHIP_QUERY => Ok(IgmpMessage::Query {
+                // an IGMPv1 query has no time in it, and means 10 seconds
+                max_response : if bytes[1] == 0 { Duration::from_secs(10) } else { Duration::from_millis(bytes[1] as u64 * 100) },
+                group : (!group.is_unspecified()).then_some(group),
+            }),
+            V1_MEMBERSHIP_REPORT | V2_MEMBERSHIP_REPORT => Ok(IgmpMessage::Report(group)),
+            LEAVE_GROUP => Ok(IgmpMessage::Leave(group)),
+            other => Err(PacketError::UnknownType(other)),
+        }
+    }
+}
+
+// Groups only for the link the packet is on, never routed anywhere else: 224.0.0.0/24 and
+// anything with a scope smaller than a site in IPv6 (ff01::/16, ff02::/16)
+pub fn is_link_local_group(group: IpAddr) -> bool {
+    match group {
+        IpAddr::V4(group) => group.is_multicast() && group.octets()[..3] == [224, 0, 0],
+        IpAddr::V6(group) => group.is_multicast() && group.segments()[0] & 0x000f <= 2,
+    }
+}
+
+// The defaults are the ones from RFC 2236
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IgmpTimers {
+    pub robustness : u32,
+    pub query_interval : Duration,
+    pub query_response_interval : Duration,
+    pub last_member_query_interval : Duration,
+    pub last_member_query_count : u32,
+}
+
+impl Default for IgmpTimers {
+    fn default() -> Self {
+        IgmpTimers {
+            robustness : 2,
+            query_interval : Duration::from_secs(125),
+            query_response_interval : Duration::from_secs(10),
+            last_member_query_interval : Duration::from_secs(1),
+            last_member_query_count : 2,
+        }
+    }
+}
+
+impl IgmpTimers {
+    // how long a group stays without any report
+    pub fn group_membership_interval(&self) -> Duration {
+        self.query_interval * self.robustness + self.query_response_interval
+    }
+    // how long a non-querier waits without hearing a query before taking over
+    pub fn other_querier_present_interval(&self) -> Duration {
+        self.query_interval * self.robustness + self.query_response_interval / 2
+    }
+    // how long after a leave the group is dropped if nobody answers
+    pub fn last_member_query_time(&self) -> Duration {
+        self.last_member_query_interval * self.last_member_query_count
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMembership {
+    pub group : Ipv4Addr,
+    pub expires_at : Instant,
+    // when the last leave was heard, while the querier is still asking around
+    pub leave_heard_at : Option<Instant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MulticastRouter {
+    pub address : Ipv4Addr,
+    pub timers : IgmpTimers,
+    pub querier : bool,
+    pub last_query_heard : Option<Instant>,
+    // when the querier sends its next general query
+    pub next_query_at : Option<Instant>,
+    pub groups : Vec<GroupMembership>,
+}
+
+impl MulticastRouter {
+    // every router starts thinking it is the querier, until it hears a lower address
+    pub fn new(address: Ipv4Addr) -> Self {
+        MulticastRouter { address, timers : IgmpTimers::default(), querier : true, last_query_heard : None, next_query_at : None, groups : vec![] }
+    }
+
+    pub fn hear_query(&mut self, from: Ipv4Addr, now: Instant) {
+        if from < self.address {
+            self.querier = false;
+            self.last_query_heard = Some(now);
+        }
+    }
+
+    // the other querier went silent for too long, so we take over
+    pub fn check_other_querier(&mut self, now: Instant) {
+        if !self.querier && self.last_query_heard
+            .is_none_or(|heard| now.saturating_duration_since(heard) >= self.timers.other_querier_present_interval()) {
+            self.querier = true;
+        }
+    }
+
+    pub fn hear_report(&mut self, group: Ipv4Addr, now: Instant) {
+        let expires_at = now + self.timers.group_membership_interval();
+        match self.groups.iter_mut().find(|membership| membership.group == group) {
+            Some(membership) => {
+                membership.expires_at = expires_at;
+                membership.leave_heard_at = None;
+            }
+            None => self.groups.push(GroupMembership { group, expires_at, leave_heard_at : None }),
+        }
+    }
+
+    // only the querier acts on leaves, by sending the group-specific queries
+    pub fn hear_leave(&mut self, group: Ipv4Addr, now: Instant) {
+        if !self.querier {
+            return;
+        }
+        let last_member_query_time = self.timers.last_member_query_time();
+        if let Some(membership) = self.groups.iter_mut().find(|membership| membership.group == group) {
+            membership.expires_at = membership.expires_at.min(now + last_member_query_time);
+            membership.leave_heard_at = Some(now);
+        }
+    }
+
+    // A non-querier hearing the querier ask about one group (after somebody left it) gives the
+    // members as long to answer as the querier does, so both drop it at about the same time
+    pub fn hear_group_query(&mut self, group: Ipv4Addr, max_response: Duration, now: Instant) {
+        if self.querier {
+            return;
+        }
+        let last_member_query_count = self.timers.last_member_query_count;
+        if let Some(membership) = self.groups.iter_mut().find(|membership| membership.group == group) {
+            membership.expires_at = membership.expires_at.min(now + max_response * last_member_query_count);
+        }
+    }
+
+    // the general query, when it is the querier's turn to send one
+    pub fn query_due(&mut self, now: Instant) -> Option<IgmpMessage> {
+        if !self.querier || self.next_query_at.is_some_and(|at| at > now) {
+            return None;
+        }
+        self.next_query_at = Some(now + self.timers.query_interval);
+        Some(IgmpMessage::Query { max_response : self.timers.query_response_interval, group : None })
+    }
+
+    pub fn has_members(&self, group: Ipv4Addr) -> bool {
+        self.groups.iter().any(|membership| membership.group == group)
+    }
+
+    // drops the timed out groups; for those that went away after a leave, gives the leave latency
+    pub fn expire_groups(&mut self, now: Instant) -> Vec<(Ipv4Addr, Option<Duration>)> {
+        let mut expired = vec![];
+        self.groups.retain(|membership| {
+            if membership.expires_at > now {
+                return true;
+            }
+            let latency = membership.leave_heard_at.map(|heard| membership.expires_at.saturating_duration_since(heard));
+            expired.push((membership.group, latency));
+            false
+        });
+        expired
+    }
+}
+
+// one round of queries on the LAN: every router that thinks it is the querier sends one,
+// and every other router hears it
+pub fn query_round(routers: &mut [MulticastRouter], now: Instant) {
+    for router in routers.iter_mut() {
+        router.check_other_querier(now);
+    }
+    let queriers: Vec<Ipv4Addr> = routers
+        .iter()
+        .filter(|router| router.querier)
+        .map(|router| router.address)
+        .collect();
+    for router in routers.iter_mut() {
+        for &querier in &queriers {
+            router.hear_query(querier, now);
+        }
+    }
+}
+
+pub fn querier(routers: &[MulticastRouter]) -> Option<Ipv4Addr> {
+    let mut queriers = routers.iter().filter(|router| router.querier);
+    let querier = queriers.next()?;
+    queriers.next().is_none().then_some(querier.address)
+}
+
+#[test]
+fn lowest_address_wins_the_election() {
+    let start = Instant::now();
+    let mut routers: Vec<MulticastRouter> = ["10.0.0.3", "10.0.0.1", "10.0.0.2"]
+        .iter()
+        .map(|address| MulticastRouter::new(address.parse().unwrap()))
+        .collect();
+    query_round(&mut routers, start);
+    assert_eq!(querier(&routers), Some("10.0.0.1".parse().unwrap()));
+
+    // the querier dies; the others notice only after the other querier present interval
+    let interval = IgmpTimers::default().other_querier_present_interval();
+    routers.remove(1);
+    query_round(&mut routers, start + interval / 2);
+    assert_eq!(querier(&routers), None);
+    query_round(&mut routers, start + interval);
+    assert_eq!(querier(&routers), Some("10.0.0.2".parse().unwrap()));
+}
+
+#[test]
+fn groups_time_out_and_leave_latency() {
+    let start = Instant::now();
+    let group: Ipv4Addr = "239.1.1.1".parse().unwrap();
+    let other: Ipv4Addr = "239.2.2.2".parse().unwrap();
+    let mut router = MulticastRouter::new("10.0.0.1".parse().unwrap());
+    router.hear_report(group, start);
+    router.hear_report(other, start);
+
+    // a host leaves the group: gone after the last member queries, 2 seconds later
+    let leave_at = start + Duration::from_secs(30);
+    router.hear_leave(group, leave_at);
+    assert!(router.expire_groups(leave_at + Duration::from_secs(1)).is_empty());
+    assert_eq!(router.expire_groups(leave_at + Duration::from_secs(2)), vec![(group, Some(Duration::from_secs(2)))]);
+
+    // the other group just stops getting reports and times out after 260 seconds
+    assert!(router.has_members(other));
+    assert!(router.expire_groups(start + Duration::from_secs(259)).is_empty());
+    assert_eq!(router.expire_groups(start + Duration::from_secs(260)), vec![(other, None)]);
+}
+
+#[test]
+fn igmp_messages_keep_a_routers_groups() {
+    use crate::packet::builder::PacketBuilder;
+    use crate::router::{Forwarded, Router, RouterInterface};
+
+    let group = Ipv4Addr::new(239, 1, 1, 1);
+    // a report as a host sends it: 16 00, the checksum, the group
+    let report = IgmpMessage::Report(group).to_bytes();
+    assert_eq!(report, [0x16, 0, 0xf9, 0xfc, 239, 1, 1, 1]);
+    assert_eq!(IgmpMessage::from_bytes(&report), Ok(IgmpMessage::Report(group)));
+    let mut damaged = report.clone();
+    damaged[7] = 2;
+    assert_eq!(IgmpMessage::from_bytes(&damaged), Err(PacketError::BadChecksum));
+    let query = IgmpMessage::Query { max_response : Duration::from_secs(10), group : None };
+    assert_eq!((query.to_bytes()[1], query.destination()), (100, ALL_SYSTEMS));
+    assert_eq!(IgmpMessage::from_bytes(&query.to_bytes()), Ok(query));
+    assert_eq!(IgmpMessage::Leave(group).destination(), ALL_ROUTERS);
+
+    let mut router = Router::new("r1");
+    router.add_interface(RouterInterface::new("uplink").with_v4(Ipv4Addr::new(10, 0, 0, 1), 24));
+    router.add_interface(RouterInterface::new("lan").with_v4(Ipv4Addr::new(10, 0, 1, 2), 24));
+    let stream = PacketBuilder::ipv4().src(Ipv4Addr::new(10, 0, 0, 50)).dst(group).hop_limit(8).udp().sport(5004).dport(5004).payload(b"video").build();
+    let start = Instant::now();
+    let host = Ipv4Addr::new(10, 0, 1, 20);
+
+    // the router is the querier on the LAN until it hears one with a lower address
+    assert_eq!(router.hear_igmp("lan", host, &IgmpMessage::Report(group), start), None);
+    assert!(matches!(router.forward(stream.clone(), "uplink"), Forwarded::Replicated(_)));
+    assert_eq!(router.igmp_tick(start), [("lan".to_string(), query)]);
+    assert_eq!(router.igmp_tick(start + Duration::from_secs(1)), []);
+    // a leave makes it ask about the group, and without an answer it stops forwarding it
+    let asked = router.hear_igmp("lan", host, &IgmpMessage::Leave(group), start + Duration::from_secs(30));
+    assert_eq!(asked, Some(IgmpMessage::Query { max_response : Duration::from_secs(1), group : Some(group) }));
+    router.igmp_tick(start + Duration::from_secs(32));
+    assert!(matches!(router.forward(stream.clone(), "uplink"), Forwarded::Dropped(_)));
+
+    // another router with a lower address takes over, until it goes quiet
+    let other = Ipv4Addr::new(10, 0, 1, 1);
+    router.hear_igmp("lan", other, &query, start + Duration::from_secs(40));
+    assert_eq!(router.igmp_tick(start + Duration::from_secs(200)), []);
+    // nobody leaves through a non-querier
+    router.hear_igmp("lan", host, &IgmpMessage::Report(group), start + Duration::from_secs(200));
+    assert_eq!(router.hear_igmp("lan", host, &IgmpMessage::Leave(group), start + Duration::from_secs(201)), None);
+    let takeover = start + Duration::from_secs(40) + IgmpTimers::default().other_querier_present_interval();
+    assert_eq!(router.igmp_tick(takeover), [("lan".to_string(), query)]);
+    // and reports that stop coming time the group out too
+    assert!(matches!(router.forward(stream.clone(), "uplink"), Forwarded::Replicated(_)));
+    router.igmp_tick(start + Duration::from_secs(200) + IgmpTimers::default().group_membership_interval());
+    assert!(matches!(router.forward(stream, "uplink"), Forwarded::Dropped(_)));
+}
@@ -0,0 +1,241 @@
+//! Network namespaces: one machine, several separate network stacks, each with its own
+//! addresses, routing table and NAT, like containers on a host. They only see each other
+//! through a bridge, and the host's own namespace usually NATs them to the outside.
+//! The same private address can be in two namespaces without them ever noticing.
+//!
+//! Each container is plugged into the bridge with a veth pair, a virtual cable with one end
+//! inside the container (its eth0) and one on the bridge. That, and the host masquerading
+//! (NAT) what goes out, is all Docker's default network is.
+use std::net::Ipv4Addr;
+
+use crate::nat_v4::NatTable;
+use crate::neighbor::MacAddr;
+use crate::networkingv4::RoutingTable;
+use crate::packet::layered::LayeredPacket;
+use crate::packet::Packet;
+
+#[derive(Debug)]
+pub struct Namespace {
+    pub name : String,
+    pub addresses : Vec<Ipv4Addr>,
+    pub routes : RoutingTable,
+    // NAT for the packets that leave the machine from this namespace
+    pub nat : Option<NatTable>,
+}
+
+impl Namespace {
+    pub fn new(name: &str, addresses: Vec<Ipv4Addr>, routes: RoutingTable) -> Self {
+        Namespace { name : name.to_string(), addresses, routes, nat : None }
+    }
+
+    pub fn with_nat(mut self, nat: NatTable) -> Self {
+        self.nat = Some(nat);
+        self
+    }
+
+    pub fn owns(&self, ip: Ipv4Addr) -> bool {
+        self.addresses.contains(&ip)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery<P = LayeredPacket> {
+    // arrived in this namespace
+    Local { namespace : usize, packet : P },
+    // left the machine
+    Outside(P),
+    Dropped,
+}
+
+// a packet can't go around the bridge forever
+const MAX_HOPS: usize = 8;
+
+// One end in a namespace, the other plugged into the bridge, like
+// `ip link add veth-web type veth peer name eth0` and moving eth0 into the container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VethPair {
+    // the end inside the namespace, usually eth0
+    pub inside : String,
+    // the end on the bridge
+    pub outside : String,
+    pub namespace : usize,
+    pub address : Ipv4Addr,
+    pub mac : MacAddr,
+}
+
+// A switch made of software, like docker0: it learns which MAC is behind which port from the
+// frames it sees, and floods frames for MACs it doesn't know yet
+#[derive(Debug)]
+pub struct Bridge {
+    pub name : String,
+    pub ports : Vec<VethPair>,
+    // (MAC, port) learned so far
+    pub fdb : Vec<(MacAddr, usize)>,
+    pub flooded : u64,
+}
+
+impl Bridge {
+    pub fn new(name: &str) -> Self {
+        Bridge { name : name.to_string(), ports : vec![], fdb : vec![], flooded : 0 }
+    }
+
+    // which port has this IP, the answer ARP would give
+    fn port_with_address(&self, ip: Ipv4Addr) -> Option<usize> {
+        self.ports.iter().position(|port| port.address == ip)
+    }
+
+    fn port_of_namespace(&self, namespace: usize) -> Option<usize> {
+        self.ports.iter().position(|port| port.namespace == namespace)
+    }
+
+    // a frame comes in on one port for this MAC; gives back the port it leaves through
+    fn switch(&mut self, from: usize, to: MacAddr) -> Option<usize> {
+        let source = self.ports[from].mac;
+        if !self.fdb.iter().any(|&(mac, _)| mac == source) {
+            self.fdb.push((source, from));
+        }
+        if let Some(&(_, port)) = self.fdb.iter().find(|&&(mac, _)| mac == to) {
+            return Some(port);
+        }
+        // not learned yet: it goes out everywhere, and only the right port takes it
+        self.flooded += 1;
+        self.ports.iter().position(|port| port.mac == to)
+    }
+}
+
+#[derive(Debug)]
+pub struct Machine {
+    pub namespaces : Vec<Namespace>,
+    pub bridge : Bridge,
+    // the interfaces the last packet went through, to explain what happened
+    pub path : Vec<String>,
+}
+
+impl Machine {
+    // the first namespace is the host's own, the one facing the outside
+    pub fn new(host: Namespace, bridge: Bridge) -> Self {
+        Machine { namespaces : vec![host], bridge, path : vec![] }
+    }
+
+    // adds a namespace; gives back its index
+    pub fn add_namespace(&mut self, namespace: Namespace) -> usize {
+        self.namespaces.push(namespace);
+        self.namespaces.len() - 1
+    }
+
+    // connects a namespace to the bridge with a veth pair. For the host itself, the bridge
+    // interface is in the host namespace, so both names are the bridge's.
+    pub fn add_veth_pair(&mut self, namespace: usize, inside: &str, outside: &str, address: Ipv4Addr, mac: MacAddr) {
+        self.bridge.ports.push(VethPair { inside : inside.to_string(), outside : outside.to_string(), namespace, address, mac });
+    }
+
+    // across the bridge, from the namespace to the one with this address
+    fn bridged(&mut self, from: usize, to: Ipv4Addr) -> Option<usize> {
+        let from_port = self.bridge.port_of_namespace(from)?;
+        let to_port = self.bridge.port_with_address(to)?;
+        let to_port = self.bridge.switch(from_port, self.bridge.ports[to_port].mac)?;
+        let (from_port, to_port) = (&self.bridge.ports[from_port], &self.bridge.ports[to_port]);
+        self.path.push(format!("{}@{}", from_port.inside, self.namespaces[from_port.namespace].name));
+        self.path.extend([from_port.outside.clone(), self.bridge.name.clone(), to_port.outside.clone()]);
+        self.path.push(format!("{}@{}", to_port.inside, self.namespaces[to_port.namespace].name));
+        // the host's own end is the bridge itself, no need to name it twice
+        self.path.dedup();
+        Some(to_port.namespace)
+    }
+
+    // a packet sent from inside a namespace
+    pub fn send<P: Packet<Address = Ipv4Addr>>(&mut self, from: usize, packet: P) -> Delivery<P> {
+        let (mut current, mut previous, mut packet) = (from, from, packet);
+        self.path = vec![];
+        for _ in 0..MAX_HOPS {
+            if self.namespaces[current].owns(packet.destination_ip()) {
+                return Delivery::Local { namespace : current, packet };
+            }
+            // on the same bridge, it is switched there directly, no routing needed
+            if let Some(next) = self.bridged(current, packet.destination_ip()) {
+                (previous, current) = (current, next);
+                continue;
+            }
+            let Some(next_hop) = self.namespaces[current].routes.find_next_hop(packet.destination_ip()) else {
+                return Delivery::Dropped;
+            };
+            match self.bridged(current, next_hop) {
+                Some(next) if next != current => (previous, current) = (current, next),
+                // the next hop is outside the machine
+                _ => {
+                    if let Some(nat) = &mut self.namespaces[current].nat {
+                        match nat.translate_outgoing(packet, previous as u16) {
+                            Ok(translated) => packet = translated,
+                            Err(_) => return Delivery::Dropped,
+                        }
+                    }
+                    self.path.push("outside".to_string());
+                    return Delivery::Outside(packet);
+                }
+            }
+        }
+        Delivery::Dropped
+    }
+
+    // a packet from the outside, coming in through the host namespace
+    pub fn receive<P: Packet<Address = Ipv4Addr>>(&mut self, packet: P) -> Delivery<P> {
+        let Some(nat) = &mut self.namespaces[0].nat else {
+            return self.send(0, packet);
+        };
+        match nat.translate_incoming(packet) {
+            Ok((packet, namespace)) => {
+                // the NAT is in the host, so that is where the packet is sent on from
+                let delivery = self.send(0, packet);
+                if matches!(delivery, Delivery::Local { namespace : arrived, .. } if arrived != namespace as usize) {
+                    return Delivery::Dropped;
+                }
+                delivery
+            }
+            Err(_) => Delivery::Dropped,
+        }
+    }
+}
+
+#[test]
+fn containers_behind_the_host_nat() {
+    use crate::networkingv4::Route;
+    use crate::packet::builder::PacketBuilder;
+
+    let address = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let routes = |default_via: &str| RoutingTable::new("main").with_routes(vec![Route::default_route(address(default_via))]);
+    // the host reaches the internet through its ISP router, and the containers through the bridge
+    let host = Namespace::new("host", vec![address("203.0.113.5"), address("172.17.0.1")], routes("203.0.113.1"))
+        .with_nat(NatTable::new("docker NAT", address("203.0.113.5")));
+    let mut machine = Machine::new(host, Bridge::new("docker0"));
+    machine.add_veth_pair(0, "docker0", "docker0", address("172.17.0.1"), MacAddr([0x02, 0x42, 0, 0, 0, 1]));
+    // both containers think they are 10.0.0.2 inside, nothing collides
+    let web = machine.add_namespace(Namespace::new("web", vec![address("172.17.0.2"), address("10.0.0.2")], routes("172.17.0.1")));
+    machine.add_veth_pair(web, "eth0", "veth-web", address("172.17.0.2"), MacAddr([0x02, 0x42, 0, 0, 0, 2]));
+    let db = machine.add_namespace(Namespace::new("db", vec![address("172.17.0.3"), address("10.0.0.2")], routes("172.17.0.1")));
+    machine.add_veth_pair(db, "eth0", "veth-db", address("172.17.0.3"), MacAddr([0x02, 0x42, 0, 0, 0, 3]));
+
+    let packet = |source: &str, destination: &str| {
+        PacketBuilder::ipv4().src(address(source)).dst(address(destination)).tcp().sport(40000).dport(5432).payload(b"SELECT 1").build()
+    };
+
+    // container to container, over the bridge
+    assert!(matches!(machine.send(web, packet("172.17.0.2", "172.17.0.3")), Delivery::Local { namespace, .. } if namespace == db));
+    assert_eq!(machine.path, ["eth0@web", "veth-web", "docker0", "veth-db", "eth0@db"]);
+    // db was not known to the bridge yet, so that first frame was flooded; the next one isn't
+    assert_eq!(machine.bridge.flooded, 1);
+    machine.send(db, packet("172.17.0.3", "172.17.0.2"));
+    assert_eq!(machine.bridge.flooded, 1);
+    // 10.0.0.2 is web's own address inside web, it never reaches db
+    assert!(matches!(machine.send(web, packet("172.17.0.2", "10.0.0.2")), Delivery::Local { namespace, .. } if namespace == web));
+
+    // out to the internet, NATed by the host, and the answer finds its way back to the container
+    let Delivery::Outside(out) = machine.send(db, packet("172.17.0.3", "93.184.216.34")) else {
+        panic!("should have left the machine");
+    };
+    assert_eq!(out.source_ip(), address("203.0.113.5"));
+    assert_eq!(machine.path, ["eth0@db", "veth-db", "docker0", "docker0@host", "outside"]);
+    let mut reply = out.clone();
+    reply.set_source(out.destination_ip(), out.destination_port());
+    reply.set_destination(out.source_ip(), out.source_port());
+    assert!(matches!(machine.receive(reply), Delivery::Local { namespace, packet } if namespace == db && packet.destination_ip() == address("172.17.0.3")));
+}
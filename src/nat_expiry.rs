@@ -0,0 +1,84 @@
+//! Expiring NAT mappings in the background, so nobody has to remember to call
+//! `prune_unnecessary_ports`. A tokio task wakes up every so often, removes what timed out
+//! and sends every removed mapping on a channel, for whoever wants to log or count them.
+//! Only there with the `tokio` feature.
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::nat_v4::{NatAddress, NatEntry};
+use crate::shared_nat::SharedNatTable;
+
+// A mapping the background task removed, and when
+#[derive(Debug)]
+pub struct ExpiryEvent<A> {
+    pub entry : NatEntry<A>,
+    pub at : Instant,
+}
+
+// Runs until the handle is aborted, or until nobody listens anymore (the receiver is dropped).
+// Waking up every 0s would spin (and tokio refuses it), so it is every 1ms at the least.
+pub fn spawn_expiry_task<A>(nat: SharedNatTable<A>, every: Duration) -> (JoinHandle<()>, mpsc::UnboundedReceiver<ExpiryEvent<A>>)
+where
+    A: NatAddress + Send + Sync + 'static,
+{
+    let (events, receiver) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every.max(Duration::from_millis(1)));
+        loop {
+            interval.tick().await;
+            if events.is_closed() {
+                return;
+            }
+            let at = Instant::now();
+            for entry in nat.write().expire_at(at) {
+                let _ = events.send(ExpiryEvent { entry, at });
+            }
+        }
+    });
+    (task, receiver)
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn mappings_expire_in_the_background() {
+    use crate::nat_v4::{NatTable, NatTimeouts};
+    use crate::packet::builder::PacketBuilder;
+    use std::net::Ipv4Addr;
+
+    let nat = SharedNatTable::new(NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap())
+        .with_timeouts(NatTimeouts { udp : Duration::from_millis(50), ..NatTimeouts::default() }));
+    let packet = PacketBuilder::ipv4()
+        .src("10.100.1.1".parse().unwrap())
+        .dst("192.168.1.1".parse().unwrap())
+        .hop_limit(30)
+        .udp()
+        .sport(8090)
+        .dport(80)
+        .payload(b"K xa bro, haal khabar?")
+        .build();
+    nat.translate_outgoing(packet, 12).unwrap();
+
+    let (task, mut events) = spawn_expiry_task(nat.clone(), Duration::from_millis(10));
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+    assert_eq!((event.entry.source_ip, event.entry.source_port, event.entry.computer), (Ipv4Addr::new(10, 100, 1, 1), 8090, 12));
+    assert!(nat.is_empty());
+    // nobody listening anymore: the task stops by itself
+    drop(events);
+    tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+
+    // a zero period doesn't panic the task
+    nat.translate_outgoing(PacketBuilder::ipv4()
+        .src("10.100.1.1".parse().unwrap())
+        .dst("192.168.1.1".parse().unwrap())
+        .hop_limit(30)
+        .udp()
+        .sport(8091)
+        .dport(80)
+        .build(), 12).unwrap();
+    let (task, mut events) = spawn_expiry_task(nat.clone(), Duration::ZERO);
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+    assert_eq!(event.entry.source_port, 8091);
+    task.abort();
+}
@@ -0,0 +1,168 @@
+//! Load testing the NAT port pool.
+//! Mappings are opened at a fixed rate and each one lives for a fixed time, so by Little's law
+//! about rate x lifetime of them are alive at once. If that is more than the pool has ports,
+//! the pool runs dry after pool / rate seconds, before the first mapping even expires.
+//! The simulation runs the real NatTable and compares it with that.
+//!
+//! It also counts the work the allocation does: finding a free port here means walking the
+//! port range and, for each candidate port, scanning the whole table (see the comments at the
+//! top of nat_v4). A router indexing by port would do one lookup per candidate instead.
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use crate::nat_v4::{NatError, NatTableV4, NatTimeouts};
+use crate::packet::builder::PacketBuilder;
+use crate::packet::Packet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadTest {
+    pub mappings_per_second : u32,
+    pub lifetime : Duration,
+    // how long the simulation runs
+    pub duration : Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    pub pool_size : usize,
+    // what Little's law says
+    pub expected_usage : f64,
+    pub expected_exhaustion : Option<Duration>,
+    // what the simulation saw
+    pub peak_usage : usize,
+    pub final_usage : usize,
+    pub exhausted_at : Option<Duration>,
+    pub opened : u64,
+    pub failed : u64,
+    // table entries looked at while searching for free ports
+    pub entries_checked : u64,
+}
+
+impl LoadReport {
+    pub fn entries_checked_per_mapping(&self) -> f64 {
+        if self.opened == 0 {
+            return 0.0;
+        }
+        self.entries_checked as f64 / self.opened as f64
+    }
+}
+
+impl LoadTest {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.mappings_per_second
+    }
+
+    // Mapping k opens at k x interval. The pool runs out when mapping number pool_size arrives
+    // while the first one is still alive.
+    pub fn expected(&self, pool_size: usize) -> (f64, Option<Duration>) {
+        let usage = self.mappings_per_second as f64 * self.lifetime.as_secs_f64();
+        let runs_out_at = self.interval() * pool_size as u32;
+        let exhaustion = (runs_out_at < self.lifetime).then_some(runs_out_at);
+        (usage.min(pool_size as f64), exhaustion)
+    }
+
+    pub fn run(&self, port_range: RangeInclusive<u16>) -> LoadReport {
+        self.simulate(port_range, false)
+    }
+
+    fn simulate(&self, port_range: RangeInclusive<u16>, stop_on_exhaustion: bool) -> LoadReport {
+        let pool_size = port_range.clone().count();
+        let first_port = *port_range.start();
+        let mut nat = NatTableV4::new("load test", Ipv4Addr::new(203, 0, 113, 1))
+            .with_port_range(port_range)
+            .with_timeouts(NatTimeouts { udp : self.lifetime, ..NatTimeouts::default() });
+        let (expected_usage, expected_exhaustion) = self.expected(pool_size);
+        let mut report = LoadReport {
+            pool_size,
+            expected_usage,
+            expected_exhaustion,
+            peak_usage : 0,
+            final_usage : 0,
+            exhausted_at : None,
+            opened : 0,
+            failed : 0,
+            entries_checked : 0,
+        };
+
+        let start = Instant::now();
+        let interval = self.interval();
+        let mappings = self.duration.as_nanos() / interval.as_nanos();
+        for k in 0..mappings as u32 {
+            let elapsed = interval * k;
+            let now = start + elapsed;
+            // every mapping from a different internal endpoint, so none are reused
+            let packet = PacketBuilder::ipv4()
+                .src(Ipv4Addr::from(0x0a00_0000 | (k >> 16)))
+                .dst(Ipv4Addr::new(198, 51, 100, 1))
+                .udp()
+                .sport(k as u16)
+                .dport(53)
+                .build();
+            let entries = nat.table.len() as u64;
+            match nat.translate_outgoing_at(packet, 0, now) {
+                Ok(translated) => {
+                    report.opened += 1;
+                    report.entries_checked += (translated.source_port() - first_port + 1) as u64 * entries;
+                }
+                Err(NatError::PortExhausted) => {
+                    report.failed += 1;
+                    report.exhausted_at.get_or_insert(elapsed);
+                    if stop_on_exhaustion {
+                        break;
+                    }
+                }
+                Err(error) => unreachable!("only the pool can run out here, got {error}"),
+            }
+            let usage = nat.table.iter().filter(|entry| !entry.is_expired(now)).count();
+            report.peak_usage = report.peak_usage.max(usage);
+            report.final_usage = usage;
+        }
+        report
+    }
+}
+
+// Binary search for the highest rate the pool survives for the whole duration.
+// Analytically that is pool_size / lifetime.
+pub fn max_sustainable_rate(port_range: RangeInclusive<u16>, lifetime: Duration, duration: Duration) -> u32 {
+    let survives = |mappings_per_second| {
+        LoadTest { mappings_per_second, lifetime, duration }
+            .simulate(port_range.clone(), true)
+            .exhausted_at
+            .is_none()
+    };
+    // every port used once per millisecond is surely too much
+    let (mut low, mut high) = (0, port_range.clone().count() as u32 * 1000);
+    while low + 1 < high {
+        let middle = (low + high) / 2;
+        if survives(middle) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+#[test]
+fn simulation_matches_littles_law() {
+    let ports = 1000..=1099;
+    // 5 per second living 10 seconds: 50 of the 100 ports in use
+    let calm = LoadTest { mappings_per_second : 5, lifetime : Duration::from_secs(10), duration : Duration::from_secs(60) };
+    let report = calm.run(ports.clone());
+    assert_eq!(report.expected_usage, 50.0);
+    assert_eq!(report.expected_exhaustion, None);
+    assert_eq!((report.peak_usage, report.final_usage, report.exhausted_at), (50, 50, None));
+
+    // 20 per second would need 200 ports: the 100 are gone after 5 seconds
+    let busy = LoadTest { mappings_per_second : 20, ..calm };
+    let report = busy.run(ports.clone());
+    assert_eq!(report.expected_exhaustion, Some(Duration::from_secs(5)));
+    assert_eq!(report.exhausted_at, report.expected_exhaustion);
+    assert_eq!(report.peak_usage, 100);
+    assert!(report.failed > 0);
+    // searching for a port gets slower as the table fills up
+    assert!(report.entries_checked_per_mapping() > calm.run(ports.clone()).entries_checked_per_mapping());
+
+    assert_eq!(max_sustainable_rate(ports, Duration::from_secs(10), Duration::from_secs(30)), 10);
+}
@@ -13,129 +13,840 @@
 /// And since, they do not need to store port, they will store ipv4_addr and the port (32 + 16 bits) there.
 /// The router would have just a single ip-address they can give.
 /// The searching of next free port could take O(n) time, but it can easily be pipelined.
-use std::net::Ipv4Addr;
+use std::fmt::{self, Debug, Display};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone)]
-pub struct RandomTransportPacket {
+use crate::packet::builder::PacketBuilder;
+use crate::packet::{icmp, Packet};
+use crate::table_limits::{TableEvent, TableLimit};
+use crate::token_bucket::TokenBucket;
+
+// Anything the NAT can translate: the same logic works for both IP versions
+pub trait NatAddress: Copy + PartialEq + Debug + Into<IpAddr> {}
+impl NatAddress for Ipv4Addr {}
+impl NatAddress for Ipv6Addr {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    Tcp,
+    #[default]
+    Udp,
+    Icmp,
+    // a tunnel, with no ports: the whole GRE header and what it carries are the data
+    Gre,
+    // 6in4: an IPv6 packet right inside an IPv4 one, the whole of it as the data
+    Ipv6,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Icmp => "icmp",
+            Protocol::Gre => "gre",
+            Protocol::Ipv6 => "ipv6",
+        };
+        f.pad(name)
+    }
+}
+
+// Only what NAT and routing look at, with the payload as text. Superseded by
+// packet::LayeredPacket, which has the real headers; code taking a packet should take any
+// `impl Packet` instead, which this still is.
+#[deprecated(note = "use packet::LayeredPacket, or take any packet::Packet")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RandomTransportPacket<A = Ipv4Addr> {
     // computer : u16, // This should be on perhaps Data Link Layer, so I removed it
-    time_to_live : Duration,
-    source_ip : Ipv4Addr,
-    destination_ip : Ipv4Addr,
-    source_port : u16,
-    destination_port: u16,
+    // the IPv4 TTL or IPv6 hop limit: every router takes one off, and the packet dies at zero
+    pub hop_limit : u8,
+    pub protocol : Protocol,
+    pub source_ip : A,
+    pub destination_ip : A,
+    pub source_port : u16,
+    pub destination_port: u16,
+
+    pub data : Vec<u8>, // The upper part should be header, and bottom part should be used separately
+}
+
+#[allow(deprecated)]
+impl<A: NatAddress> RandomTransportPacket<A> {
+    // see Packet::flow_hash
+    pub fn flow_hash(&self) -> u64 {
+        Packet::flow_hash(self)
+    }
+}
 
-    data : String, // The upper part should be header, and bottom part should be used separately
+#[allow(deprecated)]
+impl<A: NatAddress> Packet for RandomTransportPacket<A> {
+    type Address = A;
+
+    fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+    fn source_ip(&self) -> A {
+        self.source_ip
+    }
+    fn source_port(&self) -> u16 {
+        self.source_port
+    }
+    fn destination_ip(&self) -> A {
+        self.destination_ip
+    }
+    fn destination_port(&self) -> u16 {
+        self.destination_port
+    }
+    fn hop_limit(&self) -> u8 {
+        self.hop_limit
+    }
+    fn set_source(&mut self, ip: A, port: u16) {
+        (self.source_ip, self.source_port) = (ip, port);
+    }
+    fn set_destination(&mut self, ip: A, port: u16) {
+        (self.destination_ip, self.destination_port) = (ip, port);
+    }
+    fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.hop_limit = hop_limit;
+    }
+    // no ICMP header here, so the type and code go where the ports would be, and the dropped
+    // packet is quoted as text
+    fn time_exceeded(&self, from: A) -> Self {
+        let kind = match from.into() {
+            IpAddr::V4(_) => icmp::TIME_EXCEEDED,
+            IpAddr::V6(_) => icmp::ICMPV6_TIME_EXCEEDED,
+        };
+        RandomTransportPacket {
+            hop_limit : self.hop_limit,
+            protocol : Protocol::Icmp,
+            source_ip : from,
+            destination_ip : self.source_ip,
+            source_port : kind as u16,
+            destination_port : 0,
+            data : format!("{} {} > {}", self.protocol, self.source(), self.destination()).into_bytes(),
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct NatEntry {
-    pub source_ip: Ipv4Addr,
+pub struct NatEntry<A = Ipv4Addr> {
+    pub source_ip: A,
     pub source_port : u16,
+    pub protocol : Protocol,
     pub computer : u16,
     pub mangled_port : u16,
     pub mapped_on_time : Instant,
-    pub time_to_live : Duration,
+    // how long the mapping lives since it was last used, from NatTimeouts; not the IP TTL,
+    // which is the packet's hop_limit
+    pub lifetime : Duration,
+    // every remote (ip, port) this mapping has sent to, for the mapping and filtering behaviors
+    pub remotes : Vec<(A, u16)>,
+    pub state : MappingState,
+    // asked for explicitly (see request_mapping) instead of made by outgoing traffic
+    pub explicit : bool,
+}
+
+// A connection sent somewhere else than where it was going (destination NAT), see redirect_at.
+// The answers have to look like they came from where the client wanted to go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirection<A = Ipv4Addr> {
+    pub protocol : Protocol,
+    pub client : (A, u16),
+    pub wanted : (A, u16),
+    pub sent_to : (A, u16),
+    pub last_used : Instant,
+    pub lifetime : Duration,
+}
+
+// A mapping is transient until something answers through it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum MappingState {
+    #[default]
+    Transient,
+    Established,
+}
+
+// how long a mapping lives since it was last used (defaults roughly from RFC 4787 and RFC 5382)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatTimeouts {
+    pub udp : Duration,
+    pub tcp_established : Duration,
+    pub tcp_transient : Duration,
+    pub icmp : Duration,
+}
+
+impl Default for NatTimeouts {
+    fn default() -> Self {
+        NatTimeouts {
+            udp : Duration::from_secs(30),
+            tcp_established : Duration::from_secs(5 * 24 * 60 * 60),
+            tcp_transient : Duration::from_secs(60),
+            icmp : Duration::from_secs(30),
+        }
+    }
+}
+
+impl NatTimeouts {
+    pub fn for_mapping(&self, protocol: Protocol, state: MappingState) -> Duration {
+        match (protocol, state) {
+            (Protocol::Tcp, MappingState::Established) => self.tcp_established,
+            (Protocol::Tcp, MappingState::Transient) => self.tcp_transient,
+            (Protocol::Udp, _) => self.udp,
+            (Protocol::Icmp, _) => self.icmp,
+            // nothing to tell tunneled flows apart by, so they are kept like a datagram
+            (Protocol::Gre | Protocol::Ipv6, _) => self.udp,
+        }
+    }
+}
+
+impl<A: NatAddress> NatEntry<A> {
+    pub fn internal(&self) -> SocketAddr {
+        SocketAddr::new(self.source_ip.into(), self.source_port)
+    }
+    pub fn remaining_lifetime(&self, now: Instant) -> Duration {
+        self.lifetime.saturating_sub(now.saturating_duration_since(self.mapped_on_time))
+    }
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.remaining_lifetime(now).is_zero()
+    }
+}
+
+// shorthand for the mapping and filtering behaviors below
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NatType {
+    // one mapping per internal (ip, port), no matter where the packet goes, and anyone can
+    // answer through it (full cone)
+    #[default]
+    Cone,
+    // a new mapping for every different destination (ip, port), and only that one can answer
+    Symmetric,
+}
+
+// RFC 4787: mapping (can a new remote reuse a mapping) and filtering (who may answer through it)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EndpointDependence {
+    // the remote doesn't matter
+    #[default]
+    EndpointIndependent,
+    // the remote address has to be one the mapping already sent to, any port
+    AddressDependent,
+    // the remote (address, port) has to be one the mapping already sent to
+    AddressAndPortDependent,
+}
+
+impl EndpointDependence {
+    pub fn covers<A: NatAddress>(self, remotes: &[(A, u16)], remote: (A, u16)) -> bool {
+        match self {
+            EndpointDependence::EndpointIndependent => true,
+            EndpointDependence::AddressDependent => remotes.iter().any(|&(ip, _)| ip == remote.0),
+            EndpointDependence::AddressAndPortDependent => remotes.contains(&remote),
+        }
+    }
+}
+
+// Why the NAT could not translate something
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatError {
+    // every port is taken, even after pruning the expired ones
+    PortExhausted,
+    // nothing is mapped on that port (or not for whoever sent the packet)
+    NoMapping,
+    // the mapping on that port is for another protocol
+    ProtocolMismatch,
+    // this computer already has as many mappings as it is allowed
+    QuotaExceeded,
+    // another computer already has a mapping for the same internal (ip, port)
+    MappingConflict,
+    // the external port asked for is already mapped to something else
+    PortInUse,
+    // this computer is opening new mappings faster than it is allowed to
+    RateLimited,
+    // the table has as many mappings as its limit allows, and its policy is to refuse more
+    TableFull,
+}
+
+impl Display for NatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NatError::PortExhausted => write!(f, "no free port left on the NAT"),
+            NatError::NoMapping => write!(f, "no mapping for this packet"),
+            NatError::ProtocolMismatch => write!(f, "the mapping is for a different protocol"),
+            NatError::QuotaExceeded => write!(f, "the computer has used up its mapping quota"),
+            NatError::MappingConflict => write!(f, "another computer already uses this internal address and port"),
+            NatError::PortInUse => write!(f, "the requested external port is already in use"),
+            NatError::RateLimited => write!(f, "the computer is creating mappings too fast"),
+            NatError::TableFull => write!(f, "the NAT table is full"),
+        }
+    }
+}
+
+impl std::error::Error for NatError {}
+
+// What to do when a computer asks for a mapping of an internal (ip, port) that
+// another computer already has. Two computers with the same address is usually a misconfiguration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    // refuse the new mapping
+    #[default]
+    Reject,
+    // the new computer wins, the old mappings are thrown away
+    Replace,
+    // keep both, each with its own mangled port
+    AllowParallel,
+}
+
+// What to do when every port is taken, even after pruning the expired mappings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    // the new mapping fails with PortExhausted
+    #[default]
+    Fail,
+    // the mapping unused for the longest gives its port up; explicit ones are never evicted
+    LeastRecentlyUsed,
 }
 
 #[derive(Debug)]
-pub struct NatTable {
+pub struct NatTable<A = Ipv4Addr> {
     pub name : String,
-    pub translated_addr : Ipv4Addr,
-    pub table : Vec<NatEntry>,
+    pub translated_addr : A,
+    pub table : Vec<NatEntry<A>>,
+    pub mapping : EndpointDependence,
+    pub filtering : EndpointDependence,
+    // how many mappings a single computer may hold at once, None for no limit
+    pub max_mappings_per_computer : Option<usize>,
+    pub timeouts : NatTimeouts,
+    pub conflict_policy : ConflictPolicy,
+    // the ports handed out for dynamic mappings; a smaller pool runs out sooner
+    pub port_range : RangeInclusive<u16>,
+    pub eviction_policy : EvictionPolicy,
+    // mappings thrown out to make room for new ones
+    pub forced_evictions : u64,
+    // (new mappings per second, burst) allowed for each computer, None for no limit
+    pub mapping_rate_limit : Option<(f64, f64)>,
+    // one bucket per computer that created a mapping
+    pub rate_limiters : Vec<(u16, TokenBucket)>,
+    // at most this many mappings, like the routing table and the neighbor caches (see table_limits);
+    // evicting picks the least recently used one that wasn't asked for explicitly
+    pub limit : Option<TableLimit>,
+    // every mapping refused or evicted, the ones EvictionPolicy throws out included
+    pub events : Vec<TableEvent>,
+    // connections sent somewhere else, see redirect_at
+    pub redirections : Vec<Redirection<A>>,
 }
 
-impl NatTable {
+pub type NatTableV4 = NatTable<Ipv4Addr>;
+pub type NatTableV6 = NatTable<Ipv6Addr>;
+
+impl<A: NatAddress> NatTable<A> {
+    pub fn new(name: &str, translated_addr: A) -> Self {
+        NatTable {
+            name : name.to_string(),
+            translated_addr,
+            table : vec![],
+            mapping : EndpointDependence::EndpointIndependent,
+            filtering : EndpointDependence::EndpointIndependent,
+            max_mappings_per_computer : None,
+            timeouts : NatTimeouts::default(),
+            conflict_policy : ConflictPolicy::default(),
+            port_range : 0..=u16::MAX - 1,
+            eviction_policy : EvictionPolicy::Fail,
+            forced_evictions : 0,
+            mapping_rate_limit : None,
+            rate_limiters : vec![],
+            limit : None,
+            events : vec![],
+            redirections : vec![],
+        }
+    }
+    pub fn with_nat_type(self, nat_type: NatType) -> Self {
+        let behavior = match nat_type {
+            NatType::Cone => EndpointDependence::EndpointIndependent,
+            NatType::Symmetric => EndpointDependence::AddressAndPortDependent,
+        };
+        self.with_mapping(behavior).with_filtering(behavior)
+    }
+    pub fn with_mapping(mut self, mapping: EndpointDependence) -> Self {
+        self.mapping = mapping;
+        self
+    }
+    pub fn with_filtering(mut self, filtering: EndpointDependence) -> Self {
+        self.filtering = filtering;
+        self
+    }
+    pub fn with_timeouts(mut self, timeouts: NatTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+    pub fn with_quota(mut self, max_mappings_per_computer: usize) -> Self {
+        self.max_mappings_per_computer = Some(max_mappings_per_computer);
+        self
+    }
+    pub fn with_port_range(mut self, port_range: RangeInclusive<u16>) -> Self {
+        self.port_range = port_range;
+        self
+    }
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+    pub fn with_limit(mut self, limit: TableLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    pub fn with_mapping_rate_limit(mut self, per_second: f64, burst: f64) -> Self {
+        self.mapping_rate_limit = Some((per_second, burst));
+        self
+    }
     pub fn has_available_port(&self, port: u16) -> bool {
-        self.table
+        !self.table
             .iter()
-            .find(|entry| entry.mangled_port == port)
-            .is_none()
+            .any(|entry| entry.mangled_port == port)
     }
     pub fn extract_available_port(&self) -> Option<u16> {
-        (0..u16::MAX)
-            .filter(|&port| self.has_available_port(port))
-            .next()
+        self.port_range
+            .clone()
+            .find(|&port| self.has_available_port(port))
+    }
+    pub fn give_me_a_port(&mut self, my_ip : A, my_port: u16, me: u16, duration: Duration) -> Result<(A, u16), NatError> {
+        let now = Instant::now();
+        let entry = NatEntry {
+            source_ip : my_ip,
+            source_port : my_port,
+            protocol : Protocol::default(),
+            mangled_port : 0,
+            computer : me,
+            mapped_on_time : now,
+            lifetime : duration,
+            remotes : vec![],
+            state : MappingState::Transient,
+            explicit : false,
+        };
+        self.insert_mapping(entry, None, now)
     }
-    pub fn give_me_a_port(&mut self, my_ip : Ipv4Addr, my_port: u16, me: u16, duration: Duration) -> Option<(Ipv4Addr, u16)> {
+
+    // finds a port for this new entry (its mangled_port gets filled in here) and stores it
+    // with a desired port, it is that port or nothing
+    fn insert_mapping(&mut self, mut entry: NatEntry<A>, desired_port: Option<u16>, now: Instant) -> Result<(A, u16), NatError> {
+        self.check_conflict(&entry)?;
+        if let Some(quota) = self.max_mappings_per_computer {
+            if self.table.iter().filter(|table| table.computer == entry.computer).count() >= quota {
+                return Err(NatError::QuotaExceeded);
+            }
+        }
+        if !self.may_create_mapping(entry.computer, now) {
+            return Err(NatError::RateLimited);
+        }
+        // a port someone else has is a refusal, so that is known before anything is evicted
+        if let Some(port) = desired_port {
+            if !self.has_available_port(port) {
+                self.prune_unnecessary_ports_at(now);
+            }
+            if !self.has_available_port(port) {
+                return Err(NatError::PortInUse);
+            }
+        }
+        if let Some(limit) = self.limit {
+            if self.table.len() >= limit.max_entries {
+                self.prune_unnecessary_ports_at(now);
+            }
+            let oldest = |table: &[NatEntry<A>]| table
+                .iter()
+                .enumerate()
+                .filter(|(_, table)| !table.explicit)
+                .min_by_key(|(_, table)| table.mapped_on_time)
+                .map(|(index, _)| index);
+            let before = self.table.len();
+            limit.make_room(&self.name, &mut self.table, oldest, &entry, &mut self.events).map_err(|_| NatError::TableFull)?;
+            self.forced_evictions += (before - self.table.len()) as u64;
+        }
         // I am a table that will give this my computer a port
         let available_port = 
-        if let Some(port) = self.extract_available_port(){
+        if let Some(port) = desired_port {
+            port
+        } else if let Some(port) = self.extract_available_port(){
             // println!("I have available port as {port}");
             // If I have an available port, I give that
             port
         } else {
             // If I don't have then I will prune unnecessary ports
-            self.prune_unnecessary_ports();
+            self.prune_unnecessary_ports_at(now);
             // Then again, when I try to assign a port
-            // If it fails still, the none is propagated outwards
-            self.extract_available_port()?
+            // If it fails still, I may throw out the oldest one, or the error is propagated outwards
+            match self.extract_available_port() {
+                Some(port) => port,
+                None => self.evict_least_recently_used().ok_or(NatError::PortExhausted)?,
+            }
         };
 
-        let entry = NatEntry {
-            source_ip : my_ip,
-            source_port : my_port,
-            mangled_port : available_port,
-            computer : me,
-            mapped_on_time : Instant::now(),
-            time_to_live : duration,
+        // nothing can go wrong anymore, so the token is spent and the other computer's mappings can go
+        self.take_mapping_token(entry.computer, now);
+        self.replace_conflicting(&entry);
+        entry.mangled_port = available_port;
+        self.table.push(entry);
+        Ok((self.translated_addr, available_port))
+    }
+
+    // is there a token left in this computer's bucket? so a host scanning ports can't eat the whole table
+    fn may_create_mapping(&mut self, computer: u16, now: Instant) -> bool {
+        let Some((_, burst)) = self.mapping_rate_limit else {
+            return true;
         };
+        match self.rate_limiters.iter_mut().find(|(limited, _)| *limited == computer) {
+            Some((_, bucket)) => bucket.tokens(now) >= 1.0,
+            None => burst >= 1.0,
+        }
+    }
 
-        self.table.push(entry);
-        Some((self.translated_addr, available_port))
+    // takes the token, once the mapping is stored. A bucket that has filled up again is as good
+    // as a new one, so those are forgotten, and only the computers that were busy lately are kept
+    fn take_mapping_token(&mut self, computer: u16, now: Instant) {
+        let Some((per_second, burst)) = self.mapping_rate_limit else {
+            return;
+        };
+        self.rate_limiters.retain_mut(|(_, bucket)| bucket.tokens(now) < bucket.burst);
+        let index = match self.rate_limiters.iter().position(|(limited, _)| *limited == computer) {
+            Some(index) => index,
+            None => {
+                self.rate_limiters.push((computer, TokenBucket::new(per_second, burst)));
+                self.rate_limiters.len() - 1
+            }
+        };
+        self.rate_limiters[index].1.try_take(now);
+    }
+
+    // removes the mapping that was used the longest time ago, giving back its port
+    fn evict_least_recently_used(&mut self) -> Option<u16> {
+        if self.eviction_policy != EvictionPolicy::LeastRecentlyUsed {
+            return None;
+        }
+        let (index, _) = self.table
+            .iter()
+            .enumerate()
+            .filter(|(_, table)| !table.explicit)
+            .min_by_key(|(_, table)| table.mapped_on_time)?;
+        self.forced_evictions += 1;
+        let evicted = self.table.remove(index);
+        self.events.push(TableEvent::Evicted { table : self.name.clone(), entry : format!("{evicted:?}") });
+        Some(evicted.mangled_port)
+    }
+
+    // is another computer already mapped with the same internal (ip, port)?
+    fn conflicts(entry: &NatEntry<A>, table: &NatEntry<A>) -> bool {
+        table.source_ip == entry.source_ip && table.source_port == entry.source_port
+            && table.protocol == entry.protocol && table.computer != entry.computer
+    }
+
+    fn check_conflict(&self, entry: &NatEntry<A>) -> Result<(), NatError> {
+        if self.conflict_policy == ConflictPolicy::Reject && self.table.iter().any(|table| Self::conflicts(entry, table)) {
+            return Err(NatError::MappingConflict);
+        }
+        Ok(())
+    }
+
+    // only once the new mapping is sure to be stored, or a refused one would still cost the
+    // other computer its mappings
+    fn replace_conflicting(&mut self, entry: &NatEntry<A>) {
+        if self.conflict_policy == ConflictPolicy::Replace {
+            self.table.retain(|table| !Self::conflicts(entry, table));
+        }
+    }
+
+    // gives the events so far to whoever is watching, and forgets them
+    pub fn take_events(&mut self) -> Vec<TableEvent> {
+        std::mem::take(&mut self.events)
     }
 
     pub fn prune_unnecessary_ports(&mut self) {
-        let new_now = Instant::now();
+        self.prune_unnecessary_ports_at(Instant::now());
+    }
+
+    // the same, but with the time coming from some clock (see crate::clock)
+    pub fn prune_unnecessary_ports_at(&mut self, new_now: Instant) {
         self.table
-            .retain(|table| new_now.duration_since(table.mapped_on_time) < table.time_to_live );
+            .retain(|table| !table.is_expired(new_now));
+    }
+
+    // the same, but giving back the mappings that were removed
+    pub fn expire_at(&mut self, now: Instant) -> Vec<NatEntry<A>> {
+        let (expired, alive) = std::mem::take(&mut self.table)
+            .into_iter()
+            .partition(|table| table.is_expired(now));
+        self.table = alive;
+        expired
     }
 
-    pub fn found_on_nat(&self, ip_addr: Ipv4Addr, port: u16) -> Option<&NatEntry> {
+    // The table as aligned text, a bit like `conntrack -L`
+    pub fn render_table(&self, now: Instant) -> String {
+        let header = ["proto", "internal", "mangled", "computer", "expires", "state"];
+        let rows: Vec<[String; 6]> = self.table
+            .iter()
+            .map(|entry| [
+                entry.protocol.to_string(),
+                entry.internal().to_string(),
+                entry.mangled_port.to_string(),
+                entry.computer.to_string(),
+                format!("{}s", entry.remaining_lifetime(now).as_secs()),
+                match (entry.is_expired(now), entry.state) {
+                    (true, _) => "EXPIRED",
+                    (false, MappingState::Transient) => "TRANSIENT",
+                    (false, MappingState::Established) => "ESTABLISHED",
+                }.to_string(),
+            ])
+            .collect();
+
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let mut out = format!("{} ({:?}), {} entries\n", self.name, self.translated_addr, self.table.len());
+        let header = header.map(str::to_string);
+        for row in std::iter::once(&header).chain(&rows) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect();
+            out.push_str(line.join("  ").trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn found_on_nat(&self, ip_addr: A, port: u16) -> Option<&NatEntry<A>> {
         self.table
             .iter()
             .find(|table| table.source_ip == ip_addr && table.source_port == port)
     }
 
-    pub fn translate_incoming(&self, mut packet: RandomTransportPacket) -> Option<(RandomTransportPacket, u16)> {
-        let nat_entry = 
+    // the mapping this packet from this computer would use: like found_on_nat, but the protocol
+    // and computer have to match, and depending on the mapping behavior the destination too
+    pub fn found_on_nat_towards<P: Packet<Address = A>>(&self, packet: &P, computer: u16) -> Option<&NatEntry<A>> {
+        self.position_towards(packet, computer, Instant::now())
+            .map(|index| &self.table[index])
+    }
+
+    // an expired mapping is as good as gone, even before it is pruned; using it would bring it back
+    fn position_towards<P: Packet<Address = A>>(&self, packet: &P, computer: u16, now: Instant) -> Option<usize> {
+        let destination = (packet.destination_ip(), packet.destination_port());
         self.table
             .iter()
-            .find(|table| table.mangled_port == packet.destination_port)?;
-        packet.destination_ip = nat_entry.source_ip;
-        packet.destination_port = nat_entry.source_port;
-        Some((packet, nat_entry.computer))
+            .position(|table| table.source_ip == packet.source_ip() && table.source_port == packet.source_port()
+                && table.protocol == packet.protocol() && table.computer == computer
+                && self.mapping.covers(&table.remotes, destination) && !table.is_expired(now))
+    }
+
+    // the mapping was just used: it starts its timeout again, with the timeout of its current state
+    fn refresh(&mut self, index: usize, state: MappingState, now: Instant) {
+        let timeouts = self.timeouts;
+        let entry = &mut self.table[index];
+        entry.state = entry.state.max(state);
+        // an explicit mapping lives for the lifetime it was given, traffic doesn't change that
+        if !entry.explicit {
+            entry.mapped_on_time = now;
+            entry.lifetime = timeouts.for_mapping(entry.protocol, entry.state);
+        }
+    }
+
+    // Like PCP's MAP (or UPnP port forwarding): a mapping made before any traffic, so a server
+    // inside can be reached. Asking again renews it, a zero lifetime deletes it, a taken port is
+    // refused, and a desired port of 0 means any.
+    pub fn request_mapping(&mut self, computer: u16, protocol: Protocol, internal: (A, u16), desired_external_port: u16, lifetime: Duration, now: Instant) -> Result<(A, u16), NatError> {
+        let existing = self.table
+            .iter()
+            .position(|table| table.explicit && table.computer == computer && table.protocol == protocol
+                && (table.source_ip, table.source_port) == internal);
+        if let Some(index) = existing {
+            let port = self.table[index].mangled_port;
+            if lifetime.is_zero() {
+                self.table.remove(index);
+            } else {
+                self.table[index].mapped_on_time = now;
+                self.table[index].lifetime = lifetime;
+            }
+            return Ok((self.translated_addr, port));
+        }
+        if lifetime.is_zero() {
+            return Err(NatError::NoMapping);
+        }
+        let entry = NatEntry {
+            source_ip : internal.0,
+            source_port : internal.1,
+            protocol,
+            mangled_port : 0,
+            computer,
+            mapped_on_time : now,
+            lifetime,
+            remotes : vec![],
+            state : MappingState::Transient,
+            explicit : true,
+        };
+        let desired_port = (desired_external_port != 0).then_some(desired_external_port);
+        self.insert_mapping(entry, desired_port, now)
+    }
+
+    pub fn translate_incoming<P: Packet<Address = A>>(&mut self, packet: P) -> Result<(P, u16), NatError> {
+        self.translate_incoming_at(packet, Instant::now())
+    }
+
+    pub fn translate_incoming_at<P: Packet<Address = A>>(&mut self, mut packet: P, now: Instant) -> Result<(P, u16), NatError> {
+        // Depending on the filtering, only the remotes the mapping sent to can answer.
+        // An explicit mapping is there to be reached from outside, so anyone can.
+        let from = (packet.source_ip(), packet.source_port());
+        let index = 
+        self.table
+            .iter()
+            .position(|table| table.mangled_port == packet.destination_port()
+                && (table.explicit || self.filtering.covers(&table.remotes, from)) && !table.is_expired(now))
+            .ok_or(NatError::NoMapping)?;
+        if self.table[index].protocol != packet.protocol() {
+            return Err(NatError::ProtocolMismatch);
+        }
+        // someone answered, so the connection is established now
+        self.refresh(index, MappingState::Established, now);
+        let nat_entry = &self.table[index];
+        let public_port = packet.destination_port();
+        packet.set_destination(nat_entry.source_ip, nat_entry.source_port);
+        let computer = nat_entry.computer;
+        self.debug_check((self.translated_addr, public_port), (packet.destination_ip(), packet.destination_port()), computer, now);
+        Ok((packet, computer))
+    }
+
+    pub fn translate_outgoing<P: Packet<Address = A>>(&mut self, packet: P, computer: u16) -> Result<P, NatError> {
+        self.translate_outgoing_at(packet, computer, Instant::now())
+    }
+
+    pub fn translate_outgoing_at<P: Packet<Address = A>>(&mut self, mut packet: P, computer: u16, now: Instant) -> Result<P, NatError> {
+        let destination = (packet.destination_ip(), packet.destination_port());
+        let (ip, port) =
+        if let Some(index) = self.position_towards(&packet, computer, now) {
+            // Already mapped, so the same public port is used again
+            self.refresh(index, MappingState::Transient, now);
+            let entry = &mut self.table[index];
+            if !entry.remotes.contains(&destination) {
+                entry.remotes.push(destination);
+            }
+            (self.translated_addr, entry.mangled_port)
+        } else {
+            let entry = NatEntry {
+                source_ip : packet.source_ip(),
+                source_port : packet.source_port(),
+                protocol : packet.protocol(),
+                mangled_port : 0,
+                computer,
+                mapped_on_time : now,
+                lifetime : self.timeouts.for_mapping(packet.protocol(), MappingState::Transient),
+                remotes : vec![destination],
+                state : MappingState::Transient,
+                explicit : false,
+            };
+            self.insert_mapping(entry, None, now)?
+        };
+        let internal = (packet.source_ip(), packet.source_port());
+        packet.set_source(ip, port);
+        self.debug_check((ip, port), internal, computer, now);
+        Ok(packet)
+    }
+
+    // Destination NAT, like `iptables -t nat -j DNAT`: the packet goes to `to` instead, and the
+    // table remembers where it was going so the answers can be put back (see unredirect).
+    // The source is left alone.
+    pub fn redirect_at<P: Packet<Address = A>>(&mut self, mut packet: P, to: (A, u16), now: Instant) -> P {
+        self.redirections.retain(|redirection| now.saturating_duration_since(redirection.last_used) < redirection.lifetime);
+        let client = (packet.source_ip(), packet.source_port());
+        let protocol = packet.protocol();
+        self.redirections.retain(|redirection| (redirection.protocol, redirection.client) != (protocol, client));
+        self.redirections.push(Redirection {
+            protocol,
+            client,
+            wanted : (packet.destination_ip(), packet.destination_port()),
+            sent_to : to,
+            last_used : now,
+            lifetime : self.timeouts.for_mapping(protocol, MappingState::Transient),
+        });
+        packet.set_destination(to.0, to.1);
+        packet
+    }
+
+    // an answer to a redirected connection gets the source it was expecting
+    pub fn unredirect_at<P: Packet<Address = A>>(&mut self, mut packet: P, now: Instant) -> Result<P, NatError> {
+        let (from, to) = ((packet.source_ip(), packet.source_port()), (packet.destination_ip(), packet.destination_port()));
+        let redirection = self.redirections
+            .iter_mut()
+            .find(|redirection| redirection.protocol == packet.protocol() && redirection.sent_to == from && redirection.client == to
+                && now.saturating_duration_since(redirection.last_used) < redirection.lifetime)
+            .ok_or(NatError::NoMapping)?;
+        redirection.last_used = now;
+        packet.set_source(redirection.wanted.0, redirection.wanted.1);
+        Ok(packet)
+    }
+
+    // no two live mappings share a public port
+    pub fn check_invariants(&self, now: Instant) -> Result<(), String> {
+        let mut ports: Vec<u16> = self.table
+            .iter()
+            .filter(|table| !table.is_expired(now))
+            .map(|table| table.mangled_port)
+            .collect();
+        ports.sort_unstable();
+        match ports.windows(2).find(|pair| pair[0] == pair[1]) {
+            Some(pair) => Err(format!("port {} is used by more than one live mapping", pair[0])),
+            None => Ok(()),
+        }
+    }
+
+    // What has to hold for a single translation: the public side is our address, and there is
+    // a mapping joining it to the internal side, for that computer.
+    fn check_translation(&self, public: (A, u16), internal: (A, u16), computer: u16) -> Result<(), String> {
+        if public.0 != self.translated_addr {
+            return Err(format!("translated to {:?}, but the NAT's address is {:?}", public.0, self.translated_addr));
+        }
+        let mapped = self.table
+            .iter()
+            .any(|table| table.mangled_port == public.1 && (table.source_ip, table.source_port) == internal && table.computer == computer);
+        if !mapped {
+            return Err(format!("no mapping joins port {} to {:?}:{} of computer {computer}", public.1, internal.0, internal.1));
+        }
+        Ok(())
     }
 
-    pub fn translate_outgoing(&mut self, mut packet: RandomTransportPacket, computer: u16) -> Option<RandomTransportPacket> {
-        if let Some(nat_entry) = self.found_on_nat(packet.source_ip, packet.source_port) {
-            packet.source_ip = self.translated_addr;
-            packet.source_port = nat_entry.mangled_port;
+    // in debug builds, a translation that breaks something panics right there, with the whole table
+    fn debug_check(&self, public: (A, u16), internal: (A, u16), computer: u16, now: Instant) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        if let Err(problem) = self.check_invariants(now).and_then(|()| self.check_translation(public, internal, computer)) {
+            panic!("NAT invariant broken: {problem}\n{}", self.render_table(now));
         }
-        let (ip, port) = self.give_me_a_port(packet.source_ip, packet.source_port, computer , packet.time_to_live)?;
-        packet.source_ip = ip;
-        packet.source_port = port;
-        Some(packet)
+    }
+}
+
+impl<A: NatAddress> Display for NatTable<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render_table(Instant::now()))
     }
 }
 
 
 pub fn test_translation_outgoing() {
-    let my_packet = RandomTransportPacket {
-        time_to_live: Duration::from_secs(20),
-        source_ip : "10.100.1.1".parse().unwrap(),
-        destination_ip : "192.168.1.1".parse().unwrap(),
-        source_port : 8090,
-        destination_port : 80,
-
-        data : "K xa bro, haal khabar?".to_string(),
-    };
+    let my_packet = PacketBuilder::ipv4()
+        .src("10.100.1.1".parse().unwrap())
+        .dst("192.168.1.1".parse().unwrap())
+        .hop_limit(20)
+        .udp()
+        .sport(8090)
+        .dport(80)
+        .payload(b"K xa bro, haal khabar?")
+        .build();
 
-    let mut my_nattable = NatTable {
-        name : "Krischal's NAT".to_string(),
-        translated_addr : "103.5.150.9".parse().unwrap(),
-        table : vec![]
-    };
+    let mut my_nattable = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
 
     println!("\nTesting outgoing NAT\n");
     let new_packet = my_nattable.translate_outgoing(my_packet.clone(), 12);
@@ -145,15 +856,15 @@ pub fn test_translation_outgoing() {
 }
 
 pub fn test_translation_incoming() {
-    let my_packet = RandomTransportPacket {
-        time_to_live: Duration::from_secs(20),
-        source_ip : "10.100.1.1".parse().unwrap(),
-        destination_ip : "192.168.1.1".parse().unwrap(),
-        source_port : 8090,
-        destination_port : 120,
-
-        data : "K xa bro, haal khabar?".to_string(),
-    };
+    let my_packet = PacketBuilder::ipv4()
+        .src("10.100.1.1".parse().unwrap())
+        .dst("192.168.1.1".parse().unwrap())
+        .hop_limit(20)
+        .udp()
+        .sport(8090)
+        .dport(120)
+        .payload(b"K xa bro, haal khabar?")
+        .build();
 
     let mut my_nattable = NatTable {
         name : "Krischal's NAT".to_string(),
@@ -162,18 +873,35 @@ pub fn test_translation_incoming() {
             NatEntry {
                 source_ip : "103.5.150.9".parse().unwrap(),
                 source_port : 80,
+                protocol : Protocol::Udp,
                 computer : 12,
                 mangled_port : 120,
                 mapped_on_time : Instant::now(),
-                time_to_live : Duration::from_secs(30),
+                lifetime : Duration::from_secs(30),
+                remotes : vec![],
+                state : MappingState::Transient,
+                explicit : false,
             },
-        ]
+        ],
+        mapping : EndpointDependence::EndpointIndependent,
+        filtering : EndpointDependence::EndpointIndependent,
+        max_mappings_per_computer : None,
+        timeouts : NatTimeouts::default(),
+        conflict_policy : ConflictPolicy::Reject,
+        port_range : 0..=u16::MAX - 1,
+        eviction_policy : EvictionPolicy::Fail,
+        forced_evictions : 0,
+        mapping_rate_limit : None,
+        rate_limiters : vec![],
+        limit : None,
+        events : vec![],
+        redirections : vec![],
     };
 
     println!("\nTesting incoming NAT\n");
     let new_packet = my_nattable.translate_incoming(my_packet.clone());
     println!("Original packet was: \n {my_packet:#?}");
-    if let Some((packet, computer)) = new_packet {
+    if let Ok((packet, computer)) = new_packet {
         println!("New translated packet is: \n {packet:#?} ");
         println!("The packet will be translated to the computer {computer}");
     } else {
@@ -182,8 +910,376 @@ pub fn test_translation_incoming() {
 
 }
 
+// the answer to a packet, from where it went back to where it came from
+#[cfg(test)]
+fn reply_to<P: Packet + Clone>(packet: &P) -> P {
+    let mut reply = packet.clone();
+    reply.set_source(packet.destination_ip(), packet.destination_port());
+    reply.set_destination(packet.source_ip(), packet.source_port());
+    reply
+}
+
 #[test]
 fn translation_works() {
     test_translation_outgoing();
     test_translation_incoming();
-}
\ No newline at end of file
+}
+
+#[test]
+fn translation_errors() {
+    use crate::packet::ipv4::Ipv4Header;
+
+    // not text at all, the NAT shouldn't care
+    let packet = |source_port: u16| {
+        PacketBuilder::ipv4()
+            .src("10.100.1.1".parse().unwrap())
+            .dst("192.168.1.1".parse().unwrap())
+            .udp()
+            .sport(source_port)
+            .dport(80)
+            .payload([0x00, 0xff, 0xc3, 0x28, 0x80])
+            .build()
+    };
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap()).with_quota(1);
+
+    let outgoing = nat.translate_outgoing(packet(8090), 12).unwrap();
+    // the second socket of the same computer is over the quota
+    assert_eq!(nat.translate_outgoing(packet(8091), 12).unwrap_err(), NatError::QuotaExceeded);
+
+    let reply = |builder: PacketBuilder<Ipv4Header>, destination_port: u16| {
+        builder.src(outgoing.destination_ip()).dst(outgoing.source_ip()).sport(outgoing.destination_port()).dport(destination_port).payload(outgoing.payload.clone()).build()
+    };
+    let tcp_reply = reply(PacketBuilder::ipv4().tcp(), outgoing.source_port());
+    assert_eq!(nat.translate_incoming(tcp_reply).unwrap_err(), NatError::ProtocolMismatch);
+    let elsewhere = reply(PacketBuilder::ipv4().udp(), outgoing.source_port() + 1);
+    assert_eq!(nat.translate_incoming(elsewhere).unwrap_err(), NatError::NoMapping);
+    let (back, _) = nat.translate_incoming(reply(PacketBuilder::ipv4().udp(), outgoing.source_port())).unwrap();
+    assert_eq!(back.payload, packet(8090).payload);
+}
+#[test]
+fn translation_works_for_ipv6() {
+    let packet = PacketBuilder::ipv6()
+        .src("fd00::1".parse().unwrap())
+        .dst("2001:db8::80".parse().unwrap())
+        .hop_limit(20)
+        .udp()
+        .sport(8090)
+        .dport(80)
+        .payload(b"K xa bro, haal khabar?")
+        .build();
+    let mut nat = NatTableV6::new("Krischal's NAT66", "2400:1a00::9".parse().unwrap());
+
+    let outgoing = nat.translate_outgoing(packet.clone(), 12).unwrap();
+    assert_eq!(outgoing.source_ip(), nat.translated_addr);
+
+    let (incoming, computer) = nat.translate_incoming(reply_to(&outgoing)).unwrap();
+    assert_eq!((incoming.destination_ip(), incoming.destination_port()), (packet.source_ip(), packet.source_port()));
+    assert_eq!(computer, 12);
+}
+
+#[test]
+fn table_rendering() {
+    let now = Instant::now();
+    let entry = |ip: &str, port: u16, protocol: Protocol, mangled_port: u16, lifetime: u64| NatEntry {
+        source_ip : ip.parse::<Ipv4Addr>().unwrap(),
+        source_port : port,
+        protocol,
+        computer : 12,
+        mangled_port,
+        mapped_on_time : now,
+        lifetime : Duration::from_secs(lifetime),
+        remotes : vec![],
+        state : MappingState::Established,
+        explicit : false,
+    };
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    nat.table.push(entry("10.100.1.1", 8090, Protocol::Udp, 0, 30));
+    nat.table.push(entry("10.100.1.25", 443, Protocol::Tcp, 1, 10));
+
+    let text = nat.render_table(now + Duration::from_secs(12));
+    println!("{text}");
+    assert_eq!(text, "\
+Krischal's NAT (103.5.150.9), 2 entries
+proto  internal         mangled  computer  expires  state
+udp    10.100.1.1:8090  0        12        18s      ESTABLISHED
+tcp    10.100.1.25:443  1        12        0s       EXPIRED
+");
+}
+
+#[test]
+fn timeouts_come_from_nat_policy() {
+    use crate::packet::tcp::SYN;
+
+    let start = Instant::now();
+    // the IP TTL is 1, which has nothing to do with how long the mapping lives
+    let builder = PacketBuilder::ipv4().src("10.100.1.1".parse().unwrap()).dst("142.250.1.1".parse().unwrap()).hop_limit(1);
+    let packet = builder.clone().tcp().sport(40000).dport(443).flags(SYN).build();
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+
+    // the SYN makes a transient mapping
+    let outgoing = nat.translate_outgoing_at(packet.clone(), 12, start).unwrap();
+    assert_eq!(nat.table[0].state, MappingState::Transient);
+    assert_eq!(nat.table[0].remaining_lifetime(start), Duration::from_secs(60));
+
+    // the SYN+ACK comes back, and now it is established for days
+    let later = start + Duration::from_secs(10);
+    nat.translate_incoming_at(reply_to(&outgoing), later).unwrap();
+    assert_eq!(nat.table[0].state, MappingState::Established);
+    assert_eq!(nat.table[0].remaining_lifetime(later), NatTimeouts::default().tcp_established);
+
+    // more outgoing data doesn't make it transient again
+    nat.translate_outgoing_at(packet.clone(), 12, later).unwrap();
+    assert_eq!(nat.table[0].state, MappingState::Established);
+
+    // a SYN nobody answered in time: the late answer finds nothing, even before pruning,
+    // and the next SYN gets a new mapping instead of bringing the old one back
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    let outgoing = nat.translate_outgoing_at(packet.clone(), 12, start).unwrap();
+    let too_late = start + Duration::from_secs(61);
+    assert_eq!(nat.translate_incoming_at(reply_to(&outgoing), too_late).unwrap_err(), NatError::NoMapping);
+    let again = nat.translate_outgoing_at(packet.clone(), 12, too_late).unwrap();
+    assert_ne!(again.source_port(), outgoing.source_port());
+    assert!(nat.table[0].is_expired(too_late));
+
+    // UDP with shorter custom timeouts
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap())
+        .with_timeouts(NatTimeouts { udp : Duration::from_secs(5), ..NatTimeouts::default() });
+    nat.translate_outgoing_at(builder.udp().sport(40000).dport(443).build(), 12, start).unwrap();
+    nat.prune_unnecessary_ports_at(start + Duration::from_secs(6));
+    assert!(nat.table.is_empty());
+}
+
+#[test]
+fn mapping_conflict_policies() {
+    let ip: Ipv4Addr = "10.100.1.1".parse().unwrap();
+    let lifetime = Duration::from_secs(30);
+    let nat = |policy| NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap()).with_conflict_policy(policy);
+
+    // the same computer asking again is not a conflict
+    let mut rejecting = nat(ConflictPolicy::Reject);
+    rejecting.give_me_a_port(ip, 8090, 12, lifetime).unwrap();
+    assert!(rejecting.give_me_a_port(ip, 8090, 12, lifetime).is_ok());
+    assert_eq!(rejecting.give_me_a_port(ip, 8090, 13, lifetime), Err(NatError::MappingConflict));
+    assert!(rejecting.table.iter().all(|entry| entry.computer == 12));
+
+    let mut replacing = nat(ConflictPolicy::Replace);
+    replacing.give_me_a_port(ip, 8090, 12, lifetime).unwrap();
+    replacing.give_me_a_port(ip, 8090, 13, lifetime).unwrap();
+    assert_eq!(replacing.table.len(), 1);
+    assert_eq!(replacing.table[0].computer, 13);
+    // a refused request doesn't take anything away from the computer it would have replaced
+    let mut replacing = nat(ConflictPolicy::Replace).with_quota(1);
+    replacing.give_me_a_port(ip, 8090, 12, lifetime).unwrap();
+    replacing.give_me_a_port(ip, 9000, 13, lifetime).unwrap();
+    assert_eq!(replacing.give_me_a_port(ip, 8090, 13, lifetime), Err(NatError::QuotaExceeded));
+    assert!(replacing.table.iter().any(|entry| entry.computer == 12));
+
+    let mut parallel = nat(ConflictPolicy::AllowParallel);
+    let (_, first) = parallel.give_me_a_port(ip, 8090, 12, lifetime).unwrap();
+    let (_, second) = parallel.give_me_a_port(ip, 8090, 13, lifetime).unwrap();
+    assert_ne!(first, second);
+    assert_eq!(parallel.table.len(), 2);
+
+    // the same goes for outgoing packets: computer 13 doesn't get to use computer 12's mapping
+    let packet = PacketBuilder::ipv4().src(ip).dst("192.168.1.1".parse().unwrap()).udp().sport(8090).dport(80).payload(b"K xa bro, haal khabar?").build();
+    let mut rejecting = nat(ConflictPolicy::Reject);
+    rejecting.translate_outgoing(packet.clone(), 12).unwrap();
+    assert_eq!(rejecting.translate_outgoing(packet, 13).unwrap_err(), NatError::MappingConflict);
+}
+
+#[test]
+fn explicit_port_mapping_requests() {
+    use crate::table_limits::FullPolicy;
+
+    let start = Instant::now();
+    let server: Ipv4Addr = "10.100.1.5".parse().unwrap();
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+
+    // the game server asks for 25565 for 10 minutes
+    let lifetime = Duration::from_secs(600);
+    assert_eq!(nat.request_mapping(5, Protocol::Tcp, (server, 25565), 25565, lifetime, start), Ok((nat.translated_addr, 25565)));
+    // someone else wants the same external port
+    assert_eq!(nat.request_mapping(6, Protocol::Tcp, ("10.100.1.6".parse().unwrap(), 25565), 25565, lifetime, start), Err(NatError::PortInUse));
+    // and in a full table, that refusal doesn't cost anybody their mapping
+    let mut full = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap()).with_limit(TableLimit::new(2, FullPolicy::EvictOldest));
+    full.request_mapping(5, Protocol::Tcp, (server, 25565), 25565, lifetime, start).unwrap();
+    full.give_me_a_port("10.100.1.7".parse().unwrap(), 8090, 7, lifetime).unwrap();
+    assert_eq!(full.request_mapping(6, Protocol::Tcp, ("10.100.1.6".parse().unwrap(), 25565), 25565, lifetime, start), Err(NatError::PortInUse));
+    assert_eq!(full.table.len(), 2);
+
+    // anyone on the internet can reach it now, and that doesn't shorten the lifetime
+    let visitor = PacketBuilder::ipv4().src("27.34.1.7".parse().unwrap()).dst(nat.translated_addr).tcp().sport(51000).dport(25565).payload(b"can I join?").build();
+    let (packet, computer) = nat.translate_incoming_at(visitor, start + Duration::from_secs(1)).unwrap();
+    assert_eq!((packet.destination_ip(), packet.destination_port(), computer), (server, 25565, 5));
+    assert_eq!(nat.table[0].remaining_lifetime(start + Duration::from_secs(1)), Duration::from_secs(599));
+
+    // renewing before it runs out keeps it alive past the first lifetime
+    let renew_at = start + Duration::from_secs(500);
+    nat.request_mapping(5, Protocol::Tcp, (server, 25565), 25565, lifetime, renew_at).unwrap();
+    nat.prune_unnecessary_ports_at(start + Duration::from_secs(700));
+    assert_eq!(nat.table.len(), 1);
+
+    // and a zero lifetime deletes it
+    nat.request_mapping(5, Protocol::Tcp, (server, 25565), 25565, Duration::ZERO, renew_at).unwrap();
+    assert!(nat.table.is_empty());
+}
+
+#[test]
+fn mapping_and_filtering_behaviors() {
+    use crate::packet::layered::LayeredPacket;
+    use EndpointDependence::*;
+
+    let between = |source_ip: &str, source_port: u16, destination_ip: &str, destination_port: u16| {
+        PacketBuilder::ipv4()
+            .src(source_ip.parse().unwrap())
+            .dst(destination_ip.parse().unwrap())
+            .hop_limit(30)
+            .udp()
+            .sport(source_port)
+            .dport(destination_port)
+            .payload(b"K xa bro, haal khabar?")
+            .build()
+    };
+    let to = |destination_ip: &str, destination_port: u16| between("10.100.1.1", 8090, destination_ip, destination_port);
+    let from = |source_ip: &str, source_port: u16, destination_port: u16| between(source_ip, source_port, "103.5.150.9", destination_port);
+    let nat = |mapping, filtering| NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap())
+        .with_mapping(mapping)
+        .with_filtering(filtering);
+    let public_port = |nat: &mut NatTable, packet: LayeredPacket| nat.translate_outgoing(packet, 12).unwrap().source_port();
+
+    // mapping: which destinations share a public port
+    let mut independent = nat(EndpointIndependent, EndpointIndependent);
+    let mut by_address = nat(AddressDependent, EndpointIndependent);
+    let mut by_address_and_port = nat(AddressAndPortDependent, EndpointIndependent);
+    for nat in [&mut independent, &mut by_address, &mut by_address_and_port] {
+        public_port(nat, to("142.250.1.1", 443));
+        public_port(nat, to("142.250.1.1", 80));
+        public_port(nat, to("27.34.1.7", 443));
+    }
+    assert_eq!(independent.table.len(), 1);
+    assert_eq!(by_address.table.len(), 2);
+    assert_eq!(by_address_and_port.table.len(), 3);
+
+    // filtering: with one mapping for everything, who can answer through it
+    let answers = |filtering| {
+        let mut nat = nat(EndpointIndependent, filtering);
+        let port = public_port(&mut nat, to("142.250.1.1", 443));
+        [from("142.250.1.1", 443, port), from("142.250.1.1", 8443, port), from("27.34.1.7", 443, port)]
+            .map(|packet| nat.translate_incoming(packet).is_ok())
+    };
+    assert_eq!(answers(EndpointIndependent), [true, true, true]);
+    assert_eq!(answers(AddressDependent), [true, true, false]);
+    assert_eq!(answers(AddressAndPortDependent), [true, false, false]);
+
+    // the NAT types are just the two ends
+    let symmetric = NatTable::<Ipv4Addr>::new("Krischal's NAT", "103.5.150.9".parse().unwrap()).with_nat_type(NatType::Symmetric);
+    assert_eq!((symmetric.mapping, symmetric.filtering), (AddressAndPortDependent, AddressAndPortDependent));
+}
+
+#[test]
+fn least_recently_used_mapping_is_evicted_when_full() {
+    let start = Instant::now();
+    let packet_from = |source_port: u16| {
+        PacketBuilder::ipv4().src("10.100.1.1".parse().unwrap()).dst("192.168.1.1".parse().unwrap()).hop_limit(30).udp().sport(source_port).dport(80).payload(b"K xa bro, haal khabar?").build()
+    };
+    let at = |seconds| start + Duration::from_secs(seconds);
+    let nat = |policy| NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap())
+        .with_port_range(1000..=1002)
+        .with_eviction_policy(policy);
+
+    let mut failing = nat(EvictionPolicy::Fail);
+    let mut evicting = nat(EvictionPolicy::LeastRecentlyUsed);
+    for nat in [&mut failing, &mut evicting] {
+        // the static one is the oldest, but it stays
+        nat.request_mapping(5, Protocol::Udp, ("10.100.1.5".parse().unwrap(), 53), 1000, Duration::from_secs(600), at(0)).unwrap();
+        nat.translate_outgoing_at(packet_from(1), 12, at(1)).unwrap();
+        nat.translate_outgoing_at(packet_from(2), 12, at(2)).unwrap();
+        // 1 is used again, so 2 is now the least recently used
+        nat.translate_outgoing_at(packet_from(1), 12, at(3)).unwrap();
+    }
+
+    assert_eq!(failing.translate_outgoing_at(packet_from(3), 12, at(4)).unwrap_err(), NatError::PortExhausted);
+    assert_eq!(failing.forced_evictions, 0);
+
+    let translated = evicting.translate_outgoing_at(packet_from(3), 12, at(4)).unwrap();
+    assert_eq!(translated.source_port(), 1002);
+    assert_eq!(evicting.forced_evictions, 1);
+    assert!(evicting.table.iter().all(|table| table.source_port != 2));
+    assert_eq!(evicting.table.len(), 3);
+}
+
+#[test]
+fn port_scanning_host_is_rate_limited() {
+    let start = Instant::now();
+    let packet_to = |source_ip: &str, destination_port: u16| {
+        PacketBuilder::ipv4()
+            .src(source_ip.parse().unwrap())
+            .dst("192.168.1.1".parse().unwrap())
+            .hop_limit(30)
+            .tcp()
+            .sport(40000 + destination_port)
+            .dport(destination_port)
+            .flags(crate::packet::tcp::SYN)
+            .build()
+    };
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap())
+        .with_mapping_rate_limit(10.0, 20.0);
+
+    // the scanner tries 100 ports at once: only the burst gets through
+    let opened = (1..=100)
+        .filter(|&port| nat.translate_outgoing_at(packet_to("10.100.1.66", port), 66, start).is_ok())
+        .count();
+    assert_eq!(opened, 20);
+    assert_eq!(nat.translate_outgoing_at(packet_to("10.100.1.66", 101), 66, start).unwrap_err(), NatError::RateLimited);
+    // the existing mappings still work, they are not new
+    assert!(nat.translate_outgoing_at(packet_to("10.100.1.66", 1), 66, start).is_ok());
+    // another host is not affected
+    assert!(nat.translate_outgoing_at(packet_to("10.100.1.1", 443), 12, start).is_ok());
+    // a second later the scanner has 10 more
+    let later = start + Duration::from_secs(1);
+    let opened = (200..300)
+        .filter(|&port| nat.translate_outgoing_at(packet_to("10.100.1.66", port), 66, later).is_ok())
+        .count();
+    assert_eq!(opened, 10);
+
+    // a mapping that is refused anyway doesn't use up a token
+    let mut nat: NatTable = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap())
+        .with_mapping_rate_limit(1.0, 1.0);
+    let lifetime = Duration::from_secs(600);
+    nat.request_mapping(5, Protocol::Tcp, ("10.100.1.5".parse().unwrap(), 25565), 25565, lifetime, start).unwrap();
+    assert_eq!(nat.request_mapping(6, Protocol::Tcp, ("10.100.1.6".parse().unwrap(), 25565), 25565, lifetime, start), Err(NatError::PortInUse));
+    assert!(nat.request_mapping(6, Protocol::Tcp, ("10.100.1.6".parse().unwrap(), 25566), 25566, lifetime, start).is_ok());
+    // and once their buckets are full again, the quiet computers are forgotten
+    assert_eq!(nat.rate_limiters.len(), 2);
+    nat.request_mapping(7, Protocol::Tcp, ("10.100.1.7".parse().unwrap(), 25567), 25567, lifetime, start + Duration::from_secs(5)).unwrap();
+    assert_eq!(nat.rate_limiters.iter().map(|(computer, _)| *computer).collect::<Vec<_>>(), vec![7]);
+}
+
+// the check only runs in debug builds
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "port 7 is used by more than one live mapping")]
+fn broken_invariants_are_caught() {
+    let now = Instant::now();
+    let entry = |source_port: u16| NatEntry {
+        source_ip : "10.100.1.1".parse::<Ipv4Addr>().unwrap(),
+        source_port,
+        protocol : Protocol::Udp,
+        computer : 12,
+        mangled_port : 7,
+        mapped_on_time : now,
+        lifetime : Duration::from_secs(30),
+        remotes : vec![],
+        state : MappingState::Transient,
+        explicit : false,
+    };
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    nat.table.push(entry(8090));
+    assert_eq!(nat.check_invariants(now), Ok(()));
+    // a bug hands out the same port twice
+    nat.table.push(entry(8091));
+    assert!(nat.check_invariants(now).is_err());
+
+    let packet = PacketBuilder::ipv4().src("10.100.1.1".parse().unwrap()).dst("192.168.1.1".parse().unwrap()).udp().sport(8090).dport(80).build();
+    let _ = nat.translate_outgoing_at(packet, 12, now);
+}
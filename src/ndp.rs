@@ -0,0 +1,204 @@
+//! Neighbor Discovery (RFC 4861), what IPv6 has instead of ARP. It is made of ICMPv6 messages:
+//! a Neighbor Solicitation asks "who has 2001:db8::1?", a Neighbor Advertisement answers with
+//! the MAC address. There is no broadcast in IPv6, so the question goes to the target's
+//! solicited-node multicast group (ff02::1:ff plus its last 24 bits), which only the few hosts
+//! whose addresses end the same way listen to.
+//! The MAC addresses ride in options after the target address. Every NDP message is sent with
+//! a hop limit of 255, so a receiver can tell it wasn't forwarded from some other link.
+use std::net::Ipv6Addr;
+use std::time::Instant;
+
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_IPV6};
+use crate::neighbor::{NdCache, Resolution};
+use crate::packet::checksum::PseudoHeader;
+use crate::packet::ipv6::{Ipv6Header, HEADER_LEN};
+use crate::packet::{need, PacketError, IPPROTO_ICMPV6};
+
+pub const NEIGHBOR_SOLICITATION: u8 = 135;
+pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+// the hop limit of every NDP message
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+// the options carrying a MAC address
+const SOURCE_LINK_ADDRESS: u8 = 1;
+const TARGET_LINK_ADDRESS: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdpMessage {
+    // the MAC address of the one asking, so the answer can go straight back; none when it
+    // checks that nobody uses an address it wants (Duplicate Address Detection)
+    NeighborSolicitation { target : Ipv6Addr, source_mac : Option<MacAddr> },
+    // solicited when it answers a question; override to replace what the caches have
+    NeighborAdvertisement { target : Ipv6Addr, target_mac : Option<MacAddr>, router : bool, solicited : bool, override_entry : bool },
+}
+
+// the group a Neighbor Solicitation for this address goes to
+pub fn solicited_node(address: Ipv6Addr) -> Ipv6Addr {
+    let [.., a, b, c] = address.octets();
+    Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00 | a as u16, (b as u16) << 8 | c as u16)
+}
+
+// IPv6 multicast on Ethernet: 33:33 and the last 32 bits of the group
+pub fn multicast_mac(group: Ipv6Addr) -> MacAddr {
+    let [.., a, b, c, d] = group.octets();
+    MacAddr([0x33, 0x33, a, b, c, d])
+}
+
+impl NdpMessage {
+    pub fn target(&self) -> Ipv6Addr {
+        match self {
+            NdpMessage::NeighborSolicitation { target, .. } | NdpMessage::NeighborAdvertisement { target, .. } => *target,
+        }
+    }
+
+    // the ICMPv6 message, with a zero checksum like the others in packet::icmp; to_frame()
+    // fills it in, since it needs the addresses of the IPv6 header
+    pub fn to_icmpv6(&self) -> Vec<u8> {
+        let (kind, flags, option) = match *self {
+            NdpMessage::NeighborSolicitation { source_mac, .. } => (NEIGHBOR_SOLICITATION, 0, source_mac.map(|mac| (SOURCE_LINK_ADDRESS, mac))),
+            NdpMessage::NeighborAdvertisement { target_mac, router, solicited, override_entry, .. } => {
+                let flags = (router as u8) << 7 | (solicited as u8) << 6 | (override_entry as u8) << 5;
+                (NEIGHBOR_ADVERTISEMENT, flags, target_mac.map(|mac| (TARGET_LINK_ADDRESS, mac)))
+            }
+        };
+        let mut bytes = vec![kind, 0, 0, 0, flags, 0, 0, 0];
+        bytes.extend(self.target().octets());
+        if let Some((option, mac)) = option {
+            // the length is in units of 8 bytes
+            bytes.extend([option, 1]);
+            bytes.extend(mac.0);
+        }
+        bytes
+    }
+
+    pub fn from_icmpv6(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, 24)?;
+        let target = Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[8..24]).unwrap());
+        let mut link_addresses = [None; 3];
+        let mut options = &bytes[24..];
+        while !options.is_empty() {
+            need(options, 2)?;
+            let len = options[1] as usize * 8;
+            if len == 0 {
+                return Err(PacketError::BadLength(0));
+            }
+            need(options, len)?;
+            if matches!(options[0], SOURCE_LINK_ADDRESS | TARGET_LINK_ADDRESS) && len == 8 {
+                link_addresses[options[0] as usize] = Some(MacAddr(options[2..8].try_into().unwrap()));
+            }
+            options = &options[len..];
+        }
+        match bytes[0] {
+            NEIGHBOR_SOLICITATION => Ok(NdpMessage::NeighborSolicitation { target, source_mac : link_addresses[SOURCE_LINK_ADDRESS as usize] }),
+            NEIGHBOR_ADVERTISEMENT => Ok(NdpMessage::NeighborAdvertisement {
+                target,
+                target_mac : link_addresses[TARGET_LINK_ADDRESS as usize],
+                router : bytes[4] & 0x80 != 0,
+                solicited : bytes[4] & 0x40 != 0,
+                override_entry : bytes[4] & 0x20 != 0,
+            }),
+            other => Err(PacketError::UnknownType(other)),
+        }
+    }
+
+    // in an IPv6 packet in an Ethernet frame, from and to these (MAC, IP)
+    pub fn to_frame(&self, from: (MacAddr, Ipv6Addr), to: (MacAddr, Ipv6Addr)) -> EthernetFrame {
+        let mut message = self.to_icmpv6();
+        let _ = PseudoHeader::v6(from.1, to.1, IPPROTO_ICMPV6, message.len()).fill(&mut message);
+        let mut header = Ipv6Header::new(from.1, to.1, IPPROTO_ICMPV6, message.len() as u16);
+        header.hop_limit = NDP_HOP_LIMIT;
+        let mut payload = header.to_bytes();
+        payload.extend(message);
+        EthernetFrame::new(to.0, from.0, ETHERTYPE_IPV6, payload)
+    }
+
+    // the IPv6 header too, since a solicitation doesn't say who sent it otherwise
+    pub fn from_frame(frame: &EthernetFrame) -> Result<(Ipv6Header, Self), PacketError> {
+        let header = Ipv6Header::from_bytes(&frame.payload)?;
+        if header.next_header != IPPROTO_ICMPV6 {
+            return Err(PacketError::UnknownType(header.next_header));
+        }
+        let end = HEADER_LEN + header.payload_length as usize;
+        need(&frame.payload, end)?;
+        let message = &frame.payload[HEADER_LEN..end];
+        PseudoHeader::v6(header.source, header.destination, IPPROTO_ICMPV6, message.len()).verify(message)?;
+        Ok((header, NdpMessage::from_icmpv6(message)?))
+    }
+}
+
+impl NdCache {
+    // like ArpCache::resolve: the MAC from the cache, or a solicitation to send for it
+    pub fn resolve(&mut self, ip: Ipv6Addr, now: Instant, own: (MacAddr, Ipv6Addr)) -> Resolution {
+        self.expire(now);
+        match self.lookup(ip) {
+            Some(mac) => Resolution::Known(mac),
+            None => {
+                let group = solicited_node(ip);
+                let solicitation = NdpMessage::NeighborSolicitation { target : ip, source_mac : Some(own.0) };
+                Resolution::Ask(solicitation.to_frame(own, (multicast_mac(group), group)))
+            }
+        }
+    }
+
+    // Learns what the message tells about its sender, and gives back the advertisement to send
+    // back to it if it asked for one of our addresses.
+    pub fn hear(&mut self, source_ip: Ipv6Addr, message: &NdpMessage, now: Instant, own: (MacAddr, Ipv6Addr)) -> Option<NdpMessage> {
+        // a cache that is full just doesn't remember this one
+        match *message {
+            NdpMessage::NeighborSolicitation { target, source_mac } => {
+                if let Some(mac) = source_mac {
+                    let _ = self.learn(source_ip, mac, now);
+                }
+                (target == own.1).then_some(NdpMessage::NeighborAdvertisement {
+                    target,
+                    target_mac : Some(own.0),
+                    router : false,
+                    solicited : true,
+                    override_entry : true,
+                })
+            }
+            NdpMessage::NeighborAdvertisement { target, target_mac : Some(mac), solicited, override_entry, .. } => {
+                let known = self.lookup(target);
+                if solicited && known.is_none() || override_entry || known == Some(mac) {
+                    let _ = self.learn(target, mac, now);
+                }
+                None
+            }
+            NdpMessage::NeighborAdvertisement { target_mac : None, .. } => None,
+        }
+    }
+}
+
+#[test]
+fn neighbors_found_through_their_solicited_node_group() {
+    use std::time::Duration;
+
+    let host = ("02:00:00:00:00:0a".parse().unwrap(), "2001:db8:1::a".parse::<Ipv6Addr>().unwrap());
+    let gateway = ("02:00:00:00:00:01".parse().unwrap(), "2001:db8:1::1".parse::<Ipv6Addr>().unwrap());
+    let mut host_cache = NdCache::new("host eth0").with_max_age(Duration::from_secs(30));
+    let mut gateway_cache = NdCache::new("gateway lan");
+    let now = Instant::now();
+
+    let Resolution::Ask(frame) = host_cache.resolve(gateway.1, now, host) else {
+        panic!("the cache should be empty");
+    };
+    assert_eq!(frame.destination.to_string(), "33:33:ff:00:00:01");
+    let (header, solicitation) = NdpMessage::from_frame(&EthernetFrame::from_bytes(&frame.to_bytes()).unwrap()).unwrap();
+    assert_eq!((header.destination, header.hop_limit), ("ff02::1:ff00:1".parse().unwrap(), NDP_HOP_LIMIT));
+    assert_eq!(solicitation, NdpMessage::NeighborSolicitation { target : gateway.1, source_mac : Some(host.0) });
+
+    let advertisement = gateway_cache.hear(header.source, &solicitation, now, gateway).unwrap();
+    assert_eq!(gateway_cache.lookup(host.1), Some(host.0));
+    let answer = advertisement.to_frame(gateway, host);
+    assert_eq!(answer.destination, host.0);
+    let (_, advertisement) = NdpMessage::from_frame(&answer).unwrap();
+    assert_eq!(host_cache.hear(gateway.1, &advertisement, now, host), None);
+    assert_eq!(host_cache.resolve(gateway.1, now, host), Resolution::Known(gateway.0));
+    assert!(matches!(host_cache.resolve(gateway.1, now + Duration::from_secs(30), host), Resolution::Ask(_)));
+
+    // an advertisement nobody asked for doesn't add anything
+    let unasked = NdpMessage::NeighborAdvertisement { target : "2001:db8:1::66".parse().unwrap(), target_mac : Some(host.0), router : false, solicited : false, override_entry : false };
+    host_cache.hear(unasked.target(), &unasked, now, host);
+    assert_eq!(host_cache.lookup(unasked.target()), None);
+    assert_eq!(NdpMessage::from_icmpv6(&unasked.to_icmpv6()), Ok(unasked));
+}
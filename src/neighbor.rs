@@ -0,0 +1,99 @@
+//! The neighbor cache: which MAC address an IP on the same link has. For IPv4 it is filled by
+//! ARP, for IPv6 by Neighbor Discovery, but the cache itself is the same thing.
+//! It can be limited in size (see table_limits), since a host scanning a big subnet would
+//! otherwise fill it with entries for addresses that mostly don't even exist.
+//! Entries can also age: one not heard from for max_age is forgotten and asked for again, in
+//! case the address moved to another machine in the meantime.
+use std::fmt::Debug;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+pub use crate::link::MacAddr;
+use crate::link::EthernetFrame;
+use crate::table_limits::{TableEvent, TableFull, TableLimit};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbor<A> {
+    pub ip : A,
+    pub mac : MacAddr,
+    pub updated_at : Instant,
+}
+
+#[derive(Debug)]
+pub struct NeighborCache<A> {
+    pub name : String,
+    pub entries : Vec<Neighbor<A>>,
+    pub limit : Option<TableLimit>,
+    pub events : Vec<TableEvent>,
+    // how long an entry is trusted since it was last heard from, None for forever
+    pub max_age : Option<Duration>,
+}
+
+// what a cache has to say about a next hop, when a frame is to be sent to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Known(MacAddr),
+    // not known (anymore): this question (an ARP request or a Neighbor Solicitation) has to be
+    // sent, and the packet wait for the answer
+    Ask(EthernetFrame),
+}
+
+pub type ArpCache = NeighborCache<Ipv4Addr>;
+pub type NdCache = NeighborCache<Ipv6Addr>;
+
+impl<A: Copy + PartialEq + Debug> NeighborCache<A> {
+    pub fn new(name: &str) -> Self {
+        NeighborCache { name : name.to_string(), entries : vec![], limit : None, events : vec![], max_age : None }
+    }
+
+    pub fn with_limit(mut self, limit: TableLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // forgets the entries older than max_age, and gives them back
+    pub fn expire(&mut self, now: Instant) -> Vec<Neighbor<A>> {
+        let Some(max_age) = self.max_age else {
+            return vec![];
+        };
+        let (stale, fresh) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|neighbor| now.duration_since(neighbor.updated_at) >= max_age);
+        self.entries = fresh;
+        stale
+    }
+
+    // heard from this neighbor; an entry already there is just updated, that never needs room
+    pub fn learn(&mut self, ip: A, mac: MacAddr, now: Instant) -> Result<(), TableFull> {
+        if let Some(neighbor) = self.entries.iter_mut().find(|neighbor| neighbor.ip == ip) {
+            neighbor.mac = mac;
+            neighbor.updated_at = now;
+            return Ok(());
+        }
+        let neighbor = Neighbor { ip, mac, updated_at : now };
+        if let Some(limit) = self.limit {
+            let oldest = |entries: &[Neighbor<A>]| entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, neighbor)| neighbor.updated_at)
+                .map(|(index, _)| index);
+            limit.make_room(&self.name, &mut self.entries, oldest, &neighbor, &mut self.events)?;
+        }
+        self.entries.push(neighbor);
+        Ok(())
+    }
+
+    pub fn lookup(&self, ip: A) -> Option<MacAddr> {
+        self.entries.iter().find(|neighbor| neighbor.ip == ip).map(|neighbor| neighbor.mac)
+    }
+
+    // gives the events so far to whoever is watching, and forgets them
+    pub fn take_events(&mut self) -> Vec<TableEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
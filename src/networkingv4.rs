@@ -0,0 +1,9 @@
+//! The routing table for IPv4: the same longest prefix match as in routing,
+//! with 32 bit addresses, and next hops that are just the address of the next router.
+use std::net::Ipv4Addr;
+
+use crate::routing;
+
+// the next hop is the address of the next router
+pub type Route = routing::Route<Ipv4Addr, Ipv4Addr>;
+pub type RoutingTable = routing::RoutingTable<Ipv4Addr, Ipv4Addr>;
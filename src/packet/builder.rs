@@ -0,0 +1,227 @@
+//! Making a packet without putting its headers together by hand:
+//!
+//! ```text
+//! PacketBuilder::ipv4().src(laptop).dst(resolver).udp().sport(5353).dport(53).payload(query).build()
+//! ```
+//!
+//! Anything not said keeps the default of the header (a TTL of 64, no flags, a 64K window), and
+//! build() gives back the packet as it would be read off the wire: the lengths, the protocol
+//! numbers and every checksum are already filled in.
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::icmp::IcmpMessage;
+use super::ipv4::Ipv4Header;
+use super::ipv6::Ipv6Header;
+use super::layered::{IpHeader, LayeredPacket, Transport};
+use super::tcp::TcpHeader;
+use super::udp::UdpHeader;
+use crate::link::{EthernetFrame, MacAddr};
+use crate::qos::Ecn;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketBuilder<H> {
+    packet : LayeredPacket<H>,
+}
+
+impl PacketBuilder<Ipv4Header> {
+    pub fn ipv4() -> Self {
+        Self::between(Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED)
+    }
+}
+
+impl PacketBuilder<Ipv6Header> {
+    pub fn ipv6() -> Self {
+        Self::between(Ipv6Addr::UNSPECIFIED, Ipv6Addr::UNSPECIFIED)
+    }
+}
+
+impl<H: IpHeader> PacketBuilder<H> {
+    // UDP until told otherwise, with both ports 0
+    pub fn between(source: H::Address, destination: H::Address) -> Self {
+        let packet = LayeredPacket::new(H::between(source, destination), Transport::Udp(UdpHeader::new(0, 0, 0)), vec![]);
+        PacketBuilder { packet }
+    }
+
+    pub fn src(mut self, source: H::Address) -> Self {
+        self.packet.network.set_source(source);
+        self
+    }
+
+    pub fn dst(mut self, destination: H::Address) -> Self {
+        self.packet.network.set_destination(destination);
+        self
+    }
+
+    pub fn hop_limit(mut self, hop_limit: u8) -> Self {
+        self.packet.network.set_hop_limit(hop_limit);
+        self
+    }
+
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.packet.network.set_dscp(dscp);
+        self
+    }
+
+    pub fn ecn(mut self, ecn: Ecn) -> Self {
+        self.packet.network.set_ecn(ecn);
+        self
+    }
+
+    // the ports are kept when changing between TCP and UDP
+    pub fn udp(mut self) -> Self {
+        let (source, destination) = self.ports();
+        self.packet.transport = Transport::Udp(UdpHeader::new(source, destination, 0));
+        self
+    }
+
+    pub fn tcp(mut self) -> Self {
+        let (source, destination) = self.ports();
+        self.packet.transport = Transport::Tcp(TcpHeader::new(source, destination, 0));
+        self
+    }
+
+    // a ping, whose data is the payload
+    pub fn ping(mut self, identifier: u16, sequence: u16) -> Self {
+        self.packet.transport = Transport::Icmp(IcmpMessage::EchoRequest { identifier, sequence, data : vec![] });
+        self
+    }
+
+    // any other ICMP message, carrying its own data
+    pub fn icmp(mut self, message: IcmpMessage) -> Self {
+        self.packet.transport = Transport::Icmp(message);
+        self
+    }
+
+    fn ports(&self) -> (u16, u16) {
+        match &self.packet.transport {
+            Transport::Tcp(header) => (header.source_port, header.destination_port),
+            Transport::Udp(header) => (header.source_port, header.destination_port),
+            Transport::Icmp(_) | Transport::Gre(_) | Transport::Ipv6 => (0, 0),
+        }
+    }
+
+    // the identifier, for a ping
+    pub fn sport(mut self, port: u16) -> Self {
+        match &mut self.packet.transport {
+            Transport::Tcp(header) => header.source_port = port,
+            Transport::Udp(header) => header.source_port = port,
+            Transport::Icmp(IcmpMessage::EchoRequest { identifier, .. } | IcmpMessage::EchoReply { identifier, .. }) => *identifier = port,
+            Transport::Icmp(_) | Transport::Gre(_) | Transport::Ipv6 => {}
+        }
+        self
+    }
+
+    pub fn dport(mut self, port: u16) -> Self {
+        match &mut self.packet.transport {
+            Transport::Tcp(header) => header.destination_port = port,
+            Transport::Udp(header) => header.destination_port = port,
+            Transport::Icmp(_) | Transport::Gre(_) | Transport::Ipv6 => {}
+        }
+        self
+    }
+
+    // only for TCP, like the other TCP fields
+    pub fn seq(mut self, sequence: u32) -> Self {
+        if let Transport::Tcp(header) = &mut self.packet.transport {
+            header.sequence = sequence;
+        }
+        self
+    }
+
+    // sets ACK too
+    pub fn ack(mut self, acknowledgment: u32) -> Self {
+        if let Transport::Tcp(header) = &mut self.packet.transport {
+            *header = header.clone().with_acknowledgment(acknowledgment);
+        }
+        self
+    }
+
+    // added to the ones already set
+    pub fn flags(mut self, flags: u8) -> Self {
+        if let Transport::Tcp(header) = &mut self.packet.transport {
+            header.flags |= flags;
+        }
+        self
+    }
+
+    pub fn window(mut self, window: u16) -> Self {
+        if let Transport::Tcp(header) = &mut self.packet.transport {
+            header.window = window;
+        }
+        self
+    }
+
+    pub fn payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.packet.payload = payload.into();
+        self
+    }
+
+    // on an Ethernet link, from one MAC to the other
+    pub fn link(mut self, source: MacAddr, destination: MacAddr) -> Self {
+        self.packet = self.packet.with_link(destination, source);
+        self
+    }
+
+    pub fn build(self) -> LayeredPacket<H> {
+        let mut packet = self.packet;
+        if let Transport::Icmp(IcmpMessage::EchoRequest { data, .. }) = &mut packet.transport {
+            data.extend(std::mem::take(&mut packet.payload));
+        }
+        // read back, so every field is the one on the wire
+        match LayeredPacket::from_bytes(&packet.to_bytes()) {
+            Ok(read) => LayeredPacket { link : packet.link, ..read },
+            Err(_) => packet,
+        }
+    }
+
+    pub fn bytes(self) -> Vec<u8> {
+        self.build().to_bytes()
+    }
+
+    // None if no link was given
+    pub fn frame(self) -> Option<EthernetFrame> {
+        self.build().to_frame()
+    }
+}
+
+#[test]
+fn packets_built_in_one_line() {
+    use super::checksum::PseudoHeader;
+    use super::tcp::SYN;
+    use super::{Packet, IPPROTO_TCP};
+    use crate::nat_v4::Protocol;
+    use crate::qos::EF;
+
+    let (laptop, resolver) = (Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(198, 51, 100, 53));
+    let query = PacketBuilder::ipv4().src(laptop).dst(resolver).udp().sport(5353).dport(53).payload(b"example.com?").build();
+    let Transport::Udp(udp) = query.transport else {
+        panic!("expected UDP");
+    };
+    assert_eq!((query.network.total_length, udp.length), (40, 20));
+    assert!(query.network.has_valid_checksum());
+    assert_ne!(udp.checksum, 0);
+
+    // a SYN over IPv6 in a frame, voice marked, which reads back as it was built
+    let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let syn = PacketBuilder::ipv6()
+        .src(v6("2001:db8::10"))
+        .dst(v6("2001:db8::443"))
+        .tcp()
+        .sport(40000)
+        .dport(443)
+        .seq(1)
+        .flags(SYN)
+        .dscp(EF)
+        .link(MacAddr([0x02, 0, 0, 0, 0, 0x10]), MacAddr([0x02, 0, 0, 0, 0, 1]));
+    let frame = syn.clone().frame().unwrap();
+    let read = LayeredPacket::<Ipv6Header>::from_frame(&frame).unwrap();
+    assert_eq!(read, syn.clone().build());
+    assert_eq!((read.protocol(), read.source_port(), read.network.dscp(), read.network.payload_length), (Protocol::Tcp, 40000, EF, 20));
+    let segment = &syn.bytes()[40..];
+    assert_eq!(PseudoHeader::v6(v6("2001:db8::10"), v6("2001:db8::443"), IPPROTO_TCP, segment.len()).verify(segment), Ok(()));
+
+    // a ping's payload is its data
+    let ping = PacketBuilder::ipv4().dst(resolver).ping(7, 1).payload(*b"abcd").build();
+    assert_eq!(ping.transport, Transport::Icmp(IcmpMessage::EchoRequest { identifier : 7, sequence : 1, data : b"abcd".to_vec() }));
+    assert_eq!(ping.network.total_length, 32);
+}
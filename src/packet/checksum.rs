@@ -0,0 +1,148 @@
+//! The checksums of TCP, UDP and ICMPv6 don't only cover their own header and payload, but a
+//! pseudo-header too, made from the IP header: the addresses, the protocol and the length. So
+//! a segment delivered to the wrong address fails its checksum, even if the IP header was the
+//! one that got damaged. For IPv4 (RFC 793, 768):
+//!
+//! ```text
+//! | source (4) | destination (4) | zero (1) | protocol (1) | TCP/UDP length (2) |
+//! ```
+//!
+//! and for IPv6 (RFC 8200), where the protocol is the one after the extension headers:
+//!
+//! ```text
+//! | source (16) | destination (16) | length (4) | zero (3) | next header (1) |
+//! ```
+//!
+//! The pseudo-header is never sent, only added to the sum. A UDP checksum of zero means there
+//! is none, which IPv4 allows but IPv6 doesn't, since it has no header checksum of its own; and
+//! a UDP checksum that works out to zero is sent as ffff instead.
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::bit_utils::internet_checksum;
+use super::{need, PacketError, IPPROTO_ICMPV6, IPPROTO_TCP, IPPROTO_UDP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoHeader {
+    V4 { source : Ipv4Addr, destination : Ipv4Addr, protocol : u8, length : u16 },
+    V6 { source : Ipv6Addr, destination : Ipv6Addr, protocol : u8, length : u32 },
+}
+
+// where the checksum is in a segment of this protocol; None for those without a pseudo-header
+pub fn checksum_offset(protocol: u8) -> Option<usize> {
+    match protocol {
+        IPPROTO_TCP => Some(16),
+        IPPROTO_UDP => Some(6),
+        IPPROTO_ICMPV6 => Some(2),
+        _ => None,
+    }
+}
+
+impl PseudoHeader {
+    pub fn v4(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, length: usize) -> Self {
+        PseudoHeader::V4 { source, destination, protocol, length : length as u16 }
+    }
+
+    pub fn v6(source: Ipv6Addr, destination: Ipv6Addr, protocol: u8, length: usize) -> Self {
+        PseudoHeader::V6 { source, destination, protocol, length : length as u32 }
+    }
+
+    pub fn protocol(&self) -> u8 {
+        match *self {
+            PseudoHeader::V4 { protocol, .. } | PseudoHeader::V6 { protocol, .. } => protocol,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        match *self {
+            PseudoHeader::V4 { source, destination, protocol, length } => {
+                bytes.extend(source.octets());
+                bytes.extend(destination.octets());
+                bytes.extend([0, protocol]);
+                bytes.extend(length.to_be_bytes());
+            }
+            PseudoHeader::V6 { source, destination, protocol, length } => {
+                bytes.extend(source.octets());
+                bytes.extend(destination.octets());
+                bytes.extend(length.to_be_bytes());
+                bytes.extend([0, 0, 0, protocol]);
+            }
+        }
+        bytes
+    }
+
+    // the checksum of the segment, header and payload, taking its checksum field as zero
+    pub fn checksum(&self, segment: &[u8]) -> u16 {
+        let mut bytes = self.to_bytes();
+        bytes.extend(segment);
+        if let Some(at) = checksum_offset(self.protocol()).filter(|&at| segment.len() >= at + 2) {
+            let at = bytes.len() - segment.len() + at;
+            bytes[at..at + 2].fill(0);
+        }
+        match internet_checksum(&bytes) {
+            0 if self.protocol() == IPPROTO_UDP => 0xffff,
+            sum => sum,
+        }
+    }
+
+    // writes the checksum into the segment
+    pub fn fill(&self, segment: &mut [u8]) -> Result<(), PacketError> {
+        let Some(at) = checksum_offset(self.protocol()) else {
+            return Ok(());
+        };
+        need(segment, at + 2)?;
+        let sum = self.checksum(segment);
+        segment[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+        Ok(())
+    }
+
+    pub fn verify(&self, segment: &[u8]) -> Result<(), PacketError> {
+        let Some(at) = checksum_offset(self.protocol()) else {
+            return Ok(());
+        };
+        need(segment, at + 2)?;
+        let found = u16::from_be_bytes([segment[at], segment[at + 1]]);
+        if found == 0 && self.protocol() == IPPROTO_UDP {
+            return match self {
+                PseudoHeader::V4 { .. } => Ok(()),
+                PseudoHeader::V6 { .. } => Err(PacketError::BadChecksum),
+            };
+        }
+        if found != self.checksum(segment) {
+            return Err(PacketError::BadChecksum);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn pseudo_headers_for_both_versions() {
+    use super::udp::UdpHeader;
+
+    // a DNS query from 192.168.0.31 to 192.168.0.1
+    let (client, resolver) = (Ipv4Addr::new(192, 168, 0, 31), Ipv4Addr::new(192, 168, 0, 1));
+    let payload = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01";
+    let mut datagram = UdpHeader::new(54321, 53, payload.len()).datagram(payload);
+    let pseudo = PseudoHeader::v4(client, resolver, IPPROTO_UDP, datagram.len());
+    assert_eq!(pseudo.to_bytes(), [192, 168, 0, 31, 192, 168, 0, 1, 0, 17, 0, 37]);
+    // no checksum is fine over IPv4
+    assert_eq!(pseudo.verify(&datagram), Ok(()));
+    pseudo.fill(&mut datagram).unwrap();
+    assert_eq!(datagram[6..8], [0xc8, 0x2a]);
+    assert_eq!(pseudo.verify(&datagram), Ok(()));
+    // the same datagram to somebody else doesn't add up
+    let misdelivered = PseudoHeader::v4(client, Ipv4Addr::new(192, 168, 0, 2), IPPROTO_UDP, datagram.len());
+    assert_eq!(misdelivered.verify(&datagram), Err(PacketError::BadChecksum));
+
+    // over IPv6 a checksum has to be there
+    let v6 = PseudoHeader::v6("2001:db8::31".parse().unwrap(), "2001:db8::1".parse().unwrap(), IPPROTO_UDP, datagram.len());
+    assert_eq!(v6.to_bytes()[32..], [0, 0, 0, 37, 0, 0, 0, 17]);
+    datagram[6..8].fill(0);
+    assert_eq!(v6.verify(&datagram), Err(PacketError::BadChecksum));
+    v6.fill(&mut datagram).unwrap();
+    assert_eq!(v6.verify(&datagram), Ok(()));
+
+    // a sum of zero is sent as ffff: the pseudo-header here cancels the segment out exactly
+    let zeros = PseudoHeader::v4(Ipv4Addr::new(255, 255, 255, 255), Ipv4Addr::new(255, 222, 255, 255), IPPROTO_UDP, 8);
+    assert_eq!(zeros.checksum(&UdpHeader::new(0, 0, 0).to_bytes()), 0xffff);
+}
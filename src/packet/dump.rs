@@ -0,0 +1,358 @@
+//! Looking at a packet the way Wireshark shows it: a tree with a line for each layer and its
+//! fields under it, and the bytes as a classic hex dump below:
+//!
+//! ```text
+//! Internet Protocol Version 4, Src: 192.168.1.10, Dst: 198.51.100.53
+//!     Version: 4
+//!     ...
+//! User Datagram Protocol, Src Port: 5353, Dst Port: 53
+//!     ...
+//!
+//! 0000  45 00 00 28 00 00 00 00  40 11 ...                  E..(....@.
+//! ```
+//!
+//! The tree of a LayeredPacket shows it as to_bytes() writes it, so the lengths and checksums
+//! are the ones really sent, not whatever the fields had before.
+use std::fmt::{self, Display, Write};
+
+use super::gre::GreHeader;
+use super::icmp::IcmpMessage;
+use super::ipv4::Ipv4Header;
+use super::ipv6::Ipv6Header;
+use super::layered::{IpHeader, LayeredPacket, Transport};
+use super::tcp::{TcpHeader, TcpOption};
+use super::udp::UdpHeader;
+use super::{IPPROTO_GRE, IPPROTO_ICMP, IPPROTO_ICMPV6, IPPROTO_IGMP, IPPROTO_IPV6, IPPROTO_TCP, IPPROTO_UDP};
+use crate::link::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_MPLS, ETHERTYPE_QINQ, ETHERTYPE_VLAN};
+use crate::qos::{dscp_of, Ecn};
+
+// one layer of the tree: the line that sums it up, and its fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub title : String,
+    pub fields : Vec<(String, String)>,
+}
+
+impl Layer {
+    pub fn new(title: String) -> Self {
+        Layer { title, fields : vec![] }
+    }
+
+    pub fn field(mut self, name: &str, value: impl Display) -> Self {
+        self.fields.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+// the headers that can tell what is in them
+pub trait Describe {
+    fn describe(&self) -> Layer;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolTree {
+    pub layers : Vec<Layer>,
+}
+
+impl Display for ProtocolTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for layer in &self.layers {
+            writeln!(f, "{}", layer.title)?;
+            for (name, value) in &layer.fields {
+                writeln!(f, "    {name}: {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 16 bytes a line: the offset, the bytes in hex in two groups of 8, and the printable ones
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:04x} ", line * 16);
+        for (at, byte) in chunk.iter().enumerate() {
+            let gap = if at == 8 { "  " } else { " " };
+            let _ = write!(dump, "{gap}{byte:02x}");
+        }
+        // the ASCII lines up even after a short last line
+        let missing = 16 - chunk.len();
+        dump.push_str(&" ".repeat(missing * 3 + usize::from(chunk.len() <= 8) + 2));
+        dump.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push('\n');
+    }
+    dump
+}
+
+pub fn protocol_name(protocol: u8) -> &'static str {
+    match protocol {
+        IPPROTO_ICMP => "ICMP",
+        IPPROTO_IGMP => "IGMP",
+        IPPROTO_TCP => "TCP",
+        IPPROTO_UDP => "UDP",
+        IPPROTO_IPV6 => "IPv6",
+        IPPROTO_GRE => "GRE",
+        IPPROTO_ICMPV6 => "ICMPv6",
+        0 => "IPv6 Hop-by-Hop Option",
+        43 => "Routing Header for IPv6",
+        44 => "Fragment Header for IPv6",
+        60 => "Destination Options for IPv6",
+        _ => "Unknown",
+    }
+}
+
+fn ethertype_name(ethertype: u16) -> &'static str {
+    match ethertype {
+        ETHERTYPE_IPV4 => "IPv4",
+        ETHERTYPE_ARP => "ARP",
+        ETHERTYPE_IPV6 => "IPv6",
+        ETHERTYPE_MPLS => "MPLS label switched packet",
+        ETHERTYPE_VLAN => "802.1Q Virtual LAN",
+        ETHERTYPE_QINQ => "802.1ad Provider Bridging",
+        _ => "Unknown",
+    }
+}
+
+fn ecn_name(ecn: Ecn) -> &'static str {
+    match ecn {
+        Ecn::NotEct => "Not-ECT",
+        Ecn::Ect1 => "ECT(1)",
+        Ecn::Ect0 => "ECT(0)",
+        Ecn::Ce => "CE",
+    }
+}
+
+// the set flags, the way Wireshark sums them up: [SYN, ACK]
+fn tcp_flags(flags: u8) -> String {
+    let names = ["FIN", "SYN", "RST", "PSH", "ACK", "URG", "ECE", "CWR"];
+    let set: Vec<_> = (0..8).filter(|bit| flags & 1 << bit != 0).map(|bit| names[bit]).collect();
+    format!("0x{flags:03x} [{}]", set.join(", "))
+}
+
+// the way Wireshark lists them
+fn tcp_option(option: &TcpOption) -> String {
+    match option {
+        TcpOption::EndOfList => "End of Option List (EOL)".to_string(),
+        TcpOption::Nop => "No-Operation (NOP)".to_string(),
+        TcpOption::MaximumSegmentSize(mss) => format!("Maximum segment size: {mss} bytes"),
+        TcpOption::WindowScale(shift) => format!("Window scale: {shift} (multiply by {})", 1u32 << shift.min(&14)),
+        TcpOption::SackPermitted => "SACK permitted".to_string(),
+        TcpOption::Sack(blocks) => format!("SACK: {}", blocks.iter().map(|(left, right)| format!("{left}-{right}")).collect::<Vec<_>>().join(" ")),
+        TcpOption::Timestamps { value, echo_reply } => format!("Timestamps: TSval {value}, TSecr {echo_reply}"),
+        TcpOption::Unknown { kind, data } => format!("Unknown (kind {kind}, {} bytes)", data.len()),
+    }
+}
+
+// with a line for each VLAN tag, the outermost first
+impl Describe for EthernetFrame {
+    fn describe(&self) -> Layer {
+        let mut layer = Layer::new(format!("Ethernet II, Src: {}, Dst: {}", self.source, self.destination))
+            .field("Destination", self.destination)
+            .field("Source", self.source);
+        for tag in &self.tags {
+            layer = layer.field(ethertype_name(tag.tpid), format!("PRI: {}, DEI: {}, ID: {}", tag.pcp, tag.dei as u8, tag.vid));
+        }
+        layer.field("Type", format!("{} (0x{:04x})", ethertype_name(self.ethertype), self.ethertype))
+    }
+}
+
+impl Describe for Ipv4Header {
+    fn describe(&self) -> Layer {
+        let mut flags = vec![];
+        if self.dont_fragment {
+            flags.push("Don't fragment");
+        }
+        if self.more_fragments {
+            flags.push("More fragments");
+        }
+        Layer::new(format!("Internet Protocol Version 4, Src: {}, Dst: {}", self.source, self.destination))
+            .field("Version", 4)
+            .field("Header Length", format!("{} bytes ({})", self.header_len(), self.header_len() / 4))
+            .field("Differentiated Services Field", format!("0x{:02x} (DSCP: {}, ECN: {})", self.type_of_service, self.dscp(), ecn_name(self.ecn())))
+            .field("Total Length", self.total_length)
+            .field("Identification", format!("0x{:04x} ({})", self.identification, self.identification))
+            .field("Flags", if flags.is_empty() { "none".to_string() } else { flags.join(", ") })
+            .field("Fragment Offset", self.fragment_offset as usize * 8)
+            .field("Time to Live", self.time_to_live)
+            .field("Protocol", format!("{} ({})", protocol_name(self.protocol), self.protocol))
+            .field("Header Checksum", format!("0x{:04x} [{}]", self.checksum, if self.has_valid_checksum() { "correct" } else { "incorrect" }))
+            .field("Source Address", self.source)
+            .field("Destination Address", self.destination)
+    }
+}
+
+impl Describe for Ipv6Header {
+    fn describe(&self) -> Layer {
+        Layer::new(format!("Internet Protocol Version 6, Src: {}, Dst: {}", self.source, self.destination))
+            .field("Version", 6)
+            .field("Traffic Class", format!("0x{:02x} (DSCP: {}, ECN: {})", self.traffic_class, dscp_of(self.traffic_class), ecn_name(self.ecn())))
+            .field("Flow Label", format!("0x{:05x}", self.flow_label))
+            .field("Payload Length", self.payload_length)
+            .field("Next Header", format!("{} ({})", protocol_name(self.next_header), self.next_header))
+            .field("Hop Limit", self.hop_limit)
+            .field("Source Address", self.source)
+            .field("Destination Address", self.destination)
+    }
+}
+
+impl Describe for TcpHeader {
+    fn describe(&self) -> Layer {
+        let layer = Layer::new(format!("Transmission Control Protocol, Src Port: {}, Dst Port: {}, Seq: {}", self.source_port, self.destination_port, self.sequence))
+            .field("Source Port", self.source_port)
+            .field("Destination Port", self.destination_port)
+            .field("Sequence Number", self.sequence)
+            .field("Acknowledgment Number", self.acknowledgment)
+            .field("Header Length", format!("{} bytes ({})", self.header_len(), self.header_len() / 4))
+            .field("Flags", tcp_flags(self.flags))
+            .field("Window", self.window)
+            .field("Checksum", format!("0x{:04x}", self.checksum))
+            .field("Urgent Pointer", self.urgent_pointer);
+        if self.options.is_empty() {
+            return layer;
+        }
+        let options: Vec<String> = self.options.iter().map(tcp_option).collect();
+        layer.field("Options", format!("({} bytes), {}", self.header_len() - 20, options.join(", ")))
+    }
+}
+
+impl Describe for UdpHeader {
+    fn describe(&self) -> Layer {
+        Layer::new(format!("User Datagram Protocol, Src Port: {}, Dst Port: {}", self.source_port, self.destination_port))
+            .field("Source Port", self.source_port)
+            .field("Destination Port", self.destination_port)
+            .field("Length", self.length)
+            .field("Checksum", format!("0x{:04x}", self.checksum))
+    }
+}
+
+impl Describe for GreHeader {
+    fn describe(&self) -> Layer {
+        let mut layer = Layer::new(format!("Generic Routing Encapsulation ({})", ethertype_name(self.protocol_type)))
+            .field("Protocol Type", format!("{} (0x{:04x})", ethertype_name(self.protocol_type), self.protocol_type));
+        if let Some(checksum) = self.checksum {
+            layer = layer.field("Checksum", format!("0x{checksum:04x}"));
+        }
+        if let Some(key) = self.key {
+            layer = layer.field("Key", format!("0x{key:08x}"));
+        }
+        if let Some(sequence) = self.sequence {
+            layer = layer.field("Sequence Number", sequence);
+        }
+        layer
+    }
+}
+
+// the same for both versions, since the message doesn't say which it is
+impl Describe for IcmpMessage {
+    fn describe(&self) -> Layer {
+        let layer = Layer::new("Internet Control Message Protocol".to_string());
+        match self {
+            IcmpMessage::EchoRequest { identifier, sequence, data } | IcmpMessage::EchoReply { identifier, sequence, data } => {
+                let kind = if matches!(self, IcmpMessage::EchoRequest { .. }) { "Echo (ping) request" } else { "Echo (ping) reply" };
+                layer
+                    .field("Type", kind)
+                    .field("Identifier", format!("0x{identifier:04x} ({identifier})"))
+                    .field("Sequence Number", sequence)
+                    .field("Data", format!("{} bytes", data.len()))
+            }
+            IcmpMessage::DestinationUnreachable { code, original } => layer.field("Type", "Destination unreachable").field("Code", code).field("Original packet", format!("{} bytes", original.len())),
+            IcmpMessage::TimeExceeded { code, original } => layer.field("Type", "Time exceeded").field("Code", code).field("Original packet", format!("{} bytes", original.len())),
+            IcmpMessage::PacketTooBig { mtu, original } => layer.field("Type", "Packet too big").field("MTU", mtu).field("Original packet", format!("{} bytes", original.len())),
+        }
+    }
+}
+
+// the layers of a tunneled packet, if it is an IP packet that can be read
+fn inner_layers(ethertype: u16, bytes: &[u8]) -> Option<Vec<Layer>> {
+    match ethertype {
+        ETHERTYPE_IPV4 => LayeredPacket::<Ipv4Header>::from_bytes(bytes).ok().map(|inner| inner.tree().layers),
+        ETHERTYPE_IPV6 => LayeredPacket::<Ipv6Header>::from_bytes(bytes).ok().map(|inner| inner.tree().layers),
+        _ => None,
+    }
+}
+
+impl<H: IpHeader + Describe> LayeredPacket<H> {
+    // as it goes on the wire
+    pub fn tree(&self) -> ProtocolTree {
+        // read back from its bytes, to have the lengths and checksums filled in
+        let packet = Self::from_bytes(&self.to_bytes()).map(|read| LayeredPacket { link : self.link, ..read }).unwrap_or_else(|_| self.clone());
+        let mut layers = vec![];
+        if let Some(frame) = packet.to_frame() {
+            layers.push(frame.describe());
+        }
+        layers.push(packet.network.describe());
+        // what a tunnel carries is another packet, with layers of its own
+        let inner = match &packet.transport {
+            Transport::Gre(header) => inner_layers(header.protocol_type, &packet.payload),
+            Transport::Ipv6 => inner_layers(ETHERTYPE_IPV6, &packet.payload),
+            _ => None,
+        };
+        match &packet.transport {
+            Transport::Tcp(header) => layers.push(header.describe()),
+            Transport::Udp(header) => layers.push(header.describe()),
+            Transport::Icmp(message) if H::ICMP == IPPROTO_ICMPV6 => layers.push(Layer { title : "Internet Control Message Protocol v6".to_string(), ..message.describe() }),
+            Transport::Icmp(message) => layers.push(message.describe()),
+            Transport::Gre(header) => layers.push(header.describe()),
+            Transport::Ipv6 => {}
+        }
+        if let Some(inner) = inner {
+            layers.extend(inner);
+        } else if !packet.payload.is_empty() {
+            layers.push(Layer::new(format!("Data ({} bytes)", packet.payload.len())));
+        }
+        ProtocolTree { layers }
+    }
+
+    // the tree, and the bytes of the frame (or the packet, if it isn't on a link)
+    pub fn dump(&self) -> String {
+        let bytes = self.to_frame().map_or_else(|| self.to_bytes(), |frame| frame.to_bytes());
+        format!("{}\n{}", self.tree(), hex_dump(&bytes))
+    }
+}
+
+#[test]
+fn packets_dumped_like_wireshark() {
+    use crate::link::MacAddr;
+    use std::net::Ipv4Addr;
+
+    assert_eq!(hex_dump(b"GET / HTTP/1.1\r\nHost: a\r\n"), "0000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  GET / HTTP/1.1..\n0010  48 6f 73 74 3a 20 61 0d  0a                       Host: a..\n");
+    assert_eq!(hex_dump(&[0xff; 3]), "0000  ff ff ff                                          ...\n");
+
+    let (laptop, resolver) = (Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(198, 51, 100, 53));
+    let query = LayeredPacket::new(Ipv4Header::new(laptop, resolver, 0, 0), Transport::Udp(UdpHeader::new(5353, 53, 0)), b"example.com?".to_vec())
+        .with_link(MacAddr([0x02, 0, 0, 0, 0, 1]), MacAddr([0x02, 0, 0, 0, 0, 0x10]));
+    let tree = query.tree();
+    let titles: Vec<_> = tree.layers.iter().map(|layer| layer.title.as_str()).collect();
+    assert_eq!(titles, [
+        "Ethernet II, Src: 02:00:00:00:00:10, Dst: 02:00:00:00:00:01",
+        "Internet Protocol Version 4, Src: 192.168.1.10, Dst: 198.51.100.53",
+        "User Datagram Protocol, Src Port: 5353, Dst Port: 53",
+        "Data (12 bytes)",
+    ]);
+    // the filled in values, not the zeros the packet was made with
+    let text = tree.to_string();
+    assert!(text.contains("    Total Length: 40\n"));
+    assert!(text.contains("    Protocol: UDP (17)\n"));
+    assert!(text.contains("[correct]"));
+    assert!(text.contains("    Length: 20\n"));
+    let dump = query.dump();
+    assert!(dump.contains("0000  02 00 00 00 00 01 02 00  00 00 00 10 08 00 45 00  ..............E.\n"));
+
+    let tagged = query.to_frame().unwrap().with_tag(crate::link::VlanTag::new(100));
+    assert_eq!(tagged.describe().fields[2].1, "PRI: 0, DEI: 0, ID: 100");
+
+    let syn = TcpHeader::new(40000, 443, 1).with_flags(super::tcp::SYN).with_acknowledgment(7);
+    assert_eq!(syn.describe().fields[5], ("Flags".to_string(), "0x012 [SYN, ACK]".to_string()));
+    let syn = syn.with_option(TcpOption::MaximumSegmentSize(1460)).unwrap().with_option(TcpOption::Nop).unwrap().with_option(TcpOption::WindowScale(7)).unwrap();
+    assert_eq!(syn.describe().fields[9].1, "(8 bytes), Maximum segment size: 1460 bytes, No-Operation (NOP), Window scale: 7 (multiply by 128)");
+
+    // a tunneled packet shows what it carries
+    let inner = LayeredPacket::new(Ipv6Header::between("2001:db8:a::10".parse().unwrap(), "2001:db8:b::20".parse().unwrap()), Transport::Udp(UdpHeader::new(5353, 53, 0)), vec![]);
+    let tunneled = LayeredPacket::new(Ipv4Header::between(laptop, resolver), Transport::Ipv6, inner.to_bytes());
+    let titles: Vec<_> = tunneled.tree().layers.into_iter().map(|layer| layer.title).collect();
+    assert_eq!(titles[1..], [
+        "Internet Protocol Version 6, Src: 2001:db8:a::10, Dst: 2001:db8:b::20",
+        "User Datagram Protocol, Src Port: 5353, Dst Port: 53",
+    ]);
+    assert!(tunneled.tree().to_string().contains("    Protocol: IPv6 (41)\n"));
+}
@@ -0,0 +1,465 @@
+//! Fragmentation: a packet bigger than the MTU of the link it has to go on is cut into pieces,
+//! each with a copy of the header, and put back together by the destination.
+//!
+//! In IPv4 any router on the way can do it, unless the sender set Don't Fragment (then the
+//! router drops the packet and says how big it may be, which is what path MTU discovery uses).
+//! The offset and More Fragments are in the IPv4 header itself. In IPv6 only the source
+//! fragments, with a Fragment extension header in each piece; routers just send back a Packet
+//! Too Big. The headers before the Fragment header (Hop-by-Hop, Routing) are in every piece,
+//! everything after it is cut. Either way the offsets count in 8 bytes, so every piece but the
+//! last carries a multiple of 8.
+//!
+//! The destination keeps the pieces of each packet until it has all of them, but not forever:
+//! a packet missing a piece for longer than the timeout is thrown away. Pieces that overlap
+//! are how attacks sneak things past firewalls, so (like Linux, and RFC 5722 for IPv6) a packet
+//! with overlapping pieces is thrown away entirely; a piece that simply came twice is ignored.
+use std::fmt::{self, Display};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::ipv4::Ipv4Header;
+use super::ipv6::{Ipv6Header, DESTINATION_OPTIONS, FRAGMENT, HEADER_LEN, HOP_BY_HOP, ROUTING};
+use super::{need, PacketError};
+
+// how long Linux waits for the missing pieces
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentError {
+    // too big for the MTU, and not to be fragmented here: Don't Fragment was set, or it is IPv6
+    // on a router. The MTU goes back to the sender.
+    TooBig { mtu : usize },
+    // not even the headers and 8 bytes fit
+    MtuTooSmall(usize),
+    Packet(PacketError),
+}
+
+impl From<PacketError> for FragmentError {
+    fn from(error: PacketError) -> Self {
+        FragmentError::Packet(error)
+    }
+}
+
+impl Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::TooBig { mtu } => write!(f, "packet too big for the MTU of {mtu} and can't be fragmented"),
+            FragmentError::MtuTooSmall(mtu) => write!(f, "an MTU of {mtu} is too small to fragment into"),
+            FragmentError::Packet(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for FragmentError {}
+
+// how much payload fits in each piece but the last, rounded down to the 8 bytes offsets count in
+fn piece_size(mtu: usize, headers: usize) -> Result<usize, FragmentError> {
+    let size = mtu.saturating_sub(headers) / 8 * 8;
+    if size == 0 {
+        return Err(FragmentError::MtuTooSmall(mtu));
+    }
+    Ok(size)
+}
+
+// The IPv4 options copied into every piece have the highest bit of their type set; the others
+// (like Record Route) are only in the first one.
+fn copied_options(options: &[u8]) -> Vec<u8> {
+    let mut copied = vec![];
+    let mut at = 0;
+    while at < options.len() {
+        let (kind, len) = match options[at] {
+            // End of Options
+            0 => break,
+            // No Operation
+            1 => (1, 1),
+            kind => (kind, options.get(at + 1).map_or(1, |&len| (len as usize).max(2))),
+        };
+        let option = &options[at..(at + len).min(options.len())];
+        if kind & 0x80 != 0 {
+            copied.extend(option);
+        }
+        at += len;
+    }
+    copied
+}
+
+// An IPv4 packet (header and payload) as pieces that each fit in the MTU, as a router does it.
+// A packet that fits is given back as it is.
+pub fn fragment_v4(packet: &[u8], mtu: usize) -> Result<Vec<Vec<u8>>, FragmentError> {
+    let header = Ipv4Header::from_bytes(packet)?;
+    need(packet, header.total_length as usize)?;
+    if header.total_length as usize <= mtu {
+        return Ok(vec![packet[..header.total_length as usize].to_vec()]);
+    }
+    if header.dont_fragment {
+        return Err(FragmentError::TooBig { mtu });
+    }
+    let payload = &packet[header.header_len()..header.total_length as usize];
+    let mut pieces = vec![];
+    let mut start = 0;
+    while start < payload.len() {
+        let mut piece = header.clone();
+        if start > 0 {
+            piece.options = copied_options(&header.options);
+        }
+        let end = (start + piece_size(mtu, piece.header_len())?).min(payload.len());
+        piece.fragment_offset = header.fragment_offset + (start / 8) as u16;
+        // the last piece of a packet that was already a piece isn't the last one of all
+        piece.more_fragments = end < payload.len() || header.more_fragments;
+        piece.total_length = (piece.header_len() + end - start) as u16;
+        piece.update_checksum();
+        let mut bytes = piece.to_bytes();
+        bytes.extend(&payload[start..end]);
+        pieces.push(bytes);
+        start = end;
+    }
+    Ok(pieces)
+}
+
+// Skips the extension headers of these kinds at the front of an IPv6 packet, cut to the length
+// its header says: where the first header of another kind starts, and where the Next Header
+// field pointing at it is. One running past the end of the packet makes the length a lie.
+fn skip_headers(packet: &[u8], kinds: &[u8]) -> Result<(usize, usize), PacketError> {
+    let (mut at, mut next_header_at) = (HEADER_LEN, 6);
+    while kinds.contains(&packet[next_header_at]) {
+        if at + 8 > packet.len() {
+            return Err(PacketError::BadLength(packet.len()));
+        }
+        next_header_at = at;
+        at += (packet[at + 1] as usize + 1) * 8;
+    }
+    if at > packet.len() {
+        return Err(PacketError::BadLength(packet.len()));
+    }
+    Ok((at, next_header_at))
+}
+
+// where the extension headers that stay in every piece end, and where the Next Header field
+// pointing past them is
+fn unfragmentable_part(packet: &[u8]) -> Result<(usize, usize), PacketError> {
+    skip_headers(packet, &[HOP_BY_HOP, ROUTING])
+}
+
+// An IPv6 packet as pieces that each fit in the MTU, as its source does it, all with this
+// identification. A packet that fits is given back as it is.
+pub fn fragment_v6(packet: &[u8], mtu: usize, identification: u32) -> Result<Vec<Vec<u8>>, FragmentError> {
+    let header = Ipv6Header::from_bytes(packet)?;
+    let total_length = HEADER_LEN + header.payload_length as usize;
+    need(packet, total_length)?;
+    if total_length <= mtu {
+        return Ok(vec![packet[..total_length].to_vec()]);
+    }
+    let (unfragmentable, next_header_at) = unfragmentable_part(&packet[..total_length])?;
+    let payload = &packet[unfragmentable..total_length];
+    let size = piece_size(mtu, unfragmentable + 8)?;
+    let mut pieces = vec![];
+    for start in (0..payload.len()).step_by(size) {
+        let end = (start + size).min(payload.len());
+        let mut bytes = packet[..unfragmentable].to_vec();
+        let next_header = std::mem::replace(&mut bytes[next_header_at], FRAGMENT);
+        let offset_and_more = ((start / 8) as u16) << 3 | (end < payload.len()) as u16;
+        bytes.extend([next_header, 0]);
+        bytes.extend(offset_and_more.to_be_bytes());
+        bytes.extend(identification.to_be_bytes());
+        bytes.extend(&payload[start..end]);
+        let payload_length = ((bytes.len() - HEADER_LEN) as u16).to_be_bytes();
+        bytes[4..6].copy_from_slice(&payload_length);
+        pieces.push(bytes);
+    }
+    Ok(pieces)
+}
+
+// what a piece says about itself
+struct Piece {
+    // (source, destination, protocol, identification): the pieces of one packet have all four
+    // the same
+    key : (IpAddr, IpAddr, u8, u32),
+    // the header of the whole packet, if this is the first piece
+    header : Option<Vec<u8>>,
+    offset : usize,
+    more : bool,
+    data : Vec<u8>,
+}
+
+fn piece_v4(packet: &[u8]) -> Result<Option<Piece>, PacketError> {
+    let header = Ipv4Header::from_bytes(packet)?;
+    need(packet, header.total_length as usize)?;
+    if header.fragment_offset == 0 && !header.more_fragments {
+        return Ok(None);
+    }
+    let data = packet[header.header_len()..header.total_length as usize].to_vec();
+    let key = (header.source.into(), header.destination.into(), header.protocol, header.identification as u32);
+    let offset = header.fragment_offset as usize * 8;
+    let more = header.more_fragments;
+    let first = (offset == 0).then(|| {
+        let mut whole = header;
+        whole.more_fragments = false;
+        whole.to_bytes()
+    });
+    Ok(Some(Piece { key, header : first, offset, more, data }))
+}
+
+fn piece_v6(packet: &[u8]) -> Result<Option<Piece>, PacketError> {
+    let header = Ipv6Header::from_bytes(packet)?;
+    let total_length = HEADER_LEN + header.payload_length as usize;
+    need(packet, total_length)?;
+    // the Fragment header may come after a Destination Options header too
+    let (at, next_header_at) = skip_headers(&packet[..total_length], &[HOP_BY_HOP, ROUTING, DESTINATION_OPTIONS])?;
+    if packet[next_header_at] != FRAGMENT {
+        return Ok(None);
+    }
+    if at + 8 > total_length {
+        return Err(PacketError::BadLength(total_length));
+    }
+    let offset_and_more = u16::from_be_bytes([packet[at + 2], packet[at + 3]]);
+    let identification = u32::from_be_bytes([packet[at + 4], packet[at + 5], packet[at + 6], packet[at + 7]]);
+    let offset = (offset_and_more >> 3) as usize * 8;
+    let first = (offset == 0).then(|| {
+        let mut whole = packet[..at].to_vec();
+        whole[next_header_at] = packet[at];
+        whole
+    });
+    let key = (header.source.into(), header.destination.into(), packet[at], identification);
+    Ok(Some(Piece { key, header : first, offset, more : offset_and_more & 1 == 1, data : packet[at + 8..total_length].to_vec() }))
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    key : (IpAddr, IpAddr, u8, u32),
+    since : Instant,
+    header : Option<Vec<u8>>,
+    // (offset, data), in the order they came
+    pieces : Vec<(usize, Vec<u8>)>,
+    // known once the last piece came
+    length : Option<usize>,
+}
+
+impl Pending {
+    // would the whole packet be longer than its length field can say? 65535 bytes at most, the
+    // header included for IPv4 and the fixed header left out for IPv6
+    fn too_big(&self) -> bool {
+        let Some(header) = &self.header else {
+            return false;
+        };
+        let end = self.pieces.iter().map(|(offset, data)| offset + data.len()).max().unwrap_or(0);
+        let limit = if header[0] >> 4 == 4 { u16::MAX as usize } else { u16::MAX as usize + HEADER_LEN };
+        header.len() + end > limit
+    }
+
+    // the whole packet, if nothing is missing anymore (and it isn't too_big, so the lengths fit)
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let (header, length) = (self.header.as_ref()?, self.length?);
+        let mut pieces: Vec<_> = self.pieces.iter().collect();
+        pieces.sort_by_key(|(offset, _)| *offset);
+        let mut payload: Vec<u8> = Vec::with_capacity(length);
+        for (offset, data) in pieces {
+            if *offset != payload.len() {
+                return None;
+            }
+            payload.extend(data);
+        }
+        if payload.len() != length {
+            return None;
+        }
+        let mut packet = header.clone();
+        if packet[0] >> 4 == 4 {
+            let mut whole = Ipv4Header::from_bytes(&packet).ok()?;
+            whole.total_length = (whole.header_len() + length) as u16;
+            whole.update_checksum();
+            packet = whole.to_bytes();
+        } else {
+            let payload_length = ((packet.len() - HEADER_LEN + length) as u16).to_be_bytes();
+            packet[4..6].copy_from_slice(&payload_length);
+        }
+        packet.extend(payload);
+        Some(packet)
+    }
+}
+
+// The pieces a host has received of the packets not complete yet, IPv4 and IPv6 alike
+#[derive(Debug, Clone)]
+pub struct Reassembly {
+    pub timeout : Duration,
+    pending : Vec<Pending>,
+    // packets given up on, for overlapping, for being too long or for taking too long
+    pub dropped : usize,
+}
+
+impl Default for Reassembly {
+    fn default() -> Self {
+        Reassembly { timeout : DEFAULT_REASSEMBLY_TIMEOUT, pending : vec![], dropped : 0 }
+    }
+}
+
+impl Reassembly {
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Reassembly { timeout, ..Reassembly::default() }
+    }
+
+    // how many packets are waiting for pieces
+    pub fn waiting(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Takes a packet as it arrived. Gives back the whole packet once its last missing piece
+    // came, or right away if it wasn't a piece at all; None while pieces are still missing.
+    pub fn receive(&mut self, packet: &[u8], now: Instant) -> Result<Option<Vec<u8>>, PacketError> {
+        self.expire(now);
+        need(packet, 1)?;
+        let piece = match packet[0] >> 4 {
+            4 => piece_v4(packet)?,
+            6 => piece_v6(packet)?,
+            version => return Err(PacketError::WrongVersion(version)),
+        };
+        let Some(piece) = piece else {
+            return Ok(Some(packet.to_vec()));
+        };
+        let index = match self.pending.iter().position(|pending| pending.key == piece.key) {
+            Some(index) => index,
+            None => {
+                self.pending.push(Pending { key : piece.key, since : now, header : None, pieces : vec![], length : None });
+                self.pending.len() - 1
+            }
+        };
+        let pending = &mut self.pending[index];
+        let (start, end) = (piece.offset, piece.offset + piece.data.len());
+        if pending.pieces.iter().any(|(offset, data)| (*offset, offset + data.len()) == (start, end)) {
+            return Ok(None);
+        }
+        let overlaps = pending.pieces.iter().any(|(offset, data)| start < offset + data.len() && *offset < end);
+        let beyond_the_end = pending.length.is_some_and(|length| end > length || !piece.more && end != length);
+        if overlaps || beyond_the_end {
+            self.pending.remove(index);
+            self.dropped += 1;
+            return Ok(None);
+        }
+        if !piece.more {
+            pending.length = Some(end);
+        }
+        if piece.header.is_some() {
+            pending.header = piece.header;
+        }
+        pending.pieces.push((start, piece.data));
+        if pending.too_big() {
+            self.pending.remove(index);
+            self.dropped += 1;
+            return Ok(None);
+        }
+        let whole = pending.assemble();
+        if whole.is_some() {
+            self.pending.remove(index);
+        }
+        Ok(whole)
+    }
+
+    // throws away the packets still missing pieces after the timeout, and says how many
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|pending| now.duration_since(pending.since) < self.timeout);
+        let expired = before - self.pending.len();
+        self.dropped += expired;
+        expired
+    }
+}
+
+#[test]
+fn pieces_put_back_together_in_any_order() {
+    use super::ipv6::ExtensionHeader;
+    use super::IPPROTO_UDP;
+
+    let data: Vec<u8> = (0..=255).cycle().take(3000).collect();
+    let mut header = Ipv4Header::new("192.0.2.1".parse().unwrap(), "198.51.100.1".parse().unwrap(), IPPROTO_UDP, 3000);
+    header.identification = 0x1234;
+    // a Security option (copied) and a Record Route (first piece only)
+    header.options = vec![0x82, 4, 0, 0, 7, 3, 4];
+    header.total_length += 8;
+    header.update_checksum();
+    let mut packet = header.to_bytes();
+    packet.extend(&data);
+
+    // a router fragments it for an MTU of 1500, the pieces come in backwards
+    let pieces = fragment_v4(&packet, 1500).unwrap();
+    assert_eq!(pieces.iter().map(Vec::len).collect::<Vec<_>>(), [1500, 1496, 80]);
+    let second = Ipv4Header::from_bytes(&pieces[1]).unwrap();
+    assert_eq!((second.options.len(), second.fragment_offset, second.more_fragments), (4, 184, true));
+    assert!(second.has_valid_checksum());
+    let start = Instant::now();
+    let mut host = Reassembly::default();
+    assert_eq!(host.receive(&pieces[2], start), Ok(None));
+    assert_eq!(host.receive(&pieces[0], start), Ok(None));
+    assert_eq!(host.receive(&pieces[0], start), Ok(None));
+    assert_eq!(host.receive(&pieces[1], start), Ok(Some(packet.clone())));
+    assert_eq!(host.waiting(), 0);
+
+    // with Don't Fragment the router refuses, and a packet that fits goes as it is
+    let mut refused = header.clone();
+    refused.dont_fragment = true;
+    let mut bytes = refused.to_bytes();
+    bytes.extend(&data);
+    assert_eq!(fragment_v4(&bytes, 1500), Err(FragmentError::TooBig { mtu : 1500 }));
+    assert_eq!(fragment_v4(&bytes, 4000), Ok(vec![bytes]));
+    assert_eq!(fragment_v4(&packet, 30), Err(FragmentError::MtuTooSmall(30)));
+
+    // a piece overlapping another one (not just the same one again) throws the packet away
+    let mut overlapping = Ipv4Header::from_bytes(&pieces[1]).unwrap();
+    overlapping.fragment_offset -= 1;
+    let mut bytes = overlapping.to_bytes();
+    bytes.extend(&pieces[1][24..]);
+    host.receive(&pieces[0], start).unwrap();
+    assert_eq!(host.receive(&bytes, start), Ok(None));
+    assert_eq!((host.waiting(), host.dropped), (0, 1));
+
+    // pieces that add up to more than 65535 bytes are thrown away, not wrapped around
+    let piece_at = |fragment_offset: u16, more_fragments: bool, length: usize| {
+        let mut piece = Ipv4Header::new("192.0.2.1".parse().unwrap(), "198.51.100.1".parse().unwrap(), IPPROTO_UDP, length as u16);
+        (piece.identification, piece.fragment_offset, piece.more_fragments) = (0x5678, fragment_offset, more_fragments);
+        piece.update_checksum();
+        [piece.to_bytes(), vec![0; length]].concat()
+    };
+    assert_eq!(host.receive(&piece_at(8189, false, 200), start), Ok(None));
+    assert_eq!(host.receive(&piece_at(0, true, 1480), start), Ok(None));
+    assert_eq!((host.waiting(), host.dropped), (0, 2));
+
+    // IPv6: the source fragments for the minimum MTU, keeping the Hop-by-Hop header in front
+    let (next_header, mut v6) = super::ipv6::write_extension_headers(&[ExtensionHeader::HopByHop(vec![1, 4, 0, 0, 0, 0])], IPPROTO_UDP);
+    v6.extend(&data);
+    let header = Ipv6Header::new("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap(), next_header, v6.len() as u16);
+    let packet = [header.to_bytes(), v6].concat();
+    let pieces = fragment_v6(&packet, 1280, 7).unwrap();
+    assert_eq!(pieces.len(), 3);
+    assert!(pieces.iter().all(|piece| piece.len() <= 1280));
+    let (chain, protocol, _) = super::ipv6::read_extension_headers(pieces[1][6], &pieces[1][HEADER_LEN..]).unwrap();
+    assert_eq!(chain[1], ExtensionHeader::Fragment { offset : 153, more_fragments : true, identification : 7 });
+    assert_eq!(protocol, IPPROTO_UDP);
+    // the last piece comes first, and the rest too late
+    let mut host = Reassembly::with_timeout(Duration::from_secs(60));
+    assert_eq!(host.receive(&pieces[2], start), Ok(None));
+    assert_eq!(host.receive(&pieces[0], start + Duration::from_secs(30)), Ok(None));
+    assert_eq!(host.receive(&pieces[1], start + Duration::from_secs(61)), Ok(None));
+    assert_eq!((host.waiting(), host.dropped), (1, 1));
+    // in time, they make the packet again
+    let mut host = Reassembly::default();
+    for piece in [&pieces[1], &pieces[2]] {
+        assert_eq!(host.receive(piece, start), Ok(None));
+    }
+    assert_eq!(host.receive(&pieces[0], start), Ok(Some(packet)));
+}
+
+#[test]
+fn headers_longer_than_the_packet_are_refused() {
+    use super::IPPROTO_UDP;
+
+    let (source, destination) = ("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap());
+    // a Hop-by-Hop header that says it is 1608 bytes long, in a packet of 1440, with Ethernet
+    // padding or rubbish after it
+    let mut packet = Ipv6Header::new(source, destination, HOP_BY_HOP, 1400).to_bytes();
+    packet.extend([IPPROTO_UDP, 200]);
+    packet.resize(2000, 0);
+    assert_eq!(fragment_v6(&packet, 1280, 7), Err(FragmentError::Packet(PacketError::BadLength(1440))));
+    assert_eq!(Reassembly::default().receive(&packet, Instant::now()), Err(PacketError::BadLength(1440)));
+
+    // a Fragment header cut short by the payload length
+    let mut piece = Ipv6Header::new(source, destination, FRAGMENT, 4).to_bytes();
+    piece.extend([IPPROTO_UDP, 0, 0, 1, 0, 0, 0, 7]);
+    assert_eq!(Reassembly::default().receive(&piece, Instant::now()), Err(PacketError::BadLength(44)));
+}
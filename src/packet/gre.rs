@@ -0,0 +1,108 @@
+//! The GRE header (RFC 2784, with the key and sequence number of RFC 2890): what is inside is a
+//! whole other packet, and the header only says which kind, with an EtherType.
+//!
+//! ```text
+//! | C | 0 | K | S | reserved (9) | version (3) | protocol type (16) |
+//! | checksum (16) | reserved (16) |       if C
+//! | key (32) |                            if K
+//! | sequence number (32) |                if S
+//! ```
+//!
+//! So it is 4 bytes, and up to 12 more. The key tells apart several tunnels between the same two
+//! endpoints; the version is always 0.
+use super::{need, PacketError};
+
+pub const MIN_HEADER_LEN: usize = 4;
+
+const CHECKSUM_PRESENT: u8 = 0x80;
+const KEY_PRESENT: u8 = 0x20;
+const SEQUENCE_PRESENT: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GreHeader {
+    // the EtherType of what is inside, like 0x0800 for IPv4
+    pub protocol_type : u16,
+    // kept as it was read, not worked out
+    pub checksum : Option<u16>,
+    pub key : Option<u32>,
+    pub sequence : Option<u32>,
+}
+
+impl GreHeader {
+    pub fn new(protocol_type: u16) -> Self {
+        GreHeader { protocol_type, checksum : None, key : None, sequence : None }
+    }
+
+    pub fn with_key(mut self, key: u32) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u32) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    pub fn header_len(&self) -> usize {
+        MIN_HEADER_LEN + [self.checksum.is_some(), self.key.is_some(), self.sequence.is_some()].iter().filter(|&&present| present).count() * 4
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        for (present, bit) in [(self.checksum.is_some(), CHECKSUM_PRESENT), (self.key.is_some(), KEY_PRESENT), (self.sequence.is_some(), SEQUENCE_PRESENT)] {
+            if present {
+                flags |= bit;
+            }
+        }
+        let mut bytes = vec![flags, 0];
+        bytes.extend(self.protocol_type.to_be_bytes());
+        if let Some(checksum) = self.checksum {
+            bytes.extend(checksum.to_be_bytes());
+            bytes.extend([0, 0]);
+        }
+        for word in [self.key, self.sequence].into_iter().flatten() {
+            bytes.extend(word.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, MIN_HEADER_LEN)?;
+        let version = bytes[1] & 0x07;
+        if version != 0 {
+            return Err(PacketError::WrongVersion(version));
+        }
+        let mut header = GreHeader::new(u16::from_be_bytes([bytes[2], bytes[3]]));
+        let mut at = MIN_HEADER_LEN;
+        let mut word = |present: bool| -> Result<Option<u32>, PacketError> {
+            if !present {
+                return Ok(None);
+            }
+            need(bytes, at + 4)?;
+            at += 4;
+            Ok(Some(u32::from_be_bytes([bytes[at - 4], bytes[at - 3], bytes[at - 2], bytes[at - 1]])))
+        };
+        header.checksum = word(bytes[0] & CHECKSUM_PRESENT != 0)?.map(|word| (word >> 16) as u16);
+        header.key = word(bytes[0] & KEY_PRESENT != 0)?;
+        header.sequence = word(bytes[0] & SEQUENCE_PRESENT != 0)?;
+        Ok(header)
+    }
+}
+
+#[test]
+fn gre_headers_grow_with_their_flags() {
+    use crate::link::ETHERTYPE_IPV4;
+
+    let plain = GreHeader::new(ETHERTYPE_IPV4);
+    assert_eq!(plain.to_bytes(), [0, 0, 0x08, 0x00]);
+    let keyed = plain.with_key(0x0102_0304).with_sequence(7);
+    assert_eq!(keyed.to_bytes(), [0x30, 0, 0x08, 0x00, 1, 2, 3, 4, 0, 0, 0, 7]);
+    assert_eq!((keyed.header_len(), GreHeader::from_bytes(&keyed.to_bytes())), (12, Ok(keyed)));
+
+    // a checksum comes before the key
+    let captured = [0xa0, 0, 0x86, 0xdd, 0xbe, 0xef, 0, 0, 0, 0, 0, 42];
+    assert_eq!(GreHeader::from_bytes(&captured), Ok(GreHeader { checksum : Some(0xbeef), ..GreHeader::new(0x86dd).with_key(42) }));
+    assert_eq!(GreHeader::from_bytes(&captured[..8]), Err(PacketError::Truncated { needed : 12, got : 8 }));
+    // PPTP's enhanced GRE is version 1
+    assert_eq!(GreHeader::from_bytes(&[0x30, 0x81, 0x88, 0x0b]), Err(PacketError::WrongVersion(1)));
+}
@@ -0,0 +1,179 @@
+//! ICMP (RFC 792) and ICMPv6 (RFC 4443) messages: the pings and the errors.
+//! Both start with a type, a code and a checksum, and then 4 bytes whose meaning depends on the
+//! type (an identifier and a sequence number for a ping, an MTU, or nothing). An error carries
+//! the start of the packet that caused it, so the sender can tell which one that was.
+//!
+//! The two versions mostly differ in their numbers, and in that "packet too big" is a
+//! destination unreachable with code 4 in ICMP, but a type of its own in ICMPv6, where routers
+//! never fragment. ICMP's checksum only covers the message; ICMPv6's covers part of the IPv6
+//! header too, so it can't be worked out here and is left at zero.
+use crate::bit_utils::{internet_checksum, verify_internet_checksum};
+use super::{need, PacketError};
+
+pub const ECHO_REPLY: u8 = 0;
+pub const DESTINATION_UNREACHABLE: u8 = 3;
+pub const ECHO_REQUEST: u8 = 8;
+pub const TIME_EXCEEDED: u8 = 11;
+// the code of a destination unreachable that is really a packet too big
+pub const FRAGMENTATION_NEEDED: u8 = 4;
+
+pub const ICMPV6_DESTINATION_UNREACHABLE: u8 = 1;
+pub const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+pub const ICMPV6_TIME_EXCEEDED: u8 = 3;
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+
+// the type, code, checksum and the 4 bytes after them
+pub const HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IcmpMessage {
+    EchoRequest { identifier : u16, sequence : u16, data : Vec<u8> },
+    EchoReply { identifier : u16, sequence : u16, data : Vec<u8> },
+    // the code says why: no route, port unreachable, ... (the numbers differ between versions)
+    DestinationUnreachable { code : u8, original : Vec<u8> },
+    // code 0 is the hop limit running out, 1 the reassembly time
+    TimeExceeded { code : u8, original : Vec<u8> },
+    // the MTU of the link the packet didn't fit on
+    PacketTooBig { mtu : u32, original : Vec<u8> },
+}
+
+impl IcmpMessage {
+    // the (type, code, 4 bytes) of ICMP, the checksum still missing
+    fn fields_v4(&self) -> (u8, u8, [u8; 4]) {
+        match self {
+            IcmpMessage::EchoRequest { identifier, sequence, .. } => (ECHO_REQUEST, 0, echo_fields(*identifier, *sequence)),
+            IcmpMessage::EchoReply { identifier, sequence, .. } => (ECHO_REPLY, 0, echo_fields(*identifier, *sequence)),
+            IcmpMessage::DestinationUnreachable { code, .. } => (DESTINATION_UNREACHABLE, *code, [0; 4]),
+            IcmpMessage::TimeExceeded { code, .. } => (TIME_EXCEEDED, *code, [0; 4]),
+            // only 16 bits for the MTU here
+            IcmpMessage::PacketTooBig { mtu, .. } => (DESTINATION_UNREACHABLE, FRAGMENTATION_NEEDED, (*mtu as u16 as u32).to_be_bytes()),
+        }
+    }
+
+    fn fields_v6(&self) -> (u8, u8, [u8; 4]) {
+        match self {
+            IcmpMessage::EchoRequest { identifier, sequence, .. } => (ICMPV6_ECHO_REQUEST, 0, echo_fields(*identifier, *sequence)),
+            IcmpMessage::EchoReply { identifier, sequence, .. } => (ICMPV6_ECHO_REPLY, 0, echo_fields(*identifier, *sequence)),
+            IcmpMessage::DestinationUnreachable { code, .. } => (ICMPV6_DESTINATION_UNREACHABLE, *code, [0; 4]),
+            IcmpMessage::TimeExceeded { code, .. } => (ICMPV6_TIME_EXCEEDED, *code, [0; 4]),
+            IcmpMessage::PacketTooBig { mtu, .. } => (ICMPV6_PACKET_TOO_BIG, 0, mtu.to_be_bytes()),
+        }
+    }
+
+    // the data of a ping, or the packet an error is about
+    pub fn body(&self) -> &[u8] {
+        match self {
+            IcmpMessage::EchoRequest { data, .. } | IcmpMessage::EchoReply { data, .. } => data,
+            IcmpMessage::DestinationUnreachable { original, .. }
+            | IcmpMessage::TimeExceeded { original, .. }
+            | IcmpMessage::PacketTooBig { original, .. } => original,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        !matches!(self, IcmpMessage::EchoRequest { .. } | IcmpMessage::EchoReply { .. })
+    }
+
+    // the answer to a ping, None for anything else
+    pub fn reply(&self) -> Option<IcmpMessage> {
+        match self {
+            IcmpMessage::EchoRequest { identifier, sequence, data } => Some(IcmpMessage::EchoReply { identifier : *identifier, sequence : *sequence, data : data.clone() }),
+            _ => None,
+        }
+    }
+
+    // as an ICMP message, checksum included
+    pub fn to_icmp(&self) -> Vec<u8> {
+        let mut bytes = message_bytes(self.fields_v4(), self.body());
+        let sum = internet_checksum(&bytes);
+        bytes[2..4].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    // as an ICMPv6 message, with a zero checksum: it covers a pseudo-header too (see checksum)
+    pub fn to_icmpv6(&self) -> Vec<u8> {
+        message_bytes(self.fields_v6(), self.body())
+    }
+
+    // a message with a wrong checksum is refused
+    pub fn from_icmp(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, HEADER_LEN)?;
+        if !verify_internet_checksum(bytes) {
+            return Err(PacketError::BadChecksum);
+        }
+        let (identifier, sequence, body) = echo_of(bytes);
+        Ok(match (bytes[0], bytes[1]) {
+            (ECHO_REQUEST, _) => IcmpMessage::EchoRequest { identifier, sequence, data : body },
+            (ECHO_REPLY, _) => IcmpMessage::EchoReply { identifier, sequence, data : body },
+            (DESTINATION_UNREACHABLE, FRAGMENTATION_NEEDED) => IcmpMessage::PacketTooBig { mtu : sequence as u32, original : body },
+            (DESTINATION_UNREACHABLE, code) => IcmpMessage::DestinationUnreachable { code, original : body },
+            (TIME_EXCEEDED, code) => IcmpMessage::TimeExceeded { code, original : body },
+            (other, _) => return Err(PacketError::UnknownType(other)),
+        })
+    }
+
+    // the checksum is not looked at, it needs the pseudo-header
+    pub fn from_icmpv6(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, HEADER_LEN)?;
+        let (identifier, sequence, body) = echo_of(bytes);
+        Ok(match (bytes[0], bytes[1]) {
+            (ICMPV6_ECHO_REQUEST, _) => IcmpMessage::EchoRequest { identifier, sequence, data : body },
+            (ICMPV6_ECHO_REPLY, _) => IcmpMessage::EchoReply { identifier, sequence, data : body },
+            (ICMPV6_DESTINATION_UNREACHABLE, code) => IcmpMessage::DestinationUnreachable { code, original : body },
+            (ICMPV6_PACKET_TOO_BIG, _) => {
+                let mtu = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+                IcmpMessage::PacketTooBig { mtu, original : body }
+            }
+            (ICMPV6_TIME_EXCEEDED, code) => IcmpMessage::TimeExceeded { code, original : body },
+            (other, _) => return Err(PacketError::UnknownType(other)),
+        })
+    }
+}
+
+fn echo_fields(identifier: u16, sequence: u16) -> [u8; 4] {
+    let [a, b] = identifier.to_be_bytes();
+    let [c, d] = sequence.to_be_bytes();
+    [a, b, c, d]
+}
+
+// the 4 bytes after the checksum as two numbers, and the rest
+fn echo_of(bytes: &[u8]) -> (u16, u16, Vec<u8>) {
+    (u16::from_be_bytes([bytes[4], bytes[5]]), u16::from_be_bytes([bytes[6], bytes[7]]), bytes[HEADER_LEN..].to_vec())
+}
+
+fn message_bytes((kind, code, rest): (u8, u8, [u8; 4]), body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![kind, code, 0, 0];
+    bytes.extend(rest);
+    bytes.extend(body);
+    bytes
+}
+
+#[test]
+fn pings_and_errors_in_both_versions() {
+    // `ping -c 1 192.0.2.1` from a Linux host: identifier 0x1c46, sequence 1, 8 bytes of data
+    let captured = [0x08, 0x00, 0x4a, 0x23, 0x1c, 0x46, 0x00, 0x01, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68];
+    let ping = IcmpMessage::from_icmp(&captured).unwrap();
+    assert_eq!(ping, IcmpMessage::EchoRequest { identifier : 0x1c46, sequence : 1, data : b"abcdefgh".to_vec() });
+    assert_eq!(ping.to_icmp(), captured);
+    let pong = ping.reply().unwrap();
+    assert_eq!(IcmpMessage::from_icmp(&pong.to_icmp()), Ok(pong.clone()));
+    assert_eq!(pong.to_icmp()[..4], [ECHO_REPLY, 0, 0x52, 0x23]);
+    assert_eq!(IcmpMessage::from_icmpv6(&pong.to_icmpv6()), Ok(pong));
+
+    // a packet too big is a different type in each version, and ICMP has 16 bits for the MTU
+    let too_big = IcmpMessage::PacketTooBig { mtu : 1280, original : vec![0x45; 28] };
+    assert!(too_big.is_error() && too_big.reply().is_none());
+    assert_eq!(too_big.to_icmp()[..2], [DESTINATION_UNREACHABLE, FRAGMENTATION_NEEDED]);
+    assert_eq!(too_big.to_icmp()[4..8], [0, 0, 0x05, 0x00]);
+    assert_eq!(too_big.to_icmpv6()[..2], [ICMPV6_PACKET_TOO_BIG, 0]);
+    for message in [too_big, IcmpMessage::TimeExceeded { code : 0, original : vec![0x60; 48] }] {
+        assert_eq!(IcmpMessage::from_icmp(&message.to_icmp()), Ok(message.clone()));
+        assert_eq!(IcmpMessage::from_icmpv6(&message.to_icmpv6()), Ok(message));
+    }
+
+    let mut corrupted = captured;
+    corrupted[9] ^= 1;
+    assert_eq!(IcmpMessage::from_icmp(&corrupted), Err(PacketError::BadChecksum));
+    assert_eq!(IcmpMessage::from_icmpv6(&[135, 0, 0, 0, 0, 0, 0, 0]), Err(PacketError::UnknownType(135)));
+}
@@ -0,0 +1,180 @@
+//! The IPv4 header of RFC 791:
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |Version|  IHL  |Type of Service|          Total Length         |
+//! |         Identification        |Flags|      Fragment Offset    |
+//! |  Time to Live |    Protocol   |         Header Checksum       |
+//! |                       Source Address                          |
+//! |                    Destination Address                        |
+//! |                    Options                    |    Padding    |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//!
+//! IHL is the header length in 32 bit words, so 5 without options and at most 15; the options
+//! are padded with zeros to a whole word. The checksum only covers the header, which is why
+//! every router has to redo it after taking one off the TTL.
+use std::net::Ipv4Addr;
+
+use crate::bit_utils::{internet_checksum, verify_internet_checksum};
+use super::{need, PacketError};
+
+// without options
+pub const MIN_HEADER_LEN: usize = 20;
+pub const MAX_HEADER_LEN: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub type_of_service : u8,
+    // header and payload, in bytes
+    pub total_length : u16,
+    pub identification : u16,
+    pub dont_fragment : bool,
+    pub more_fragments : bool,
+    // where the payload of this fragment goes in the original one, in units of 8 bytes
+    pub fragment_offset : u16,
+    pub time_to_live : u8,
+    pub protocol : u8,
+    pub checksum : u16,
+    pub source : Ipv4Addr,
+    pub destination : Ipv4Addr,
+    // as they are on the wire, without the padding
+    pub options : Vec<u8>,
+}
+
+impl Ipv4Header {
+    // no options, not a fragment, and the checksum already right
+    pub fn new(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, payload_len: u16) -> Self {
+        let mut header = Ipv4Header {
+            type_of_service : 0,
+            total_length : MIN_HEADER_LEN as u16 + payload_len,
+            identification : 0,
+            dont_fragment : false,
+            more_fragments : false,
+            fragment_offset : 0,
+            time_to_live : 64,
+            protocol,
+            checksum : 0,
+            source,
+            destination,
+            options : vec![],
+        };
+        header.update_checksum();
+        header
+    }
+
+    // with the options padded to a whole number of words
+    pub fn header_len(&self) -> usize {
+        MIN_HEADER_LEN + self.options.len().div_ceil(4) * 4
+    }
+
+    pub fn payload_len(&self) -> usize {
+        (self.total_length as usize).saturating_sub(self.header_len())
+    }
+
+    // the checksum this header should have, whatever is in the field now
+    pub fn compute_checksum(&self) -> u16 {
+        let mut bytes = self.to_bytes();
+        bytes[10..12].fill(0);
+        internet_checksum(&bytes)
+    }
+
+    // to be called after changing any field
+    pub fn update_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    pub fn has_valid_checksum(&self) -> bool {
+        verify_internet_checksum(&self.to_bytes())
+    }
+
+    // exactly header_len() bytes, with the checksum field as it is
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let ihl = (self.header_len() / 4) as u8;
+        let flags = (self.dont_fragment as u16) << 14 | (self.more_fragments as u16) << 13;
+        let mut bytes = vec![4 << 4 | ihl, self.type_of_service];
+        bytes.extend(self.total_length.to_be_bytes());
+        bytes.extend(self.identification.to_be_bytes());
+        bytes.extend((flags | self.fragment_offset & 0x1fff).to_be_bytes());
+        bytes.extend([self.time_to_live, self.protocol]);
+        bytes.extend(self.checksum.to_be_bytes());
+        bytes.extend(self.source.octets());
+        bytes.extend(self.destination.octets());
+        bytes.extend(&self.options);
+        bytes.resize(self.header_len(), 0);
+        bytes
+    }
+
+    // Reads the header at the start of these bytes; the payload is what comes after
+    // header_len(), up to total_length. The checksum is not checked, see has_valid_checksum.
+    // The padding is part of the options, since it can't be told apart from an End of Options.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, MIN_HEADER_LEN)?;
+        let version = bytes[0] >> 4;
+        if version != 4 {
+            return Err(PacketError::WrongVersion(version));
+        }
+        let header_len = (bytes[0] & 0x0f) as usize * 4;
+        if header_len < MIN_HEADER_LEN {
+            return Err(PacketError::BadLength(header_len));
+        }
+        need(bytes, header_len)?;
+        let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let total_length = word(2);
+        if (total_length as usize) < header_len {
+            return Err(PacketError::BadLength(total_length as usize));
+        }
+        let address = |at: usize| Ipv4Addr::new(bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]);
+        Ok(Ipv4Header {
+            type_of_service : bytes[1],
+            total_length,
+            identification : word(4),
+            dont_fragment : word(6) & 0x4000 != 0,
+            more_fragments : word(6) & 0x2000 != 0,
+            fragment_offset : word(6) & 0x1fff,
+            time_to_live : bytes[8],
+            protocol : bytes[9],
+            checksum : word(10),
+            source : address(12),
+            destination : address(16),
+            options : bytes[MIN_HEADER_LEN..header_len].to_vec(),
+        })
+    }
+}
+
+#[test]
+fn headers_read_and_written_byte_for_byte() {
+    use super::IPPROTO_UDP;
+
+    // a captured UDP packet from 192.168.0.1 to 192.168.0.199, header only
+    let captured = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+    let header = Ipv4Header::from_bytes(&captured).unwrap();
+    assert_eq!((header.source, header.destination), (Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(192, 168, 0, 199)));
+    assert_eq!((header.protocol, header.time_to_live, header.total_length, header.payload_len()), (IPPROTO_UDP, 64, 115, 95));
+    assert!(header.dont_fragment && !header.more_fragments);
+    assert!(header.has_valid_checksum());
+    assert_eq!(header.to_bytes(), captured);
+
+    // a router takes one off the TTL, and the checksum has to follow
+    let mut forwarded = header.clone();
+    forwarded.time_to_live -= 1;
+    assert!(!forwarded.has_valid_checksum());
+    forwarded.update_checksum();
+    assert_eq!(forwarded.checksum, 0xb961);
+    assert_eq!(Ipv4Header::new(header.source, header.destination, IPPROTO_UDP, 95).total_length, 115);
+
+    // a Router Alert option makes the header one word longer
+    let mut alert = Ipv4Header::new(header.source, Ipv4Addr::new(224, 0, 0, 22), 2, 8);
+    alert.options = vec![0x94, 0x04, 0x00, 0x00];
+    alert.total_length += 4;
+    alert.update_checksum();
+    let bytes = alert.to_bytes();
+    assert_eq!((bytes.len(), bytes[0]), (24, 0x46));
+    assert_eq!(Ipv4Header::from_bytes(&bytes).unwrap(), alert);
+
+    assert_eq!(Ipv4Header::from_bytes(&captured[..12]), Err(PacketError::Truncated { needed : 20, got : 12 }));
+    assert_eq!(Ipv4Header::from_bytes(&[0x44; 20]), Err(PacketError::BadLength(16)));
+    assert_eq!(Ipv4Header::from_bytes(&[0x65; 20]), Err(PacketError::WrongVersion(6)));
+}
@@ -0,0 +1,197 @@
+//! The IPv6 header of RFC 8200, and the extension headers that can come after it.
+//!
+//! ```text
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |Version| Traffic Class |           Flow Label                  |
+//! |         Payload Length        |  Next Header  |   Hop Limit   |
+//! |                  Source Address (16 bytes)                    |
+//! |               Destination Address (16 bytes)                  |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//!
+//! The header is always 40 bytes, with no options and no checksum. Anything extra goes in
+//! extension headers: Next Header says what follows, either one of them (which has its own
+//! Next Header) or the transport protocol, so they make a chain ending at TCP, UDP or ICMPv6.
+use std::net::Ipv6Addr;
+
+use super::{need, PacketError};
+
+pub const HEADER_LEN: usize = 40;
+
+// the Next Header values of the extension headers
+pub const HOP_BY_HOP: u8 = 0;
+pub const ROUTING: u8 = 43;
+pub const FRAGMENT: u8 = 44;
+pub const DESTINATION_OPTIONS: u8 = 60;
+// nothing follows
+pub const NO_NEXT_HEADER: u8 = 59;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv6Header {
+    pub traffic_class : u8,
+    // 20 bits, the same for every packet of a flow
+    pub flow_label : u32,
+    // everything after this header, extension headers included
+    pub payload_length : u16,
+    pub next_header : u8,
+    pub hop_limit : u8,
+    pub source : Ipv6Addr,
+    pub destination : Ipv6Addr,
+}
+
+impl Ipv6Header {
+    pub fn new(source: Ipv6Addr, destination: Ipv6Addr, next_header: u8, payload_length: u16) -> Self {
+        Ipv6Header { traffic_class : 0, flow_label : 0, payload_length, next_header, hop_limit : 64, source, destination }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let first = 6 << 28 | (self.traffic_class as u32) << 20 | self.flow_label & 0x000f_ffff;
+        let mut bytes = first.to_be_bytes().to_vec();
+        bytes.extend(self.payload_length.to_be_bytes());
+        bytes.extend([self.next_header, self.hop_limit]);
+        bytes.extend(self.source.octets());
+        bytes.extend(self.destination.octets());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, HEADER_LEN)?;
+        let first = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let version = (first >> 28) as u8;
+        if version != 6 {
+            return Err(PacketError::WrongVersion(version));
+        }
+        let address = |at: usize| Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[at..at + 16]).unwrap());
+        Ok(Ipv6Header {
+            traffic_class : (first >> 20) as u8,
+            flow_label : first & 0x000f_ffff,
+            payload_length : u16::from_be_bytes([bytes[4], bytes[5]]),
+            next_header : bytes[6],
+            hop_limit : bytes[7],
+            source : address(8),
+            destination : address(24),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionHeader {
+    // options every router on the way has to look at, like the Router Alert of MLD
+    HopByHop(Vec<u8>),
+    // the addresses to go through on the way (segments_left of them still to visit), in the
+    // format of this routing type
+    Routing { routing_type : u8, segments_left : u8, data : Vec<u8> },
+    // only ever added by the source, since IPv6 routers don't fragment
+    Fragment { offset : u16, more_fragments : bool, identification : u32 },
+    // options only for the destination
+    DestinationOptions(Vec<u8>),
+}
+
+impl ExtensionHeader {
+    // its value in the Next Header field of whatever comes before it
+    pub fn number(&self) -> u8 {
+        match self {
+            ExtensionHeader::HopByHop(_) => HOP_BY_HOP,
+            ExtensionHeader::Routing { .. } => ROUTING,
+            ExtensionHeader::Fragment { .. } => FRAGMENT,
+            ExtensionHeader::DestinationOptions(_) => DESTINATION_OPTIONS,
+        }
+    }
+
+    // Everything is a multiple of 8 bytes, the second byte counting the ones after the first 8,
+    // so options are padded with Pad1 (zero) options to fit.
+    pub fn to_bytes(&self, next_header: u8) -> Vec<u8> {
+        let mut bytes = vec![next_header, 0];
+        match self {
+            ExtensionHeader::HopByHop(options) | ExtensionHeader::DestinationOptions(options) => bytes.extend(options),
+            ExtensionHeader::Routing { routing_type, segments_left, data } => {
+                bytes.extend([*routing_type, *segments_left]);
+                bytes.extend(data);
+            }
+            ExtensionHeader::Fragment { offset, more_fragments, identification } => {
+                bytes.extend((offset << 3 | *more_fragments as u16).to_be_bytes());
+                bytes.extend(identification.to_be_bytes());
+            }
+        }
+        bytes.resize(bytes.len().div_ceil(8) * 8, 0);
+        if !matches!(self, ExtensionHeader::Fragment { .. }) {
+            bytes[1] = (bytes.len() / 8 - 1) as u8;
+        }
+        bytes
+    }
+}
+
+// The extension headers of a chain starting with this Next Header, one after the other, until
+// something that isn't one. Gives back the headers, the protocol after them, and the number of
+// bytes they took, which is where that protocol starts.
+pub fn read_extension_headers(mut next_header: u8, bytes: &[u8]) -> Result<(Vec<ExtensionHeader>, u8, usize), PacketError> {
+    let mut headers = vec![];
+    let mut at = 0;
+    while matches!(next_header, HOP_BY_HOP | ROUTING | FRAGMENT | DESTINATION_OPTIONS) {
+        let rest = &bytes[at..];
+        need(rest, 8)?;
+        let len = if next_header == FRAGMENT { 8 } else { (rest[1] as usize + 1) * 8 };
+        need(rest, len)?;
+        headers.push(match next_header {
+            HOP_BY_HOP => ExtensionHeader::HopByHop(rest[2..len].to_vec()),
+            DESTINATION_OPTIONS => ExtensionHeader::DestinationOptions(rest[2..len].to_vec()),
+            ROUTING => ExtensionHeader::Routing { routing_type : rest[2], segments_left : rest[3], data : rest[4..len].to_vec() },
+            _ => {
+                let word = u16::from_be_bytes([rest[2], rest[3]]);
+                ExtensionHeader::Fragment {
+                    offset : word >> 3,
+                    more_fragments : word & 1 == 1,
+                    identification : u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]),
+                }
+            }
+        });
+        next_header = rest[0];
+        at += len;
+    }
+    Ok((headers, next_header, at))
+}
+
+// the other way around: the chain in front of this protocol, and the Next Header for it
+pub fn write_extension_headers(headers: &[ExtensionHeader], protocol: u8) -> (u8, Vec<u8>) {
+    let mut bytes = vec![];
+    for (index, header) in headers.iter().enumerate() {
+        let next_header = headers.get(index + 1).map_or(protocol, ExtensionHeader::number);
+        bytes.extend(header.to_bytes(next_header));
+    }
+    (headers.first().map_or(protocol, ExtensionHeader::number), bytes)
+}
+
+#[test]
+fn headers_and_their_extension_chain() {
+    use super::IPPROTO_ICMPV6;
+
+    // an MLD report as a host sends it: a Hop-by-Hop header with a Router Alert, then ICMPv6
+    let captured = [
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x24, 0x00, 0x01,
+        0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x02, 0x1b, 0x21, 0xff, 0xfe, 0x3c, 0x4d, 0x5e,
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16,
+        0x3a, 0x00, 0x05, 0x02, 0x00, 0x00, 0x01, 0x00,
+    ];
+    let header = Ipv6Header::from_bytes(&captured).unwrap();
+    assert_eq!((header.next_header, header.hop_limit, header.payload_length), (HOP_BY_HOP, 1, 36));
+    assert_eq!(header.destination, "ff02::16".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(header.to_bytes(), captured[..HEADER_LEN]);
+    let (extensions, protocol, len) = read_extension_headers(header.next_header, &captured[HEADER_LEN..]).unwrap();
+    assert_eq!(extensions, [ExtensionHeader::HopByHop(vec![0x05, 0x02, 0x00, 0x00, 0x01, 0x00])]);
+    assert_eq!((protocol, len), (IPPROTO_ICMPV6, 8));
+
+    // a second fragment after a routing header, and back
+    let chain = vec![
+        ExtensionHeader::Routing { routing_type : 4, segments_left : 1, data : vec![0; 20] },
+        ExtensionHeader::Fragment { offset : 185, more_fragments : true, identification : 0xdead_beef },
+    ];
+    let (first, bytes) = write_extension_headers(&chain, IPPROTO_ICMPV6);
+    assert_eq!((first, bytes.len(), bytes[1]), (ROUTING, 32, 2));
+    assert_eq!(read_extension_headers(first, &bytes), Ok((chain, IPPROTO_ICMPV6, 32)));
+    assert_eq!(read_extension_headers(first, &bytes[..30]), Err(PacketError::Truncated { needed : 8, got : 6 }));
+
+    let mut header = Ipv6Header::new("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap(), NO_NEXT_HEADER, 0);
+    (header.traffic_class, header.flow_label) = (0xb8, 0x12345);
+    assert_eq!(Ipv6Header::from_bytes(&header.to_bytes()), Ok(header));
+    assert_eq!(Ipv6Header::from_bytes(&[0x45; 40]), Err(PacketError::WrongVersion(4)));
+}
@@ -0,0 +1,414 @@
+//! A packet as the layers it is made of, each with its real header: maybe an Ethernet header,
+//! an IPv4 or IPv6 header, a TCP, UDP, ICMP or GRE header (or a tunneled IPv6 packet), and the
+//! payload. to_bytes() fills in
+//! what follows from the layers above (the lengths, the protocol numbers, the checksums), so
+//! changing one layer never leaves the others saying something else, and from_bytes() refuses
+//! a packet whose transport checksum doesn't add up.
+//!
+//! It is generic over the IP header, like the rest of the crate is over the address, so
+//! LayeredPacket<Ipv4Header> and LayeredPacket<Ipv6Header> are both a Packet to the NAT.
+use std::fmt::Debug;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+use super::checksum::PseudoHeader;
+use super::gre::GreHeader;
+use super::icmp::IcmpMessage;
+use super::ipv4::Ipv4Header;
+use super::ipv6::{read_extension_headers, Ipv6Header, HEADER_LEN};
+use super::tcp::TcpHeader;
+use super::udp::{read_datagram, UdpHeader};
+use super::{need, Packet, PacketError, IPPROTO_GRE, IPPROTO_ICMP, IPPROTO_ICMPV6, IPPROTO_IPV6, IPPROTO_TCP, IPPROTO_UDP};
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use crate::nat_v4::{NatAddress, Protocol};
+use crate::qos::{dscp_of, traffic_class, Ecn};
+
+// The network layer: what both IP headers have, and how each is written and read
+pub trait IpHeader: Clone + Debug + PartialEq {
+    type Address: NatAddress;
+    const ETHERTYPE: u16;
+    // the protocol number of ICMP for this version
+    const ICMP: u8;
+    // a header from one to the other, with the rest left to to_bytes() or the defaults
+    fn between(source: Self::Address, destination: Self::Address) -> Self;
+    fn source(&self) -> Self::Address;
+    fn destination(&self) -> Self::Address;
+    fn set_source(&mut self, source: Self::Address);
+    fn set_destination(&mut self, destination: Self::Address);
+    fn hop_limit(&self) -> u8;
+    fn set_hop_limit(&mut self, hop_limit: u8);
+    // the type of service or traffic class byte, as it is
+    fn traffic_class(&self) -> u8;
+    fn set_traffic_class(&mut self, traffic_class: u8);
+    // the header on the wire, in front of a payload of this protocol and length
+    fn header_bytes(&self, protocol: u8, payload_len: usize) -> Vec<u8>;
+    // the header, the protocol it carries, and where that is in the bytes
+    fn parse(bytes: &[u8]) -> Result<(Self, u8, Range<usize>), PacketError>;
+    fn icmp_bytes(message: &IcmpMessage) -> Vec<u8>;
+    fn parse_icmp(bytes: &[u8]) -> Result<IcmpMessage, PacketError>;
+    // what the transport checksum covers of this header, for a segment this long
+    fn pseudo_header(&self, protocol: u8, length: usize) -> PseudoHeader;
+
+    // the top six bits of the traffic class, what the QoS is decided on
+    fn dscp(&self) -> u8 {
+        dscp_of(self.traffic_class())
+    }
+    fn set_dscp(&mut self, dscp: u8) {
+        self.set_traffic_class(traffic_class(dscp, self.ecn()));
+    }
+    // the bottom two
+    fn ecn(&self) -> Ecn {
+        Ecn::of(self.traffic_class())
+    }
+    fn set_ecn(&mut self, ecn: Ecn) {
+        self.set_traffic_class(traffic_class(self.dscp(), ecn));
+    }
+}
+
+impl IpHeader for Ipv4Header {
+    type Address = Ipv4Addr;
+    const ETHERTYPE: u16 = ETHERTYPE_IPV4;
+    const ICMP: u8 = IPPROTO_ICMP;
+
+    fn between(source: Ipv4Addr, destination: Ipv4Addr) -> Self {
+        Ipv4Header::new(source, destination, 0, 0)
+    }
+    fn source(&self) -> Ipv4Addr {
+        self.source
+    }
+    fn destination(&self) -> Ipv4Addr {
+        self.destination
+    }
+    fn set_source(&mut self, source: Ipv4Addr) {
+        self.source = source;
+    }
+    fn set_destination(&mut self, destination: Ipv4Addr) {
+        self.destination = destination;
+    }
+    fn hop_limit(&self) -> u8 {
+        self.time_to_live
+    }
+    fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.time_to_live = hop_limit;
+    }
+    fn traffic_class(&self) -> u8 {
+        self.type_of_service
+    }
+    fn set_traffic_class(&mut self, traffic_class: u8) {
+        self.type_of_service = traffic_class;
+    }
+
+    fn header_bytes(&self, protocol: u8, payload_len: usize) -> Vec<u8> {
+        let mut header = self.clone();
+        header.protocol = protocol;
+        header.total_length = (header.header_len() + payload_len) as u16;
+        header.update_checksum();
+        header.to_bytes()
+    }
+
+    fn parse(bytes: &[u8]) -> Result<(Self, u8, Range<usize>), PacketError> {
+        let header = Ipv4Header::from_bytes(bytes)?;
+        need(bytes, header.total_length as usize)?;
+        let payload = header.header_len()..header.total_length as usize;
+        Ok((header.clone(), header.protocol, payload))
+    }
+
+    fn icmp_bytes(message: &IcmpMessage) -> Vec<u8> {
+        message.to_icmp()
+    }
+
+    fn parse_icmp(bytes: &[u8]) -> Result<IcmpMessage, PacketError> {
+        IcmpMessage::from_icmp(bytes)
+    }
+
+    fn pseudo_header(&self, protocol: u8, length: usize) -> PseudoHeader {
+        PseudoHeader::v4(self.source, self.destination, protocol, length)
+    }
+}
+
+// the extension headers are skipped when reading, and not written back
+impl IpHeader for Ipv6Header {
+    type Address = Ipv6Addr;
+    const ETHERTYPE: u16 = ETHERTYPE_IPV6;
+    const ICMP: u8 = IPPROTO_ICMPV6;
+
+    fn between(source: Ipv6Addr, destination: Ipv6Addr) -> Self {
+        Ipv6Header::new(source, destination, 0, 0)
+    }
+    fn source(&self) -> Ipv6Addr {
+        self.source
+    }
+    fn destination(&self) -> Ipv6Addr {
+        self.destination
+    }
+    fn set_source(&mut self, source: Ipv6Addr) {
+        self.source = source;
+    }
+    fn set_destination(&mut self, destination: Ipv6Addr) {
+        self.destination = destination;
+    }
+    fn hop_limit(&self) -> u8 {
+        self.hop_limit
+    }
+    fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.hop_limit = hop_limit;
+    }
+    fn traffic_class(&self) -> u8 {
+        self.traffic_class
+    }
+    fn set_traffic_class(&mut self, traffic_class: u8) {
+        self.traffic_class = traffic_class;
+    }
+
+    fn header_bytes(&self, protocol: u8, payload_len: usize) -> Vec<u8> {
+        Ipv6Header { next_header : protocol, payload_length : payload_len as u16, ..self.clone() }.to_bytes()
+    }
+
+    fn parse(bytes: &[u8]) -> Result<(Self, u8, Range<usize>), PacketError> {
+        let header = Ipv6Header::from_bytes(bytes)?;
+        let end = HEADER_LEN + header.payload_length as usize;
+        need(bytes, end)?;
+        let (_, protocol, extensions_len) = read_extension_headers(header.next_header, &bytes[HEADER_LEN..end])?;
+        Ok((header, protocol, HEADER_LEN + extensions_len..end))
+    }
+
+    fn icmp_bytes(message: &IcmpMessage) -> Vec<u8> {
+        message.to_icmpv6()
+    }
+
+    fn parse_icmp(bytes: &[u8]) -> Result<IcmpMessage, PacketError> {
+        IcmpMessage::from_icmpv6(bytes)
+    }
+
+    fn pseudo_header(&self, protocol: u8, length: usize) -> PseudoHeader {
+        PseudoHeader::v6(self.source, self.destination, protocol, length)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Tcp(TcpHeader),
+    // the length is worked out from the payload
+    Udp(UdpHeader),
+    // the whole message: a ping's data or an error's quote is in it, not in the payload
+    Icmp(IcmpMessage),
+    // a tunnel: the packet it carries is the payload
+    Gre(GreHeader),
+    // 6in4, no header of its own: the IPv6 packet is the payload
+    Ipv6,
+}
+
+impl Transport {
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            Transport::Tcp(_) => Protocol::Tcp,
+            Transport::Udp(_) => Protocol::Udp,
+            Transport::Icmp(_) => Protocol::Icmp,
+            Transport::Gre(_) => Protocol::Gre,
+            Transport::Ipv6 => Protocol::Ipv6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkLayer {
+    pub destination : MacAddr,
+    pub source : MacAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayeredPacket<H = Ipv4Header> {
+    // None for a packet not on a link yet, or taken off it
+    pub link : Option<LinkLayer>,
+    pub network : H,
+    pub transport : Transport,
+    pub payload : Vec<u8>,
+}
+
+impl<H: IpHeader> LayeredPacket<H> {
+    pub fn new(network: H, transport: Transport, payload: Vec<u8>) -> Self {
+        LayeredPacket { link : None, network, transport, payload }
+    }
+
+    pub fn with_link(mut self, destination: MacAddr, source: MacAddr) -> Self {
+        self.link = Some(LinkLayer { destination, source });
+        self
+    }
+
+    // the IP packet, without the link layer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (protocol, mut transport) = match &self.transport {
+            Transport::Tcp(header) => (IPPROTO_TCP, header.to_bytes()),
+            Transport::Udp(header) => (IPPROTO_UDP, UdpHeader::new(header.source_port, header.destination_port, self.payload.len()).to_bytes()),
+            Transport::Icmp(message) => (H::ICMP, H::icmp_bytes(message)),
+            Transport::Gre(header) => (IPPROTO_GRE, header.to_bytes()),
+            Transport::Ipv6 => (IPPROTO_IPV6, vec![]),
+        };
+        if !matches!(self.transport, Transport::Icmp(_)) {
+            transport.extend(&self.payload);
+        }
+        // the transport header was just written, so it can't be too short; ICMP over IPv4 has
+        // its checksum already, without a pseudo-header
+        let _ = self.network.pseudo_header(protocol, transport.len()).fill(&mut transport);
+        let mut bytes = self.network.header_bytes(protocol, transport.len());
+        bytes.extend(transport);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        let (network, protocol, range) = H::parse(bytes)?;
+        let body = &bytes[range];
+        network.pseudo_header(protocol, body.len()).verify(body)?;
+        let (transport, payload) = match protocol {
+            IPPROTO_TCP => {
+                let header = TcpHeader::from_bytes(body)?;
+                let payload = body[header.header_len()..].to_vec();
+                (Transport::Tcp(header), payload)
+            }
+            IPPROTO_UDP => {
+                let (header, payload) = read_datagram(body)?;
+                (Transport::Udp(header), payload.to_vec())
+            }
+            icmp if icmp == H::ICMP => (Transport::Icmp(H::parse_icmp(body)?), vec![]),
+            IPPROTO_GRE => {
+                let header = GreHeader::from_bytes(body)?;
+                (Transport::Gre(header), body[header.header_len()..].to_vec())
+            }
+            IPPROTO_IPV6 => (Transport::Ipv6, body.to_vec()),
+            other => return Err(PacketError::UnknownType(other)),
+        };
+        Ok(LayeredPacket::new(network, transport, payload))
+    }
+
+    // None without a link layer
+    pub fn to_frame(&self) -> Option<EthernetFrame> {
+        let link = self.link?;
+        Some(EthernetFrame::new(link.destination, link.source, H::ETHERTYPE, self.to_bytes()))
+    }
+
+    pub fn from_frame(frame: &EthernetFrame) -> Result<Self, PacketError> {
+        Ok(Self::from_bytes(&frame.payload)?.with_link(frame.destination, frame.source))
+    }
+}
+
+impl<H: IpHeader> Packet for LayeredPacket<H> {
+    type Address = H::Address;
+
+    fn protocol(&self) -> Protocol {
+        self.transport.protocol()
+    }
+    fn source_ip(&self) -> H::Address {
+        self.network.source()
+    }
+    fn source_port(&self) -> u16 {
+        match &self.transport {
+            Transport::Tcp(header) => header.source_port,
+            Transport::Udp(header) => header.source_port,
+            Transport::Icmp(message) => echo_identifier(message),
+            Transport::Gre(_) | Transport::Ipv6 => 0,
+        }
+    }
+    fn destination_ip(&self) -> H::Address {
+        self.network.destination()
+    }
+    fn destination_port(&self) -> u16 {
+        match &self.transport {
+            Transport::Tcp(header) => header.destination_port,
+            Transport::Udp(header) => header.destination_port,
+            Transport::Icmp(message) => echo_identifier(message),
+            Transport::Gre(_) | Transport::Ipv6 => 0,
+        }
+    }
+    fn hop_limit(&self) -> u8 {
+        self.network.hop_limit()
+    }
+    fn set_source(&mut self, ip: H::Address, port: u16) {
+        self.network.set_source(ip);
+        match &mut self.transport {
+            Transport::Tcp(header) => header.source_port = port,
+            Transport::Udp(header) => header.source_port = port,
+            Transport::Icmp(message) => set_echo_identifier(message, port),
+            Transport::Gre(_) | Transport::Ipv6 => {}
+        }
+    }
+    fn set_destination(&mut self, ip: H::Address, port: u16) {
+        self.network.set_destination(ip);
+        match &mut self.transport {
+            Transport::Tcp(header) => header.destination_port = port,
+            Transport::Udp(header) => header.destination_port = port,
+            Transport::Icmp(message) => set_echo_identifier(message, port),
+            Transport::Gre(_) | Transport::Ipv6 => {}
+        }
+    }
+    fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.network.set_hop_limit(hop_limit);
+    }
+    // the IP header and the first 8 bytes of what it carries, which is all RFC 792 asks for
+    fn time_exceeded(&self, from: H::Address) -> Self {
+        let bytes = self.to_bytes();
+        let quoted = H::parse(&bytes).map_or(bytes.len(), |(_, _, payload)| payload.start + 8).min(bytes.len());
+        let message = IcmpMessage::TimeExceeded { code : 0, original : bytes[..quoted].to_vec() };
+        LayeredPacket::new(H::between(from, self.source_ip()), Transport::Icmp(message), vec![])
+    }
+    fn traffic_class(&self) -> u8 {
+        self.network.traffic_class()
+    }
+    fn set_traffic_class(&mut self, traffic_class: u8) {
+        self.network.set_traffic_class(traffic_class);
+    }
+}
+
+// A ping's identifier is its "port" in both directions, which is how NATs tell pings apart;
+// errors have none
+fn echo_identifier(message: &IcmpMessage) -> u16 {
+    match message {
+        IcmpMessage::EchoRequest { identifier, .. } | IcmpMessage::EchoReply { identifier, .. } => *identifier,
+        _ => 0,
+    }
+}
+
+fn set_echo_identifier(message: &mut IcmpMessage, port: u16) {
+    if let IcmpMessage::EchoRequest { identifier, .. } | IcmpMessage::EchoReply { identifier, .. } = message {
+        *identifier = port;
+    }
+}
+
+#[test]
+fn layered_packets_through_the_nat() {
+    use crate::nat_v4::NatTable;
+
+    let laptop = Ipv4Addr::new(192, 168, 1, 10);
+    let resolver = Ipv4Addr::new(198, 51, 100, 53);
+    let query = LayeredPacket::new(Ipv4Header::new(laptop, resolver, 0, 0), Transport::Udp(UdpHeader::new(5353, 53, 0)), b"example.com?".to_vec());
+    // the lengths and the checksum are filled in, and it reads back the same
+    let bytes = query.to_bytes();
+    assert_eq!((bytes.len(), bytes[9], bytes[25]), (40, IPPROTO_UDP, 20));
+    let read = LayeredPacket::<Ipv4Header>::from_bytes(&bytes).unwrap();
+    assert!(read.network.has_valid_checksum());
+    assert_eq!((read.payload.as_slice(), read.source_port(), read.destination_ip()), (b"example.com?".as_slice(), 5353, resolver));
+    // the UDP checksum catches a damaged payload, which the IPv4 one doesn't cover
+    let mut damaged = bytes.clone();
+    damaged[30] ^= 0x20;
+    assert_eq!(LayeredPacket::<Ipv4Header>::from_bytes(&damaged), Err(PacketError::BadChecksum));
+
+    // the NAT takes it like any other packet
+    let mut nat = NatTable::new("home", Ipv4Addr::new(203, 0, 113, 5));
+    let out = nat.translate_outgoing(query.clone(), 7).unwrap();
+    assert_eq!(out.source_ip(), Ipv4Addr::new(203, 0, 113, 5));
+    let mut answer = out.clone();
+    answer.set_source(resolver, 53);
+    answer.set_destination(out.source_ip(), out.source_port());
+    let (back, computer) = nat.translate_incoming(answer).unwrap();
+    assert_eq!((back.destination_ip(), back.destination_port(), computer), (laptop, 5353, 7));
+
+    // a ping over IPv6 is told apart by its identifier, and keeps its data in the message
+    let ping = IcmpMessage::EchoRequest { identifier : 0x1c46, sequence : 1, data : b"abcd".to_vec() };
+    let v6 = Ipv6Header::new("2001:db8::10".parse().unwrap(), "2001:db8::1".parse().unwrap(), IPPROTO_ICMPV6, 12);
+    let ping = LayeredPacket::new(v6, Transport::Icmp(ping), vec![]).with_link(MacAddr([0x02, 0, 0, 0, 0, 1]), MacAddr([0x02, 0, 0, 0, 0, 0x10]));
+    let frame = ping.to_frame().unwrap();
+    assert_eq!((frame.ethertype, frame.payload[6]), (ETHERTYPE_IPV6, IPPROTO_ICMPV6));
+    let read = LayeredPacket::<Ipv6Header>::from_frame(&frame).unwrap();
+    assert_eq!(read, ping);
+    assert_eq!((read.protocol(), read.source_port()), (Protocol::Icmp, 0x1c46));
+    assert_eq!(LayeredPacket::<Ipv6Header>::from_bytes(&bytes), Err(PacketError::WrongVersion(4)));
+}
@@ -0,0 +1,115 @@
+//! Packets as bytes, the way they travel on the wire: every header as a struct with its real
+//! fields, and to_bytes()/from_bytes() to go between the two, numbers in network byte order
+//! (most significant byte first). LayeredPacket puts them together, one layer on top of the
+//! other, and the Packet trait is what NAT and routing need from any packet: the addresses,
+//! the ports, the protocol and the hop limit. The old flat RandomTransportPacket implements it
+//! too, for code outside the crate that still has some.
+use std::fmt::{self, Display};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::bit_utils::fnv1a;
+use crate::nat_v4::{NatAddress, Protocol};
+
+pub mod builder;
+pub mod checksum;
+pub mod dump;
+pub mod fragment;
+pub mod gre;
+pub mod icmp;
+pub mod ipv4;
+pub mod ipv6;
+pub mod layered;
+pub mod tcp;
+pub mod udp;
+
+// the protocol numbers of the IPv4 protocol field and the IPv6 next header field
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_IGMP: u8 = 2;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+// an IPv6 packet inside an IPv4 one (6in4)
+pub const IPPROTO_IPV6: u8 = 41;
+pub const IPPROTO_GRE: u8 = 47;
+pub const IPPROTO_ICMPV6: u8 = 58;
+
+// What the NAT and the routers look at in a packet, whatever it is made of. A protocol without
+// ports (ICMP) uses whatever identifies its flows instead, like the identifier of a ping.
+pub trait Packet: Clone {
+    type Address: NatAddress;
+    fn protocol(&self) -> Protocol;
+    fn source_ip(&self) -> Self::Address;
+    fn source_port(&self) -> u16;
+    fn destination_ip(&self) -> Self::Address;
+    fn destination_port(&self) -> u16;
+    fn hop_limit(&self) -> u8;
+    fn set_source(&mut self, ip: Self::Address, port: u16);
+    fn set_destination(&mut self, ip: Self::Address, port: u16);
+    fn set_hop_limit(&mut self, hop_limit: u8);
+    // the ICMP Time Exceeded a router at `from` sends back when this packet runs out of hops,
+    // with enough of this one in it for the sender to tell which packet it was
+    fn time_exceeded(&self, from: Self::Address) -> Self;
+
+    // The IPv4 type of service or IPv6 traffic class: the DSCP and the ECN bits, see qos.
+    // A packet that doesn't have one is best effort, and can't be marked.
+    fn traffic_class(&self) -> u8 {
+        0
+    }
+    fn set_traffic_class(&mut self, _traffic_class: u8) {}
+
+    fn source(&self) -> SocketAddr {
+        SocketAddr::new(self.source_ip().into(), self.source_port())
+    }
+    fn destination(&self) -> SocketAddr {
+        SocketAddr::new(self.destination_ip().into(), self.destination_port())
+    }
+
+    // a hash of the 5-tuple: every packet of a flow gets the same number, on every run, so a
+    // router spreading flows over several paths (ECMP) keeps each flow on one of them
+    fn flow_hash(&self) -> u64 {
+        let mut bytes = vec![self.protocol() as u8];
+        for (ip, port) in [(self.source_ip(), self.source_port()), (self.destination_ip(), self.destination_port())] {
+            match ip.into() {
+                IpAddr::V4(ip) => bytes.extend(ip.octets()),
+                IpAddr::V6(ip) => bytes.extend(ip.octets()),
+            }
+            bytes.extend(port.to_be_bytes());
+        }
+        fnv1a(&bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketError {
+    // fewer bytes than the header says there are
+    Truncated { needed : usize, got : usize },
+    // the version field is not the one of this header
+    WrongVersion(u8),
+    // a length field that can't be right, like an IPv4 header shorter than 20 bytes
+    BadLength(usize),
+    // the checksum doesn't add up
+    BadChecksum,
+    // a message type this crate doesn't know about
+    UnknownType(u8),
+}
+
+impl Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Truncated { needed, got } => write!(f, "truncated: {needed} bytes needed, only {got} there"),
+            PacketError::WrongVersion(version) => write!(f, "unexpected version {version}"),
+            PacketError::BadLength(length) => write!(f, "impossible length {length}"),
+            PacketError::BadChecksum => write!(f, "wrong checksum"),
+            PacketError::UnknownType(kind) => write!(f, "unknown message type {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+// at least this many bytes, or Truncated
+pub(crate) fn need(bytes: &[u8], needed: usize) -> Result<(), PacketError> {
+    if bytes.len() < needed {
+        return Err(PacketError::Truncated { needed, got : bytes.len() });
+    }
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! The TCP header of RFC 9293:
+//!
+//! ```text
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |          Source Port          |       Destination Port        |
+//! |                        Sequence Number                        |
+//! |                    Acknowledgment Number                      |
+//! |  Data |       |C|E|U|A|P|R|S|F|                               |
+//! | Offset| Rsrvd |W|C|R|C|S|S|Y|I|            Window             |
+//! |       |       |R|E|G|K|H|T|N|N|                               |
+//! |           Checksum            |         Urgent Pointer        |
+//! |                           Options                             |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//!
+//! Data Offset is the header length in 32 bit words, like the IHL of IPv4. The checksum covers
+//! the payload and part of the IP header too, so it can't be worked out from this header alone.
+//!
+//! The options are kind, length, value (except End of List and No-Operation, which are one
+//! byte), and mostly only on the SYNs, where both sides say what they can do: the biggest
+//! segment they take (MSS), how many bits to shift the window left (window scale, RFC 7323),
+//! and whether they understand selective acknowledgments (SACK, RFC 2018).
+use super::{need, PacketError};
+
+pub const MIN_HEADER_LEN: usize = 20;
+// Data Offset can't point further than 60 bytes, and 20 of them are the fixed part
+pub const MAX_OPTIONS_LEN: usize = 40;
+
+// the bits of the flags byte
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const RST: u8 = 0x04;
+pub const PSH: u8 = 0x08;
+pub const ACK: u8 = 0x10;
+pub const URG: u8 = 0x20;
+pub const ECE: u8 = 0x40;
+pub const CWR: u8 = 0x80;
+
+// the biggest shift RFC 7323 allows: windows up to 1 GB
+pub const MAX_WINDOW_SCALE: u8 = 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpOption {
+    // the rest of the header is padding
+    EndOfList,
+    // to line the next option up on a word
+    Nop,
+    MaximumSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    // the (left, right) edges of blocks that arrived past a gap
+    Sack(Vec<(u32, u32)>),
+    Timestamps { value : u32, echo_reply : u32 },
+    // whatever this crate doesn't know, as it was
+    Unknown { kind : u8, data : Vec<u8> },
+}
+
+impl TcpOption {
+    pub fn kind(&self) -> u8 {
+        match self {
+            TcpOption::EndOfList => 0,
+            TcpOption::Nop => 1,
+            TcpOption::MaximumSegmentSize(_) => 2,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 4,
+            TcpOption::Sack(_) => 5,
+            TcpOption::Timestamps { .. } => 8,
+            TcpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    // an option too long for its length byte to say is refused
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PacketError> {
+        let data = match self {
+            TcpOption::EndOfList | TcpOption::Nop => return Ok(vec![self.kind()]),
+            TcpOption::MaximumSegmentSize(mss) => mss.to_be_bytes().to_vec(),
+            TcpOption::WindowScale(shift) => vec![*shift],
+            TcpOption::SackPermitted => vec![],
+            TcpOption::Sack(blocks) => blocks.iter().flat_map(|(left, right)| [left.to_be_bytes(), right.to_be_bytes()]).flatten().collect(),
+            TcpOption::Timestamps { value, echo_reply } => [value.to_be_bytes(), echo_reply.to_be_bytes()].concat(),
+            TcpOption::Unknown { data, .. } => data.clone(),
+        };
+        // the length counts the kind and itself too
+        let len = u8::try_from(data.len() + 2).map_err(|_| PacketError::BadLength(data.len() + 2))?;
+        let mut bytes = vec![self.kind(), len];
+        bytes.extend(data);
+        Ok(bytes)
+    }
+
+    // All the options in these bytes, up to End of List if there is one
+    pub fn read_all(mut bytes: &[u8]) -> Result<Vec<TcpOption>, PacketError> {
+        let mut options = vec![];
+        while let Some(&kind) = bytes.first() {
+            match kind {
+                0 => {
+                    options.push(TcpOption::EndOfList);
+                    break;
+                }
+                1 => {
+                    options.push(TcpOption::Nop);
+                    bytes = &bytes[1..];
+                    continue;
+                }
+                _ => {}
+            }
+            need(bytes, 2)?;
+            let len = bytes[1] as usize;
+            if len < 2 {
+                return Err(PacketError::BadLength(len));
+            }
+            need(bytes, len)?;
+            let data = &bytes[2..len];
+            let long = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+            options.push(match (kind, data.len()) {
+                (2, 2) => TcpOption::MaximumSegmentSize(u16::from_be_bytes([data[0], data[1]])),
+                (3, 1) => TcpOption::WindowScale(data[0]),
+                (4, 0) => TcpOption::SackPermitted,
+                (5, n) if n % 8 == 0 && n > 0 => TcpOption::Sack((0..n).step_by(8).map(|at| (long(at), long(at + 4))).collect()),
+                (8, 8) => TcpOption::Timestamps { value : long(0), echo_reply : long(4) },
+                (2..=5 | 8, _) => return Err(PacketError::BadLength(len)),
+                _ => TcpOption::Unknown { kind, data : data.to_vec() },
+            });
+            bytes = &bytes[len..];
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpHeader {
+    pub source_port : u16,
+    pub destination_port : u16,
+    pub sequence : u32,
+    pub acknowledgment : u32,
+    pub flags : u8,
+    pub window : u16,
+    pub checksum : u16,
+    pub urgent_pointer : u16,
+    // in the order they are on the wire, without the padding
+    pub options : Vec<TcpOption>,
+}
+
+impl TcpHeader {
+    // no flags, no options, and a window of 64K
+    pub fn new(source_port: u16, destination_port: u16, sequence: u32) -> Self {
+        TcpHeader {
+            source_port,
+            destination_port,
+            sequence,
+            acknowledgment : 0,
+            flags : 0,
+            window : u16::MAX,
+            checksum : 0,
+            urgent_pointer : 0,
+            options : vec![],
+        }
+    }
+
+    // the flags are added to the ones already set
+    pub fn with_flags(mut self, flags: u8) -> Self {
+        self.flags |= flags;
+        self
+    }
+
+    // sets ACK too, since the number means nothing without it
+    pub fn with_acknowledgment(mut self, acknowledgment: u32) -> Self {
+        self.acknowledgment = acknowledgment;
+        self.with_flags(ACK)
+    }
+
+    // refused if the options wouldn't fit in the header anymore
+    pub fn with_option(mut self, option: TcpOption) -> Result<Self, PacketError> {
+        self.options.push(option);
+        self.check_options()?;
+        Ok(self)
+    }
+
+    pub fn mss(&self) -> Option<u16> {
+        self.options.iter().find_map(|option| match option {
+            TcpOption::MaximumSegmentSize(mss) => Some(*mss),
+            _ => None,
+        })
+    }
+
+    // more than 14 is taken as 14
+    pub fn window_scale(&self) -> Option<u8> {
+        self.options.iter().find_map(|option| match option {
+            TcpOption::WindowScale(shift) => Some((*shift).min(MAX_WINDOW_SCALE)),
+            _ => None,
+        })
+    }
+
+    pub fn sack_permitted(&self) -> bool {
+        self.options.contains(&TcpOption::SackPermitted)
+    }
+
+    // What a router does for a link with a smaller MTU (iptables' TCPMSS --clamp-mss-to-pmtu):
+    // an MSS above this is lowered to it, so neither side sends segments too big for the link.
+    // True if it changed, and then the checksum has to be worked out again.
+    pub fn clamp_mss(&mut self, max: u16) -> bool {
+        let mut clamped = false;
+        for option in &mut self.options {
+            if let TcpOption::MaximumSegmentSize(mss) = option {
+                if *mss > max {
+                    *mss = max;
+                    clamped = true;
+                }
+            }
+        }
+        clamped
+    }
+
+    fn options_bytes(&self) -> Result<Vec<u8>, PacketError> {
+        let mut bytes = vec![];
+        for option in &self.options {
+            bytes.extend(option.to_bytes()?);
+        }
+        if bytes.len() > MAX_OPTIONS_LEN {
+            return Err(PacketError::BadLength(bytes.len()));
+        }
+        Ok(bytes)
+    }
+
+    // every option can be written, and all of them fit behind Data Offset
+    pub fn check_options(&self) -> Result<(), PacketError> {
+        self.options_bytes().map(|_| ())
+    }
+
+    pub fn syn(&self) -> bool {
+        self.flags & SYN != 0
+    }
+
+    pub fn ack(&self) -> bool {
+        self.flags & ACK != 0
+    }
+
+    pub fn fin(&self) -> bool {
+        self.flags & FIN != 0
+    }
+
+    pub fn rst(&self) -> bool {
+        self.flags & RST != 0
+    }
+
+    pub fn psh(&self) -> bool {
+        self.flags & PSH != 0
+    }
+
+    pub fn urg(&self) -> bool {
+        self.flags & URG != 0
+    }
+
+    // With the options padded to a whole number of words. Options that don't pass
+    // check_options are left out, rather than written with a Data Offset that is wrong.
+    pub fn header_len(&self) -> usize {
+        MIN_HEADER_LEN + self.options_bytes().map_or(0, |bytes| bytes.len().div_ceil(4) * 4)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.source_port.to_be_bytes().to_vec();
+        bytes.extend(self.destination_port.to_be_bytes());
+        bytes.extend(self.sequence.to_be_bytes());
+        bytes.extend(self.acknowledgment.to_be_bytes());
+        bytes.extend([((self.header_len() / 4) as u8) << 4, self.flags]);
+        bytes.extend(self.window.to_be_bytes());
+        bytes.extend(self.checksum.to_be_bytes());
+        bytes.extend(self.urgent_pointer.to_be_bytes());
+        bytes.extend(self.options_bytes().unwrap_or_default());
+        bytes.resize(self.header_len(), 0);
+        bytes
+    }
+
+    // reads the header at the start of these bytes; the payload starts at header_len()
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, MIN_HEADER_LEN)?;
+        let header_len = (bytes[12] >> 4) as usize * 4;
+        if header_len < MIN_HEADER_LEN {
+            return Err(PacketError::BadLength(header_len));
+        }
+        need(bytes, header_len)?;
+        let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let long = |at: usize| u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+        Ok(TcpHeader {
+            source_port : word(0),
+            destination_port : word(2),
+            sequence : long(4),
+            acknowledgment : long(8),
+            flags : bytes[13],
+            window : word(14),
+            checksum : word(16),
+            urgent_pointer : word(18),
+            options : TcpOption::read_all(&bytes[MIN_HEADER_LEN..header_len])?,
+        })
+    }
+}
+
+#[test]
+fn handshake_headers_on_the_wire() {
+    // a SYN from a Linux client to a web server, with MSS, SACK, timestamps and window scaling
+    let captured = [
+        0xc0, 0x12, 0x00, 0x50, 0x3a, 0x9e, 0x7b, 0x1c, 0x00, 0x00, 0x00, 0x00, 0xa0, 0x02, 0xfa, 0xf0,
+        0x8e, 0x31, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a, 0x00, 0x3c, 0x4f, 0x1d,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x03, 0x07,
+    ];
+    let syn = TcpHeader::from_bytes(&captured).unwrap();
+    assert_eq!((syn.source_port, syn.destination_port, syn.sequence, syn.window), (49170, 80, 0x3a9e_7b1c, 64240));
+    assert!(syn.syn() && !syn.ack() && !syn.fin() && !syn.rst());
+    assert_eq!(syn.header_len(), 40);
+    assert_eq!(syn.options, [
+        TcpOption::MaximumSegmentSize(1460),
+        TcpOption::SackPermitted,
+        TcpOption::Timestamps { value : 0x003c_4f1d, echo_reply : 0 },
+        TcpOption::Nop,
+        TcpOption::WindowScale(7),
+    ]);
+    assert_eq!((syn.mss(), syn.window_scale(), syn.sack_permitted()), (Some(1460), Some(7), true));
+    assert_eq!(syn.to_bytes(), captured);
+
+    // clamped for a tunnel with a 1400 byte MTU: 1400 - 20 - 20
+    let mut clamped = syn.clone();
+    assert!(clamped.clamp_mss(1360));
+    assert!(!clamped.clamp_mss(1400));
+    assert_eq!((clamped.mss(), clamped.to_bytes()[22..24].to_vec()), (Some(1360), 1360u16.to_be_bytes().to_vec()));
+
+    let syn_ack = TcpHeader::new(80, 49170, 0x1000).with_flags(SYN).with_acknowledgment(syn.sequence + 1);
+    assert!(syn_ack.syn() && syn_ack.ack());
+    let bytes = syn_ack.to_bytes();
+    assert_eq!((bytes.len(), bytes[12], bytes[13]), (20, 0x50, SYN | ACK));
+    assert_eq!(TcpHeader::from_bytes(&bytes), Ok(syn_ack));
+
+    assert_eq!(TcpHeader::from_bytes(&captured[..30]), Err(PacketError::Truncated { needed : 40, got : 30 }));
+    assert_eq!(TcpHeader::from_bytes(&[0; 20]), Err(PacketError::BadLength(0)));
+
+    // a SACK block, and an option with a length that can't be right
+    let sack = TcpHeader::new(80, 49170, 1).with_option(TcpOption::Nop).unwrap().with_option(TcpOption::Nop).unwrap();
+    let sack = sack.with_option(TcpOption::Sack(vec![(1000, 2000)])).unwrap();
+    assert_eq!(sack.header_len(), 32);
+    assert_eq!(TcpHeader::from_bytes(&sack.to_bytes()), Ok(sack.clone()));
+    let mut broken = captured;
+    broken[21] = 3;
+    assert_eq!(TcpHeader::from_bytes(&broken), Err(PacketError::BadLength(3)));
+
+    // options that can't be written are refused: 12 bytes of them and 4 more SACK blocks are
+    // 46, and a length byte can't count 256
+    assert_eq!(sack.clone().with_option(TcpOption::Sack(vec![(0, 1); 4])), Err(PacketError::BadLength(46)));
+    let unknown = TcpOption::Unknown { kind : 99, data : vec![0; 254] };
+    assert_eq!(unknown.to_bytes(), Err(PacketError::BadLength(256)));
+    // and set by hand anyway, they are left out instead of breaking the header
+    let mut too_many = sack;
+    too_many.options.push(unknown);
+    assert_eq!((too_many.header_len(), too_many.to_bytes()[12]), (20, 0x50));
+}
@@ -0,0 +1,85 @@
+//! The UDP header of RFC 768: two ports, a length and a checksum, 8 bytes in all.
+//! The length counts the header too, so it is never less than 8. The checksum covers the
+//! payload and part of the IP header as well; zero means there is none (IPv4 only).
+use super::{need, PacketError};
+
+pub const HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpHeader {
+    pub source_port : u16,
+    pub destination_port : u16,
+    // header and payload, in bytes
+    pub length : u16,
+    pub checksum : u16,
+}
+
+impl UdpHeader {
+    // for this many bytes of payload, without a checksum
+    pub fn new(source_port: u16, destination_port: u16, payload_len: usize) -> Self {
+        UdpHeader { source_port, destination_port, length : (HEADER_LEN + payload_len) as u16, checksum : 0 }
+    }
+
+    pub fn payload_len(&self) -> usize {
+        (self.length as usize).saturating_sub(HEADER_LEN)
+    }
+
+    // the length says exactly how much payload there is
+    pub fn check_length(&self, payload: &[u8]) -> Result<(), PacketError> {
+        if (self.length as usize) < HEADER_LEN || self.payload_len() != payload.len() {
+            return Err(PacketError::BadLength(self.length as usize));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.source_port, self.destination_port, self.length, self.checksum]
+            .iter()
+            .flat_map(|field| field.to_be_bytes())
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, HEADER_LEN)?;
+        let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        Ok(UdpHeader { source_port : word(0), destination_port : word(2), length : word(4), checksum : word(6) })
+    }
+
+    // the header and the payload after it
+    pub fn datagram(&self, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        bytes.extend(payload);
+        bytes
+    }
+}
+
+// A whole datagram, header and payload. Only as many bytes as the length says are payload:
+// anything after that is padding from the layer below, like Ethernet's minimum frame size.
+pub fn read_datagram(bytes: &[u8]) -> Result<(UdpHeader, &[u8]), PacketError> {
+    let header = UdpHeader::from_bytes(bytes)?;
+    if (header.length as usize) < HEADER_LEN {
+        return Err(PacketError::BadLength(header.length as usize));
+    }
+    need(bytes, header.length as usize)?;
+    let payload = &bytes[HEADER_LEN..header.length as usize];
+    Ok((header, payload))
+}
+
+#[test]
+fn datagrams_and_their_length() {
+    // a DNS query for example.com, as captured, with 2 bytes of padding after it
+    let mut captured = vec![0xd4, 0x31, 0x00, 0x35, 0x00, 0x25, 0x6b, 0x9a];
+    captured.extend(b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01");
+    captured.extend([0, 0]);
+    let (header, payload) = read_datagram(&captured).unwrap();
+    assert_eq!((header.source_port, header.destination_port, header.length, header.checksum), (54321, 53, 37, 0x6b9a));
+    assert_eq!(payload.len(), 29);
+    assert_eq!(header.check_length(payload), Ok(()));
+    assert_eq!(header.datagram(payload), captured[..37]);
+
+    let reply = UdpHeader::new(53, 54321, 45);
+    assert_eq!(UdpHeader::from_bytes(&reply.to_bytes()), Ok(reply));
+    assert_eq!(reply.check_length(payload), Err(PacketError::BadLength(53)));
+    assert_eq!(read_datagram(&captured[..30]), Err(PacketError::Truncated { needed : 37, got : 30 }));
+    assert_eq!(read_datagram(&[0, 53, 0, 53, 0, 4, 0, 0]), Err(PacketError::BadLength(4)));
+}
@@ -0,0 +1,448 @@
+//! Captures in the classic pcap format, the one of tcpdump -w and Wireshark's "pcap" option:
+//! reading them, so real traffic can be replayed through the NAT and the routing code, and
+//! writing them, so what a simulation sent can be looked at in Wireshark.
+//!
+//! ```text
+//! file:   | magic (4) | version 2.4 (2+2) | zone (4) | sigfigs (4) | snaplen (4) | link type (4) |
+//! record: | seconds (4) | micro or nanoseconds (4) | captured length (4) | original length (4) | data |
+//! ```
+//!
+//! The magic number says both the byte order the file was written in (it is the one of the
+//! machine that captured) and whether the timestamps have micro or nanoseconds. The link type
+//! says what each record starts with: usually an Ethernet header, or directly the IP packet.
+//! A record can be shorter than the packet was, if it was cut at the snaplen.
+//!
+//! Packets captured on the machine that sent them often have wrong TCP and UDP checksums,
+//! because the network card fills them in later; those are refused when parsed like any other
+//! packet with a bad checksum, so capture on another machine, or turn the offload off.
+//!
+//! What is written has the virtual clock's time since the start of the simulation as its
+//! timestamps, so Wireshark shows it as happening at the very beginning of 1970.
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::arp::ArpMessage;
+use crate::clock::VirtualClock;
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use crate::packet::ipv4::Ipv4Header;
+use crate::packet::ipv6::Ipv6Header;
+use crate::packet::layered::{IpHeader, LayeredPacket};
+use crate::packet::PacketError;
+use crate::router::Forwarded;
+
+pub const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+pub const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+pub const FILE_HEADER_LEN: usize = 24;
+pub const RECORD_HEADER_LEN: usize = 16;
+// what tcpdump keeps of each packet by default
+pub const DEFAULT_SNAPLEN: u32 = 262_144;
+
+// the link types of tcpdump.org/linktypes.html this knows
+pub const LINKTYPE_ETHERNET: u32 = 1;
+// IPv4 or IPv6, told apart by the version
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+
+#[derive(Debug)]
+pub enum PcapError {
+    Io(io::Error),
+    // not a pcap file, or a pcapng one
+    BadMagic(u32),
+    // the file ends in the middle of a header or a record
+    Truncated,
+    // a record or a block says it has more bytes than any capture would keep
+    TooLong(u32),
+}
+
+impl From<io::Error> for PcapError {
+    fn from(error: io::Error) -> Self {
+        PcapError::Io(error)
+    }
+}
+
+impl Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapError::Io(error) => write!(f, "{error}"),
+            PcapError::BadMagic(magic) => write!(f, "not a pcap file: magic number {magic:08x}"),
+            PcapError::Truncated => write!(f, "the capture ends in the middle of a record"),
+            PcapError::TooLong(len) => write!(f, "a record of {len} bytes, more than any capture would keep"),
+        }
+    }
+}
+
+impl std::error::Error for PcapError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcapHeader {
+    pub big_endian : bool,
+    pub nanoseconds : bool,
+    pub version : (u16, u16),
+    // the most bytes of a packet that were kept
+    pub snaplen : u32,
+    pub link_type : u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapRecord {
+    // since the Unix epoch
+    pub timestamp : Duration,
+    // how long the packet was on the wire; more than data.len() if it was cut
+    pub original_len : u32,
+    pub data : Vec<u8>,
+}
+
+impl PcapRecord {
+    pub fn is_truncated(&self) -> bool {
+        (self.data.len() as u32) < self.original_len
+    }
+}
+
+// A record read into the crate's types, as far as they go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Captured {
+    V4(LayeredPacket<Ipv4Header>),
+    V6(LayeredPacket<Ipv6Header>),
+    Arp(ArpMessage),
+    // an Ethernet frame with something else in it
+    Frame(EthernetFrame),
+}
+
+impl Captured {
+    // the record's data, for a capture of this link type
+    pub fn parse(link_type: u32, data: &[u8]) -> Result<Self, PacketError> {
+        match link_type {
+            LINKTYPE_ETHERNET => {
+                let frame = EthernetFrame::from_bytes(data)?;
+                Ok(match frame.ethertype {
+                    ETHERTYPE_IPV4 => Captured::V4(LayeredPacket::from_frame(&frame)?),
+                    ETHERTYPE_IPV6 => Captured::V6(LayeredPacket::from_frame(&frame)?),
+                    ETHERTYPE_ARP => Captured::Arp(ArpMessage::from_bytes(&frame.payload)?),
+                    _ => Captured::Frame(frame),
+                })
+            }
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => match data.first().map(|byte| byte >> 4) {
+                Some(4) => Ok(Captured::V4(LayeredPacket::from_bytes(data)?)),
+                Some(6) => Ok(Captured::V6(LayeredPacket::from_bytes(data)?)),
+                Some(version) => Err(PacketError::WrongVersion(version)),
+                None => Err(PacketError::Truncated { needed : 1, got : 0 }),
+            },
+            // only the low byte, but the ones known here all fit in it
+            other => Err(PacketError::UnknownType(other as u8)),
+        }
+    }
+}
+
+// Reads a capture record by record; it is an iterator of them, ending at the end of the file
+#[derive(Debug)]
+pub struct PcapReader<R> {
+    reader : R,
+    pub header : PcapHeader,
+}
+
+impl<R: Read> PcapReader<R> {
+    // reads the file header
+    pub fn new(mut reader: R) -> Result<Self, PcapError> {
+        let mut bytes = [0; FILE_HEADER_LEN];
+        if read_up_to(&mut reader, &mut bytes)? < FILE_HEADER_LEN {
+            return Err(PcapError::Truncated);
+        }
+        let magic = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+        let (big_endian, nanoseconds) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(PcapError::BadMagic(magic)),
+        };
+        let half = |at: usize| {
+            let pair = [bytes[at], bytes[at + 1]];
+            if big_endian { u16::from_be_bytes(pair) } else { u16::from_le_bytes(pair) }
+        };
+        let word = |at: usize| read_u32(&bytes[at..at + 4], big_endian);
+        let header = PcapHeader { big_endian, nanoseconds, version : (half(4), half(6)), snaplen : word(16), link_type : word(20) };
+        Ok(PcapReader { reader, header })
+    }
+
+    // the next record; None at the end of the file
+    pub fn next_record(&mut self) -> Result<Option<PcapRecord>, PcapError> {
+        let mut bytes = [0; RECORD_HEADER_LEN];
+        match read_up_to(&mut self.reader, &mut bytes)? {
+            0 => return Ok(None),
+            RECORD_HEADER_LEN => {}
+            _ => return Err(PcapError::Truncated),
+        }
+        let word = |at: usize| read_u32(&bytes[at..at + 4], self.header.big_endian);
+        let fraction = if self.header.nanoseconds { Duration::from_nanos(word(4) as u64) } else { Duration::from_micros(word(4) as u64) };
+        // checked before anything is allocated for it, since a broken file could say 4 GB: not
+        // more than the snaplen, nor than tcpdump would ever keep
+        let captured = word(8);
+        if captured > self.header.snaplen.min(DEFAULT_SNAPLEN) {
+            return Err(PcapError::TooLong(captured));
+        }
+        let mut data = vec![0; captured as usize];
+        if read_up_to(&mut self.reader, &mut data)? < data.len() {
+            return Err(PcapError::Truncated);
+        }
+        Ok(Some(PcapRecord { timestamp : Duration::from_secs(word(0) as u64) + fraction, original_len : word(12), data }))
+    }
+
+    // the records parsed, with their timestamp; a record that can't be is an Err in its place
+    pub fn packets(self) -> impl Iterator<Item = Result<(Duration, Result<Captured, PacketError>), PcapError>> {
+        let link_type = self.header.link_type;
+        self.map(move |record| record.map(|record| (record.timestamp, Captured::parse(link_type, &record.data))))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<PcapRecord, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+// Writes a capture, little endian and with nanoseconds, like Wireshark does
+#[derive(Debug)]
+pub struct PcapWriter<W> {
+    writer : W,
+    pub header : PcapHeader,
+}
+
+impl<W: Write> PcapWriter<W> {
+    // writes the file header
+    pub fn new(writer: W, link_type: u32) -> io::Result<Self> {
+        Self::cut_at(writer, link_type, DEFAULT_SNAPLEN)
+    }
+
+    // keeping only so many bytes of each packet, like tcpdump -s
+    pub fn cut_at(mut writer: W, link_type: u32, snaplen: u32) -> io::Result<Self> {
+        let header = PcapHeader { big_endian : false, nanoseconds : true, version : (2, 4), snaplen, link_type };
+        let mut bytes = MAGIC_NANOS.to_le_bytes().to_vec();
+        bytes.extend(header.version.0.to_le_bytes());
+        bytes.extend(header.version.1.to_le_bytes());
+        // the time zone and the accuracy, which are always zero
+        bytes.extend([0; 8]);
+        bytes.extend(header.snaplen.to_le_bytes());
+        bytes.extend(link_type.to_le_bytes());
+        writer.write_all(&bytes)?;
+        Ok(PcapWriter { writer, header })
+    }
+
+    // the data is cut at the snaplen, like a capture would
+    pub fn write_record(&mut self, timestamp: Duration, data: &[u8]) -> io::Result<()> {
+        let kept = &data[..data.len().min(self.header.snaplen as usize)];
+        let mut bytes = (timestamp.as_secs() as u32).to_le_bytes().to_vec();
+        bytes.extend(timestamp.subsec_nanos().to_le_bytes());
+        bytes.extend((kept.len() as u32).to_le_bytes());
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(kept);
+        self.writer.write_all(&bytes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// Like `tcpdump -i eth0 -w file`: one device or link is watched, and whatever the simulation
+// says went through it is written, at the virtual time it happened
+#[derive(Debug)]
+pub struct Capture<W> {
+    pub device : String,
+    clock : VirtualClock,
+    pub writer : PcapWriter<W>,
+}
+
+impl<W: Write> Capture<W> {
+    pub fn new(device: &str, clock: VirtualClock, writer: PcapWriter<W>) -> Self {
+        Capture { device : device.to_string(), clock, writer }
+    }
+
+    // a frame that went through this device; nothing is written for any other
+    pub fn frame(&mut self, device: &str, frame: &EthernetFrame) -> io::Result<()> {
+        if device != self.device {
+            return Ok(());
+        }
+        self.writer.write_record(self.clock.elapsed(), &frame.to_bytes())
+    }
+
+    // as a frame in an Ethernet capture, the IP packet for any other link type
+    pub fn packet<H: IpHeader>(&mut self, device: &str, packet: &LayeredPacket<H>) -> io::Result<()> {
+        if self.writer.header.link_type != LINKTYPE_ETHERNET {
+            if device != self.device {
+                return Ok(());
+            }
+            return self.writer.write_record(self.clock.elapsed(), &packet.to_bytes());
+        }
+        self.frame(device, &frame_of(packet))
+    }
+
+    // what a router sent out, if it went out of this interface
+    pub fn forwarded<H: IpHeader>(&mut self, forwarded: &Forwarded<LayeredPacket<H>>) -> io::Result<()> {
+        sent_by(forwarded).try_for_each(|(interface, packet)| self.packet(interface, packet))
+    }
+}
+
+// the packet's frame, or one with zero MACs if it isn't on a link
+pub(crate) fn frame_of<H: IpHeader>(packet: &LayeredPacket<H>) -> EthernetFrame {
+    let zeros = MacAddr::default();
+    packet.to_frame().unwrap_or_else(|| EthernetFrame::new(zeros, zeros, H::ETHERTYPE, packet.to_bytes()))
+}
+
+// the packets a router sent, and the interfaces they left through
+pub(crate) fn sent_by<H: IpHeader>(forwarded: &Forwarded<LayeredPacket<H>>) -> impl Iterator<Item = (&str, &LayeredPacket<H>)> {
+    let packets: Vec<_> = match forwarded {
+        Forwarded::Out { interface, packet, .. } | Forwarded::Icmp { interface, packet, .. } => vec![(interface, packet)],
+        Forwarded::Replicated(copies) => copies.iter().map(|(interface, packet)| (interface, packet)).collect(),
+        Forwarded::Local(_) | Forwarded::Dropped(_) => vec![],
+    };
+    packets.into_iter().map(|(interface, packet)| (interface.as_str(), packet))
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = bytes.try_into().unwrap();
+    if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+}
+
+// like read_exact, but says how far it got before the end of the file instead of failing
+pub(crate) fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+#[test]
+fn captures_are_read_into_packets() {
+    use crate::link::MacAddr;
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+    use std::net::Ipv4Addr;
+
+    let (laptop, resolver) = (Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(198, 51, 100, 53));
+    let query = PacketBuilder::ipv4()
+        .src(laptop)
+        .dst(resolver)
+        .udp()
+        .sport(5353)
+        .dport(53)
+        .payload(b"example.com?")
+        .link(MacAddr([0x02, 0, 0, 0, 0, 0x10]), MacAddr([0x02, 0, 0, 0, 0, 1]))
+        .build();
+    let arp = ArpMessage::request(MacAddr([0x02, 0, 0, 0, 0, 0x10]), laptop, Ipv4Addr::new(192, 168, 1, 1));
+
+    // as a little endian machine writes it, with microseconds
+    let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0];
+    for (seconds, micros, frame) in [(1_700_000_000u32, 250_000u32, query.to_frame().unwrap()), (1_700_000_001, 0, arp.to_frame())] {
+        let data = frame.to_bytes();
+        for word in [seconds, micros, data.len() as u32, data.len() as u32] {
+            file.extend(word.to_le_bytes());
+        }
+        file.extend(data);
+    }
+    let reader = PcapReader::new(file.as_slice()).unwrap();
+    assert_eq!((reader.header.version, reader.header.snaplen, reader.header.link_type), ((2, 4), 65535, LINKTYPE_ETHERNET));
+    let packets: Vec<_> = reader.packets().map(Result::unwrap).collect();
+    assert_eq!(packets[0].0, Duration::from_millis(1_700_000_000_250));
+    let Ok(Captured::V4(read)) = &packets[0].1 else {
+        panic!("expected the UDP packet, got {:?}", packets[0].1);
+    };
+    assert_eq!((read.source_port(), read.destination_ip(), read.payload.as_slice()), (5353, resolver, b"example.com?".as_slice()));
+    assert_eq!(packets[1].1, Ok(Captured::Arp(arp)));
+
+    // and replayed through a NAT
+    let mut nat = NatTable::new("home", Ipv4Addr::new(203, 0, 113, 5));
+    assert_eq!(nat.translate_outgoing(read.clone(), 1).unwrap().source_ip(), Ipv4Addr::new(203, 0, 113, 5));
+
+    // big endian with nanoseconds, raw IP, and cut off in the middle of the record
+    let ip = query.to_bytes();
+    let mut file = vec![0xa1, 0xb2, 0x3c, 0x4d, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 24, 0, 0, 0, 101];
+    for word in [7u32, 5, 24, ip.len() as u32] {
+        file.extend(word.to_be_bytes());
+    }
+    file.extend(&ip[..24]);
+    let mut reader = PcapReader::new(file.as_slice()).unwrap();
+    assert!(reader.header.big_endian && reader.header.nanoseconds);
+    let record = reader.next_record().unwrap().unwrap();
+    assert!(record.is_truncated());
+    assert_eq!(record.timestamp, Duration::new(7, 5));
+    assert!(Captured::parse(reader.header.link_type, &record.data).is_err());
+    assert!(reader.next().is_none());
+    // and the file itself cut short
+    file.pop();
+    assert!(matches!(PcapReader::new(file.as_slice()).unwrap().next_record(), Err(PcapError::Truncated)));
+    assert!(matches!(PcapReader::new(&file[..20]), Err(PcapError::Truncated)));
+    assert!(matches!(PcapReader::new(&[0u8; 24][..]), Err(PcapError::BadMagic(0))));
+    // a record longer than the snaplen is refused before reading it
+    file[FILE_HEADER_LEN + 8..FILE_HEADER_LEN + 12].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert!(matches!(PcapReader::new(file.as_slice()).unwrap().next_record(), Err(PcapError::TooLong(u32::MAX))));
+}
+
+#[test]
+fn what_a_router_sends_is_written_down() {
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::layered::Transport;
+    use crate::packet::Packet;
+    use crate::router::{Router, RouterInterface};
+    use crate::routing::Route;
+    use std::net::Ipv4Addr;
+
+    let mut router = Router::new("home").with_nat(NatTable::new("home NAT", Ipv4Addr::new(203, 0, 113, 5)));
+    router.add_interface(RouterInterface::new("lan").with_v4(Ipv4Addr::new(192, 168, 1, 1), 24));
+    router.add_interface(RouterInterface::new("wan").with_v4(Ipv4Addr::new(203, 0, 113, 5), 24).nat_outside());
+    router.routes_v4.add_route(Route::default_route(Ipv4Addr::new(203, 0, 113, 1))).unwrap();
+
+    let clock = VirtualClock::new();
+    let mut capture = Capture::new("wan", clock.clone(), PcapWriter::new(vec![], LINKTYPE_ETHERNET).unwrap());
+    for (port, hop_limit) in [(40000, 64), (40001, 1)] {
+        clock.advance(Duration::from_millis(10));
+        let packet = PacketBuilder::ipv4()
+            .src(Ipv4Addr::new(192, 168, 1, 20))
+            .dst(Ipv4Addr::new(198, 51, 100, 53))
+            .hop_limit(hop_limit)
+            .udp()
+            .sport(port)
+            .dport(53)
+            .payload(vec![0x12, 0x34, 0x01, 0x00])
+            .build();
+        // the second runs out of hops, and its Time Exceeded goes back out of lan, unseen
+        capture.forwarded(&router.forward(packet, "lan")).unwrap();
+    }
+    // a frame on some other link isn't either
+    let zeros = MacAddr::default();
+    capture.frame("lan", &EthernetFrame::new(zeros, zeros, ETHERTYPE_ARP, vec![])).unwrap();
+
+    let file = capture.writer.into_inner();
+    let reader = PcapReader::new(file.as_slice()).unwrap();
+    assert_eq!((reader.header.nanoseconds, reader.header.link_type), (true, LINKTYPE_ETHERNET));
+    let packets: Vec<_> = reader.packets().map(Result::unwrap).collect();
+    assert_eq!(packets.len(), 1);
+    let (timestamp, Ok(Captured::V4(sent))) = &packets[0] else {
+        panic!("expected a packet, got {:?}", packets[0]);
+    };
+    // as it left: translated, one hop less, with real headers and checksums
+    assert_eq!(*timestamp, Duration::from_millis(10));
+    assert_eq!((sent.source_ip(), sent.hop_limit(), sent.payload.as_slice()), (Ipv4Addr::new(203, 0, 113, 5), 63, [0x12, 0x34, 0x01, 0x00].as_slice()));
+    assert!(matches!(sent.transport, Transport::Udp(_)));
+
+    // a snaplen cuts what is kept, but not the original length
+    let mut writer = PcapWriter::cut_at(vec![], LINKTYPE_RAW, 20).unwrap();
+    writer.write_record(Duration::new(1, 5), &sent.to_bytes()).unwrap();
+    let record = PcapReader::new(writer.into_inner().as_slice()).unwrap().next_record().unwrap().unwrap();
+    assert_eq!((record.data.len(), record.original_len, record.timestamp), (20, 32, Duration::new(1, 5)));
+}
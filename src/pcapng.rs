@@ -0,0 +1,390 @@
+//! Captures in pcapng, the format Wireshark saves in by default. Unlike pcap it can have packets
+//! from several interfaces in one file, each described once and then referred to by number, so
+//! a whole simulation (every link of every router) fits in a single capture.
+//!
+//! The file is a list of blocks, each starting with its type and length and ending with the
+//! length again:
+//!
+//! ```text
+//! | type (4) | length (4) | body, padded to 4 bytes | length (4) |
+//! ```
+//!
+//! - Section Header (0x0a0d0d0a): first, and its byte order magic says the order of everything
+//!   after it;
+//! - Interface Description (1): the link type and snaplen of the next interface, numbered from
+//!   0 in the order they come, and options like its name (if_name) and how fine its
+//!   timestamps are (if_tsresol, microseconds if not said);
+//! - Enhanced Packet (6): the interface number, a 64 bit timestamp, and the packet.
+//!
+//! Blocks of any other type (statistics, name resolution, comments) are skipped when reading.
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::clock::VirtualClock;
+use crate::link::EthernetFrame;
+use crate::packet::layered::{IpHeader, LayeredPacket};
+use crate::pcap::{frame_of, read_up_to, sent_by, PcapError, DEFAULT_SNAPLEN, LINKTYPE_ETHERNET};
+use crate::router::Forwarded;
+
+pub const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+pub const INTERFACE_DESCRIPTION: u32 = 1;
+pub const ENHANCED_PACKET: u32 = 6;
+pub const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+// the options of an interface this knows
+const OPT_END: u16 = 0;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+
+// Wireshark refuses blocks longer than this; a packet cut at the snaplen, with its options,
+// is far from it
+pub const MAX_BLOCK_LEN: u32 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapngInterface {
+    pub name : Option<String>,
+    pub link_type : u32,
+    pub snaplen : u32,
+    // how many units of its timestamps make a second
+    pub units_per_second : u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapngPacket {
+    // which of the interfaces, in the order they were described
+    pub interface : u32,
+    pub timestamp : Duration,
+    pub original_len : u32,
+    pub data : Vec<u8>,
+}
+
+// Writes a section in little endian; interfaces are described as they are added, and every
+// timestamp is in nanoseconds
+#[derive(Debug)]
+pub struct PcapngWriter<W> {
+    writer : W,
+    pub interfaces : Vec<PcapngInterface>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    // writes the section header
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut section = PcapngWriter { writer, interfaces : vec![] };
+        let mut body = BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        // version 1.0, and a section length of -1 for not known
+        body.extend([1, 0, 0, 0]);
+        body.extend((-1i64).to_le_bytes());
+        section.write_block(SECTION_HEADER, &body)?;
+        Ok(section)
+    }
+
+    // describes one more interface, and gives back its number for write_packet()
+    pub fn add_interface(&mut self, name: &str, link_type: u32) -> io::Result<u32> {
+        let mut body = (link_type as u16).to_le_bytes().to_vec();
+        body.extend([0, 0]);
+        body.extend(DEFAULT_SNAPLEN.to_le_bytes());
+        write_option(&mut body, IF_NAME, name.as_bytes());
+        write_option(&mut body, IF_TSRESOL, &[9]);
+        write_option(&mut body, OPT_END, &[]);
+        self.write_block(INTERFACE_DESCRIPTION, &body)?;
+        let interface = PcapngInterface { name : Some(name.to_string()), link_type, snaplen : DEFAULT_SNAPLEN, units_per_second : 1_000_000_000 };
+        self.interfaces.push(interface);
+        Ok(self.interfaces.len() as u32 - 1)
+    }
+
+    pub fn interface(&self, name: &str) -> Option<u32> {
+        self.interfaces.iter().position(|interface| interface.name.as_deref() == Some(name)).map(|index| index as u32)
+    }
+
+    // a packet seen on that interface, cut at its snaplen
+    pub fn write_packet(&mut self, interface: u32, timestamp: Duration, data: &[u8]) -> io::Result<()> {
+        let snaplen = self.interfaces.get(interface as usize).map(|interface| interface.snaplen).ok_or(io::ErrorKind::NotFound)?;
+        let kept = &data[..data.len().min(snaplen as usize)];
+        let nanos = timestamp.as_nanos() as u64;
+        let mut body = interface.to_le_bytes().to_vec();
+        body.extend(((nanos >> 32) as u32).to_le_bytes());
+        body.extend((nanos as u32).to_le_bytes());
+        body.extend((kept.len() as u32).to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(kept);
+        body.resize(body.len().next_multiple_of(4), 0);
+        self.write_block(ENHANCED_PACKET, &body)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let length = (12 + body.len()) as u32;
+        let mut bytes = block_type.to_le_bytes().to_vec();
+        bytes.extend(length.to_le_bytes());
+        bytes.extend(body);
+        bytes.extend(length.to_le_bytes());
+        self.writer.write_all(&bytes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn write_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend(code.to_le_bytes());
+    body.extend((value.len() as u16).to_le_bytes());
+    body.extend(value);
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+// Like pcap::Capture, but for every device at once: each one becomes an interface of the file
+// the first time something goes through it
+#[derive(Debug)]
+pub struct PcapngCapture<W> {
+    clock : VirtualClock,
+    pub writer : PcapngWriter<W>,
+}
+
+impl<W: Write> PcapngCapture<W> {
+    pub fn new(clock: VirtualClock, writer: PcapngWriter<W>) -> Self {
+        PcapngCapture { clock, writer }
+    }
+
+    pub fn frame(&mut self, device: &str, frame: &EthernetFrame) -> io::Result<()> {
+        let interface = match self.writer.interface(device) {
+            Some(interface) => interface,
+            None => self.writer.add_interface(device, LINKTYPE_ETHERNET)?,
+        };
+        self.writer.write_packet(interface, self.clock.elapsed(), &frame.to_bytes())
+    }
+
+    pub fn packet<H: IpHeader>(&mut self, device: &str, packet: &LayeredPacket<H>) -> io::Result<()> {
+        self.frame(device, &frame_of(packet))
+    }
+
+    // what a router sent out, on the interface it left through
+    pub fn forwarded<H: IpHeader>(&mut self, forwarded: &Forwarded<LayeredPacket<H>>) -> io::Result<()> {
+        sent_by(forwarded).try_for_each(|(interface, packet)| self.packet(interface, packet))
+    }
+}
+
+// Reads the packets of a file one after the other, learning the interfaces on the way; it is
+// an iterator of them
+#[derive(Debug)]
+pub struct PcapngReader<R> {
+    reader : R,
+    // None until the first section header says
+    big_endian : Option<bool>,
+    // of the current section, since each one numbers its own from 0
+    pub interfaces : Vec<PcapngInterface>,
+}
+
+impl<R: Read> PcapngReader<R> {
+    // reads the first section header
+    pub fn new(reader: R) -> Result<Self, PcapError> {
+        let mut file = PcapngReader { reader, big_endian : None, interfaces : vec![] };
+        // anything else first is refused by next_block()
+        match file.next_block()? {
+            Some(_) => Ok(file),
+            None => Err(PcapError::Truncated),
+        }
+    }
+
+    // the type and body of the next block; None at the end of the file
+    fn next_block(&mut self) -> Result<Option<(u32, Vec<u8>)>, PcapError> {
+        let mut start = [0; 8];
+        match read_up_to(&mut self.reader, &mut start)? {
+            0 => return Ok(None),
+            8 => {}
+            _ => return Err(PcapError::Truncated),
+        }
+        let block_type = u32::from_le_bytes(start[..4].try_into().unwrap());
+        if block_type != SECTION_HEADER && self.big_endian.is_none() {
+            return Err(PcapError::BadMagic(block_type));
+        }
+        // a section header says itself what order it is in, the same either way round
+        if block_type == SECTION_HEADER {
+            let mut magic = [0; 4];
+            if read_up_to(&mut self.reader, &mut magic)? < 4 {
+                return Err(PcapError::Truncated);
+            }
+            self.big_endian = Some(match u32::from_le_bytes(magic) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                magic => return Err(PcapError::BadMagic(magic)),
+            });
+            self.interfaces.clear();
+            let length = self.block_len(&start[4..8])?;
+            let mut rest = vec![0; length.checked_sub(12).ok_or(PcapError::Truncated)?];
+            if read_up_to(&mut self.reader, &mut rest)? < rest.len() {
+                return Err(PcapError::Truncated);
+            }
+            return Ok(Some((SECTION_HEADER, rest)));
+        }
+        let block_type = self.u32(&start[..4]);
+        let length = self.block_len(&start[4..8])?;
+        let mut body = vec![0; length.checked_sub(8).ok_or(PcapError::Truncated)?];
+        if read_up_to(&mut self.reader, &mut body)? < body.len() || body.len() < 4 {
+            return Err(PcapError::Truncated);
+        }
+        body.truncate(body.len() - 4);
+        Ok(Some((block_type, body)))
+    }
+
+    // the next packet; None at the end of the file
+    pub fn next_packet(&mut self) -> Result<Option<PcapngPacket>, PcapError> {
+        while let Some((block_type, body)) = self.next_block()? {
+            match block_type {
+                INTERFACE_DESCRIPTION => {
+                    let interface = self.interface(&body)?;
+                    self.interfaces.push(interface);
+                }
+                ENHANCED_PACKET => return self.packet(&body).map(Some),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn interface(&self, body: &[u8]) -> Result<PcapngInterface, PcapError> {
+        if body.len() < 8 {
+            return Err(PcapError::Truncated);
+        }
+        let mut interface = PcapngInterface { name : None, link_type : self.u16(&body[0..2]) as u32, snaplen : self.u32(&body[4..8]), units_per_second : 1_000_000 };
+        let mut at = 8;
+        while at + 4 <= body.len() {
+            let (code, len) = (self.u16(&body[at..at + 2]), self.u16(&body[at + 2..at + 4]) as usize);
+            let value = body.get(at + 4..at + 4 + len).ok_or(PcapError::Truncated)?;
+            match code {
+                OPT_END => break,
+                IF_NAME => interface.name = Some(String::from_utf8_lossy(value).into_owned()),
+                // a power of ten, or of two with the top bit set
+                IF_TSRESOL if len == 1 => {
+                    let exponent = value[0] & 0x7f;
+                    interface.units_per_second = if value[0] & 0x80 == 0 { 10u64.pow(exponent.min(19) as u32) } else { 1 << exponent.min(63) };
+                }
+                _ => {}
+            }
+            at += 4 + len.next_multiple_of(4);
+        }
+        Ok(interface)
+    }
+
+    fn packet(&self, body: &[u8]) -> Result<PcapngPacket, PcapError> {
+        if body.len() < 20 {
+            return Err(PcapError::Truncated);
+        }
+        let interface = self.u32(&body[0..4]);
+        let per_second = self.interfaces.get(interface as usize).ok_or(PcapError::Truncated)?.units_per_second;
+        let units = (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64;
+        let nanos = (units % per_second) as u128 * 1_000_000_000 / per_second as u128;
+        let captured = self.u32(&body[12..16]) as usize;
+        let data = body.get(20..20 + captured).ok_or(PcapError::Truncated)?;
+        Ok(PcapngPacket {
+            interface,
+            timestamp : Duration::new(units / per_second, nanos as u32),
+            original_len : self.u32(&body[16..20]),
+            data : data.to_vec(),
+        })
+    }
+
+    // checked before the block is read in, so a broken length can't ask for 4 GB
+    fn block_len(&self, bytes: &[u8]) -> Result<usize, PcapError> {
+        match self.u32(bytes) {
+            length if length > MAX_BLOCK_LEN => Err(PcapError::TooLong(length)),
+            length => Ok(length as usize),
+        }
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian == Some(true) { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian == Some(true) { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+impl<R: Read> Iterator for PcapngReader<R> {
+    type Item = Result<PcapngPacket, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+#[test]
+fn every_interface_in_one_file() {
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+    use crate::pcap::Captured;
+    use crate::router::{Router, RouterInterface};
+    use crate::routing::Route;
+    use std::net::Ipv4Addr;
+
+    let mut router = Router::new("home").with_nat(NatTable::new("home NAT", Ipv4Addr::new(203, 0, 113, 5)));
+    router.add_interface(RouterInterface::new("lan").with_v4(Ipv4Addr::new(192, 168, 1, 1), 24));
+    router.add_interface(RouterInterface::new("wan").with_v4(Ipv4Addr::new(203, 0, 113, 5), 24).nat_outside());
+    router.routes_v4.add_route(Route::default_route(Ipv4Addr::new(203, 0, 113, 1))).unwrap();
+
+    // a query goes out of wan, and its answer 30 ms later out of lan
+    let clock = VirtualClock::new();
+    let mut capture = PcapngCapture::new(clock.clone(), PcapngWriter::new(vec![]).unwrap());
+    let query = PacketBuilder::ipv4()
+        .src(Ipv4Addr::new(192, 168, 1, 20))
+        .dst(Ipv4Addr::new(198, 51, 100, 53))
+        .udp()
+        .sport(40000)
+        .dport(53)
+        .payload(b"where is example.com")
+        .build();
+    let forwarded = router.forward(query, "lan");
+    capture.forwarded(&forwarded).unwrap();
+    let crate::router::Forwarded::Out { packet : sent, .. } = forwarded else {
+        panic!("the query wasn't sent");
+    };
+    clock.advance(Duration::from_millis(30));
+    let mut answer = sent.clone();
+    answer.set_source(sent.destination_ip(), 53);
+    answer.set_destination(sent.source_ip(), sent.source_port());
+    answer.payload = b"at 93.184.216.34".to_vec();
+    capture.forwarded(&router.forward(answer, "wan")).unwrap();
+
+    let file = capture.writer.into_inner();
+    let mut reader = PcapngReader::new(file.as_slice()).unwrap();
+    let packets: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+    let names: Vec<_> = reader.interfaces.iter().map(|interface| interface.name.as_deref().unwrap()).collect();
+    assert_eq!(names, ["wan", "lan"]);
+    assert_eq!(packets.iter().map(|packet| (packet.interface, packet.timestamp)).collect::<Vec<_>>(), [(0, Duration::ZERO), (1, Duration::from_millis(30))]);
+    let Ok(Captured::V4(back)) = Captured::parse(reader.interfaces[1].link_type, &packets[1].data) else {
+        panic!("expected the answer");
+    };
+    assert_eq!((back.destination_ip(), back.destination_port()), (Ipv4Addr::new(192, 168, 1, 20), 40000));
+
+    // written big endian by someone else: microseconds unless said, and a block this doesn't
+    // know about (a name resolution one) in between
+    let block = |block_type: u32, body: &[u8]| {
+        let length = (12 + body.len()) as u32;
+        [&block_type.to_be_bytes()[..], &length.to_be_bytes(), body, &length.to_be_bytes()].concat()
+    };
+    let mut file = block(SECTION_HEADER, &[0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    file.extend(block(INTERFACE_DESCRIPTION, &[0, 1, 0, 0, 0, 0, 0xff, 0xff]));
+    file.extend(block(4, &[0, 0, 0, 0]));
+    file.extend(block(ENHANCED_PACKET, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0f, 0x42, 0x41, 0, 0, 0, 2, 0, 0, 0, 60, 0xab, 0xcd, 0, 0]));
+    let mut reader = PcapngReader::new(file.as_slice()).unwrap();
+    let packet = reader.next_packet().unwrap().unwrap();
+    assert_eq!((packet.timestamp, packet.original_len, packet.data.as_slice()), (Duration::new(1, 1000), 60, [0xab, 0xcd].as_slice()));
+    assert_eq!((reader.interfaces[0].link_type, reader.interfaces[0].units_per_second), (LINKTYPE_ETHERNET, 1_000_000));
+    assert!(reader.next().is_none());
+    assert!(matches!(PcapngReader::new(&file[..20]), Err(PcapError::Truncated)));
+    assert!(matches!(PcapngReader::new(&file[..40]).unwrap().next_packet(), Err(PcapError::Truncated)));
+    assert!(matches!(PcapngReader::new(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0][..]), Err(PcapError::BadMagic(_))));
+    // a block saying it is huge is refused before it is read
+    let mut huge = file.clone();
+    huge.extend([&ENHANCED_PACKET.to_be_bytes()[..], &u32::MAX.to_be_bytes()].concat());
+    let mut reader = PcapngReader::new(huge.as_slice()).unwrap();
+    reader.next_packet().unwrap();
+    assert!(matches!(reader.next_packet(), Err(PcapError::TooLong(u32::MAX))));
+}
@@ -0,0 +1,192 @@
+//! Policy routing, the Linux way: several routing tables, and `ip rule`s saying which table a
+//! packet is looked up in. Rules are tried by priority; one matching the packet sends the lookup
+//! to its table, and if that table has no route the next rule is tried.
+//! Together with firewall marks that is the usual trick for "mail goes out through the second
+//! ISP": the firewall marks the packets (see HostFirewall::mark) and a rule matches the mark,
+//! or the rule matches the protocol and port itself.
+use std::fmt::{self, Display};
+use std::net::Ipv4Addr;
+
+use crate::metadata::Tagged;
+use crate::nat_v4::Protocol;
+use crate::packet::Packet;
+use crate::routing::{IpAddrTools, RoutingTableV4};
+
+// Like `ip rule add priority 100 fwmark 0x1 from 192.168.1.0/24 lookup isp2`;
+// None means "anything", as in the firewall rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpRule {
+    pub priority : u32,
+    pub fwmark : Option<u32>,
+    // (network, mask)
+    pub from : Option<(Ipv4Addr, Ipv4Addr)>,
+    // `ipproto tcp` and `dport 25`
+    pub protocol : Option<Protocol>,
+    pub destination_port : Option<u16>,
+    pub table : String,
+}
+
+impl IpRule {
+    pub fn new(priority: u32, table: &str) -> Self {
+        IpRule { priority, fwmark : None, from : None, protocol : None, destination_port : None, table : table.to_string() }
+    }
+
+    pub fn matches<P: Packet<Address = Ipv4Addr>>(&self, packet: &Tagged<P>) -> bool {
+        self.fwmark.is_none_or(|mark| mark == packet.meta.mark)
+            && self.from.is_none_or(|(network, mask)| packet.packet.source_ip().mask(mask) == network)
+            && self.protocol.is_none_or(|protocol| protocol == packet.packet.protocol())
+            && self.destination_port.is_none_or(|port| port == packet.packet.destination_port())
+    }
+}
+
+// as `ip rule show` prints it
+impl Display for IpRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:\tfrom ", self.priority)?;
+        match self.from {
+            Some((network, mask)) => write!(f, "{network}/{}", mask.count_contiguous_ones())?,
+            None => write!(f, "all")?,
+        }
+        if let Some(mark) = self.fwmark {
+            write!(f, " fwmark {mark:#x}")?;
+        }
+        if let Some(protocol) = self.protocol {
+            write!(f, " ipproto {protocol}")?;
+        }
+        if let Some(port) = self.destination_port {
+            write!(f, " dport {port}")?;
+        }
+        write!(f, " lookup {}", self.table)
+    }
+}
+
+#[derive(Debug)]
+pub struct PolicyRouter {
+    pub rules : Vec<IpRule>,
+    pub tables : Vec<RoutingTableV4>,
+}
+
+impl PolicyRouter {
+    // with only the main table, and the rule sending everything to it, like a fresh Linux box
+    pub fn new(main: RoutingTableV4) -> Self {
+        let rule = IpRule::new(32766, &main.name);
+        PolicyRouter { rules : vec![rule], tables : vec![main] }
+    }
+
+    pub fn add_table(&mut self, table: RoutingTableV4) {
+        self.tables.push(table);
+    }
+
+    pub fn add_rule(&mut self, rule: IpRule) {
+        self.rules.push(rule);
+        self.rules.sort_by_key(|rule| rule.priority);
+    }
+
+    // one line per rule, in the order they are tried
+    pub fn list_rules(&self) -> Vec<String> {
+        self.rules.iter().map(IpRule::to_string).collect()
+    }
+
+    // moves the rules with this priority to another one; gives back how many moved.
+    // Rules with the same priority keep the order they were added in.
+    pub fn reorder_rule(&mut self, priority: u32, new_priority: u32) -> usize {
+        let mut moved = 0;
+        for rule in self.rules.iter_mut().filter(|rule| rule.priority == priority) {
+            rule.priority = new_priority;
+            moved += 1;
+        }
+        self.rules.sort_by_key(|rule| rule.priority);
+        moved
+    }
+
+    pub fn remove_rule(&mut self, priority: u32) -> Vec<IpRule> {
+        let (removed, kept) = std::mem::take(&mut self.rules).into_iter().partition(|rule| rule.priority == priority);
+        self.rules = kept;
+        removed
+    }
+
+    fn table(&self, name: &str) -> Option<&RoutingTableV4> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    // the next hop for this packet, and the table it was found in
+    pub fn find_next_hop<P: Packet<Address = Ipv4Addr>>(&self, packet: &Tagged<P>) -> Option<(Ipv4Addr, &str)> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(packet))
+            .filter_map(|rule| self.table(&rule.table))
+            .find_map(|table| table.find_next_hop(packet.packet.destination_ip()).map(|hop| (hop, table.name.as_str())))
+    }
+}
+
+#[test]
+fn marked_traffic_takes_the_other_isp() {
+    use crate::firewall::{Action, Direction, FirewallRule, HostFirewall};
+    use crate::packet::builder::PacketBuilder;
+    use crate::routing::RouteV4;
+
+    let default_route = |next_hop: &str| RouteV4 {
+        destination : "0.0.0.0".parse().unwrap(),
+        prefix_len : 0,
+        next_hop : next_hop.parse().unwrap(),
+        equal_cost : vec![],
+        distance : 1,
+        metric : 0,
+        expires_at : None,
+    };
+    let mut router = PolicyRouter::new(RoutingTableV4::new("main").with_routes(vec![default_route("10.0.0.1")]));
+    router.add_table(RoutingTableV4::new("isp2").with_routes(vec![default_route("172.16.0.1")]));
+    router.add_rule(IpRule { fwmark : Some(0x1), ..IpRule::new(100, "isp2") });
+    // a rule pointing at a table with no routes doesn't stop the lookup
+    router.add_table(RoutingTableV4::new("empty"));
+    router.add_rule(IpRule { from : Some(("192.168.1.0".parse().unwrap(), "255.255.255.0".parse().unwrap())), ..IpRule::new(50, "empty") });
+
+    let mut firewall = HostFirewall::new(12);
+    firewall.add_mark_rule(FirewallRule { remote_port : Some(25), ..FirewallRule::new(Direction::Outbound, Action::Allow) }, 0x1);
+
+    let packet_to = |port: u16| {
+        Tagged::new(PacketBuilder::ipv4().src("192.168.1.50".parse().unwrap()).dst("93.184.216.34".parse().unwrap()).tcp().sport(40000).dport(port).payload(b"hello?").build())
+    };
+    let mut mail = packet_to(25);
+    let mut web = packet_to(443);
+    firewall.mark(Direction::Outbound, &mut mail);
+    firewall.mark(Direction::Outbound, &mut web);
+    assert_eq!((mail.meta.mark, web.meta.mark), (0x1, 0));
+
+    assert_eq!(router.find_next_hop(&mail), Some(("172.16.0.1".parse().unwrap(), "isp2")));
+    assert_eq!(router.find_next_hop(&web), Some(("10.0.0.1".parse().unwrap(), "main")));
+}
+
+#[test]
+fn rules_by_source_and_port_can_be_reordered() {
+    use crate::packet::builder::PacketBuilder;
+
+    let table = |name: &str, next_hop: &str| RoutingTableV4::new(name).with_routes(vec![crate::routing::RouteV4::default_route(next_hop.parse().unwrap())]);
+    let mut router = PolicyRouter::new(table("main", "10.0.0.1"));
+    router.add_table(table("guests", "172.16.0.1"));
+    router.add_table(table("voip", "172.17.0.1"));
+    router.add_rule(IpRule { from : Some(("192.168.50.0".parse().unwrap(), "255.255.255.0".parse().unwrap())), ..IpRule::new(100, "guests") });
+    router.add_rule(IpRule { protocol : Some(Protocol::Udp), destination_port : Some(5060), ..IpRule::new(200, "voip") });
+    assert_eq!(router.list_rules(), [
+        "100:\tfrom 192.168.50.0/24 lookup guests",
+        "200:\tfrom all ipproto udp dport 5060 lookup voip",
+        "32766:\tfrom all lookup main",
+    ]);
+
+    let packet = |source: &str, protocol: Protocol, port: u16| {
+        let builder = PacketBuilder::ipv4().src(source.parse().unwrap()).dst("198.51.100.7".parse().unwrap());
+        let builder = if protocol == Protocol::Tcp { builder.tcp() } else { builder.udp() };
+        Tagged::new(builder.sport(5060).dport(port).build())
+    };
+    let guest_call = packet("192.168.50.20", Protocol::Udp, 5060);
+    assert_eq!(router.find_next_hop(&guest_call).map(|(_, table)| table), Some("guests"));
+    assert_eq!(router.find_next_hop(&packet("192.168.1.20", Protocol::Udp, 5060)).map(|(_, table)| table), Some("voip"));
+    assert_eq!(router.find_next_hop(&packet("192.168.1.20", Protocol::Tcp, 5060)).map(|(_, table)| table), Some("main"));
+
+    // calls first, even for guests
+    assert_eq!(router.reorder_rule(200, 50), 1);
+    assert_eq!(router.find_next_hop(&guest_call).map(|(_, table)| table), Some("voip"));
+    assert_eq!(router.remove_rule(50).len(), 1);
+    assert_eq!(router.reorder_rule(50, 10), 0);
+    assert_eq!(router.rules.len(), 2);
+}
@@ -0,0 +1,255 @@
+//! Path-vector routing between autonomous systems (ASes), a toy BGP. Every route carries the
+//! list of ASes it went through, the AS path. A router adds its own AS number in front when it
+//! passes a route on, and refuses any route whose path already has its AS in it: that is the
+//! whole loop prevention, no counting to infinity. Among the routes to a prefix, the one with
+//! the shortest AS path wins (real BGP looks at local preference and more before that).
+//!
+//! Each router advertises its whole best table to every peer each round, and a peer's latest
+//! advertisement replaces everything it sent before, so a route left out is a withdrawn route.
+use std::net::Ipv4Addr;
+
+use crate::networkingv4::{Route, RoutingTable};
+use crate::routing::{CONNECTED_DISTANCE, EBGP_DISTANCE};
+use crate::state_machine::StateMachine;
+use crate::topology::{NodeId, Topology};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BgpRoute {
+    pub prefix : Ipv4Addr,
+    pub prefix_len : u8,
+    // the nearest AS first, the one that originated the prefix last
+    pub as_path : Vec<u32>,
+    pub next_hop : Ipv4Addr,
+}
+
+#[derive(Debug)]
+pub struct BgpRouter {
+    pub asn : u32,
+    pub address : Ipv4Addr,
+    // the prefixes of its own AS
+    pub originated : Vec<(Ipv4Addr, u8)>,
+    // what each peer advertised last (Adj-RIB-In)
+    pub received : Vec<(Ipv4Addr, Vec<BgpRoute>)>,
+    // routes refused because the AS was already in their path
+    pub loops_rejected : u64,
+    // the session state machine of every peer it ever had a session with
+    pub peers : Vec<(Ipv4Addr, StateMachine)>,
+    pub table : RoutingTable,
+}
+
+impl BgpRouter {
+    pub fn new(name: &str, asn: u32, address: Ipv4Addr) -> Self {
+        BgpRouter {
+            asn,
+            address,
+            originated : vec![],
+            received : vec![],
+            loops_rejected : 0,
+            peers : vec![],
+            table : RoutingTable::new(name),
+        }
+    }
+
+    pub fn with_originated(mut self, prefix: Ipv4Addr, prefix_len: u8) -> Self {
+        self.originated.push((prefix, prefix_len));
+        self
+    }
+
+    pub fn session(&self, peer: Ipv4Addr) -> Option<&StateMachine> {
+        self.peers.iter().find(|(address, _)| *address == peer).map(|(_, machine)| machine)
+    }
+
+    // walks the peer's session machine through these events, from wherever it is
+    fn session_events(&mut self, peer: Ipv4Addr, events: &[&str]) {
+        let index = match self.peers.iter().position(|(address, _)| *address == peer) {
+            Some(index) => index,
+            None => {
+                self.peers.push((peer, StateMachine::bgp()));
+                self.peers.len() - 1
+            }
+        };
+        for event in events {
+            self.peers[index].1.fire(event);
+        }
+    }
+
+    // the best route to every prefix it knows: its own ones, then the shortest AS path,
+    // then the lowest next hop so the choice doesn't depend on the order things came in
+    pub fn best_routes(&self) -> Vec<BgpRoute> {
+        let mut best: Vec<BgpRoute> = self.originated
+            .iter()
+            .map(|&(prefix, prefix_len)| BgpRoute { prefix, prefix_len, as_path : vec![], next_hop : self.address })
+            .collect();
+        for route in self.received.iter().flat_map(|(_, routes)| routes) {
+            let rank = |route: &BgpRoute| (route.as_path.len(), route.next_hop);
+            match best.iter_mut().find(|known| (known.prefix, known.prefix_len) == (route.prefix, route.prefix_len)) {
+                Some(known) if known.next_hop == self.address || rank(known) <= rank(route) => {}
+                Some(known) => *known = route.clone(),
+                None => best.push(route.clone()),
+            }
+        }
+        best
+    }
+
+    // what it tells its peers: its best routes, with its own AS in front
+    pub fn export(&self) -> Vec<BgpRoute> {
+        self.best_routes()
+            .into_iter()
+            .map(|route| BgpRoute {
+                as_path : std::iter::once(self.asn).chain(route.as_path).collect(),
+                next_hop : self.address,
+                ..route
+            })
+            .collect()
+    }
+
+    // a peer's advertisement replaces what it said before; gives back whether anything changed
+    pub fn receive(&mut self, from: Ipv4Addr, routes: Vec<BgpRoute>) -> bool {
+        self.session_events(from, &["recv UPDATE or KEEPALIVE"]);
+        let (looped, accepted): (Vec<BgpRoute>, Vec<BgpRoute>) = routes.into_iter().partition(|route| route.as_path.contains(&self.asn));
+        self.loops_rejected += looped.len() as u64;
+        match self.received.iter_mut().find(|(peer, _)| *peer == from) {
+            Some((_, known)) if *known == accepted => false,
+            Some((_, known)) => {
+                *known = accepted;
+                true
+            }
+            None => {
+                self.received.push((from, accepted));
+                true
+            }
+        }
+    }
+
+    // the session went down: everything the peer said is forgotten
+    pub fn drop_peer(&mut self, peer: Ipv4Addr) {
+        self.received.retain(|(known, _)| *known != peer);
+    }
+
+    // puts the best routes in the routing table
+    pub fn update_table(&mut self) {
+        let routes = self.best_routes()
+            .into_iter()
+            .filter_map(|route| {
+                let distance = if route.as_path.is_empty() { CONNECTED_DISTANCE } else { EBGP_DISTANCE };
+                let metric = route.as_path.len() as u32;
+                Some(Route::with_prefix(route.prefix, route.prefix_len, route.next_hop).ok()?.with_distance(distance).with_metric(metric))
+            })
+            .collect();
+        // what a full table refused is in its events
+        let _ = self.table.set_routes(routes);
+    }
+}
+
+#[derive(Debug)]
+pub struct BgpNetwork {
+    pub routers : Vec<BgpRouter>,
+    pub sessions : Vec<(usize, usize)>,
+}
+
+impl BgpNetwork {
+    pub fn new(routers: Vec<BgpRouter>) -> Self {
+        BgpNetwork { routers, sessions : vec![] }
+    }
+
+    // the TCP connection comes up and both send their OPEN, then a KEEPALIVE to confirm it
+    pub fn connect(&mut self, a: usize, b: usize) {
+        self.sessions.push((a, b));
+        let (address_a, address_b) = (self.routers[a].address, self.routers[b].address);
+        let coming_up = ["ManualStart", "TCP connected, send OPEN", "recv OPEN, send KEEPALIVE", "recv KEEPALIVE"];
+        self.routers[a].session_events(address_b, &coming_up);
+        self.routers[b].session_events(address_a, &coming_up);
+    }
+
+    // a session over every working link of the topology between two of these routers (by name)
+    pub fn connect_over(&mut self, topology: &Topology) {
+        for (_, link) in topology.links().filter(|(_, link)| link.up) {
+            let position = |end: &(NodeId, String)| {
+                let name = &topology.node(end.0)?.name;
+                self.routers.iter().position(|router| &router.table.name == name)
+            };
+            if let (Some(a), Some(b)) = (position(&link.a), position(&link.b)) {
+                self.connect(a, b);
+            }
+        }
+    }
+
+    // nothing more comes from the other side, until the hold timer gives up on it
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        self.sessions.retain(|&session| session != (a, b) && session != (b, a));
+        let (address_a, address_b) = (self.routers[a].address, self.routers[b].address);
+        self.routers[a].drop_peer(address_b);
+        self.routers[b].drop_peer(address_a);
+        self.routers[a].session_events(address_b, &["HoldTimer expires"]);
+        self.routers[b].session_events(address_a, &["HoldTimer expires"]);
+    }
+
+    // rounds of everyone advertising to everyone until nothing changes, then the routing tables
+    // are updated; gives back how many rounds it took, None if it didn't settle
+    pub fn converge(&mut self, max_rounds: usize) -> Option<usize> {
+        let rounds = (1..=max_rounds).find(|_| {
+            let mut messages = vec![];
+            for &(a, b) in &self.sessions {
+                messages.push((b, self.routers[a].address, self.routers[a].export()));
+                messages.push((a, self.routers[b].address, self.routers[b].export()));
+            }
+            let mut changed = false;
+            for (to, from, routes) in messages {
+                changed |= self.routers[to].receive(from, routes);
+            }
+            !changed
+        });
+        for router in &mut self.routers {
+            router.update_table();
+        }
+        rounds
+    }
+}
+
+#[test]
+fn shortest_as_path_wins_and_loops_are_refused() {
+    // AS 100 -- AS 200 -- AS 300, and AS 100 -- AS 300 directly; AS 300 has 203.0.113.0/24
+    let customer = Ipv4Addr::new(203, 0, 113, 0);
+    let mut network = BgpNetwork::new(vec![
+        BgpRouter::new("AS100", 100, Ipv4Addr::new(192, 0, 2, 1)),
+        BgpRouter::new("AS200", 200, Ipv4Addr::new(192, 0, 2, 2)),
+        BgpRouter::new("AS300", 300, Ipv4Addr::new(192, 0, 2, 3)).with_originated(customer, 24),
+    ]);
+    network.connect(0, 1);
+    network.connect(1, 2);
+    network.connect(0, 2);
+    assert!(network.converge(10).is_some());
+
+    let path_from = |network: &BgpNetwork, router: usize| network.routers[router]
+        .best_routes()
+        .into_iter()
+        .find(|route| route.prefix == customer)
+        .map(|route| route.as_path);
+    // straight to AS 300 rather than through AS 200
+    assert_eq!(path_from(&network, 0), Some(vec![300]));
+    assert_eq!(network.routers[0].table.find_next_hop(Ipv4Addr::new(203, 0, 113, 10)), Some(Ipv4Addr::new(192, 0, 2, 3)));
+    // AS 300 hears its own prefix back from both peers and refuses it
+    assert!(network.routers[2].loops_rejected > 0);
+    assert_eq!(path_from(&network, 2), Some(vec![]));
+
+    // the direct session goes down: AS 100 now goes through AS 200
+    network.disconnect(0, 2);
+    assert!(network.converge(10).is_some());
+    assert_eq!(path_from(&network, 0), Some(vec![200, 300]));
+    assert_eq!(network.routers[0].table.find_next_hop(Ipv4Addr::new(203, 0, 113, 10)), Some(Ipv4Addr::new(192, 0, 2, 2)));
+    assert_eq!(network.routers[0].table.find_best_route(customer).unwrap().metric, 2);
+
+    // and once AS 300 is cut off completely, the prefix is withdrawn everywhere
+    network.disconnect(1, 2);
+    assert!(network.converge(10).is_some());
+    assert_eq!(path_from(&network, 0), None);
+    assert_eq!(path_from(&network, 1), None);
+
+    // AS 200 lost both sessions to AS 300; the one to AS 100 kept exchanging updates
+    let as300 = network.routers[1].session(Ipv4Addr::new(192, 0, 2, 3)).unwrap();
+    assert_eq!(as300.current, "Idle");
+    assert!(as300.to_dot().contains("\"Established\" -> \"Idle\" [label=\"HoldTimer expires (1)\", color=red, penwidth=2];"));
+    let as100 = network.routers[1].session(Ipv4Addr::new(192, 0, 2, 1)).unwrap();
+    assert_eq!(as100.current, "Established");
+    assert!(as100.to_dot().contains("[label=\"recv UPDATE or KEEPALIVE ("));
+}
@@ -0,0 +1,5 @@
+//! Routing protocols: routers filling their own routing tables by talking to each other,
+//! instead of someone typing every route in.
+pub mod bgp;
+pub mod ospf;
+pub mod rip;
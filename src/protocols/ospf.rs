@@ -0,0 +1,284 @@
+//! Link-state routing, the way OSPF does it. Instead of telling its neighbors what it thinks of
+//! the whole network (like RIP), each router describes only itself: its neighbors with the cost
+//! of each link, and the networks it is on. That link-state advertisement (LSA) is flooded to
+//! every router, so they all end up with the same link-state database: a map of the whole
+//! network. Each then runs Dijkstra's shortest path first (SPF) on the map, with itself as the
+//! root, and fills its routing table from the result.
+//!
+//! When a link fails, the two routers on it send new LSAs (with a higher sequence number, so
+//! they replace the old ones everywhere), and every router runs SPF again. No counting to
+//! infinity: everybody has the same map.
+use std::net::Ipv4Addr;
+
+use crate::networkingv4::{Route, RoutingTable};
+use crate::routing::{CONNECTED_DISTANCE, OSPF_DISTANCE};
+use crate::state_machine::StateMachine;
+use crate::topology::{NodeId, Topology};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lsa {
+    pub router : Ipv4Addr,
+    // a newer LSA from the same router replaces the older one
+    pub sequence : u32,
+    // (neighbor, cost)
+    pub links : Vec<(Ipv4Addr, u32)>,
+    pub networks : Vec<(Ipv4Addr, u8)>,
+}
+
+#[derive(Debug)]
+pub struct OspfRouter {
+    pub id : Ipv4Addr,
+    pub networks : Vec<(Ipv4Addr, u8)>,
+    pub neighbors : Vec<(Ipv4Addr, u32)>,
+    // the neighbor state machine of every router it was ever connected to
+    pub adjacencies : Vec<(Ipv4Addr, StateMachine)>,
+    sequence : u32,
+    pub lsdb : Vec<Lsa>,
+    pub table : RoutingTable,
+}
+
+impl OspfRouter {
+    pub fn new(name: &str, id: Ipv4Addr) -> Self {
+        let mut router = OspfRouter {
+            id,
+            networks : vec![],
+            neighbors : vec![],
+            adjacencies : vec![],
+            sequence : 0,
+            lsdb : vec![],
+            table : RoutingTable::new(name),
+        };
+        router.originate();
+        router
+    }
+
+    pub fn with_network(mut self, network: Ipv4Addr, prefix_len: u8) -> Self {
+        self.networks.push((network, prefix_len));
+        self.originate();
+        self
+    }
+
+    pub fn adjacency(&self, neighbor: Ipv4Addr) -> Option<&StateMachine> {
+        self.adjacencies.iter().find(|(id, _)| *id == neighbor).map(|(_, machine)| machine)
+    }
+
+    // walks the neighbor's state machine through these events, from wherever it is
+    fn neighbor_events(&mut self, neighbor: Ipv4Addr, events: &[&str]) {
+        let index = match self.adjacencies.iter().position(|(id, _)| *id == neighbor) {
+            Some(index) => index,
+            None => {
+                self.adjacencies.push((neighbor, StateMachine::ospf_neighbor()));
+                self.adjacencies.len() - 1
+            }
+        };
+        for event in events {
+            self.adjacencies[index].1.fire(event);
+        }
+    }
+
+    // a new LSA about itself, after something about it changed
+    fn originate(&mut self) {
+        self.sequence += 1;
+        let lsa = Lsa { router : self.id, sequence : self.sequence, links : self.neighbors.clone(), networks : self.networks.clone() };
+        self.install(lsa);
+    }
+
+    // keeps the LSA if it is newer than what the database has; gives back whether it was
+    pub fn install(&mut self, lsa: Lsa) -> bool {
+        match self.lsdb.iter_mut().find(|known| known.router == lsa.router) {
+            Some(known) if known.sequence >= lsa.sequence => false,
+            Some(known) => {
+                *known = lsa;
+                true
+            }
+            None => {
+                self.lsdb.push(lsa);
+                true
+            }
+        }
+    }
+
+    // a link counts only if both ends list each other
+    fn two_way(&self, from: Ipv4Addr, to: Ipv4Addr) -> bool {
+        self.lsdb
+            .iter()
+            .find(|lsa| lsa.router == to)
+            .is_some_and(|lsa| lsa.links.iter().any(|&(neighbor, _)| neighbor == from))
+    }
+
+    // Dijkstra from this router: (router, cost, first hop), for every router it can reach
+    pub fn shortest_paths(&self) -> Vec<(Ipv4Addr, u32, Ipv4Addr)> {
+        let mut done: Vec<(Ipv4Addr, u32, Ipv4Addr)> = vec![];
+        let mut candidates = vec![(self.id, 0, self.id)];
+        // the cheapest candidate, the lowest router id on a tie, so every run picks the same
+        while let Some(index) = (0..candidates.len()).min_by_key(|&i| (candidates[i].1, candidates[i].0)) {
+            let (router, cost, first_hop) = candidates.swap_remove(index);
+            if done.iter().any(|&(known, _, _)| known == router) {
+                continue;
+            }
+            done.push((router, cost, first_hop));
+            let Some(lsa) = self.lsdb.iter().find(|lsa| lsa.router == router) else {
+                continue;
+            };
+            for &(neighbor, link_cost) in &lsa.links {
+                if self.two_way(router, neighbor) && !done.iter().any(|&(known, _, _)| known == neighbor) {
+                    // leaving the root, the first hop is the neighbor itself
+                    let first_hop = if router == self.id { neighbor } else { first_hop };
+                    candidates.push((neighbor, cost + link_cost, first_hop));
+                }
+            }
+        }
+        done
+    }
+
+    // fills the routing table from the database
+    pub fn run_spf(&mut self) {
+        let mut best: Vec<(Ipv4Addr, u8, u32, Ipv4Addr)> = vec![];
+        for (router, cost, first_hop) in self.shortest_paths() {
+            let lsa = self.lsdb.iter().find(|lsa| lsa.router == router).expect("reached through its LSA");
+            for &(network, prefix_len) in &lsa.networks {
+                match best.iter_mut().find(|known| (known.0, known.1) == (network, prefix_len)) {
+                    Some(known) if known.2 <= cost => {}
+                    Some(known) => *known = (network, prefix_len, cost, first_hop),
+                    None => best.push((network, prefix_len, cost, first_hop)),
+                }
+            }
+        }
+        let routes = best
+            .into_iter()
+            .filter_map(|(network, prefix_len, cost, first_hop)| {
+                let distance = if cost == 0 { CONNECTED_DISTANCE } else { OSPF_DISTANCE };
+                Some(Route::with_prefix(network, prefix_len, first_hop).ok()?.with_distance(distance).with_metric(cost))
+            })
+            .collect();
+        // what a full table refused is in its events
+        let _ = self.table.set_routes(routes);
+    }
+}
+
+#[derive(Debug)]
+pub struct OspfNetwork {
+    pub routers : Vec<OspfRouter>,
+    // LSAs sent while flooding so far, to see what a change costs
+    pub lsas_sent : u64,
+}
+
+impl OspfNetwork {
+    pub fn new(routers: Vec<OspfRouter>) -> Self {
+        OspfNetwork { routers, lsas_sent : 0 }
+    }
+
+    pub fn connect(&mut self, a: usize, b: usize, cost: u32) {
+        let (id_a, id_b) = (self.routers[a].id, self.routers[b].id);
+        self.routers[a].neighbors.push((id_b, cost));
+        self.routers[b].neighbors.push((id_a, cost));
+        // hellos both ways, then the databases are exchanged; the flooding below is what
+        // really makes them the same
+        let coming_up = ["HelloReceived", "2-WayReceived", "NegotiationDone", "ExchangeDone", "LoadingDone"];
+        self.routers[a].neighbor_events(id_b, &coming_up);
+        self.routers[b].neighbor_events(id_a, &coming_up);
+        self.routers[a].originate();
+        self.routers[b].originate();
+    }
+
+    // every working link of the topology between two of these routers (by name), at its cost
+    pub fn connect_over(&mut self, topology: &Topology) {
+        for (_, link) in topology.links().filter(|(_, link)| link.up) {
+            let position = |end: &(NodeId, String)| {
+                let name = &topology.node(end.0)?.name;
+                self.routers.iter().position(|router| &router.table.name == name)
+            };
+            if let (Some(a), Some(b)) = (position(&link.a), position(&link.b)) {
+                self.connect(a, b, link.cost);
+            }
+        }
+    }
+
+    // both ends notice the link is down and say so
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        let (id_a, id_b) = (self.routers[a].id, self.routers[b].id);
+        self.routers[a].neighbors.retain(|&(neighbor, _)| neighbor != id_b);
+        self.routers[b].neighbors.retain(|&(neighbor, _)| neighbor != id_a);
+        self.routers[a].neighbor_events(id_b, &["InactivityTimer"]);
+        self.routers[b].neighbor_events(id_a, &["InactivityTimer"]);
+        self.routers[a].originate();
+        self.routers[b].originate();
+    }
+
+    fn neighbors_of(&self, router: usize) -> Vec<usize> {
+        self.routers[router]
+            .neighbors
+            .iter()
+            .filter_map(|&(id, _)| self.routers.iter().position(|other| other.id == id))
+            .collect()
+    }
+
+    // Every router sends its whole database to its neighbors until nothing new is learned,
+    // then they all run SPF. Gives back how many rounds flooding took.
+    pub fn converge(&mut self) -> usize {
+        let mut rounds = 0;
+        loop {
+            let mut messages = vec![];
+            for from in 0..self.routers.len() {
+                for to in self.neighbors_of(from) {
+                    messages.push((to, self.routers[from].lsdb.clone()));
+                }
+            }
+            let mut learned = false;
+            for (to, lsas) in messages {
+                self.lsas_sent += lsas.len() as u64;
+                for lsa in lsas {
+                    learned |= self.routers[to].install(lsa);
+                }
+            }
+            rounds += 1;
+            if !learned {
+                break;
+            }
+        }
+        for router in &mut self.routers {
+            router.run_spf();
+        }
+        rounds
+    }
+}
+
+#[test]
+fn link_failure_moves_traffic_to_the_other_way() {
+    //   A --1-- B --1-- C
+    //   |               | 1
+    //   +-------5------ D   with 10.0.4.0/24 behind it
+    let router = |name: &str, last: u8| OspfRouter::new(name, Ipv4Addr::new(1, 1, 1, last)).with_network(Ipv4Addr::new(10, 0, last, 0), 24);
+    let mut network = OspfNetwork::new(vec![router("A", 1), router("B", 2), router("C", 3), router("D", 4)]);
+    network.connect(0, 1, 1);
+    network.connect(1, 2, 1);
+    network.connect(2, 3, 1);
+    network.connect(0, 3, 5);
+    network.converge();
+
+    // everybody has the same map
+    let sorted = |router: &OspfRouter| {
+        let mut lsdb = router.lsdb.clone();
+        lsdb.sort_by_key(|lsa| lsa.router);
+        lsdb
+    };
+    assert!(network.routers.iter().all(|router| sorted(router) == sorted(&network.routers[0])));
+
+    let to_d = Ipv4Addr::new(10, 0, 4, 9);
+    let a = &network.routers[0];
+    // A-B-C-D costs 3, less than the direct 5
+    assert_eq!(a.table.find_next_hop(to_d), Some(Ipv4Addr::new(1, 1, 1, 2)));
+    assert_eq!(a.table.find_best_route(to_d).unwrap().metric, 3);
+    assert_eq!(a.table.find_best_route(Ipv4Addr::new(10, 0, 1, 1)).unwrap().distance, CONNECTED_DISTANCE);
+
+    network.disconnect(1, 2);
+    let sent = network.lsas_sent;
+    network.converge();
+    assert!(network.lsas_sent > sent);
+    let a = &network.routers[0];
+    assert_eq!(a.table.find_next_hop(to_d), Some(Ipv4Addr::new(1, 1, 1, 4)));
+    assert_eq!(a.table.find_best_route(to_d).unwrap().metric, 5);
+    // B now goes the long way around through A
+    assert_eq!(network.routers[1].table.find_next_hop(Ipv4Addr::new(10, 0, 3, 1)), Some(Ipv4Addr::new(1, 1, 1, 1)));
+    assert_eq!(network.routers[1].table.find_best_route(Ipv4Addr::new(10, 0, 3, 1)).unwrap().metric, 7);
+}
@@ -0,0 +1,311 @@
+//! RIP, the simplest routing protocol: every 30 seconds each router tells its neighbors every
+//! network it knows and how many hops away it is (distance vector). A neighbor one hop further
+//! takes the route if it is better than what it had, or if it came from the router it already
+//! goes through. 16 hops means unreachable, which keeps bad news from going around forever.
+//!
+//! Bad news still travels slowly. When a network goes away, the router next to it only notices
+//! when its route times out (180s), and by then its neighbor may be advertising the same
+//! network back to it, one hop further. The two then bounce the route between them, one hop
+//! worse each round, until they reach 16: counting to infinity. Split horizon (never advertise
+//! a route back to the router it was learned from) stops that between two routers.
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use crate::networkingv4::{Route, RoutingTable};
+use crate::routing::{CONNECTED_DISTANCE, RIP_DISTANCE};
+use crate::state_machine::StateMachine;
+use crate::topology::{NodeId, Topology};
+
+// hop count meaning unreachable
+pub const INFINITY: u32 = 16;
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(30);
+// a route not heard about for this long is unreachable
+pub const TIMEOUT: Duration = Duration::from_secs(180);
+// and still advertised as unreachable for this long, so the neighbors hear about it
+pub const GARBAGE_COLLECTION: Duration = Duration::from_secs(120);
+
+// (destination, prefix_len, hop count)
+pub type Advertisement = Vec<(Ipv4Addr, u8, u32)>;
+
+#[derive(Debug)]
+pub struct RipRouter {
+    pub address : Ipv4Addr,
+    pub table : RoutingTable,
+    pub split_horizon : bool,
+    // routes gone unreachable, advertised with INFINITY until this time
+    withdrawn : Vec<(Ipv4Addr, u8, Duration)>,
+    // the timer state machine of every route it ever learned
+    pub route_states : Vec<((Ipv4Addr, u8), StateMachine)>,
+}
+
+impl RipRouter {
+    pub fn new(name: &str, address: Ipv4Addr) -> Self {
+        RipRouter {
+            address,
+            table : RoutingTable::new(name),
+            split_horizon : true,
+            withdrawn : vec![],
+            route_states : vec![],
+        }
+    }
+
+    pub fn without_split_horizon(mut self) -> Self {
+        self.split_horizon = false;
+        self
+    }
+
+    // a network the router is directly on; one with a prefix longer than an address is ignored
+    pub fn with_network(mut self, network: Ipv4Addr, prefix_len: u8) -> Self {
+        if let Ok(route) = Route::with_prefix(network, prefix_len, self.address) {
+            // the same network twice is still one network
+            let _ = self.table.add_route(route.with_distance(CONNECTED_DISTANCE));
+        }
+        self
+    }
+
+    // hops to the network, 0 when directly connected
+    pub fn metric_to(&self, network: Ipv4Addr, prefix_len: u8) -> Option<u32> {
+        self.table
+            .routes()
+            .iter()
+            .find(|route| (route.destination, route.prefix_len) == (network, prefix_len))
+            .map(|route| route.metric)
+    }
+
+    pub fn route_state(&self, destination: Ipv4Addr, prefix_len: u8) -> Option<&StateMachine> {
+        self.route_states.iter().find(|(prefix, _)| *prefix == (destination, prefix_len)).map(|(_, machine)| machine)
+    }
+
+    fn route_event(&mut self, destination: Ipv4Addr, prefix_len: u8, event: &str) {
+        let index = match self.route_states.iter().position(|(prefix, _)| *prefix == (destination, prefix_len)) {
+            Some(index) => index,
+            None => {
+                self.route_states.push(((destination, prefix_len), StateMachine::rip_route()));
+                self.route_states.len() - 1
+            }
+        };
+        self.route_states[index].1.fire(event);
+    }
+
+    // what it tells the neighbor with this address
+    pub fn advertisement(&self, neighbor: Ipv4Addr) -> Advertisement {
+        let routes = self.table
+            .routes()
+            .iter()
+            .filter(|route| !(self.split_horizon && route.distance == RIP_DISTANCE && route.next_hop == neighbor))
+            .map(|route| (route.destination, route.prefix_len, route.metric));
+        let withdrawn = self.withdrawn.iter().map(|&(destination, prefix_len, _)| (destination, prefix_len, INFINITY));
+        routes.chain(withdrawn).collect()
+    }
+
+    // heard about again, through this neighbor at this cost, so it lives another TIMEOUT
+    fn hear(&mut self, route: &Route, from: Ipv4Addr, metric: u32, now: Duration) {
+        // it was just found in the table, so it is there
+        let _ = self.table.modify_route(route.destination, route.prefix_len, RIP_DISTANCE, |route| {
+            (route.next_hop, route.metric, route.expires_at) = (from, metric, Some(now + TIMEOUT));
+        });
+        self.route_event(route.destination, route.prefix_len, "recv route");
+    }
+
+    fn withdraw(&mut self, route: &Route, now: Duration) {
+        let _ = self.table.remove_route_from(route.destination, route.prefix_len, RIP_DISTANCE);
+        self.withdrawn.push((route.destination, route.prefix_len, now + GARBAGE_COLLECTION));
+        self.route_event(route.destination, route.prefix_len, "recv metric 16");
+    }
+
+    // an advertisement from a neighbor; gives back whether the table changed
+    pub fn receive(&mut self, from: Ipv4Addr, advertisement: &Advertisement, now: Duration) -> bool {
+        let mut changed = false;
+        for &(destination, prefix_len, metric) in advertisement {
+            let metric = metric.saturating_add(1).min(INFINITY);
+            let existing = self.table
+                .routes()
+                .iter()
+                .find(|route| (route.destination, route.prefix_len) == (destination, prefix_len))
+                .cloned();
+            match existing {
+                // what the router is directly on is better than anything it hears
+                Some(route) if route.distance != RIP_DISTANCE => {}
+                // the router it goes through always has the last word, good news or bad
+                Some(route) if route.next_hop == from => {
+                    if metric == INFINITY {
+                        self.withdraw(&route, now);
+                        changed = true;
+                        continue;
+                    }
+                    changed |= route.metric != metric;
+                    self.hear(&route, from, metric, now);
+                }
+                Some(route) if metric < route.metric => {
+                    self.hear(&route, from, metric, now);
+                    changed = true;
+                }
+                Some(_) => {}
+                // an entry with a prefix longer than an address is garbage, and is ignored
+                None if metric < INFINITY => if let Ok(route) = Route::with_prefix(destination, prefix_len, from) {
+                    self.withdrawn.retain(|&(withdrawn, withdrawn_len, _)| (withdrawn, withdrawn_len) != (destination, prefix_len));
+                    if self.table.add_route(route.with_distance(RIP_DISTANCE).with_metric(metric).with_expiry(now + TIMEOUT)).is_ok() {
+                        self.route_event(destination, prefix_len, "recv route");
+                        changed = true;
+                    }
+                }
+                None => {}
+            }
+        }
+        changed
+    }
+
+    // times out the routes not heard about for too long; gives back whether the table changed
+    pub fn expire(&mut self, now: Duration) -> bool {
+        let (collected, withdrawn) = std::mem::take(&mut self.withdrawn).into_iter().partition(|&(_, _, until)| until <= now);
+        self.withdrawn = withdrawn;
+        for (destination, prefix_len, _) in collected {
+            self.route_event(destination, prefix_len, "garbage-collection timer");
+        }
+        let expired = self.table.purge_expired(now);
+        for route in &expired {
+            self.withdrawn.push((route.destination, route.prefix_len, now + GARBAGE_COLLECTION));
+            self.route_event(route.destination, route.prefix_len, "timeout");
+        }
+        !expired.is_empty()
+    }
+}
+
+// Routers and the links between them, updating all at once every UPDATE_INTERVAL
+#[derive(Debug)]
+pub struct RipNetwork {
+    pub routers : Vec<RipRouter>,
+    pub links : Vec<(usize, usize)>,
+    pub now : Duration,
+}
+
+impl RipNetwork {
+    pub fn new(routers: Vec<RipRouter>) -> Self {
+        RipNetwork { routers, links : vec![], now : Duration::ZERO }
+    }
+
+    pub fn connect(&mut self, a: usize, b: usize) {
+        self.links.push((a, b));
+    }
+
+    // every working link of the topology between two of these routers (by name)
+    pub fn connect_over(&mut self, topology: &Topology) {
+        for (_, link) in topology.links().filter(|(_, link)| link.up) {
+            let position = |end: &(NodeId, String)| {
+                let name = &topology.node(end.0)?.name;
+                self.routers.iter().position(|router| &router.table.name == name)
+            };
+            if let (Some(a), Some(b)) = (position(&link.a), position(&link.b)) {
+                self.connect(a, b);
+            }
+        }
+    }
+
+    // the cable is cut; nobody is told, the routes just stop being refreshed
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        self.links.retain(|&link| link != (a, b) && link != (b, a));
+    }
+
+    // one update interval: every router advertises to every neighbor; gives back whether any
+    // table changed
+    pub fn round(&mut self) -> bool {
+        self.now += UPDATE_INTERVAL;
+        let now = self.now;
+        let mut changed = false;
+        for router in &mut self.routers {
+            changed |= router.expire(now);
+        }
+        // everything is sent before anything is received
+        let mut messages = vec![];
+        for &(a, b) in &self.links {
+            messages.push((b, self.routers[a].address, self.routers[a].advertisement(self.routers[b].address)));
+            messages.push((a, self.routers[b].address, self.routers[b].advertisement(self.routers[a].address)));
+        }
+        for (to, from, advertisement) in messages {
+            changed |= self.routers[to].receive(from, &advertisement, now);
+        }
+        changed
+    }
+
+    // rounds until one changes nothing; gives back how many it took, None if that never happened
+    pub fn run_until_converged(&mut self, max_rounds: usize) -> Option<usize> {
+        (1..=max_rounds).find(|_| !self.round())
+    }
+}
+
+#[test]
+fn split_horizon_stops_counting_to_infinity() {
+    // A - B - C in a line, with the network 10.0.3.0/24 behind C
+    let network = |split_horizon: bool| {
+        let router = |name: &str, last: u8| {
+            let router = RipRouter::new(name, Ipv4Addr::new(192, 168, 0, last)).with_network(Ipv4Addr::new(10, 0, last, 0), 24);
+            if split_horizon { router } else { router.without_split_horizon() }
+        };
+        let mut network = RipNetwork::new(vec![router("A", 1), router("B", 2), router("C", 3)]);
+        network.connect(0, 1);
+        network.connect(1, 2);
+        network
+    };
+    let behind_c = Ipv4Addr::new(10, 0, 3, 0);
+
+    for split_horizon in [true, false] {
+        let mut network = network(split_horizon);
+        assert!(network.run_until_converged(10).is_some());
+        assert_eq!(network.routers[0].metric_to(behind_c, 24), Some(2));
+        assert_eq!(network.routers[0].table.find_next_hop(Ipv4Addr::new(10, 0, 3, 7)), Some(Ipv4Addr::new(192, 168, 0, 2)));
+        assert_eq!(network.routers[1].metric_to(behind_c, 24), Some(1));
+        assert_eq!(network.routers[2].metric_to(Ipv4Addr::new(10, 0, 1, 0), 24), Some(2));
+
+        network.disconnect(1, 2);
+        let mut b_metrics = vec![];
+        for _ in 0..30 {
+            network.round();
+            b_metrics.push(network.routers[1].metric_to(behind_c, 24));
+        }
+        let forgotten = |network: &RipNetwork| network.routers[..2].iter().all(|router| router.metric_to(behind_c, 24).is_none());
+        if split_horizon {
+            // B times out the route, tells A, and that's it
+            let timed_out = (TIMEOUT.as_secs() / UPDATE_INTERVAL.as_secs()) as usize;
+            assert_eq!(b_metrics[timed_out - 1], None);
+            assert!(b_metrics[timed_out..].iter().all(Option::is_none));
+            assert!(forgotten(&network));
+        } else {
+            // B takes the route back from A, and they count up to 16 between them
+            let counted: Vec<u32> = b_metrics.iter().flatten().copied().filter(|&metric| metric > 1).collect();
+            assert_eq!(counted, [3, 5, 7, 9, 11, 13, 15]);
+            assert!(forgotten(&network));
+        }
+    }
+}
+
+#[test]
+fn routes_live_as_long_as_they_are_heard_about() {
+    let neighbor = Ipv4Addr::new(192, 168, 0, 2);
+    let network = Ipv4Addr::new(10, 0, 2, 0);
+    let mut router = RipRouter::new("A", Ipv4Addr::new(192, 168, 0, 1));
+    let advertisement = vec![(network, 24, 1)];
+    let seconds = Duration::from_secs;
+    router.receive(neighbor, &advertisement, seconds(0));
+    assert_eq!(router.table.routes()[0].expires_at, Some(TIMEOUT));
+
+    // heard again, so it doesn't go at 180s
+    router.receive(neighbor, &advertisement, seconds(150));
+    assert!(!router.expire(TIMEOUT));
+    assert_eq!(router.metric_to(network, 24), Some(2));
+    // then the neighbor goes silent
+    assert!(!router.expire(seconds(150) + TIMEOUT - seconds(1)));
+    assert!(router.expire(seconds(150) + TIMEOUT));
+    assert_eq!(router.metric_to(network, 24), None);
+    // and it is advertised as unreachable for a while
+    assert_eq!(router.advertisement(Ipv4Addr::new(192, 168, 0, 3)), vec![(network, 24, INFINITY)]);
+    assert_eq!(router.route_state(network, 24).map(|machine| machine.current), Some("Garbage collection"));
+    router.expire(seconds(150) + TIMEOUT + GARBAGE_COLLECTION);
+    let machine = router.route_state(network, 24).unwrap();
+    assert_eq!(machine.current, "Unknown");
+    assert!(machine.to_dot().contains("\"Valid\" -> \"Valid\" [label=\"recv route (1)\", color=red, penwidth=2];"));
+    assert!(machine.to_dot().contains("\"Valid\" -> \"Garbage collection\" [label=\"recv metric 16\", color=gray];"));
+
+    // a metric that makes no sense is just unreachable, not an overflow
+    assert!(!router.receive(neighbor, &vec![(network, 24, u32::MAX)], seconds(1000)));
+    assert_eq!(router.metric_to(network, 24), None);
+}
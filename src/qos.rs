@@ -0,0 +1,234 @@
+//! Quality of service with DiffServ. The IPv4 type of service byte and the IPv6 traffic class
+//! are both split the same way now: the top six bits are the DSCP (RFC 2474), saying how the
+//! packet wants to be treated, and the bottom two are ECN (RFC 3168).
+//!
+//! A router doesn't look at the DSCP for the route, only to pick which of its output queues
+//! the packet waits in, and the higher queues are always served first: a voice packet marked
+//! EF goes out before the bulk download that was queued long before it.
+//!
+//! ECN is for when a queue starts to fill up: instead of dropping a packet to tell the sender
+//! to slow down, the router marks it Congestion Experienced and the receiver echoes that back.
+//! It only does so for packets that say both ends understand it (ECT); the rest are dropped
+//! like before.
+use std::collections::VecDeque;
+
+use crate::packet::Packet;
+
+// the class selectors, which are the old IP precedence values
+pub const CS0: u8 = 0;
+pub const CS1: u8 = 8;
+pub const CS2: u8 = 16;
+pub const CS3: u8 = 24;
+pub const CS4: u8 = 32;
+pub const CS5: u8 = 40;
+pub const CS6: u8 = 48;
+pub const CS7: u8 = 56;
+// assured forwarding: AFxy is class x, with y the drop precedence inside it
+pub const AF11: u8 = 10;
+pub const AF12: u8 = 12;
+pub const AF13: u8 = 14;
+pub const AF21: u8 = 18;
+pub const AF22: u8 = 20;
+pub const AF23: u8 = 22;
+pub const AF31: u8 = 26;
+pub const AF32: u8 = 28;
+pub const AF33: u8 = 30;
+pub const AF41: u8 = 34;
+pub const AF42: u8 = 36;
+pub const AF43: u8 = 38;
+// expedited forwarding, for voice
+pub const EF: u8 = 46;
+
+pub fn dscp_of(traffic_class: u8) -> u8 {
+    traffic_class >> 2
+}
+
+pub fn traffic_class(dscp: u8, ecn: Ecn) -> u8 {
+    dscp << 2 | ecn as u8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    // the sender doesn't know about ECN
+    NotEct = 0,
+    Ect1 = 1,
+    Ect0 = 2,
+    // set by a congested router
+    Ce = 3,
+}
+
+impl Ecn {
+    pub fn of(traffic_class: u8) -> Self {
+        match traffic_class & 0b11 {
+            0 => Ecn::NotEct,
+            1 => Ecn::Ect1,
+            2 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+
+    // whether a router may mark it instead of dropping it
+    pub fn is_capable(self) -> bool {
+        self != Ecn::NotEct
+    }
+}
+
+// the output queues, the most important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    // routing protocols and the like (CS6, CS7), or the network falls apart
+    NetworkControl,
+    // voice and video (EF, CS4, CS5)
+    Realtime,
+    // the AF classes, and CS2, CS3
+    Assured,
+    BestEffort,
+    // the "lower effort" of CS1, only what nothing else wants
+    Background,
+}
+
+// Which queue each DSCP goes to. Without rules it is the usual mapping of RFC 4594; a rule
+// changes it for one DSCP, like a `class-map match dscp` on a Cisco router.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QosClassifier {
+    pub rules : Vec<(u8, Priority)>,
+}
+
+impl QosClassifier {
+    // the last rule for a DSCP wins
+    pub fn with_rule(mut self, dscp: u8, priority: Priority) -> Self {
+        self.rules.retain(|&(other, _)| other != dscp);
+        self.rules.push((dscp, priority));
+        self
+    }
+
+    pub fn classify(&self, dscp: u8) -> Priority {
+        if let Some(&(_, priority)) = self.rules.iter().find(|&&(other, _)| other == dscp) {
+            return priority;
+        }
+        match dscp {
+            CS6 | CS7 => Priority::NetworkControl,
+            EF | CS4 | CS5 => Priority::Realtime,
+            AF11 | AF12 | AF13 | AF21 | AF22 | AF23 | AF31 | AF32 | AF33 | AF41 | AF42 | AF43 | CS2 | CS3 => Priority::Assured,
+            CS1 => Priority::Background,
+            // the unknown ones too, as RFC 2474 says
+            _ => Priority::BestEffort,
+        }
+    }
+
+    pub fn classify_packet<P: Packet>(&self, packet: &P) -> Priority {
+        self.classify(dscp_of(packet.traffic_class()))
+    }
+}
+
+// An interface's output, one queue per priority, served strictly in order
+#[derive(Debug, Clone)]
+pub struct QosQueue<P> {
+    pub classifier : QosClassifier,
+    // packets per queue
+    pub limit : usize,
+    // from this many packets waiting, ECT packets are marked and the others dropped
+    pub mark_at : Option<usize>,
+    queues : [VecDeque<P>; 5],
+    pub marked : u64,
+    pub dropped : u64,
+}
+
+impl<P: Packet> QosQueue<P> {
+    pub fn new(classifier: QosClassifier, limit: usize) -> Self {
+        QosQueue { classifier, limit, mark_at : None, queues : Default::default(), marked : 0, dropped : 0 }
+    }
+
+    pub fn with_ecn(mut self, mark_at: usize) -> Self {
+        self.mark_at = Some(mark_at);
+        self
+    }
+
+    // the queue it went in, or the packet back if it was dropped
+    pub fn enqueue(&mut self, mut packet: P) -> Result<Priority, P> {
+        let priority = self.classifier.classify_packet(&packet);
+        let queue = &mut self.queues[priority as usize];
+        if queue.len() >= self.limit {
+            self.dropped += 1;
+            return Err(packet);
+        }
+        if self.mark_at.is_some_and(|mark_at| queue.len() >= mark_at) {
+            let ecn = Ecn::of(packet.traffic_class());
+            if !ecn.is_capable() {
+                self.dropped += 1;
+                return Err(packet);
+            }
+            if ecn != Ecn::Ce {
+                packet.set_traffic_class(traffic_class(dscp_of(packet.traffic_class()), Ecn::Ce));
+                self.marked += 1;
+            }
+        }
+        queue.push_back(packet);
+        Ok(priority)
+    }
+
+    // the next packet to go on the wire
+    pub fn dequeue(&mut self) -> Option<P> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    pub fn waiting(&self, priority: Priority) -> usize {
+        self.queues[priority as usize].len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn voice_goes_first_and_congestion_is_marked() {
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::layered::IpHeader;
+    use crate::router::{Forwarded, Router, RouterInterface};
+    use crate::routing::Route;
+    use std::net::Ipv4Addr;
+
+    let classifier = QosClassifier::default();
+    assert_eq!([EF, AF41, CS6, CS1, 0, 63].map(|dscp| classifier.classify(dscp)), [Priority::Realtime, Priority::Assured, Priority::NetworkControl, Priority::Background, Priority::BestEffort, Priority::BestEffort]);
+    assert_eq!(classifier.clone().with_rule(AF41, Priority::Realtime).classify(AF41), Priority::Realtime);
+    // 0xb8 is how EF looks in the whole byte
+    assert_eq!((dscp_of(0xb8), Ecn::of(0xb8), traffic_class(EF, Ecn::Ect0)), (EF, Ecn::NotEct, 0xba));
+
+    // the packets are queued as the router forwards them, so the marking has to survive that
+    let mut router = Router::new("edge").with_qos(classifier);
+    router.add_interface(RouterInterface::new("lan").with_v4(Ipv4Addr::new(10, 0, 0, 1), 24));
+    router.add_interface(RouterInterface::new("wan").with_v4(Ipv4Addr::new(203, 0, 113, 5), 24));
+    router.routes_v4.add_route(Route::default_route(Ipv4Addr::new(203, 0, 113, 1))).unwrap();
+    let mut queue = router.output_queue(4).with_ecn(2);
+    let mut packet = |dscp: u8, ecn: Ecn, port: u16| {
+        let packet = PacketBuilder::ipv4().src(Ipv4Addr::new(10, 0, 0, 2)).dst(Ipv4Addr::new(198, 51, 100, 7)).udp().sport(port).dport(5004).dscp(dscp).ecn(ecn).build();
+        let Forwarded::Out { interface, packet, .. } = router.forward(packet, "lan") else {
+            panic!("should have been forwarded");
+        };
+        assert_eq!((interface.as_str(), packet.network.dscp(), packet.network.ecn()), ("wan", dscp, ecn));
+        packet
+    };
+    // a download fills the best effort queue: from the third packet on they are marked
+    for port in 1..=3 {
+        queue.enqueue(packet(CS0, Ecn::Ect0, port)).unwrap();
+    }
+    // where a sender without ECN loses them instead
+    assert!(queue.enqueue(packet(CS0, Ecn::NotEct, 9)).is_err());
+    queue.enqueue(packet(CS0, Ecn::Ect0, 4)).unwrap();
+    // and once full, everyone does
+    assert!(queue.enqueue(packet(CS0, Ecn::Ect0, 5)).is_err());
+    assert_eq!((queue.waiting(Priority::BestEffort), queue.marked, queue.dropped), (4, 2, 2));
+    assert_eq!(queue.enqueue(packet(EF, Ecn::NotEct, 6)), Ok(Priority::Realtime));
+
+    // the voice packet is out first, even though it came last
+    let first = queue.dequeue().unwrap();
+    assert_eq!((first.source_port(), first.network.dscp()), (6, EF));
+    let sent: Vec<_> = std::iter::from_fn(|| queue.dequeue()).map(|packet| (packet.source_port(), packet.network.ecn())).collect();
+    assert_eq!(sent, [(1, Ecn::Ect0), (2, Ecn::Ect0), (3, Ecn::Ce), (4, Ecn::Ce)]);
+    assert!(queue.is_empty());
+}
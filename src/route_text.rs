@@ -0,0 +1,292 @@
+//! Routing tables as text, close to what `ip route` and `ip -6 route` print, so a real table
+//! can be pasted in and a simulated one dumped out:
+//!
+//! ```text
+//! default via fe80::1 proto static metric 1024
+//! 2001:db8::/32 dev eth3 proto ospf metric 20
+//! 2001:db8:1::/48 proto static
+//!     nexthop via 2001:db8::1 weight 1
+//!     nexthop via 2001:db8::2 weight 1
+//! ```
+//!
+//! The proto is how the administrative distance is written (kernel for connected routes,
+//! static, bgp, ospf, rip, or just the number). An address without a prefix length is a host
+//! route. Keys the simulator has no use for, like `pref medium` or `scope link`, are skipped
+//! when parsing.
+//!
+//! An IPv4 table has addresses as next hops, and a connected route there has the router's own
+//! address on the link (see Router::add_interface), which is what `src` says:
+//!
+//! ```text
+//! 10.0.0.0/8 dev eth0 proto kernel scope link src 10.0.0.1
+//! ```
+use std::fmt::{self, Debug, Display};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::routing::{Interface, Route, RouteAddress, RoutingTable, CONNECTED_DISTANCE, EBGP_DISTANCE, OSPF_DISTANCE, RIP_DISTANCE, STATIC_DISTANCE};
+
+// How a next hop is written: `via <address>` or `dev <interface>`
+pub trait NextHopText: Sized {
+    fn to_text(&self) -> String;
+    // from what came after via, dev and src on the line, if they were there
+    fn from_text(via: Option<&str>, dev: Option<&str>, src: Option<&str>) -> Option<Self>;
+    // for a connected route, the address to write as `src` instead of the next hop, if it is one
+    fn to_source_text(&self) -> Option<String> {
+        None
+    }
+}
+
+// A device read from text only has its name, and the number it ends with as its index
+impl NextHopText for Interface {
+    fn to_text(&self) -> String {
+        match self {
+            Interface::IpAddr(ip) => format!("via {ip}"),
+            Interface::Dev(device) => format!("dev {device}"),
+        }
+    }
+    fn from_text(via: Option<&str>, dev: Option<&str>, _: Option<&str>) -> Option<Self> {
+        if let Some(via) = via {
+            return via.parse().ok().map(Interface::IpAddr);
+        }
+        dev.map(Interface::dev)
+    }
+}
+
+impl NextHopText for Ipv4Addr {
+    fn to_text(&self) -> String {
+        format!("via {self}")
+    }
+    // a route with only a dev goes out through the router's own address on that link
+    fn from_text(via: Option<&str>, _: Option<&str>, src: Option<&str>) -> Option<Self> {
+        via.or(src)?.parse().ok()
+    }
+    fn to_source_text(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl NextHopText for Ipv6Addr {
+    fn to_text(&self) -> String {
+        format!("via {self}")
+    }
+    // a route with only a dev goes out through the router's own address on that link
+    fn from_text(via: Option<&str>, _: Option<&str>, src: Option<&str>) -> Option<Self> {
+        via.or(src)?.parse().ok()
+    }
+    fn to_source_text(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+fn proto_name(distance: u8) -> String {
+    match distance {
+        CONNECTED_DISTANCE => "kernel".to_string(),
+        STATIC_DISTANCE => "static".to_string(),
+        EBGP_DISTANCE => "bgp".to_string(),
+        OSPF_DISTANCE => "ospf".to_string(),
+        RIP_DISTANCE => "rip".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn proto_distance(name: &str) -> Option<u8> {
+    match name {
+        "kernel" => Some(CONNECTED_DISTANCE),
+        // routes from DHCP and router advertisements are as good as static ones here
+        "static" | "boot" | "dhcp" | "ra" => Some(STATIC_DISTANCE),
+        "bgp" => Some(EBGP_DISTANCE),
+        "ospf" => Some(OSPF_DISTANCE),
+        "rip" => Some(RIP_DISTANCE),
+        other => other.parse().ok(),
+    }
+}
+
+impl<A: RouteAddress + Display, H: NextHopText> Display for Route<A, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_default() {
+            write!(f, "default")?;
+        } else {
+            write!(f, "{}/{}", self.destination, self.prefix_len)?;
+        }
+        let source = self.next_hop.to_source_text().filter(|_| self.distance == CONNECTED_DISTANCE && self.equal_cost.is_empty());
+        if let Some(source) = &source {
+            write!(f, " proto {} scope link src {source}", proto_name(self.distance))?;
+        } else {
+            if self.equal_cost.is_empty() {
+                write!(f, " {}", self.next_hop.to_text())?;
+            }
+            write!(f, " proto {}", proto_name(self.distance))?;
+        }
+        if self.metric != 0 {
+            write!(f, " metric {}", self.metric)?;
+        }
+        if !self.equal_cost.is_empty() {
+            for next_hop in self.next_hops() {
+                write!(f, "\n\tnexthop {} weight 1", next_hop.to_text())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<A: RouteAddress + Display, H: NextHopText> Display for RoutingTable<A, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for route in self.routes() {
+            writeln!(f, "{route}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRouteError {
+    // counting from 1, like an editor
+    pub line : usize,
+    pub reason : String,
+}
+
+impl Display for ParseRouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseRouteError {}
+
+// the values of via, dev, proto, metric and src on a line; everything else is skipped
+fn keys<'a>(words: &[&'a str]) -> [Option<&'a str>; 5] {
+    let mut values = [None; 5];
+    for pair in words.windows(2) {
+        if let Some(index) = ["via", "dev", "proto", "metric", "src"].iter().position(|&key| key == pair[0]) {
+            values[index].get_or_insert(pair[1]);
+        }
+    }
+    values
+}
+
+impl<A: RouteAddress + FromStr, H: NextHopText + Clone + Debug> FromStr for RoutingTable<A, H> {
+    type Err = ParseRouteError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut table = RoutingTable::default();
+        // a multipath route waiting for its nexthop lines
+        let mut pending: Option<(A, u8, u8, u32, Vec<H>)> = None;
+        let width = A::ALL_ONES.count_ones() as u8;
+        for (index, line) in text.lines().enumerate() {
+            let error = |reason: &str| ParseRouteError { line : index + 1, reason : reason.to_string() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some(&first) = words.first() else {
+                continue;
+            };
+            let [via, dev, proto, metric, src] = keys(&words);
+            if first == "nexthop" {
+                let hops = &mut pending.as_mut().ok_or_else(|| error("nexthop without a route before it"))?.4;
+                hops.push(H::from_text(via, dev, src).ok_or_else(|| error("can't read the next hop"))?);
+                continue;
+            }
+            if let Some((destination, prefix_len, distance, metric, hops)) = pending.take() {
+                let route = multipath(destination, prefix_len, distance, metric, hops).ok_or_else(|| error("multipath route without a nexthop"))?;
+                table.add_route(route).map_err(|_| error("the same route twice"))?;
+            }
+
+            let (destination, prefix_len) = match first {
+                "default" => (A::from_bits(0), 0),
+                prefix => {
+                    let (address, prefix_len) = match prefix.split_once('/') {
+                        Some((address, prefix_len)) => (address, prefix_len.parse().map_err(|_| error("bad prefix length"))?),
+                        // a single address, a host route
+                        None => (prefix, width),
+                    };
+                    if prefix_len > width {
+                        return Err(error("prefix length too long"));
+                    }
+                    (address.parse().map_err(|_| error("bad address"))?, prefix_len)
+                }
+            };
+            let distance = match proto {
+                Some(proto) => proto_distance(proto).ok_or_else(|| error("unknown proto"))?,
+                None => STATIC_DISTANCE,
+            };
+            let metric = match metric {
+                Some(metric) => metric.parse().map_err(|_| error("bad metric"))?,
+                None => 0,
+            };
+            if via.is_none() && dev.is_none() && src.is_none() {
+                pending = Some((destination, prefix_len, distance, metric, vec![]));
+                continue;
+            }
+            let next_hop = H::from_text(via, dev, src).ok_or_else(|| error("can't read the next hop"))?;
+            let route = Route::with_prefix(destination, prefix_len, next_hop).map_err(|_| error("prefix longer than the address"))?;
+            table.add_route(route.with_distance(distance).with_metric(metric)).map_err(|_| error("the same route twice"))?;
+        }
+        if let Some((destination, prefix_len, distance, metric, hops)) = pending {
+            let error = |reason: &str| ParseRouteError { line : text.lines().count(), reason : reason.to_string() };
+            let route = multipath(destination, prefix_len, distance, metric, hops).ok_or_else(|| error("multipath route without a nexthop"))?;
+            table.add_route(route).map_err(|_| error("the same route twice"))?;
+        }
+        Ok(table)
+    }
+}
+
+fn multipath<A: RouteAddress, H>(destination: A, prefix_len: u8, distance: u8, metric: u32, hops: Vec<H>) -> Option<Route<A, H>> {
+    let mut hops = hops.into_iter();
+    let route = Route::with_prefix(destination, prefix_len, hops.next()?).ok()?.with_distance(distance).with_metric(metric);
+    Some(hops.fold(route, Route::with_equal_cost))
+}
+
+#[test]
+fn pasted_tables_work_and_print_back() {
+    // what `ip -6 route` printed on a router, more or less
+    let pasted = "
+default via fe80::1 dev eth0 proto static metric 1024 pref medium
+2001:db8::/32 dev eth3 proto ospf metric 20 pref medium
+2001:db8:1::/48 proto static metric 10 pref medium
+	nexthop via 2001:db8::1 dev eth1 weight 1
+	nexthop via 2001:db8::2 dev eth2 weight 1
+fe80::/64 dev eth0 proto kernel metric 256 pref medium
+";
+    let table: RoutingTable = pasted.parse().unwrap();
+    assert_eq!(table.routes().len(), 4);
+    assert_eq!(table.find_next_hop("2001:db8:ffff::1".parse().unwrap()), Some(Interface::dev("eth3")));
+    assert_eq!(table.find_next_hops("2001:db8:1::5".parse().unwrap()), [
+        Interface::IpAddr("2001:db8::1".parse().unwrap()),
+        Interface::IpAddr("2001:db8::2".parse().unwrap()),
+    ]);
+    assert_eq!(table.find_next_hop("2606:4700::1111".parse().unwrap()), Some(Interface::IpAddr("fe80::1".parse().unwrap())));
+
+    let printed = table.to_string();
+    assert_eq!(printed, "\
+default via fe80::1 proto static metric 1024
+2001:db8::/32 dev eth3 proto ospf metric 20
+2001:db8:1::/48 proto static metric 10
+\tnexthop via 2001:db8::1 weight 1
+\tnexthop via 2001:db8::2 weight 1
+fe80::/64 dev eth0 proto kernel metric 256
+");
+    // and what it prints reads back the same
+    assert_eq!(printed.parse::<RoutingTable>().unwrap().to_string(), printed);
+
+    // what `ip route` printed, the connected routes with only a dev and the address on it
+    let pasted = "
+default via 192.168.1.1 dev wlan0 proto dhcp metric 100
+10.0.0.0/8 via 10.255.0.1
+10.255.0.0/16 dev eth0 proto kernel scope link src 10.255.0.7
+192.168.1.0/24 dev wlan0 proto kernel scope link src 192.168.1.42 metric 600
+";
+    let v4: crate::networkingv4::RoutingTable = pasted.parse().unwrap();
+    assert_eq!(v4.find_next_hop("10.1.2.3".parse().unwrap()), Some("10.255.0.1".parse().unwrap()));
+    assert_eq!(v4.find_next_hop("192.168.1.20".parse().unwrap()), Some("192.168.1.42".parse().unwrap()));
+    assert_eq!(v4.routes()[2].distance, CONNECTED_DISTANCE);
+    // the device names are gone, there is nowhere to keep them in an IPv4 table
+    assert_eq!(v4.to_string(), "\
+default via 192.168.1.1 proto static metric 100
+10.0.0.0/8 via 10.255.0.1 proto static
+10.255.0.0/16 proto kernel scope link src 10.255.0.7
+192.168.1.0/24 proto kernel scope link src 192.168.1.42 metric 600
+");
+    assert_eq!(v4.to_string().parse::<crate::networkingv4::RoutingTable>().unwrap().to_string(), v4.to_string());
+
+    let error = "2001:db8::/200 dev eth0".parse::<RoutingTable>().unwrap_err();
+    assert_eq!(error, ParseRouteError { line : 1, reason : "prefix length too long".to_string() });
+}
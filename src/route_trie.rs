@@ -0,0 +1,245 @@
+//! Longest prefix match with a binary trie instead of looking at every route.
+//! Every route sits on the node reached by following the bits of its prefix, one bit per level,
+//! so a lookup walks down the bits of the address and remembers the last routes it passed.
+//! That is at most 32 (or 128) steps, however many routes there are. RoutingTable keeps one
+//! next to its list of routes, and does its lookups in it.
+use std::cmp::Reverse;
+use std::fmt::Debug;
+
+use crate::routing::{Route, RouteAddress, RoutingTable};
+
+#[derive(Debug, Clone)]
+struct Node<A, H> {
+    children : [Option<usize>; 2],
+    // the routes for exactly this prefix, in the order they were added; a node left empty by a
+    // removal stays, it just has nothing to say
+    routes : Vec<Route<A, H>>,
+}
+
+impl<A, H> Default for Node<A, H> {
+    fn default() -> Self {
+        Node { children : [None, None], routes : vec![] }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteTrie<A, H> {
+    nodes : Vec<Node<A, H>>,
+}
+
+impl<A, H> Default for RouteTrie<A, H> {
+    fn default() -> Self {
+        RouteTrie { nodes : vec![Node::default()] }
+    }
+}
+
+impl<A: RouteAddress, H: Clone + Debug> RouteTrie<A, H> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_table(table: &RoutingTable<A, H>) -> Self {
+        Self::from_routes(table.routes())
+    }
+
+    pub fn from_routes(routes: &[Route<A, H>]) -> Self {
+        let mut trie = Self::new();
+        for route in routes {
+            trie.insert(route.clone());
+        }
+        trie
+    }
+
+    // the bit of the address at this depth, from the most significant one
+    fn bit(address: u128, depth: u32) -> usize {
+        let width = A::ALL_ONES.count_ones();
+        ((address >> (width - 1 - depth)) & 1) as usize
+    }
+
+    // the node of this prefix, made on the way down if it isn't there yet
+    fn node_for(&mut self, destination: A, prefix_len: u8) -> usize {
+        let destination = destination.to_bits();
+        let mut node = 0;
+        for depth in 0..prefix_len.into() {
+            let bit = Self::bit(destination, depth);
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(Node::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[bit] = Some(child);
+                    child
+                }
+            };
+        }
+        node
+    }
+
+    // a destination with bits outside its mask can never match anything, so it isn't kept
+    fn is_prefix(destination: A, prefix_len: u8) -> bool {
+        let mask = A::from_bits(A::ALL_ONES & !A::ALL_ONES.checked_shr(prefix_len.into()).unwrap_or(0));
+        destination.mask(mask) == destination
+    }
+
+    pub fn insert(&mut self, route: Route<A, H>) {
+        if !Self::is_prefix(route.destination, route.prefix_len) {
+            return;
+        }
+        let node = self.node_for(route.destination, route.prefix_len);
+        self.nodes[node].routes.push(route);
+    }
+
+    // the routes for destination/prefix_len are these now, whatever they were before
+    pub fn set(&mut self, destination: A, prefix_len: u8, routes: Vec<Route<A, H>>) {
+        if !Self::is_prefix(destination, prefix_len) {
+            return;
+        }
+        let node = self.node_for(destination, prefix_len);
+        self.nodes[node].routes = routes;
+    }
+
+    // the routes of the longest prefix the address is in
+    pub fn longest_match(&self, ipaddr: A) -> &[Route<A, H>] {
+        let address = ipaddr.to_bits();
+        let mut best = &self.nodes[0].routes;
+        let mut node = 0;
+        for depth in 0..A::ALL_ONES.count_ones() {
+            let Some(child) = self.nodes[node].children[Self::bit(address, depth)] else {
+                break;
+            };
+            node = child;
+            if !self.nodes[node].routes.is_empty() {
+                best = &self.nodes[node].routes;
+            }
+        }
+        best
+    }
+
+    // of the routes for the longest prefix, the lowest distance, then the lowest metric, then
+    // the one added last
+    pub fn find_best_route(&self, ipaddr: A) -> Option<&Route<A, H>> {
+        self.longest_match(ipaddr)
+            .iter()
+            .max_by_key(|route| (Reverse(route.distance), Reverse(route.metric)))
+    }
+
+    pub fn find_next_hop(&self, ipaddr: A) -> Option<H> {
+        self.find_best_route(ipaddr)
+            .map(|route| route.next_hop.clone())
+    }
+}
+
+#[test]
+fn trie_agrees_with_the_table() {
+    use crate::traffic::SimpleRng;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn compare<A: RouteAddress>(rng: &mut SimpleRng) {
+        let width = A::ALL_ONES.count_ones() as u64;
+        let random_address = |rng: &mut SimpleRng| A::from_bits(((rng.next_u64() as u128) << 64 | rng.next_u64() as u128) & A::ALL_ONES);
+        let mut routes = vec![];
+        for port in 0..300 {
+            // short prefixes too, so that lookups match more than one route; some duplicates
+            let prefix_len = rng.between(0, width.min(24)) as u8;
+            let route = Route::with_prefix(random_address(rng), prefix_len, port).unwrap()
+                .with_distance(rng.between(0, 2) as u8)
+                .with_metric(rng.between(0, 2) as u32);
+            routes.push(route);
+        }
+        let table: RoutingTable<A, u64> = RoutingTable::default().with_routes(routes);
+        let trie = RouteTrie::from_table(&table);
+        for _ in 0..2000 {
+            // half of the addresses inside some route, so there is something to find
+            let address = if rng.next_u64().is_multiple_of(2) {
+                let route = &table.routes()[rng.between(0, 299) as usize];
+                A::from_bits(route.destination.to_bits() | (random_address(rng).to_bits() & !route.mask().to_bits()))
+            } else {
+                random_address(rng)
+            };
+            assert_eq!(trie.find_next_hop(address), table.find_next_hop(address), "for {address:?}");
+        }
+    }
+
+    let mut rng = SimpleRng::new(7);
+    compare::<Ipv4Addr>(&mut rng);
+    compare::<Ipv6Addr>(&mut rng);
+}
+
+// The simplest longest prefix match there is, to check the others against: an address matches
+// when its first prefix_len bits are the route's, and the longest such route wins, then the
+// lowest distance, then the lowest metric, then the one added last
+#[cfg(test)]
+fn reference_next_hop(routes: &[(u32, u8, u8, u32, usize)], address: u32) -> Option<usize> {
+    let same_first_bits = |destination: u32, prefix_len: u8| prefix_len == 0 || (destination ^ address) >> (32 - prefix_len as u32) == 0;
+    let mut best: Option<(u8, u8, u32, usize)> = None;
+    for &(destination, prefix_len, distance, metric, hop) in routes {
+        let better = best.is_none_or(|(best_len, best_distance, best_metric, _)| {
+            (prefix_len, best_distance, best_metric) >= (best_len, distance, metric)
+        });
+        if same_first_bits(destination, prefix_len) && better {
+            best = Some((prefix_len, distance, metric, hop));
+        }
+    }
+    best.map(|(_, _, _, hop)| hop)
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn lookups_agree_with_the_reference(
+        routes in proptest::collection::vec((proptest::num::u32::ANY, 0u8..=32, 0u8..3, 0u32..3), 1..60),
+        addresses in proptest::collection::vec((proptest::num::u32::ANY, proptest::num::usize::ANY), 1..60),
+    ) {
+        use std::net::Ipv4Addr;
+
+        // destinations without bits past their prefix, the numbers as next hops
+        let routes: Vec<(u32, u8, u8, u32, usize)> = routes
+            .into_iter()
+            .enumerate()
+            .map(|(hop, (destination, prefix_len, distance, metric))| {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                (destination & mask, prefix_len, distance, metric, hop)
+            })
+            .collect();
+        let table: RoutingTable<Ipv4Addr, usize> = RoutingTable::default().with_routes(routes
+            .iter()
+            .map(|&(destination, prefix_len, distance, metric, hop)| Route::with_prefix(destination.into(), prefix_len, hop).unwrap().with_distance(distance).with_metric(metric))
+            .collect());
+        let trie = RouteTrie::from_table(&table);
+        for (random, inside) in addresses {
+            // keep the first bits of one of the routes, so most addresses match something
+            let (destination, prefix_len, ..) = routes[inside % routes.len()];
+            let kept = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            for address in [random, destination | (random & !kept)] {
+                let expected = reference_next_hop(&routes, address);
+                proptest::prop_assert_eq!(table.find_next_hop(address.into()), expected);
+                proptest::prop_assert_eq!(trie.find_next_hop(address.into()), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn more_specific_routes_always_win(
+        destination in proptest::num::u32::ANY,
+        prefix_len in 0u8..32,
+        extra_bits in 1u8..=32,
+        host in proptest::num::u32::ANY,
+        // however much worse the specific route looks otherwise
+        distance in proptest::num::u8::ANY,
+        metric in proptest::num::u32::ANY,
+    ) {
+        use std::net::Ipv4Addr;
+
+        let longer = (prefix_len + extra_bits).min(32);
+        let mask = |len: u8| u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+        let wide = Route::with_prefix(Ipv4Addr::from(destination & mask(prefix_len)), prefix_len, "wide").unwrap().with_distance(0);
+        // inside the wide one: its first bits, then anything
+        let narrow_destination = (destination & mask(prefix_len)) | (host & mask(longer) & !mask(prefix_len));
+        let narrow = Route::with_prefix(Ipv4Addr::from(narrow_destination), longer, "narrow").unwrap().with_distance(distance).with_metric(metric);
+        let address = Ipv4Addr::from(narrow_destination | (host & !mask(longer)));
+
+        let table: RoutingTable<Ipv4Addr, &str> = RoutingTable::default().with_routes(vec![wide, narrow]);
+        proptest::prop_assert_eq!(table.find_next_hop(address), Some("narrow"));
+        proptest::prop_assert_eq!(RouteTrie::from_table(&table).find_next_hop(address), Some("narrow"));
+    }
+}
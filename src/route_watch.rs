@@ -0,0 +1,136 @@
+//! Telling others when a routing table changes, like `ip monitor route` does with netlink
+//! messages. Whoever wants to know (a routing protocol redistributing routes, something drawing
+//! the table) calls watch() once and gets every change after that as it happens, instead of
+//! comparing the whole table now and then to find out what changed.
+//! Each watcher has its own channel, so it can sit in another thread, and a watcher that is
+//! dropped is simply forgotten at the next change.
+//! The routing protocols change their tables through the same methods, so whatever OSPF, RIP
+//! or BGP work out is heard about too, and only what actually changed.
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::routing::Route;
+
+#[derive(Debug, Clone)]
+pub enum RouteChange<A, H> {
+    Added(Route<A, H>),
+    // removed by hand, or evicted to make room
+    Removed(Route<A, H>),
+    Replaced { old : Route<A, H>, new : Route<A, H> },
+}
+
+#[derive(Debug)]
+pub struct RouteWatcher<A, H> {
+    receiver : Receiver<RouteChange<A, H>>,
+}
+
+impl<A, H> RouteWatcher<A, H> {
+    // the table keeps the sender, the watcher the receiver
+    pub(crate) fn new() -> (Sender<RouteChange<A, H>>, Self) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        (sender, RouteWatcher { receiver })
+    }
+
+    // the changes since the last call, oldest first, without waiting for more
+    pub fn changes(&self) -> Vec<RouteChange<A, H>> {
+        self.receiver.try_iter().collect()
+    }
+
+    // waits for the next change; None once the table is gone
+    pub fn next_change(&self) -> Option<RouteChange<A, H>> {
+        self.receiver.recv().ok()
+    }
+}
+
+#[test]
+fn watchers_hear_about_every_change() {
+    use crate::route_text::NextHopText;
+    use crate::routing::{Interface, RoutingTable};
+    use crate::table_limits::{FullPolicy, TableLimit};
+    use std::net::Ipv6Addr;
+
+    let prefix = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let mut table: RoutingTable = RoutingTable::default().with_limit(TableLimit::new(2, FullPolicy::EvictOldest));
+    let watcher = table.watch();
+    let described = |watcher: &RouteWatcher<Ipv6Addr, Interface>| watcher
+        .changes()
+        .into_iter()
+        .map(|change| match change {
+            RouteChange::Added(route) => format!("+ {}", route.destination),
+            RouteChange::Removed(route) => format!("- {}", route.destination),
+            RouteChange::Replaced { old, new } => format!("~ {} {} -> {}", new.destination, old.next_hop.to_text(), new.next_hop.to_text()),
+        })
+        .collect::<Vec<_>>();
+
+    table.add_route(Route::with_prefix(prefix("2001:db8:1::"), 48, Interface::dev("eth1")).unwrap()).unwrap();
+    table.add_route(Route::with_prefix(prefix("2001:db8:2::"), 48, Interface::dev("eth2")).unwrap()).unwrap();
+    assert_eq!(described(&watcher), ["+ 2001:db8:1::", "+ 2001:db8:2::"]);
+    // a failed change is no change
+    assert!(table.add_route(Route::with_prefix(prefix("2001:db8:2::"), 48, Interface::dev("eth2")).unwrap()).is_err());
+    assert!(described(&watcher).is_empty());
+
+    // the third route pushes the first one out, and that is a removal too
+    let late = table.watch();
+    table.add_route(Route::with_prefix(prefix("2001:db8:3::"), 48, Interface::dev("eth3")).unwrap()).unwrap();
+    table.replace_route(Route::with_prefix(prefix("2001:db8:3::"), 48, Interface::dev("eth4")).unwrap()).unwrap();
+    table.remove_route(prefix("2001:db8:2::"), 48).unwrap();
+    let expected = ["- 2001:db8:1::", "+ 2001:db8:3::", "~ 2001:db8:3:: dev eth3 -> dev eth4", "- 2001:db8:2::"];
+    assert_eq!(described(&watcher), expected);
+    // the one watching since later sees the same from then on
+    assert_eq!(described(&late), expected);
+
+    // a watcher that went away doesn't stop the others
+    drop(late);
+    table.remove_route(prefix("2001:db8:3::"), 48).unwrap();
+    assert_eq!(described(&watcher), ["- 2001:db8:3::"]);
+    assert_eq!(table.watchers.len(), 1);
+}
+
+#[test]
+fn routing_protocols_tell_the_watchers_too() {
+    use crate::protocols::ospf::{OspfNetwork, OspfRouter};
+    use crate::protocols::rip::{RipRouter, INFINITY};
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    let described = |watcher: &RouteWatcher<Ipv4Addr, Ipv4Addr>| watcher
+        .changes()
+        .into_iter()
+        .map(|change| match change {
+            RouteChange::Added(route) => format!("+ {}/{} via {}", route.destination, route.prefix_len, route.next_hop),
+            RouteChange::Removed(route) => format!("- {}/{}", route.destination, route.prefix_len),
+            RouteChange::Replaced { old, new } => format!("~ {}/{} via {} cost {} -> via {} cost {}",
+                new.destination, new.prefix_len, old.next_hop, old.metric, new.next_hop, new.metric),
+        })
+        .collect::<Vec<_>>();
+
+    // A --1-- B --1-- C, and A --5-- C the long way
+    let router = |name: &str, last: u8| OspfRouter::new(name, Ipv4Addr::new(1, 1, 1, last)).with_network(Ipv4Addr::new(10, 0, last, 0), 24);
+    let mut network = OspfNetwork::new(vec![router("A", 1), router("B", 2), router("C", 3)]);
+    network.connect(0, 1, 1);
+    network.connect(1, 2, 1);
+    network.connect(0, 2, 5);
+    let watcher = network.routers[0].table.watch();
+    network.converge();
+    assert_eq!(described(&watcher), ["+ 10.0.1.0/24 via 1.1.1.1", "+ 10.0.2.0/24 via 1.1.1.2", "+ 10.0.3.0/24 via 1.1.1.2"]);
+    // running SPF again with nothing new changes nothing
+    network.routers[0].run_spf();
+    assert!(described(&watcher).is_empty());
+    network.disconnect(1, 2);
+    network.converge();
+    assert_eq!(described(&watcher), ["~ 10.0.3.0/24 via 1.1.1.2 cost 2 -> via 1.1.1.3 cost 5"]);
+
+    let mut rip = RipRouter::new("R", Ipv4Addr::new(192, 168, 0, 1));
+    let watcher = rip.table.watch();
+    let neighbor = Ipv4Addr::new(192, 168, 0, 2);
+    let now = Duration::ZERO;
+    rip.receive(neighbor, &vec![(Ipv4Addr::new(10, 9, 0, 0), 16, 2)], now);
+    // heard again: only the expiry moves, which is not news
+    rip.receive(neighbor, &vec![(Ipv4Addr::new(10, 9, 0, 0), 16, 2)], now + Duration::from_secs(30));
+    rip.receive(neighbor, &vec![(Ipv4Addr::new(10, 9, 0, 0), 16, 4)], now + Duration::from_secs(60));
+    rip.receive(neighbor, &vec![(Ipv4Addr::new(10, 9, 0, 0), 16, INFINITY)], now + Duration::from_secs(90));
+    assert_eq!(described(&watcher), [
+        "+ 10.9.0.0/16 via 192.168.0.2",
+        "~ 10.9.0.0/16 via 192.168.0.2 cost 3 -> via 192.168.0.2 cost 5",
+        "- 10.9.0.0/16",
+    ]);
+}
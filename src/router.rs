@@ -0,0 +1,738 @@
+//! A router: interfaces, a routing table for each IP version, and maybe a NAT, with the steps
+//! a packet goes through in the order Linux does them:
+//!
+//! 1. a reply coming in on the NAT's outside interface is translated back first (like
+//!    conntrack in PREROUTING), so the routing sees the real inside destination;
+//! 2. a packet for one of the router's own addresses stops here;
+//! 3. the hop limit goes down by one, and a packet that runs out is dropped with an ICMP Time
+//!    Exceeded sent back to its source;
+//! 4. the route lookup picks the interface and the next hop;
+//! 5. a packet leaving through the NAT's outside interface gets its source translated (SNAT
+//!    in POSTROUTING), after routing, because only then is the interface known;
+//! 6. before it goes on the wire, the next hop's MAC address comes from the neighbor cache, or
+//!    is asked for with ARP or Neighbor Discovery (resolve_link).
+//!
+//! receive_frame() does all of it for a frame off a link, and answers ARP and Neighbor
+//! Discovery for the router's own addresses too, so a router can sit in a topology of hosts
+//! and switches.
+//!
+//! A multicast packet isn't routed to one place: it is copied out of every interface where
+//! some host said it is in the group. With IGMP (hear_igmp, igmp_tick) that is soft state: each
+//! interface has its own querier election and group timers, and a group nobody reports for any
+//! more stops being forwarded there.
+//!
+//! What waits for the wire waits in an output queue, and which one comes from the packet's
+//! DSCP (see qos), so voice gets ahead of a download.
+//!
+//! A router can switch labels too (see mpls): label_push() starts a packet on a label switched
+//! path instead of routing it, and switch() forwards a labeled one on its top label alone.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
+
+use crate::arp::ArpMessage;
+use crate::interface::NetInterface;
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use crate::mpls::{LabelOperation, LabelTable, LabeledPacket, Switched};
+use crate::multicast::{is_link_local_group, GroupMessage, IgmpMessage, MulticastRouter};
+use crate::nat_v4::{NatAddress, NatError, NatTable};
+use crate::ndp::NdpMessage;
+use crate::neighbor::{ArpCache, NdCache, Resolution};
+use crate::packet::ipv4::Ipv4Header;
+use crate::packet::ipv6::Ipv6Header;
+use crate::packet::layered::{IpHeader, LayeredPacket};
+use crate::packet::{icmp, Packet};
+use crate::networkingv4::RoutingTable as RoutingTableV4;
+use crate::qos::{QosClassifier, QosQueue};
+use crate::routing::{Interface, ResolveError, Route, RouteAddress, RoutingTable, CONNECTED_DISTANCE};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterInterface {
+    // its index is the position in the router's list, set when it is added
+    pub device : NetInterface,
+    // the NAT translates what leaves through here
+    pub nat_outside : bool,
+}
+
+impl RouterInterface {
+    pub fn new(name: &str) -> Self {
+        RouterInterface { device : NetInterface::new(0, name), nat_outside : false }
+    }
+
+    pub fn with_mac(mut self, mac: MacAddr) -> Self {
+        self.device.mac = mac;
+        self
+    }
+
+    pub fn with_v4(mut self, address: Ipv4Addr, prefix_len: u8) -> Self {
+        self.device.addresses.push((IpAddr::V4(address), prefix_len));
+        self
+    }
+
+    pub fn with_v6(mut self, address: Ipv6Addr, prefix_len: u8) -> Self {
+        self.device.addresses.push((IpAddr::V6(address), prefix_len));
+        self
+    }
+
+    // (address, prefix_len), the first of each version
+    pub fn v4(&self) -> Option<(Ipv4Addr, u8)> {
+        self.device.addresses.iter().find_map(|&(address, prefix_len)| match address {
+            IpAddr::V4(address) => Some((address, prefix_len)),
+            IpAddr::V6(_) => None,
+        })
+    }
+
+    pub fn v6(&self) -> Option<(Ipv6Addr, u8)> {
+        self.device.addresses.iter().find_map(|&(address, prefix_len)| match address {
+            IpAddr::V6(address) => Some((address, prefix_len)),
+            IpAddr::V4(_) => None,
+        })
+    }
+
+    pub fn nat_outside(mut self) -> Self {
+        self.nat_outside = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DropReason {
+    NoRoute,
+    InterfaceDown,
+    // a multicast packet for a group nobody on the other interfaces is in
+    NoMembers,
+    // and not even the Time Exceeded could be sent back
+    TtlExpired,
+    Nat(NatError),
+    // a labeled packet with a label the router has no entry for
+    UnknownLabel(u32),
+}
+
+// What became of a packet, which is still the same kind of packet it came in as
+#[derive(Debug, Clone, PartialEq)]
+pub enum Forwarded<P: Packet = LayeredPacket> {
+    Out { interface : String, next_hop : P::Address, packet : P },
+    // for the router itself
+    Local(P),
+    // a multicast packet, with a copy for every interface that has members of its group
+    Replicated(Vec<(String, P)>),
+    // the packet was dropped, and this ICMP error goes back to where it came from instead
+    Icmp { interface : String, next_hop : P::Address, packet : P },
+    Dropped(DropReason),
+}
+
+// ICMP's type for Time Exceeded, and ICMPv6's
+pub const ICMP_TIME_EXCEEDED: u16 = icmp::TIME_EXCEEDED as u16;
+pub const ICMPV6_TIME_EXCEEDED: u16 = icmp::ICMPV6_TIME_EXCEEDED as u16;
+// what a router starts its own packets with
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+#[derive(Debug)]
+pub struct Router {
+    pub name : String,
+    pub interfaces : Vec<RouterInterface>,
+    // the next hop is a gateway, or the router's own address on the interface for a network
+    // it is directly on
+    pub routes_v4 : RoutingTableV4,
+    pub routes_v6 : RoutingTable,
+    pub nat : Option<NatTable>,
+    // (interface, group) for every multicast group with members behind that interface
+    pub groups : Vec<(usize, IpAddr)>,
+    // the IGMP state of every interface that has heard or sent any
+    pub igmp : Vec<(usize, MulticastRouter)>,
+    // the MAC addresses of the neighbors, one cache per interface, in the same order: the
+    // same IP can be a different machine on another link
+    pub arp : Vec<ArpCache>,
+    pub nd : Vec<NdCache>,
+    // which output queue a packet waits in, from its DSCP
+    pub qos : QosClassifier,
+    pub labels : LabelTable,
+}
+
+// The IP versions a router forwards, each with its own table
+pub trait RouterAddress: NatAddress + RouteAddress {
+    // the IP header of this version, for when the packet has to be written out
+    type Header: IpHeader<Address = Self>;
+    fn address_on(interface: &RouterInterface) -> Option<Self>;
+    // what happens before routing, like undoing the NAT
+    fn arrive<P: Packet<Address = Self>>(router: &mut Router, packet: P, ingress: usize) -> P;
+    // the lookup and what happens after it; ingress is None for the router's own packets
+    fn route<P: Packet<Address = Self>>(router: &mut Router, packet: P, ingress: Option<usize>) -> Forwarded<P>;
+    // the neighbor cache of this version asked about a next hop through that interface
+    fn resolve(router: &mut Router, egress: usize, next_hop: Self, now: Instant) -> Option<Resolution>;
+}
+
+impl Router {
+    pub fn new(name: &str) -> Self {
+        Router {
+            name : name.to_string(),
+            interfaces : vec![],
+            routes_v4 : RoutingTableV4::new(name),
+            routes_v6 : RoutingTable::new(name),
+            nat : None,
+            groups : vec![],
+            igmp : vec![],
+            arp : vec![],
+            nd : vec![],
+            qos : QosClassifier::default(),
+            labels : LabelTable::default(),
+        }
+    }
+
+    pub fn with_nat(mut self, nat: NatTable) -> Self {
+        self.nat = Some(nat);
+        self
+    }
+
+    pub fn with_qos(mut self, qos: QosClassifier) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    // an output queue for one of the interfaces, sorting packets like this router does
+    pub fn output_queue<P: Packet>(&self, limit: usize) -> QosQueue<P> {
+        QosQueue::new(self.qos.clone(), limit)
+    }
+
+    // adds the interface and the routes to the networks it is on; an address with a prefix
+    // longer than itself is on no network, so it gets no route
+    pub fn add_interface(&mut self, mut interface: RouterInterface) {
+        interface.device.index = self.interfaces.len() as u64;
+        if let Some((address, prefix_len)) = interface.v4() {
+            if let Ok(route) = Route::with_prefix(address.mask_to(prefix_len), prefix_len, address) {
+                // a second interface on the same network adds no route
+                let _ = self.routes_v4.add_route(route.with_distance(CONNECTED_DISTANCE));
+            }
+        }
+        if let Some((address, prefix_len)) = interface.v6() {
+            if let Ok(route) = Route::with_prefix(address.mask_to(prefix_len), prefix_len, Interface::dev(&interface.device.name)) {
+                let _ = self.routes_v6.add_route(route.with_distance(CONNECTED_DISTANCE));
+            }
+        }
+        self.arp.push(ArpCache::new(&format!("{} {}", self.name, interface.device.name)));
+        self.nd.push(NdCache::new(&format!("{} {}", self.name, interface.device.name)));
+        self.interfaces.push(interface);
+    }
+
+    pub fn interface(&self, name: &str) -> Option<usize> {
+        self.interfaces.iter().position(|interface| interface.device.name == name)
+    }
+
+    // like `ip link set eth0 down`; the routes stay, they just lead nowhere until it is up again
+    pub fn set_up(&mut self, name: &str, up: bool) {
+        if let Some(index) = self.interface(name) {
+            self.interfaces[index].device.up = up;
+        }
+    }
+
+    fn is_own<A: RouterAddress>(&self, ip: A) -> bool {
+        self.interfaces.iter().any(|interface| A::address_on(interface) == Some(ip))
+    }
+
+    // the single way in: the packet arrived on the interface with this name
+    pub fn forward<P: Packet>(&mut self, packet: P, ingress: &str) -> Forwarded<P>
+    where
+        P::Address: RouterAddress,
+    {
+        let Some(ingress) = self.interface(ingress) else {
+            return Forwarded::Dropped(DropReason::NoRoute);
+        };
+        if !self.interfaces[ingress].device.up {
+            return Forwarded::Dropped(DropReason::InterfaceDown);
+        }
+        if Into::<IpAddr>::into(packet.destination_ip()).is_multicast() {
+            return self.replicate(packet, ingress);
+        }
+        let mut packet = P::Address::arrive(self, packet, ingress);
+        if self.is_own(packet.destination_ip()) {
+            return Forwarded::Local(packet);
+        }
+        // a router never sends a packet on with a hop limit of 0
+        if packet.hop_limit() <= 1 {
+            return self.time_exceeded(&packet, ingress);
+        }
+        packet.set_hop_limit(packet.hop_limit() - 1);
+        P::Address::route(self, packet, Some(ingress))
+    }
+
+    // the router's own packets, like the ones it wraps up for a tunnel: routed, but not a hop
+    // older, and never translated
+    pub fn send<P: Packet>(&mut self, packet: P) -> Forwarded<P>
+    where
+        P::Address: RouterAddress,
+    {
+        P::Address::route(self, packet, None)
+    }
+
+    // At the start of a label switched path: a packet to a prefix with a label bound to it gets
+    // the label pushed, as one hop; any other one is routed as usual.
+    pub fn label_push(&mut self, packet: LayeredPacket, ingress: &str) -> Switched {
+        let Some(binding) = self.labels.binding_for(packet.destination_ip()).cloned() else {
+            return Switched::Routed(self.forward(packet, ingress));
+        };
+        if packet.hop_limit() <= 1 {
+            return Switched::Routed(self.forward(packet, ingress));
+        }
+        let mut labeled = LabeledPacket::new(packet);
+        labeled.push(binding.label);
+        labeled.labels[0].ttl -= 1;
+        Switched::Labeled { interface : binding.interface, next_hop : binding.next_hop, packet : labeled }
+    }
+
+    // Only the top label is looked at. Popping the last one (the router before the end of the
+    // path) sends the IP packet on as it is, to the router that will route it.
+    pub fn switch(&mut self, mut packet: LabeledPacket, ingress: &str) -> Switched {
+        let Some(index) = self.interface(ingress) else {
+            return Switched::Routed(Forwarded::Dropped(DropReason::NoRoute));
+        };
+        if !self.interfaces[index].device.up {
+            return Switched::Routed(Forwarded::Dropped(DropReason::InterfaceDown));
+        }
+        let Some(&top) = packet.top() else {
+            return Switched::Routed(self.forward(packet.packet, ingress));
+        };
+        if top.ttl <= 1 {
+            return Switched::Routed(Forwarded::Dropped(DropReason::TtlExpired));
+        }
+        let Some(entry) = self.labels.entry(top.label).cloned() else {
+            return Switched::Routed(Forwarded::Dropped(DropReason::UnknownLabel(top.label)));
+        };
+        packet.labels[0].ttl -= 1;
+        match entry.operation {
+            LabelOperation::Swap(label) => packet.swap(label),
+            LabelOperation::Pop => {
+                packet.pop();
+            }
+        }
+        if packet.labels.is_empty() {
+            return Switched::Routed(Forwarded::Out { interface : entry.interface, next_hop : entry.next_hop, packet : packet.packet });
+        }
+        Switched::Labeled { interface : entry.interface, next_hop : entry.next_hop, packet }
+    }
+
+    // The MAC address to send a packet that came out of forward() to, or the question to send
+    // first to find it out. None for an interface without an address of that version.
+    pub fn resolve_link<A: RouterAddress>(&mut self, interface: &str, next_hop: A, now: Instant) -> Option<Resolution> {
+        let egress = self.interface(interface)?;
+        A::resolve(self, egress, next_hop, now)
+    }
+
+    // an ARP message that came in on this interface, and the reply to send back if any
+    pub fn hear_arp(&mut self, interface: &str, message: &ArpMessage, now: Instant) -> Option<ArpMessage> {
+        let index = self.interface(interface)?;
+        let interface = &self.interfaces[index];
+        let own = (interface.device.mac, interface.v4()?.0);
+        self.arp[index].hear(message, now, own)
+    }
+
+    // A frame off the link into that interface, and the frames that go out because of it, with
+    // the interface each goes out of. ARP and Neighbor Discovery are answered, and IP packets
+    // to the interface's MAC are forwarded. A packet whose next hop isn't known yet is dropped
+    // while the router asks, like most routers do with the first ping: the next one gets through.
+    pub fn receive_frame(&mut self, interface: &str, frame: &EthernetFrame, now: Instant) -> Vec<(String, EthernetFrame)> {
+        let Some(index) = self.interface(interface) else {
+            return vec![];
+        };
+        let mac = self.interfaces[index].device.mac;
+        if frame.destination != mac && !frame.destination.is_broadcast() && !frame.destination.is_multicast() {
+            return vec![];
+        }
+        let answer = match frame.ethertype {
+            ETHERTYPE_ARP => {
+                let Ok(message) = ArpMessage::from_bytes(&frame.payload) else {
+                    return vec![];
+                };
+                self.hear_arp(interface, &message, now).map(|reply| reply.to_frame())
+            }
+            ETHERTYPE_IPV6 => match NdpMessage::from_frame(frame) {
+                Ok((header, message)) => {
+                    let own = self.interfaces[index].v6();
+                    let answer = self.hear_ndp(interface, header.source, &message, now);
+                    answer.zip(own).map(|(answer, (own, _))| answer.to_frame((mac, own), (frame.source, header.source)))
+                }
+                Err(_) => return self.route_frame::<Ipv6Header>(interface, frame, now),
+            },
+            ETHERTYPE_IPV4 => return self.route_frame::<Ipv4Header>(interface, frame, now),
+            _ => None,
+        };
+        answer.map(|answer| (interface.to_string(), answer)).into_iter().collect()
+    }
+
+    fn route_frame<H: IpHeader>(&mut self, interface: &str, frame: &EthernetFrame, now: Instant) -> Vec<(String, EthernetFrame)>
+    where
+        H::Address: RouterAddress,
+    {
+        let Ok(packet) = LayeredPacket::<H>::from_frame(frame) else {
+            return vec![];
+        };
+        let (egress, next_hop, packet) = match self.forward(packet, interface) {
+            Forwarded::Out { interface, next_hop, packet } | Forwarded::Icmp { interface, next_hop, packet } => (interface, next_hop, packet),
+            Forwarded::Local(_) | Forwarded::Replicated(_) | Forwarded::Dropped(_) => return vec![],
+        };
+        let Some(index) = self.interface(&egress) else {
+            return vec![];
+        };
+        let frame = match self.resolve_link(&egress, next_hop, now) {
+            Some(Resolution::Known(mac)) => packet.with_link(mac, self.interfaces[index].device.mac).to_frame(),
+            Some(Resolution::Ask(question)) => Some(question),
+            None => None,
+        };
+        frame.map(|frame| (egress, frame)).into_iter().collect()
+    }
+
+    // the same for Neighbor Discovery, which needs the source of the IPv6 packet
+    pub fn hear_ndp(&mut self, interface: &str, source_ip: Ipv6Addr, message: &NdpMessage, now: Instant) -> Option<NdpMessage> {
+        let index = self.interface(interface)?;
+        let interface = &self.interfaces[index];
+        let own = (interface.device.mac, interface.v6()?.0);
+        self.nd[index].hear(source_ip, message, now, own)
+    }
+
+    // what the hosts on an interface say about the groups they are in
+    pub fn hear_group_message<A: RouterAddress>(&mut self, interface: &str, message: GroupMessage<A>) {
+        let Some(interface) = self.interface(interface) else {
+            return;
+        };
+        match message {
+            GroupMessage::Report(group) if !self.groups.contains(&(interface, group.into())) => self.groups.push((interface, group.into())),
+            GroupMessage::Report(_) => {}
+            GroupMessage::Leave(group) => self.groups.retain(|&joined| joined != (interface, group.into())),
+        }
+    }
+
+    // the IGMP state of an interface with an IPv4 address, made the first time it's needed
+    fn igmp_on(&mut self, interface: usize) -> Option<&mut MulticastRouter> {
+        if !self.igmp.iter().any(|(index, _)| *index == interface) {
+            let address = self.interfaces[interface].v4()?.0;
+            self.igmp.push((interface, MulticastRouter::new(address)));
+        }
+        self.igmp.iter_mut().find(|(index, _)| *index == interface).map(|(_, state)| state)
+    }
+
+    // An IGMP message from this source on the interface. Gives back the group-specific query to
+    // send when this router is the querier and a member just left.
+    pub fn hear_igmp(&mut self, interface: &str, source: Ipv4Addr, message: &IgmpMessage, now: Instant) -> Option<IgmpMessage> {
+        let index = self.interface(interface)?;
+        let state = self.igmp_on(index)?;
+        match *message {
+            IgmpMessage::Query { max_response, group } => {
+                state.hear_query(source, now);
+                if let Some(group) = group {
+                    state.hear_group_query(group, max_response, now);
+                }
+                None
+            }
+            IgmpMessage::Report(group) => {
+                state.hear_report(group, now);
+                self.hear_group_message(interface, GroupMessage::Report(group));
+                None
+            }
+            IgmpMessage::Leave(group) => {
+                if !state.querier || !state.has_members(group) {
+                    return None;
+                }
+                state.hear_leave(group, now);
+                Some(IgmpMessage::Query { max_response : state.timers.last_member_query_interval, group : Some(group) })
+            }
+        }
+    }
+
+    // The timers of every interface: groups that timed out aren't forwarded there any more,
+    // a querier gone quiet is taken over from, and the general queries due are given back.
+    pub fn igmp_tick(&mut self, now: Instant) -> Vec<(String, IgmpMessage)> {
+        let mut queries = vec![];
+        for (index, state) in &mut self.igmp {
+            state.check_other_querier(now);
+            for (group, _) in state.expire_groups(now) {
+                self.groups.retain(|&joined| joined != (*index, group.into()));
+            }
+            if let Some(query) = state.query_due(now) {
+                queries.push((self.interfaces[*index].device.name.clone(), query));
+            }
+        }
+        queries
+    }
+
+    // A copy out of every other interface with members of the group. Link-local groups stay on
+    // their link, and no ICMP error is ever sent about a multicast packet (RFC 1812).
+    fn replicate<P: Packet>(&mut self, mut packet: P, ingress: usize) -> Forwarded<P> {
+        let group: IpAddr = packet.destination_ip().into();
+        if is_link_local_group(group) {
+            return Forwarded::Local(packet);
+        }
+        if packet.hop_limit() <= 1 {
+            return Forwarded::Dropped(DropReason::TtlExpired);
+        }
+        packet.set_hop_limit(packet.hop_limit() - 1);
+        let copies: Vec<_> = self.interfaces
+            .iter()
+            .enumerate()
+            .filter(|&(index, interface)| index != ingress && interface.device.up && self.groups.contains(&(index, group)))
+            .map(|(_, interface)| (interface.device.name.clone(), packet.clone()))
+            .collect();
+        if copies.is_empty() {
+            return Forwarded::Dropped(DropReason::NoMembers);
+        }
+        Forwarded::Replicated(copies)
+    }
+
+    // Time Exceeded, from the address the packet came in on (the one traceroute shows), with
+    // the start of the dropped packet in it so the sender can tell which one it was
+    fn time_exceeded<P: Packet>(&mut self, dropped: &P, ingress: usize) -> Forwarded<P>
+    where
+        P::Address: RouterAddress,
+    {
+        let Some(source_ip) = P::Address::address_on(&self.interfaces[ingress]) else {
+            return Forwarded::Dropped(DropReason::TtlExpired);
+        };
+        let mut error = dropped.time_exceeded(source_ip);
+        error.set_hop_limit(DEFAULT_HOP_LIMIT);
+        match P::Address::route(self, error, None) {
+            Forwarded::Out { interface, next_hop, packet } => Forwarded::Icmp { interface, next_hop, packet },
+            _ => Forwarded::Dropped(DropReason::TtlExpired),
+        }
+    }
+}
+
+trait MaskTo {
+    fn mask_to(self, prefix_len: u8) -> Self;
+}
+
+impl<A: RouteAddress> MaskTo for A {
+    fn mask_to(self, prefix_len: u8) -> Self {
+        Route::<A, ()>::with_prefix(self, prefix_len, ()).map_or(self, |route| A::from_bits(self.to_bits() & route.mask().to_bits()))
+    }
+}
+
+impl RouterAddress for Ipv4Addr {
+    type Header = Ipv4Header;
+
+    fn address_on(interface: &RouterInterface) -> Option<Self> {
+        interface.v4().map(|(address, _)| address)
+    }
+
+    // replies to what the NAT sent out are translated back before anything else
+    fn arrive<P: Packet<Address = Ipv4Addr>>(router: &mut Router, packet: P, ingress: usize) -> P {
+        match router.nat.as_mut().filter(|_| router.interfaces[ingress].nat_outside) {
+            Some(nat) => nat.translate_incoming(packet.clone()).map_or(packet, |(translated, _)| translated),
+            None => packet,
+        }
+    }
+
+    fn route<P: Packet<Address = Ipv4Addr>>(router: &mut Router, mut packet: P, ingress: Option<usize>) -> Forwarded<P> {
+        let Some(gateway) = router.routes_v4.lookup(packet.destination_ip()) else {
+            return Forwarded::Dropped(DropReason::NoRoute);
+        };
+        // the router's own address means the destination is on that link
+        let next_hop = if router.is_own(gateway) { packet.destination_ip() } else { gateway };
+        let on_link = |interface: &RouterInterface| interface.v4().is_some_and(|(address, prefix_len)| address.mask_to(prefix_len) == next_hop.mask_to(prefix_len));
+        let Some(egress) = router.interfaces.iter().position(on_link) else {
+            return Forwarded::Dropped(DropReason::NoRoute);
+        };
+        if !router.interfaces[egress].device.up {
+            return Forwarded::Dropped(DropReason::InterfaceDown);
+        }
+        // only what is passing through is translated, the router's own packets already have
+        // an outside address
+        if let (Some(nat), Some(ingress)) = (router.nat.as_mut().filter(|_| router.interfaces[egress].nat_outside), ingress) {
+            match nat.translate_outgoing(packet, ingress as u16) {
+                Ok(translated) => packet = translated,
+                Err(error) => return Forwarded::Dropped(DropReason::Nat(error)),
+            }
+        }
+        Forwarded::Out { interface : router.interfaces[egress].device.name.clone(), next_hop, packet }
+    }
+
+    fn resolve(router: &mut Router, egress: usize, next_hop: Ipv4Addr, now: Instant) -> Option<Resolution> {
+        let interface = &router.interfaces[egress];
+        let own = (interface.device.mac, interface.v4()?.0);
+        Some(router.arp[egress].resolve(next_hop, now, own))
+    }
+}
+
+impl RouterAddress for Ipv6Addr {
+    type Header = Ipv6Header;
+
+    fn address_on(interface: &RouterInterface) -> Option<Self> {
+        interface.v6().map(|(address, _)| address)
+    }
+
+    fn arrive<P: Packet<Address = Ipv6Addr>>(_: &mut Router, packet: P, _: usize) -> P {
+        packet
+    }
+
+    fn route<P: Packet<Address = Ipv6Addr>>(router: &mut Router, packet: P, _: Option<usize>) -> Forwarded<P> {
+        let is_up = |name: &str| router.interface(name).is_some_and(|index| router.interfaces[index].device.up);
+        let (device, gateway) = match router.routes_v6.resolve_next_hop(packet.destination_ip(), is_up) {
+            Ok(resolved) => resolved,
+            Err(ResolveError::InterfaceDown(_)) => return Forwarded::Dropped(DropReason::InterfaceDown),
+            Err(_) => return Forwarded::Dropped(DropReason::NoRoute),
+        };
+        Forwarded::Out { interface : device, next_hop : gateway.unwrap_or(packet.destination_ip()), packet }
+    }
+
+    fn resolve(router: &mut Router, egress: usize, next_hop: Ipv6Addr, now: Instant) -> Option<Resolution> {
+        let interface = &router.interfaces[egress];
+        let own = (interface.device.mac, interface.v6()?.0);
+        Some(router.nd[egress].resolve(next_hop, now, own))
+    }
+}
+
+#[test]
+fn home_router_forwards_both_versions() {
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::icmp::IcmpMessage;
+    use crate::packet::layered::Transport;
+
+    let v4 = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let mut router = Router::new("home").with_nat(NatTable::new("home NAT", v4("203.0.113.5")));
+    router.add_interface(RouterInterface::new("lan").with_v4(v4("192.168.1.1"), 24).with_v6(v6("2001:db8:1::1"), 64));
+    router.add_interface(RouterInterface::new("wan").with_v4(v4("203.0.113.5"), 24).with_v6(v6("2001:db8:ff::2"), 64).nat_outside());
+    router.routes_v4.add_route(Route::default_route(v4("203.0.113.1"))).unwrap();
+    router.routes_v6.add_route(Route::default_route(Interface::IpAddr(v6("2001:db8:ff::1")))).unwrap();
+
+    let packet = |source: &str, destination: &str, hop_limit: u8| {
+        PacketBuilder::ipv4().src(v4(source)).dst(v4(destination)).hop_limit(hop_limit).tcp().sport(40000).dport(443).payload(b"GET /").build()
+    };
+    // out to the internet: routed to the ISP, NATed, one hop older
+    let Forwarded::Out { interface, next_hop, packet : out } = router.forward(packet("192.168.1.20", "93.184.216.34", 64), "lan") else {
+        panic!("should have been forwarded");
+    };
+    assert_eq!((interface.as_str(), next_hop, out.source_ip()), ("wan", v4("203.0.113.1"), v4("203.0.113.5")));
+    assert_eq!(out.hop_limit(), 63);
+
+    // the reply is translated back before routing, and delivered on the LAN
+    let mut reply = out.clone();
+    reply.set_source(out.destination_ip(), out.destination_port());
+    reply.set_destination(out.source_ip(), out.source_port());
+    let Forwarded::Out { interface, next_hop, packet : back } = router.forward(reply, "wan") else {
+        panic!("the reply should have found its way back");
+    };
+    assert_eq!((interface.as_str(), next_hop, back.destination_port()), ("lan", v4("192.168.1.20"), 40000));
+
+    // out of hops: dropped, and the sender hears about it from the LAN side of the router, with
+    // the IP header and the ports of what was dropped
+    let expired = packet("192.168.1.20", "93.184.216.34", 1);
+    let Forwarded::Icmp { interface, packet : error, .. } = router.forward(expired.clone(), "lan") else {
+        panic!("should have sent a Time Exceeded");
+    };
+    assert_eq!((interface.as_str(), error.source_ip(), error.destination_ip()), ("lan", v4("192.168.1.1"), v4("192.168.1.20")));
+    let Transport::Icmp(IcmpMessage::TimeExceeded { original, .. }) = &error.transport else {
+        panic!("expected a Time Exceeded, got {error:?}");
+    };
+    assert_eq!(original.as_slice(), &expired.to_bytes()[..28]);
+    // and one from outside gets it from the WAN address, untranslated
+    let Forwarded::Icmp { interface, packet : error, .. } = router.forward(packet("198.51.100.1", "192.168.1.20", 1), "wan") else {
+        panic!("should have sent a Time Exceeded");
+    };
+    assert_eq!((interface.as_str(), error.source_ip(), error.destination_ip()), ("wan", v4("203.0.113.5"), v4("198.51.100.1")));
+    assert!(matches!(router.forward(packet("192.168.1.20", "192.168.1.1", 64), "lan"), Forwarded::Local(_)));
+    // nothing mapped for this one, so it is for the router itself
+    assert!(matches!(router.forward(packet("198.51.100.1", "203.0.113.5", 64), "wan"), Forwarded::Local(_)));
+
+    let tcp_v4 = packet("192.168.1.20", "93.184.216.34", 64);
+
+    // IPv6 isn't NATed; the default gateway is found through the WAN's own route
+    let packet = |hop_limit: u8| PacketBuilder::ipv6().src(v6("2001:db8:1::20")).dst(v6("2606:4700::1111")).hop_limit(hop_limit).udp().sport(5353).dport(53).build();
+    let Forwarded::Out { interface, next_hop, packet : out } = router.forward(packet(64), "lan") else {
+        panic!("should have been forwarded");
+    };
+    assert_eq!((interface.as_str(), next_hop, out.source_ip()), ("wan", v6("2001:db8:ff::1"), v6("2001:db8:1::20")));
+    let Forwarded::Icmp { packet : error, .. } = router.forward(packet(1), "lan") else {
+        panic!("should have sent a Time Exceeded");
+    };
+    assert_eq!(error.source_ip(), v6("2001:db8:1::1"));
+    assert!(matches!(error.transport, Transport::Icmp(IcmpMessage::TimeExceeded { .. })));
+
+    // nothing gets out while the WAN is down
+    router.set_up("wan", false);
+    assert_eq!(router.forward(packet(64), "lan"), Forwarded::Dropped(DropReason::InterfaceDown));
+    assert_eq!(router.forward(tcp_v4, "lan"), Forwarded::Dropped(DropReason::InterfaceDown));
+}
+
+#[test]
+fn multicast_goes_only_where_the_group_is_wanted() {
+    use crate::packet::builder::PacketBuilder;
+
+    let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let mut router = Router::new("core");
+    for (name, net) in [("eth0", 1), ("eth1", 2), ("eth2", 3)] {
+        router.add_interface(RouterInterface::new(name).with_v4(Ipv4Addr::new(10, 0, net, 1), 24).with_v6(v6(&format!("2001:db8:{net}::1")), 64));
+    }
+    let group = Ipv4Addr::new(239, 1, 1, 1);
+    let stream = |destination: Ipv4Addr, hop_limit: u8| {
+        PacketBuilder::ipv4().src(Ipv4Addr::new(10, 0, 1, 50)).dst(destination).hop_limit(hop_limit).udp().sport(5004).dport(5004).payload(b"video").build()
+    };
+    let copies = |forwarded: Forwarded| {
+        let Forwarded::Replicated(copies) = forwarded else {
+            panic!("should have been replicated: {forwarded:?}");
+        };
+        copies.into_iter().map(|(interface, packet)| (interface, packet.hop_limit())).collect::<Vec<_>>()
+    };
+    assert_eq!(router.forward(stream(group, 8), "eth0"), Forwarded::Dropped(DropReason::NoMembers));
+
+    router.hear_group_message("eth1", GroupMessage::Report(group));
+    router.hear_group_message("eth2", GroupMessage::Report(group));
+    router.hear_group_message("eth2", GroupMessage::Report(group));
+    // a member on the interface it came from doesn't get it back
+    router.hear_group_message("eth0", GroupMessage::Report(group));
+    assert_eq!(copies(router.forward(stream(group, 8), "eth0")), vec![("eth1".to_string(), 7), ("eth2".to_string(), 7)]);
+    router.hear_group_message("eth2", GroupMessage::Leave(group));
+    assert_eq!(copies(router.forward(stream(group, 8), "eth0")), vec![("eth1".to_string(), 7)]);
+
+    // out of hops is just dropped, and link-local groups never leave the link
+    assert_eq!(router.forward(stream(group, 1), "eth0"), Forwarded::Dropped(DropReason::TtlExpired));
+    assert!(matches!(router.forward(stream(Ipv4Addr::new(224, 0, 0, 22), 1), "eth1"), Forwarded::Local(_)));
+
+    // MLD does the same for IPv6
+    let group = v6("ff0e::1:3");
+    router.hear_group_message("eth2", GroupMessage::Report(group));
+    let packet = PacketBuilder::ipv6().src(v6("2001:db8:1::50")).dst(group).hop_limit(8).udp().sport(5004).dport(5004).payload(b"video").build();
+    let Forwarded::Replicated(copies) = router.forward(packet, "eth0") else {
+        panic!("should have been replicated");
+    };
+    assert_eq!(copies.len(), 1);
+    assert_eq!(copies[0].0, "eth2");
+}
+
+#[test]
+fn next_hops_are_resolved_before_sending() {
+    use crate::packet::builder::PacketBuilder;
+
+    let mac = |text: &str| text.parse::<MacAddr>().unwrap();
+    let v4 = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let mut router = Router::new("edge");
+    router.add_interface(RouterInterface::new("lan").with_v4(v4("192.168.1.1"), 24).with_v6(v6("2001:db8:1::1"), 64));
+    router.add_interface(RouterInterface::new("wan").with_mac(mac("02:00:00:00:ff:02")).with_v4(v4("203.0.113.5"), 24).with_v6(v6("2001:db8:ff::2"), 64));
+    router.routes_v4.add_route(Route::default_route(v4("203.0.113.1"))).unwrap();
+    router.routes_v6.add_route(Route::default_route(Interface::IpAddr(v6("2001:db8:ff::1")))).unwrap();
+    let isp = mac("02:00:00:00:ff:01");
+    let now = Instant::now();
+
+    let packet = PacketBuilder::ipv6().src(v6("2001:db8:1::20")).dst(v6("2606:4700::1111")).udp().sport(5353).dport(53).build();
+    let Forwarded::Out { interface, next_hop, .. } = router.forward(packet, "lan") else {
+        panic!("should have been forwarded");
+    };
+    // the gateway isn't known yet: a solicitation for it goes out of the WAN first
+    let Some(Resolution::Ask(frame)) = router.resolve_link(&interface, next_hop, now) else {
+        panic!("should have asked for the gateway");
+    };
+    let (header, solicitation) = NdpMessage::from_frame(&frame).unwrap();
+    assert_eq!((header.source, solicitation.target()), (v6("2001:db8:ff::2"), v6("2001:db8:ff::1")));
+    let advertisement = NdpMessage::NeighborAdvertisement { target : next_hop, target_mac : Some(isp), router : true, solicited : true, override_entry : true };
+    assert_eq!(router.hear_ndp("wan", next_hop, &advertisement, now), None);
+    assert_eq!(router.resolve_link(&interface, next_hop, now), Some(Resolution::Known(isp)));
+
+    // and the same with ARP for IPv4
+    let gateway = v4("203.0.113.1");
+    let Some(Resolution::Ask(frame)) = router.resolve_link("wan", gateway, now) else {
+        panic!("should have asked for the gateway");
+    };
+    let request = ArpMessage::from_bytes(&frame.payload).unwrap();
+    assert_eq!(router.hear_arp("wan", &request.reply(isp), now), None);
+    assert_eq!(router.resolve_link("wan", gateway, now), Some(Resolution::Known(isp)));
+    // what was heard on the WAN says nothing about the LAN
+    assert!(matches!(router.resolve_link("lan", gateway, now), Some(Resolution::Ask(_))));
+    assert_eq!(router.resolve_link("nowhere", gateway, now), None);
+}
@@ -1,77 +1,734 @@
+use std::cmp::Reverse;
+use std::fmt::{self, Debug, Display};
 use std::net::Ipv6Addr;
 use std::net::Ipv4Addr;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 use crate::bit_utils::popcount;
+use crate::packet::Packet;
+use crate::route_cache::RouteCache;
+use crate::route_trie::RouteTrie;
+use crate::route_watch::{RouteChange, RouteWatcher};
+use crate::table_limits::{TableEvent, TableFull, TableLimit};
 
 pub trait IpAddrTools {
+    // really counts all the ones, so it only means the prefix length for a valid prefix mask
     fn count_contiguous_ones(self) -> usize;
     fn mask(self, mask:Self) -> Self;
+    // ones and then only zeros, like 255.255.240.0, and not 255.0.255.0
+    fn is_valid_prefix_mask(&self) -> bool;
 }
 
 impl IpAddrTools for Ipv6Addr {
     fn count_contiguous_ones(self) -> usize {
         popcount::<u128>(self.into())
     }
+    fn is_valid_prefix_mask(&self) -> bool {
+        let mask = u128::from(*self);
+        mask.leading_ones() == mask.count_ones()
+    }
     fn mask(self, mask:Self) -> Self {
         let ip : u128 = self.into();
-        let mask : u128 = self.into();
+        let mask : u128 = mask.into();
         let result = ip & mask;
-        return result.into();
+        result.into()
+    }
+}
+
+// A mask with a zero between its ones, which can't be a prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidMask;
+
+impl Display for InvalidMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the mask is not contiguous, so it is not a prefix")
+    }
+}
+
+impl std::error::Error for InvalidMask {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    // see TableLimit
+    TableFull,
+    // the same prefix with the same distance and metric is already there
+    Duplicate,
+    NotFound,
+    // a prefix longer than the address, like 10.0.0.0/33
+    BadPrefixLen(u8),
+}
+
+impl From<TableFull> for RouteError {
+    fn from(_: TableFull) -> Self {
+        RouteError::TableFull
+    }
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::TableFull => write!(f, "the routing table is full"),
+            RouteError::Duplicate => write!(f, "the route is already in the table"),
+            RouteError::NotFound => write!(f, "no such route in the table"),
+            RouteError::BadPrefixLen(prefix_len) => write!(f, "a /{prefix_len} prefix is longer than the address"),
+        }
     }
 }
 
+impl std::error::Error for RouteError {}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Interface {
     IpAddr(Ipv6Addr),
-    Port(u64),
+    // the name of the device; whether it is up is the interface's business, not the route's
+    Dev(String),
+}
+
+impl Interface {
+    pub fn dev(name: &str) -> Self {
+        Interface::Dev(name.to_string())
+    }
+}
+
+// What resolve_next_hop needs from a next hop: either a gateway to look up in the table again,
+// or the device the packet goes out of
+pub trait NextHop<A> {
+    fn gateway(&self) -> Option<A>;
+    fn device(&self) -> Option<&str>;
 }
 
+impl NextHop<Ipv6Addr> for Interface {
+    fn gateway(&self) -> Option<Ipv6Addr> {
+        match self {
+            Interface::IpAddr(gateway) => Some(*gateway),
+            Interface::Dev(_) => None,
+        }
+    }
+    fn device(&self) -> Option<&str> {
+        match self {
+            Interface::Dev(device) => Some(device),
+            Interface::IpAddr(_) => None,
+        }
+    }
+}
+
+// What a routing table needs from an address: masking and counting mask bits (IpAddrTools),
+// and the address as a number, for the ranges covered by routes
+pub trait RouteAddress: IpAddrTools + Copy + PartialEq + Debug {
+    // the largest address, all ones
+    const ALL_ONES: u128;
+    fn to_bits(self) -> u128;
+    fn from_bits(bits: u128) -> Self;
+    // how many bits the address has, so the longest prefix there can be
+    fn width() -> u8 {
+        Self::ALL_ONES.count_ones() as u8
+    }
+}
+
+impl RouteAddress for Ipv6Addr {
+    const ALL_ONES: u128 = u128::MAX;
+    fn to_bits(self) -> u128 {
+        self.into()
+    }
+    fn from_bits(bits: u128) -> Self {
+        bits.into()
+    }
+}
+
+impl RouteAddress for Ipv4Addr {
+    const ALL_ONES: u128 = u32::MAX as u128;
+    fn to_bits(self) -> u128 {
+        u32::from(self).into()
+    }
+    fn from_bits(bits: u128) -> Self {
+        (bits as u32).into()
+    }
+}
+
+// The same route and routing table work for both IP versions; without type parameters they are
+// the IPv6 ones, with the Interface as next hop (see networkingv4 for the IPv4 ones)
 #[derive(Debug, Clone)]
-pub struct Route {
-    pub destination: Ipv6Addr,
-    pub mask : Ipv6Addr,
-    pub next_hop :Interface,
+pub struct Route<A = Ipv6Addr, H = Interface> {
+    pub destination: A,
+    // the mask is this many ones followed by zeros, so it can't be anything but a prefix
+    pub prefix_len : u8,
+    pub next_hop :H,
+    // other next hops just as good as next_hop, for equal cost multipath (ECMP)
+    pub equal_cost : Vec<H>,
+    // how much the source of the route is trusted, lower wins (see the distances below)
+    pub distance : u8,
+    // the cost the routing protocol gave it, lower wins between routes from the same source
+    pub metric : u32,
+    // when a learned route goes away unless it is heard about again, in simulated time since
+    // the start; None for routes that stay until removed
+    pub expires_at : Option<Duration>,
 }
 
-impl Route {
+// Administrative distances, as on Cisco routers
+pub const CONNECTED_DISTANCE: u8 = 0;
+pub const STATIC_DISTANCE: u8 = 1;
+pub const EBGP_DISTANCE: u8 = 20;
+pub const OSPF_DISTANCE: u8 = 110;
+pub const RIP_DISTANCE: u8 = 120;
+
+impl<A: RouteAddress, H> Route<A, H> {
+    // a static route for destination/prefix_len, like 192.168.0.0/16; no longer prefix than the address
+    pub fn with_prefix(destination: A, prefix_len: u8, next_hop: H) -> Result<Self, RouteError> {
+        if prefix_len > A::width() {
+            return Err(RouteError::BadPrefixLen(prefix_len));
+        }
+        Ok(Self::prefix(destination, prefix_len, next_hop))
+    }
+    // for prefix lengths that can't be wrong; the host bits are cleared, like a router does with
+    // `ip route 10.0.0.5 255.0.0.0`, or nothing would ever match the route
+    fn prefix(destination: A, prefix_len: u8, next_hop: H) -> Self {
+        let route = Route { destination, prefix_len, next_hop, equal_cost : vec![], distance : STATIC_DISTANCE, metric : 0, expires_at : None };
+        Route { destination : destination.mask(route.mask()), ..route }
+    }
+    // 0.0.0.0/0 or ::/0, matching every address, so the route of last resort
+    pub fn default_route(next_hop: H) -> Self {
+        Self::prefix(A::from_bits(0), 0, next_hop)
+    }
+    pub fn is_default(&self) -> bool {
+        self.prefix_len == 0
+    }
+    pub fn with_equal_cost(mut self, next_hop: H) -> Self {
+        self.equal_cost.push(next_hop);
+        self
+    }
+    pub fn next_hops(&self) -> impl Iterator<Item = &H> {
+        std::iter::once(&self.next_hop).chain(&self.equal_cost)
+    }
+    pub fn with_distance(mut self, distance: u8) -> Self {
+        self.distance = distance;
+        self
+    }
+    pub fn with_metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
+    pub fn with_expiry(mut self, expires_at: Duration) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+    // from a mask written out, like 255.255.255.0, which has to be a prefix mask
+    pub fn from_mask(destination: A, mask: A, next_hop: H) -> Result<Self, InvalidMask> {
+        if !mask.is_valid_prefix_mask() {
+            return Err(InvalidMask);
+        }
+        Ok(Self::prefix(destination, mask.count_contiguous_ones() as u8, next_hop))
+    }
+    pub fn mask(&self) -> A {
+        A::from_bits(A::ALL_ONES & !A::ALL_ONES.checked_shr(self.prefix_len.into()).unwrap_or(0))
+    }
     // checks the match of this ipaddr with the route
-    pub fn matches(&self, ipaddr: Ipv6Addr) -> bool {
-        ipaddr.mask(self.mask) == self.destination
+    pub fn matches(&self, ipaddr: A) -> bool {
+        ipaddr.mask(self.mask()) == self.destination
+    }
+    // the first and the last address covered by this route
+    pub fn address_range(&self) -> (u128, u128) {
+        let mask = self.mask().to_bits();
+        let start = self.destination.to_bits() & mask;
+        (start, start | (A::ALL_ONES & !mask))
     }
 }
 
 #[derive(Debug)]
-struct RoutingTable {
-    name : String,
-    table : Vec<Route>,
+pub struct RoutingTable<A = Ipv6Addr, H = Interface> {
+    pub name : String,
+    // only changed through the methods below, so the cache and the watchers always know
+    table : Vec<Route<A, H>>,
+    // the same routes by prefix, for the lookups
+    trie : RouteTrie<A, H>,
+    // how many routes fit, None for no limit
+    pub limit : Option<TableLimit>,
+    pub events : Vec<TableEvent>,
+    // recent answers of lookup(), None for no cache; cleared by every change to the table
+    pub cache : Option<RouteCache<A, H>>,
+    // one for every watch(), told about each change made through the methods below
+    pub watchers : Vec<Sender<RouteChange<A, H>>>,
+}
+
+impl<A, H> Default for RoutingTable<A, H> {
+    fn default() -> Self {
+        RoutingTable { name : String::new(), table : vec![], trie : RouteTrie::default(), limit : None, events : vec![], cache : None, watchers : vec![] }
+    }
+}
+
+impl<A, H> RoutingTable<A, H> {
+    // every route, in the order they were added
+    pub fn routes(&self) -> &[Route<A, H>] {
+        &self.table
+    }
 }
 
-impl RoutingTable {
-    // finds the best matching address from the routing table
-    pub fn find_best_route(&self, ipaddr:Ipv6Addr) -> Option<&Route> {
+impl<A: RouteAddress, H: Clone + Debug> RoutingTable<A, H> {
+    pub fn new(name: &str) -> Self {
+        RoutingTable { name : name.to_string(), ..RoutingTable::default() }
+    }
+    // a table with only a default route, what most hosts have (plus their own subnet)
+    pub fn with_default(next_hop: H) -> Self {
+        RoutingTable::default().with_routes(vec![Route::default_route(next_hop)])
+    }
+    // starts out with these routes, as they are: no limit is checked, and nobody is watching yet
+    pub fn with_routes(mut self, routes: Vec<Route<A, H>>) -> Self {
+        self.trie = RouteTrie::from_routes(&routes);
+        self.table = routes;
+        self
+    }
+    pub fn with_limit(mut self, limit: TableLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    // the default route that would be used, if there is one
+    pub fn default_route(&self) -> Option<&Route<A, H>> {
         self.table
             .iter()
-            .filter(|route| route.matches(ipaddr))
-            .max_by_key(|route| route.mask.count_contiguous_ones())
+            .filter(|route| route.is_default())
+            .max_by_key(|route| (Reverse(route.distance), Reverse(route.metric)))
+    }
+    // finds the best matching address from the routing table: the longest prefix, and between
+    // routes for the same prefix the lowest distance, then the lowest metric. The default
+    // route has the shortest prefix of all, so it is only used when nothing else matches.
+    pub fn find_best_route(&self, ipaddr: A) -> Option<&Route<A, H>> {
+        self.trie.find_best_route(ipaddr)
+    }
+    pub fn find_next_hop(&self, ipaddr: A) -> Option<H> {
+        self.find_best_route(ipaddr)
+            .map(|route| route.next_hop.clone())
+    }
+    // every next hop as good as the best one: those of the best route, and of the other routes
+    // just as good (same prefix, distance and metric)
+    pub fn find_next_hops(&self, ipaddr: A) -> Vec<H> {
+        let Some(best) = self.find_best_route(ipaddr) else {
+            return vec![];
+        };
+        let rank = |route: &Route<A, H>| (route.distance, route.metric);
+        self.trie
+            .longest_match(ipaddr)
+            .iter()
+            .filter(|route| rank(route) == rank(best))
+            .flat_map(|route| route.next_hops().cloned())
+            .collect()
+    }
+    // one of the equal cost next hops, always the same one for the same flow hash
+    // (see Packet::flow_hash)
+    pub fn find_next_hop_for_flow(&self, ipaddr: A, flow_hash: u64) -> Option<H> {
+        let hops = self.find_next_hops(ipaddr);
+        (!hops.is_empty()).then(|| hops[(flow_hash % hops.len() as u64) as usize].clone())
+    }
+    // the next hop for this packet, the same one for every packet of its flow
+    pub fn find_next_hop_for_packet<P: Packet<Address = A>>(&self, packet: &P) -> Option<H> {
+        self.find_next_hop_for_flow(packet.destination_ip(), packet.flow_hash())
+    }
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = Some(RouteCache::new(capacity));
+        self
+    }
+    pub fn clear_cache(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+    }
+    // find_next_hop, through the cache if there is one
+    pub fn lookup(&mut self, ipaddr: A) -> Option<H> {
+        if let Some(next_hop) = self.cache.as_mut().and_then(|cache| cache.get(ipaddr)) {
+            return next_hop;
+        }
+        let next_hop = self.find_next_hop(ipaddr);
+        if let Some(cache) = &mut self.cache {
+            cache.insert(ipaddr, next_hop.clone());
+        }
+        next_hop
+    }
+    // puts the routes for this prefix in the trie again, after they changed in the table
+    fn reindex(&mut self, destination: A, prefix_len: u8) {
+        let routes = self.table.iter().filter(|route| (route.destination, route.prefix_len) == (destination, prefix_len)).cloned().collect();
+        self.trie.set(destination, prefix_len, routes);
+    }
+    // where the route for the same prefix, from the same source and at the same cost is
+    fn position_of_same(&self, route: &Route<A, H>) -> Option<usize> {
+        let key = |route: &Route<A, H>| (route.destination, route.prefix_len, route.distance, route.metric);
+        self.table.iter().position(|existing| key(existing) == key(route))
+    }
+    // adds a route, if it fits and isn't there yet (like `ip route add`); with eviction, the
+    // route added first goes away. Several next hops for a prefix go in one route, as equal_cost.
+    pub fn add_route(&mut self, route: Route<A, H>) -> Result<(), RouteError> {
+        if self.position_of_same(&route).is_some() {
+            return Err(RouteError::Duplicate);
+        }
+        if let Some(limit) = self.limit {
+            // the oldest routes go first, so these are the ones that may be evicted
+            let before = self.table.len();
+            let doomed: Vec<_> = self.table.iter().take((before + 1).saturating_sub(limit.max_entries)).cloned().collect();
+            let made_room = limit.make_room(&self.name, &mut self.table, |routes| (!routes.is_empty()).then_some(0), &route, &mut self.events);
+            let evicted: Vec<_> = doomed.into_iter().take(before - self.table.len()).collect();
+            for route in &evicted {
+                self.reindex(route.destination, route.prefix_len);
+            }
+            self.notify(evicted.into_iter().map(RouteChange::Removed));
+            made_room?;
+        }
+        self.notify([RouteChange::Added(route.clone())]);
+        self.trie.insert(route.clone());
+        self.table.push(route);
+        self.clear_cache();
+        Ok(())
+    }
+    // like add_route, but a route already there is replaced instead (like `ip route replace`),
+    // and given back
+    pub fn replace_route(&mut self, route: Route<A, H>) -> Result<Option<Route<A, H>>, RouteError> {
+        match self.position_of_same(&route) {
+            Some(index) => {
+                self.clear_cache();
+                let old = std::mem::replace(&mut self.table[index], route.clone());
+                self.reindex(route.destination, route.prefix_len);
+                self.notify([RouteChange::Replaced { old : old.clone(), new : route }]);
+                Ok(Some(old))
+            }
+            None => self.add_route(route).map(|()| None),
+        }
+    }
+    // removes every route for destination/prefix_len, whatever their source
+    pub fn remove_route(&mut self, destination: A, prefix_len: u8) -> Result<Vec<Route<A, H>>, RouteError> {
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.table)
+            .into_iter()
+            .partition(|route| route.destination == destination && route.prefix_len == prefix_len);
+        self.table = kept;
+        if removed.is_empty() {
+            return Err(RouteError::NotFound);
+        }
+        self.trie.set(destination, prefix_len, vec![]);
+        self.clear_cache();
+        self.notify(removed.iter().cloned().map(RouteChange::Removed));
+        Ok(removed)
+    }
+    // the route from the same source (same distance) for the same prefix
+    fn position_of_source(&self, route: &Route<A, H>) -> Option<usize> {
+        self.position_from(route.destination, route.prefix_len, route.distance)
+    }
+    fn position_from(&self, destination: A, prefix_len: u8, distance: u8) -> Option<usize> {
+        self.table
+            .iter()
+            .position(|existing| (existing.destination, existing.prefix_len, existing.distance) == (destination, prefix_len, distance))
+    }
+    // removes the route for destination/prefix_len from one source only, unlike remove_route
+    pub fn remove_route_from(&mut self, destination: A, prefix_len: u8, distance: u8) -> Result<Route<A, H>, RouteError> {
+        let index = self.position_from(destination, prefix_len, distance).ok_or(RouteError::NotFound)?;
+        let removed = self.table.remove(index);
+        self.reindex(destination, prefix_len);
+        self.clear_cache();
+        self.notify([RouteChange::Removed(removed.clone())]);
+        Ok(removed)
+    }
+    // Changes the route for destination/prefix_len from one source where it is, like a routing
+    // protocol does when it hears of a better next hop or a new metric. Only a change of next
+    // hops or metric is news for the watchers; a new expiry time is not.
+    pub fn modify_route(&mut self, destination: A, prefix_len: u8, distance: u8, change: impl FnOnce(&mut Route<A, H>)) -> Result<(), RouteError> where H: PartialEq {
+        let index = self.position_from(destination, prefix_len, distance).ok_or(RouteError::NotFound)?;
+        let old = self.table[index].clone();
+        change(&mut self.table[index]);
+        let new = self.table[index].clone();
+        self.reindex(old.destination, old.prefix_len);
+        self.reindex(new.destination, new.prefix_len);
+        if (&old.next_hop, &old.equal_cost, old.metric) != (&new.next_hop, &new.equal_cost, new.metric) {
+            self.clear_cache();
+            self.notify([RouteChange::Replaced { old, new }]);
+        }
+        Ok(())
+    }
+    // Makes the table hold exactly these routes, like a routing protocol putting in what it has
+    // just worked out. Only the routes that are different are touched, so the watchers hear
+    // about what changed and nothing else. A full table still takes all the routes it can.
+    pub fn set_routes(&mut self, routes: Vec<Route<A, H>>) -> Result<(), RouteError> where H: PartialEq {
+        let diff = self.diff(&RoutingTable::default().with_routes(routes));
+        for route in diff.removed {
+            self.remove_route_from(route.destination, route.prefix_len, route.distance)?;
+        }
+        for (old, new) in diff.changed {
+            self.modify_route(old.destination, old.prefix_len, old.distance, |route| *route = new)?;
+        }
+        let mut result = Ok(());
+        for route in diff.added {
+            if let Err(error) = self.add_route(route) {
+                result = Err(error);
+            }
+        }
+        result
+    }
+    // What it takes to turn this table into the other one. Routes are told apart by prefix and
+    // source (distance); the same prefix from the same source with another next hop or metric
+    // is a changed route, not one removed and one added.
+    pub fn diff(&self, other: &RoutingTable<A, H>) -> RouteDiff<A, H> where H: PartialEq {
+        let mut diff = RouteDiff { added : vec![], removed : vec![], changed : vec![] };
+        for ours in &self.table {
+            match other.position_of_source(ours).map(|index| &other.table[index]) {
+                None => diff.removed.push(ours.clone()),
+                Some(theirs) if (&theirs.next_hop, &theirs.equal_cost, theirs.metric) != (&ours.next_hop, &ours.equal_cost, ours.metric) => {
+                    diff.changed.push((ours.clone(), theirs.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added = other.table.iter().filter(|theirs| self.position_of_source(theirs).is_none()).cloned().collect();
+        diff
+    }
+    // Takes in the other table's routes. When both have a route for the same prefix from the
+    // same source, `prefer` decides by the distance whose is kept.
+    pub fn merge(&mut self, other: &RoutingTable<A, H>, prefer: impl Fn(u8) -> Prefer) -> Result<(), RouteError> {
+        for theirs in &other.table {
+            match self.position_of_source(theirs) {
+                None => self.add_route(theirs.clone())?,
+                Some(index) if prefer(theirs.distance) == Prefer::Theirs => {
+                    let old = std::mem::replace(&mut self.table[index], theirs.clone());
+                    self.reindex(theirs.destination, theirs.prefix_len);
+                    self.notify([RouteChange::Replaced { old, new : theirs.clone() }]);
+                    self.clear_cache();
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+    // removes the routes whose time is up, and gives them back
+    pub fn purge_expired(&mut self, now: Duration) -> Vec<Route<A, H>> {
+        let (expired, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.table)
+            .into_iter()
+            .partition(|route| route.expires_at.is_some_and(|expires_at| expires_at <= now));
+        self.table = kept;
+        for route in &expired {
+            self.reindex(route.destination, route.prefix_len);
+        }
+        if !expired.is_empty() {
+            self.clear_cache();
+            self.notify(expired.iter().cloned().map(RouteChange::Removed));
+        }
+        expired
+    }
+    // every change made through the methods above from now on, as it happens
+    pub fn watch(&mut self) -> RouteWatcher<A, H> {
+        let (sender, watcher) = RouteWatcher::new();
+        self.watchers.push(sender);
+        watcher
+    }
+    // the watchers that went away are dropped here
+    fn notify(&mut self, changes: impl IntoIterator<Item = RouteChange<A, H>>) {
+        for change in changes {
+            self.watchers.retain(|watcher| watcher.send(change.clone()).is_ok());
+        }
+    }
+    pub fn take_events(&mut self) -> Vec<TableEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+// how many gateways deep resolve_next_hop goes before giving up
+pub const MAX_RESOLVE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError<A = Ipv6Addr> {
+    // nothing matches this address
+    NoRoute(A),
+    // the gateways in the order they were tried, the last one being tried twice
+    Loop(Vec<A>),
+    TooDeep,
+    // the route goes out of an interface that is down
+    InterfaceDown(String),
+}
+
+impl<A: Display + Debug> Display for ResolveError<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NoRoute(ip) => write!(f, "no route to {ip}"),
+            ResolveError::Loop(gateways) => write!(f, "the gateways point at each other: {gateways:?}"),
+            ResolveError::TooDeep => write!(f, "more than {MAX_RESOLVE_DEPTH} gateways deep"),
+            ResolveError::InterfaceDown(name) => write!(f, "{name} is down"),
+        }
+    }
+}
+
+impl<A: Display + Debug> std::error::Error for ResolveError<A> {}
+
+// What changed from one routing table to another; changed routes as (ours, theirs)
+#[derive(Debug, Clone)]
+pub struct RouteDiff<A = Ipv6Addr, H = Interface> {
+    pub added : Vec<Route<A, H>>,
+    pub removed : Vec<Route<A, H>>,
+    pub changed : Vec<(Route<A, H>, Route<A, H>)>,
+}
+
+impl<A, H> RouteDiff<A, H> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Whose route merge keeps when both tables have one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefer {
+    Ours,
+    Theirs,
+}
+
+// A prefix where two routing tables send traffic to different places
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardingDiff<A = Ipv6Addr, H = Interface> {
+    pub destination : A,
+    pub prefix_len : u8,
+    pub ours : Option<H>,
+    pub theirs : Option<H>,
+}
+
+impl<A: RouteAddress, H: Clone + Debug + PartialEq> RoutingTable<A, H> {
+    // follows gateways that aren't directly connected through the table, until a route out of
+    // an interface; gives back that interface and the gateway there (None if on-link)
+    pub fn resolve_next_hop(&self, ipaddr: A, is_up: impl Fn(&str) -> bool) -> Result<(String, Option<A>), ResolveError<A>> where H: NextHop<A> {
+        let mut gateways: Vec<A> = vec![];
+        let mut current = ipaddr;
+        loop {
+            let Some(next_hop) = self.find_best_route(current).map(|route| &route.next_hop) else {
+                return Err(ResolveError::NoRoute(current));
+            };
+            match (next_hop.device(), next_hop.gateway()) {
+                (Some(device), _) if !is_up(device) => return Err(ResolveError::InterfaceDown(device.to_string())),
+                (Some(device), _) => return Ok((device.to_string(), gateways.last().copied())),
+                (None, None) => return Err(ResolveError::NoRoute(current)),
+                (None, Some(gateway)) => {
+                    let looped = gateways.contains(&gateway);
+                    gateways.push(gateway);
+                    if looped {
+                        return Err(ResolveError::Loop(gateways));
+                    }
+                    if gateways.len() > MAX_RESOLVE_DEPTH {
+                        return Err(ResolveError::TooDeep);
+                    }
+                    current = gateway;
+                }
+            }
+        }
+    }
+
+    // between two route boundaries the best route can't change, so one address per range is
+    // enough; gives back the (first, last) ranges where the tables disagree, with both next hops
+    fn differing_ranges(&self, other: &RoutingTable<A, H>) -> Vec<(u128, u128, Option<H>, Option<H>)> {
+        let mut boundaries = vec![0];
+        for route in self.table.iter().chain(&other.table) {
+            let (start, end) = route.address_range();
+            boundaries.push(start);
+            if let Some(after_end) = end.checked_add(1) {
+                boundaries.push(after_end);
+            }
+        }
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut ranges: Vec<(u128, u128, Option<H>, Option<H>)> = vec![];
+        for (i, &start) in boundaries.iter().enumerate() {
+            let end = boundaries.get(i + 1).map_or(A::ALL_ONES, |next| next - 1);
+            let ours = self.find_next_hop(A::from_bits(start));
+            let theirs = other.find_next_hop(A::from_bits(start));
+            match ranges.last_mut() {
+                // same answers as the range just before, so it is the same range
+                Some(last) if last.1 + 1 == start && last.2 == ours && last.3 == theirs => last.1 = end,
+                _ if ours != theirs => ranges.push((start, end, ours, theirs)),
+                _ => {}
+            }
+        }
+        ranges
     }
-    pub fn find_next_hop(&self, ipaddr: Ipv6Addr) -> Option<Interface> {
-        if let Some(route) = self.find_best_route(ipaddr) {
-            return Some(route.next_hop.clone());
-        } else {
-            return None;
+
+    // every prefix whose next hop is different in the other table
+    pub fn forwarding_diff(&self, other: &RoutingTable<A, H>) -> Vec<ForwardingDiff<A, H>> {
+        let mut diff = vec![];
+        for (start, end, ours, theirs) in self.differing_ranges(other) {
+            for (destination, prefix_len) in range_to_prefixes::<A>(start, end) {
+                diff.push(ForwardingDiff { destination, prefix_len, ours : ours.clone(), theirs : theirs.clone() });
+            }
         }
+        diff
+    }
+}
 
+// the fewest prefixes that exactly cover the addresses from start to end
+fn range_to_prefixes<A: RouteAddress>(mut start: u128, end: u128) -> Vec<(A, u8)> {
+    let width = A::width() as u32;
+    let mut prefixes = vec![];
+    loop {
+        let remaining = end - start;
+        let bits_by_size = if remaining == A::ALL_ONES { width } else { 127 - (remaining + 1).leading_zeros() };
+        let bits = start.trailing_zeros().min(bits_by_size);
+        prefixes.push((A::from_bits(start), (width - bits) as u8));
+        match start.checked_add(1u128.checked_shl(bits).unwrap_or(0)) {
+            Some(next) if bits < width && next <= end && next != 0 => start = next,
+            _ => return prefixes,
+        }
     }
 }
 
+// whether the summarized table forwards every address like the original; if not, one address
+// for every range where they disagree
+pub fn verify_summary<A: RouteAddress, H: Clone + Debug + PartialEq>(original: &RoutingTable<A, H>, summarized: &RoutingTable<A, H>) -> Result<(), Vec<A>> {
+    let counterexamples: Vec<A> = original
+        .differing_ranges(summarized)
+        .into_iter()
+        .map(|(start, _, _, _)| A::from_bits(start))
+        .collect();
+    if counterexamples.is_empty() {
+        Ok(())
+    } else {
+        Err(counterexamples)
+    }
+}
+
+// Supernetting: routes to the same next hop as the first one, in as few prefixes as possible.
+// Prefixes inside others are dropped and two halves become their parent, until nothing changes.
+pub fn summarize<A: RouteAddress, H: Clone>(routes: &[Route<A, H>]) -> Vec<Route<A, H>> {
+    let Some(first) = routes.first() else {
+        return vec![];
+    };
+    let width = A::ALL_ONES.count_ones();
+    let mask = |len: u8| A::ALL_ONES & !A::ALL_ONES.checked_shr(len.into()).unwrap_or(0);
+    let mut prefixes: Vec<(u128, u8)> = routes
+        .iter()
+        .map(|route| (route.destination.to_bits() & route.mask().to_bits(), route.prefix_len))
+        .collect();
+    loop {
+        let before = prefixes.clone();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        let all = prefixes.clone();
+        prefixes.retain(|&(bits, len)| !all.iter().any(|&(other, other_len)| other_len < len && bits & mask(other_len) == other));
+        // with the covered ones gone, the other half of a prefix comes right after it
+        let mut merged = vec![];
+        let mut i = 0;
+        while i < prefixes.len() {
+            let (bits, len) = prefixes[i];
+            let half = if len == 0 { 0 } else { 1u128 << (width - len as u32) };
+            if len > 0 && bits & half == 0 && prefixes.get(i + 1) == Some(&(bits | half, len)) {
+                merged.push((bits, len - 1));
+                i += 2;
+            } else {
+                merged.push((bits, len));
+                i += 1;
+            }
+        }
+        prefixes = merged;
+        if prefixes == before {
+            break;
+        }
+    }
+    prefixes
+        .into_iter()
+        .map(|(bits, len)| Route::prefix(A::from_bits(bits), len, first.next_hop.clone()))
+        .collect()
+}
+
 // #[test]
 pub fn check_routing() {
-    let my_routing_table = RoutingTable {
-        name: "Krischal's router".into(),
-        table : vec![
-            Route {destination: 0.into(), mask: (u128::MAX).into() , next_hop: Interface::Port(30)},
-        ],
-    };
+    let my_routing_table: RoutingTable = RoutingTable::new("Krischal's router").with_routes(vec![
+            Route {destination: 0.into(), prefix_len: 128, next_hop: Interface::dev("eth30"), equal_cost: vec![], distance: STATIC_DISTANCE, metric: 0, expires_at: None},
+        ]);
     let my_ip_addr = 0.into();
 
     let my_best_route = my_routing_table.find_best_route(my_ip_addr);
@@ -88,49 +745,343 @@ pub fn routing_works() {
     check_routing();
 }
 
-impl IpAddrTools for Ipv4Addr {
-    fn count_contiguous_ones(self) -> usize {
-        popcount::<u32>(self.into())
+// the same checks for both IP versions, since it is the same code
+#[cfg(test)]
+fn longest_prefix_match_suite<A>(wide: &str, narrow: &str, in_narrow: &str, in_wide: &str, outside: &str, wide_len: u8, narrow_len: u8)
+where
+    A: RouteAddress + std::str::FromStr,
+    A::Err: Debug,
+{
+    let address = |text: &str| text.parse::<A>().unwrap();
+    let mut routing_table: RoutingTable<A, u64> = RoutingTable { name : "suite".into(), ..RoutingTable::default() };
+    routing_table.add_route(Route::with_prefix(A::from_bits(0), 0, 1).unwrap()).unwrap();
+    routing_table.add_route(Route::with_prefix(address(narrow), narrow_len, 3).unwrap()).unwrap();
+    routing_table.add_route(Route::with_prefix(address(wide), wide_len, 2).unwrap()).unwrap();
+
+    assert_eq!(routing_table.find_next_hop(address(in_narrow)), Some(3));
+    assert_eq!(routing_table.find_next_hop(address(in_wide)), Some(2));
+    assert_eq!(routing_table.find_next_hop(address(outside)), Some(1));
+    // the default route covers everything
+    assert_eq!(routing_table.table[0].address_range(), (0, A::ALL_ONES));
+
+    // without the default route, nothing matches outside
+    routing_table.remove_route(A::from_bits(0), 0).unwrap();
+    assert_eq!(routing_table.find_next_hop(address(outside)), None);
+}
+
+#[test]
+fn longest_prefix_match_for_both_versions() {
+    longest_prefix_match_suite::<Ipv4Addr>("192.168.0.0", "192.168.1.0", "192.168.1.7", "192.168.2.7", "8.8.8.8", 16, 24);
+    longest_prefix_match_suite::<Ipv6Addr>("2001:db8::", "2001:db8:1::", "2001:db8:1::7", "2001:db8:2::7", "2001:4860::8888", 32, 48);
+}
+
+#[test]
+fn only_prefix_masks_make_routes() {
+    let v4 = |mask: &str| Route::from_mask(Ipv4Addr::UNSPECIFIED, mask.parse::<Ipv4Addr>().unwrap(), 1).map(|route| route.prefix_len);
+    assert_eq!(v4("255.255.240.0"), Ok(20));
+    assert_eq!(v4("0.0.0.0"), Ok(0));
+    assert_eq!(v4("255.255.255.255"), Ok(32));
+    // a popcount would call this a /16
+    assert_eq!(v4("255.0.255.0"), Err(InvalidMask));
+
+    let v6 = |mask: &str| Route::from_mask(Ipv6Addr::UNSPECIFIED, mask.parse::<Ipv6Addr>().unwrap(), 1).map(|route| route.prefix_len);
+    assert_eq!(v6("ffff:ffff:ffff::"), Ok(48));
+    assert_eq!(v6("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"), Ok(128));
+    assert_eq!(v6("f0f0::"), Err(InvalidMask));
+
+    // and a prefix can't be longer than the address it is a prefix of
+    let prefix_len = |route: Result<Route<Ipv4Addr, u8>, RouteError>| route.map(|route| route.prefix_len);
+    assert_eq!(prefix_len(Route::with_prefix(Ipv4Addr::UNSPECIFIED, 32, 1)), Ok(32));
+    assert_eq!(prefix_len(Route::with_prefix(Ipv4Addr::UNSPECIFIED, 33, 1)), Err(RouteError::BadPrefixLen(33)));
+    assert_eq!(Route::with_prefix(Ipv6Addr::UNSPECIFIED, 128, 1).map(|route| route.prefix_len), Ok(128));
+    assert!(Route::with_prefix(Ipv6Addr::UNSPECIFIED, 129, 1).is_err());
+
+    // an address inside the prefix is taken for the prefix itself
+    let route = Route::with_prefix(Ipv4Addr::new(10, 0, 0, 5), 8, 1).unwrap();
+    assert_eq!(route.destination, Ipv4Addr::new(10, 0, 0, 0));
+    assert!(route.matches(Ipv4Addr::new(10, 1, 2, 3)));
+    let route = Route::from_mask("2001:db8::5".parse::<Ipv6Addr>().unwrap(), "ffff:ffff::".parse().unwrap(), 1).unwrap();
+    assert_eq!(route.destination, "2001:db8::".parse::<Ipv6Addr>().unwrap());
+}
+
+#[test]
+fn distance_then_metric_break_ties() {
+    use crate::route_trie::RouteTrie;
+
+    let address = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let mut table: RoutingTable<Ipv4Addr, &str> = RoutingTable::default();
+    // the same prefix learned from OSPF, through two neighbors with different costs
+    table.add_route(Route::with_prefix(address("10.1.0.0"), 16, "ospf far").unwrap().with_distance(OSPF_DISTANCE).with_metric(30)).unwrap();
+    table.add_route(Route::with_prefix(address("10.1.0.0"), 16, "ospf near").unwrap().with_distance(OSPF_DISTANCE).with_metric(20)).unwrap();
+    assert_eq!(table.find_next_hop(address("10.1.2.3")), Some("ospf near"));
+    // the static route is trusted more than whatever OSPF says, however low its metric
+    table.add_route(Route::with_prefix(address("10.1.0.0"), 16, "static").unwrap().with_metric(1000)).unwrap();
+    assert_eq!(table.find_next_hop(address("10.1.2.3")), Some("static"));
+    // RIP is trusted less than OSPF, so it only matters once the others are gone
+    table.add_route(Route::with_prefix(address("10.1.0.0"), 16, "rip").unwrap().with_distance(RIP_DISTANCE)).unwrap();
+    assert_eq!(table.find_next_hop(address("10.1.2.3")), Some("static"));
+    // but the longest prefix still comes first, whatever its distance
+    table.add_route(Route::with_prefix(address("10.1.2.0"), 24, "rip /24").unwrap().with_distance(RIP_DISTANCE)).unwrap();
+    assert_eq!(table.find_next_hop(address("10.1.2.3")), Some("rip /24"));
+    assert_eq!(RouteTrie::from_table(&table).find_next_hop(address("10.1.2.3")), Some("rip /24"));
+    assert_eq!(RouteTrie::from_table(&table).find_next_hop(address("10.1.3.3")), Some("static"));
+}
+
+#[test]
+fn flows_stick_to_one_of_the_equal_cost_paths() {
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::layered::LayeredPacket;
+    use crate::packet::Packet;
+
+    let address = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let mut table: RoutingTable<Ipv4Addr, &str> = RoutingTable::default();
+    table.add_route(Route::with_prefix(address("0.0.0.0"), 0, "uplink 1").unwrap().with_equal_cost("uplink 2").with_equal_cost("uplink 3")).unwrap();
+    // a worse route doesn't add its next hop to the set
+    table.add_route(Route::with_prefix(address("0.0.0.0"), 0, "backup").unwrap().with_metric(10)).unwrap();
+    assert_eq!(table.find_next_hops(address("8.8.8.8")), ["uplink 1", "uplink 2", "uplink 3"]);
+
+    let mut used = vec![];
+    for source_port in 40000..40100 {
+        let packet = PacketBuilder::ipv4().src(address("10.0.0.2")).dst(address("8.8.8.8")).tcp().sport(source_port).dport(443).build();
+        let hop = table.find_next_hop_for_flow(packet.destination_ip(), packet.flow_hash()).unwrap();
+        // every packet of the flow goes the same way, whatever it carries
+        let later = LayeredPacket { payload : b"more data".to_vec(), ..packet.clone() };
+        assert_eq!(table.find_next_hop_for_packet(&later), Some(hop));
+        used.push(hop);
     }
-    fn mask(self, mask:Self) -> Self {
-        let ip : u32 = self.into();
-        let mask : u32 = self.into();
-        let result = ip & mask;
-        return result.into();
+    for uplink in ["uplink 1", "uplink 2", "uplink 3"] {
+        assert!(used.iter().filter(|&&hop| hop == uplink).count() > 20, "{uplink} is barely used");
     }
+    assert!(!used.contains(&"backup"));
 }
 
-#[derive(Debug)]
-pub struct RouteV4 {
-    pub destination : Ipv4Addr,
-    pub mask : Ipv4Addr,
-    pub next_hop : Ipv4Addr,
+#[test]
+fn default_route_is_the_last_resort() {
+    let mut table: RoutingTable<Ipv4Addr, &str> = RoutingTable::with_default("isp");
+    table.add_route(Route::with_prefix("192.168.1.0".parse().unwrap(), 24, "lan").unwrap()).unwrap();
+    assert_eq!(table.find_next_hop("192.168.1.20".parse().unwrap()), Some("lan"));
+    assert_eq!(table.find_next_hop("8.8.8.8".parse().unwrap()), Some("isp"));
+    // a backup default, only used if the first one goes away
+    table.add_route(Route::default_route("lte").with_distance(200)).unwrap();
+    assert_eq!(table.default_route().map(|route| route.next_hop), Some("isp"));
+    table.remove_route_from(Ipv4Addr::UNSPECIFIED, 0, STATIC_DISTANCE).unwrap();
+    assert_eq!(table.find_next_hop("8.8.8.8".parse().unwrap()), Some("lte"));
+    // without a default, what matches nothing has nowhere to go
+    table.remove_route(Ipv4Addr::UNSPECIFIED, 0).unwrap();
+    assert_eq!(table.find_next_hop("8.8.8.8".parse().unwrap()), None);
+
+    let v6: RoutingTable = RoutingTable::with_default(Interface::dev("eth1"));
+    assert_eq!(v6.find_next_hop("2001:db8::1".parse().unwrap()), Some(Interface::dev("eth1")));
+    assert_eq!(v6.default_route().unwrap().destination, Ipv6Addr::UNSPECIFIED);
+}
+
+#[test]
+fn routes_are_added_replaced_and_removed() {
+    let address = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let mut table: RoutingTable<Ipv4Addr, &str> = RoutingTable::default();
+    table.add_route(Route::with_prefix(address("10.0.0.0"), 8, "r1").unwrap()).unwrap();
+    assert_eq!(table.add_route(Route::with_prefix(address("10.0.0.0"), 8, "r2").unwrap()), Err(RouteError::Duplicate));
+    // the same prefix from another source is a different route
+    table.add_route(Route::with_prefix(address("10.0.0.0"), 8, "ospf").unwrap().with_distance(OSPF_DISTANCE)).unwrap();
+    table.add_route(Route::with_prefix(address("10.0.0.0"), 16, "r3").unwrap()).unwrap();
+
+    let replaced = table.replace_route(Route::with_prefix(address("10.0.0.0"), 8, "r2").unwrap()).unwrap();
+    assert_eq!(replaced.map(|route| route.next_hop), Some("r1"));
+    assert_eq!(table.find_next_hop(address("10.1.0.1")), Some("r2"));
+    // replacing what isn't there just adds it
+    assert!(table.replace_route(Route::with_prefix(address("192.168.0.0"), 16, "r4").unwrap()).unwrap().is_none());
+    assert_eq!(table.table.len(), 4);
+
+    let removed = table.remove_route(address("10.0.0.0"), 8).unwrap();
+    assert_eq!(removed.iter().map(|route| route.next_hop).collect::<Vec<_>>(), ["r2", "ospf"]);
+    assert_eq!(table.find_next_hop(address("10.1.0.1")), None);
+    assert_eq!(table.find_next_hop(address("10.0.0.1")), Some("r3"));
+    assert_eq!(table.remove_route(address("10.0.0.0"), 8).unwrap_err(), RouteError::NotFound);
+}
+
+#[test]
+fn adjacent_prefixes_are_summarized() {
+    let route = |destination: &str, prefix_len: u8| Route::with_prefix(destination.parse::<Ipv4Addr>().unwrap(), prefix_len, "isp").unwrap();
+    let routes = [
+        route("10.0.0.0", 25),
+        route("10.0.0.128", 25),
+        // already inside the /25
+        route("10.0.0.64", 26),
+        route("10.0.1.0", 24),
+        // 10.0.3.0/24 is missing, so this one stays as it is
+        route("10.0.2.0", 24),
+    ];
+    let summary: Vec<String> = summarize(&routes).iter().map(|route| format!("{}/{}", route.destination, route.prefix_len)).collect();
+    assert_eq!(summary, ["10.0.0.0/23", "10.0.2.0/24"]);
+
+    // forwarding doesn't change, even with more specific routes elsewhere in the table
+    let v6 = |destination: &str, prefix_len: u8, port: u64| Route::with_prefix(destination.parse::<Ipv6Addr>().unwrap(), prefix_len, Interface::dev(&format!("eth{port}"))).unwrap();
+    let to_port_1 = [v6("2001:db8::", 34, 1), v6("2001:db8:4000::", 34, 1), v6("2001:db8:8000::", 33, 1)];
+    let original = RoutingTable::default().with_routes([&to_port_1[..], &[v6("2001:db8:1::", 48, 2)]].concat());
+    let mut summarized = RoutingTable::default().with_routes(summarize(&to_port_1));
+    assert_eq!(summarized.table.len(), 1);
+    summarized.add_route(v6("2001:db8:1::", 48, 2)).unwrap();
+    assert_eq!(verify_summary(&original, &summarized), Ok(()));
 }
 
-impl RouteV4 {
-    pub fn matches(&self, ipaddr : Ipv4Addr) -> bool {
-        ipaddr.mask(self.mask) == self.destination
+#[test]
+fn gateways_are_resolved_through_the_table() {
+    let address = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let route = |destination: &str, prefix_len: u8, next_hop: Interface| Route::with_prefix(address(destination), prefix_len, next_hop).unwrap();
+    let mut table: RoutingTable = RoutingTable::default();
+    // the link on eth1, and a static route to a remote network through a router on it...
+    table.add_route(route("2001:db8:1::", 64, Interface::dev("eth1"))).unwrap();
+    table.add_route(route("2001:db8:9::", 48, Interface::IpAddr(address("2001:db8:7::1")))).unwrap();
+    // ...which is only reachable through another router, on the link
+    table.add_route(route("2001:db8:7::", 64, Interface::IpAddr(address("2001:db8:1::2")))).unwrap();
+    let all_up = |_: &str| true;
+    let eth1 = "eth1".to_string();
+    assert_eq!(table.resolve_next_hop(address("2001:db8:9::5"), all_up), Ok((eth1.clone(), Some(address("2001:db8:1::2")))));
+    assert_eq!(table.resolve_next_hop(address("2001:db8:1::77"), all_up), Ok((eth1.clone(), None)));
+    assert_eq!(table.resolve_next_hop(address("2001:db8:5::1"), all_up), Err(ResolveError::NoRoute(address("2001:db8:5::1"))));
+
+    // two gateways each reachable only through the other
+    table.add_route(route("2001:db8:a::", 64, Interface::IpAddr(address("2001:db8:b::1")))).unwrap();
+    table.add_route(route("2001:db8:b::", 64, Interface::IpAddr(address("2001:db8:a::1")))).unwrap();
+    assert_eq!(table.resolve_next_hop(address("2001:db8:a::9"), all_up), Err(ResolveError::Loop(vec![
+        address("2001:db8:b::1"),
+        address("2001:db8:a::1"),
+        address("2001:db8:b::1"),
+    ])));
+
+    // a long chain of gateways, each in the next /64
+    let mut chain: RoutingTable = RoutingTable::default();
+    for hop in 0..20u16 {
+        let gateway = Ipv6Addr::new(0x2001, 0xdb8, hop + 1, 0, 0, 0, 0, 1);
+        chain.add_route(Route::with_prefix(Ipv6Addr::new(0x2001, 0xdb8, hop, 0, 0, 0, 0, 0), 64, Interface::IpAddr(gateway)).unwrap()).unwrap();
     }
+    assert_eq!(chain.resolve_next_hop(address("2001:db8::1"), all_up), Err(ResolveError::TooDeep));
+
+    // nothing goes out of an interface that is down; the route itself stays as it was
+    assert_eq!(table.resolve_next_hop(address("2001:db8:9::5"), |name| name != "eth1"), Err(ResolveError::InterfaceDown(eth1)));
 }
 
-#[derive(Debug)]
-struct RoutingTableV4 {
-    name : String,
-    table : Vec<RouteV4>,
+#[test]
+fn summary_verification() {
+    let route = |destination: &str, prefix_len: u8, port: u64| Route {
+        destination : destination.parse::<Ipv6Addr>().unwrap(),
+        prefix_len,
+        next_hop : Interface::dev(&format!("eth{port}")),
+        equal_cost : vec![],
+        distance : STATIC_DISTANCE,
+        metric : 0,
+        expires_at : None,
+    };
+    let original = RoutingTable::new("original").with_routes(vec![
+            route("2001:db8::", 48, 1),
+            route("2001:db8:1::", 48, 1),
+            route("2001:db8:2::", 48, 2),
+        ]);
+
+    // the two /48s to port 1 are one /47
+    let good = RoutingTable::new("summarized").with_routes(vec![route("2001:db8::", 47, 1), route("2001:db8:2::", 48, 2)]);
+    assert_eq!(verify_summary(&original, &good), Ok(()));
+
+    // a /32 is too greedy: it also catches what had no route before
+    let too_big = RoutingTable::new("summarized").with_routes(vec![route("2001:db8::", 32, 1), route("2001:db8:2::", 48, 2)]);
+    assert_eq!(verify_summary(&original, &too_big), Err(vec!["2001:db8:3::".parse().unwrap()]));
 }
 
-impl RoutingTableV4 {
-    pub fn find_best_route(&self, ipaddr: Ipv4Addr) -> Option<&RouteV4> {
-        self.table
-            .iter()
-            .filter(|route| route.matches(ipaddr))
-            .max_by_key(|route| route.mask.count_contiguous_ones())
+#[test]
+fn forwarding_diff_finds_prefixes() {
+    let route = |destination: &str, prefix_len: u8, port: u64| Route {
+        destination : destination.parse::<Ipv6Addr>().unwrap(),
+        prefix_len,
+        next_hop : Interface::dev(&format!("eth{port}")),
+        equal_cost : vec![],
+        distance : STATIC_DISTANCE,
+        metric : 0,
+        expires_at : None,
+    };
+    let before = RoutingTable::new("before").with_routes(vec![route("2001:db8::", 32, 1), route("2001:db8:1::", 48, 3)]);
+    let after = RoutingTable::new("after").with_routes(vec![route("2001:db8::", 32, 1), route("2001:db8:8000::", 33, 2), route("2001:db8::", 47, 3)]);
+    assert_eq!(before.forwarding_diff(&after), vec![
+        ForwardingDiff { destination : "2001:db8::".parse().unwrap(), prefix_len : 48, ours : Some(Interface::dev("eth1")), theirs : Some(Interface::dev("eth3")) },
+        ForwardingDiff { destination : "2001:db8:8000::".parse().unwrap(), prefix_len : 33, ours : Some(Interface::dev("eth1")), theirs : Some(Interface::dev("eth2")) },
+    ]);
+    assert!(after.forwarding_diff(&after).is_empty());
+
+    // an odd range is cut into aligned prefixes: 3 to 5 is 3/128, then 4 and 5 as one /127
+    assert_eq!(range_to_prefixes::<Ipv6Addr>(3, 5), vec![(3.into(), 128), (4.into(), 127)]);
+    assert_eq!(range_to_prefixes::<Ipv6Addr>(0, u128::MAX), vec![(0.into(), 0)]);
+    assert_eq!(range_to_prefixes::<Ipv4Addr>(0, u32::MAX.into()), vec![(0.into(), 0)]);
+
+    // the same for IPv4 tables, with gateways as next hops
+    let v4 = |destination: &str, prefix_len: u8, gateway: &str| Route::with_prefix(destination.parse().unwrap(), prefix_len, gateway.parse::<Ipv4Addr>().unwrap()).unwrap();
+    let before: RoutingTable<Ipv4Addr, Ipv4Addr> = RoutingTable::default().with_routes(vec![v4("0.0.0.0", 0, "10.0.0.1")]);
+    let after = RoutingTable::default().with_routes(vec![v4("0.0.0.0", 0, "10.0.0.1"), v4("192.168.0.0", 23, "10.0.0.2")]);
+    assert_eq!(before.forwarding_diff(&after), vec![ForwardingDiff {
+        destination : "192.168.0.0".parse().unwrap(),
+        prefix_len : 23,
+        ours : Some("10.0.0.1".parse().unwrap()),
+        theirs : Some("10.0.0.2".parse().unwrap()),
+    }]);
+    assert_eq!(verify_summary(&before, &after), Err(vec!["192.168.0.0".parse().unwrap()]));
+}
+
+impl IpAddrTools for Ipv4Addr {
+    fn count_contiguous_ones(self) -> usize {
+        popcount::<u32>(self.into())
     }
-    pub fn find_next_hop(&self, ipaddr : Ipv4Addr) -> Option<Ipv4Addr> {
-        if let Some(route) = self.find_best_route(ipaddr) {
-            return Some(route.next_hop);
-        } else {
-            return None;
-        }
+    fn is_valid_prefix_mask(&self) -> bool {
+        let mask = u32::from(*self);
+        mask.leading_ones() == mask.count_ones()
     }
-}
\ No newline at end of file
+    fn mask(self, mask:Self) -> Self {
+        let ip : u32 = self.into();
+        let mask : u32 = mask.into();
+        let result = ip & mask;
+        result.into()
+    }
+}
+
+// the IPv4 version lives in its own module now, these names are kept for the code using them
+pub use crate::networkingv4::{Route as RouteV4, RoutingTable as RoutingTableV4};
+
+#[test]
+fn converged_table_compared_and_merged() {
+    use crate::protocols::ospf::{OspfNetwork, OspfRouter};
+    use std::net::Ipv4Addr;
+
+    // A --1-- B --1-- C, each with its own /24
+    let router = |name: &str, last: u8| OspfRouter::new(name, Ipv4Addr::new(1, 1, 1, last)).with_network(Ipv4Addr::new(10, 0, last, 0), 24);
+    let mut network = OspfNetwork::new(vec![router("A", 1), router("B", 2), router("C", 3)]);
+    network.connect(0, 1, 1);
+    network.connect(1, 2, 1);
+    network.converge();
+
+    let expected: RoutingTable<Ipv4Addr, Ipv4Addr> = RoutingTable::default().with_routes(vec![
+            Route::with_prefix(Ipv4Addr::new(10, 0, 1, 0), 24, Ipv4Addr::new(1, 1, 1, 1)).unwrap().with_distance(CONNECTED_DISTANCE),
+            Route::with_prefix(Ipv4Addr::new(10, 0, 2, 0), 24, Ipv4Addr::new(1, 1, 1, 2)).unwrap().with_distance(OSPF_DISTANCE).with_metric(1),
+            Route::with_prefix(Ipv4Addr::new(10, 0, 3, 0), 24, Ipv4Addr::new(1, 1, 1, 2)).unwrap().with_distance(OSPF_DISTANCE).with_metric(2),
+        ]);
+    assert!(network.routers[0].table.diff(&expected).is_empty());
+
+    // C's network moves behind a costlier link, and A gets a static route of its own
+    network.disconnect(1, 2);
+    network.connect(0, 2, 5);
+    network.converge();
+    let mut actual = RoutingTable::default().with_routes(network.routers[0].table.routes().to_vec());
+    actual.add_route(Route::with_prefix(Ipv4Addr::new(192, 168, 0, 0), 16, Ipv4Addr::new(1, 1, 1, 9)).unwrap()).unwrap();
+    let diff = expected.diff(&actual);
+    let prefixes = |routes: Vec<&Route<Ipv4Addr, Ipv4Addr>>| routes.iter().map(|route| route.destination.to_string()).collect::<Vec<_>>();
+    assert_eq!(prefixes(diff.added.iter().collect()), ["192.168.0.0"]);
+    assert!(diff.removed.is_empty());
+    let (before, after) = &diff.changed[0];
+    assert_eq!((diff.changed.len(), before.metric, after.metric, after.next_hop), (1, 2, 5, Ipv4Addr::new(1, 1, 1, 3)));
+
+    // the OSPF routes from what was learned, anything else from the expected table
+    let mut merged = RoutingTable::default().with_routes(expected.routes().to_vec());
+    let mut pinned = RoutingTable::default().with_routes(vec![Route::with_prefix(Ipv4Addr::new(10, 0, 2, 0), 24, Ipv4Addr::new(1, 1, 1, 7)).unwrap().with_distance(OSPF_DISTANCE)]);
+    merged.merge(&actual, |distance| if distance == OSPF_DISTANCE { Prefer::Theirs } else { Prefer::Ours }).unwrap();
+    assert!(merged.diff(&actual).is_empty());
+    // and the other way round, ours wins
+    pinned.merge(&actual, |_| Prefer::Ours).unwrap();
+    assert_eq!(pinned.find_next_hop(Ipv4Addr::new(10, 0, 2, 1)), Some(Ipv4Addr::new(1, 1, 1, 7)));
+    assert_eq!(pinned.table.len(), actual.table.len());
+}
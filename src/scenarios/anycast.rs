@@ -0,0 +1,72 @@
+//! Anycast: the same prefix announced from several places at once, like the root DNS servers
+//! or a CDN. Nothing special happens in the routers: they see the prefix more than once and
+//! keep the cheapest way to it, as with any other prefix, so each client ends up at the
+//! instance nearest to it. When a link fails, routing finds the next nearest one by itself.
+//! Here the routing is OSPF, and a packet is followed hop by hop with each router's own table.
+use std::net::Ipv4Addr;
+
+use crate::protocols::ospf::{OspfNetwork, OspfRouter};
+use crate::routing::CONNECTED_DISTANCE;
+
+#[derive(Debug)]
+pub struct AnycastScenario {
+    pub network : OspfNetwork,
+    // the shared prefix, and the routers announcing it
+    pub prefix : (Ipv4Addr, u8),
+    pub instances : Vec<usize>,
+}
+
+impl AnycastScenario {
+    pub fn new(routers: Vec<OspfRouter>, prefix: Ipv4Addr, prefix_len: u8, instances: Vec<usize>) -> Self {
+        let routers = routers
+            .into_iter()
+            .enumerate()
+            .map(|(index, router)| if instances.contains(&index) { router.with_network(prefix, prefix_len) } else { router })
+            .collect();
+        AnycastScenario { network : OspfNetwork::new(routers), prefix : (prefix, prefix_len), instances }
+    }
+
+    // Follows a packet for the anycast address from this router, each router sending it to its
+    // own next hop, until one has the prefix itself. Gives back that router; None if the packet
+    // got lost or went around in circles.
+    pub fn reached_from(&self, router: usize) -> Option<usize> {
+        let routers = &self.network.routers;
+        let address = self.prefix.0;
+        let mut at = router;
+        for _ in 0..routers.len() {
+            let route = routers[at].table.find_best_route(address)?;
+            if route.distance == CONNECTED_DISTANCE {
+                return Some(at);
+            }
+            at = routers.iter().position(|other| other.id == route.next_hop)?;
+        }
+        None
+    }
+}
+
+#[test]
+fn clients_reach_the_nearest_instance() {
+    //  client1 --1-- r1 --1-- dns-a
+    //     |3
+    //  client2 --1-- r2 --1-- dns-b
+    let router = |name: &str, last: u8| OspfRouter::new(name, Ipv4Addr::new(1, 1, 1, last));
+    let names = ["client1", "r1", "dns-a", "client2", "r2", "dns-b"];
+    let routers = names.iter().zip(1..).map(|(name, last)| router(name, last)).collect();
+    let mut anycast = AnycastScenario::new(routers, Ipv4Addr::new(192, 0, 2, 0), 24, vec![2, 5]);
+    for (a, b, cost) in [(0, 1, 1), (1, 2, 1), (0, 3, 3), (3, 4, 1), (4, 5, 1)] {
+        anycast.network.connect(a, b, cost);
+    }
+    anycast.network.converge();
+    assert_eq!(anycast.reached_from(0), Some(2));
+    assert_eq!(anycast.reached_from(3), Some(5));
+    // the instances answer for themselves
+    assert_eq!(anycast.reached_from(5), Some(5));
+
+    // client1's link towards dns-a fails: it goes to dns-b now, through client2
+    anycast.network.disconnect(0, 1);
+    anycast.network.converge();
+    assert_eq!(anycast.reached_from(0), Some(5));
+    assert_eq!(anycast.network.routers[0].table.find_best_route(anycast.prefix.0).unwrap().metric, 5);
+    // r1 still has dns-a right next to it
+    assert_eq!(anycast.reached_from(1), Some(2));
+}
@@ -0,0 +1,150 @@
+//! The hotel Wi-Fi login page. Until a host has logged in, the gateway catches its HTTP
+//! requests and quietly sends them to the portal instead (DNAT: the destination is rewritten),
+//! remembering where they were going so the portal's answer looks like it came from the site
+//! the host asked for. The answer is a redirect to the login page. Anything else from such a
+//! host is dropped, except DNS, or the browser would never even get to send the HTTP request.
+//! Once the host logs in, its (MAC, IP) is let through untouched.
+//!
+//! Nothing here is new: the rewriting is the NAT table's, what gets through is decided by a
+//! firewall, and the portal speaks the toy HTTP of the http module.
+use std::net::Ipv4Addr;
+use std::time::Instant;
+
+use crate::firewall::{Action, Direction, FirewallRule, HostFirewall};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::nat_v4::NatTableV4;
+use crate::neighbor::MacAddr;
+use crate::packet::layered::LayeredPacket;
+use crate::packet::Packet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GatewayVerdict {
+    Forward(LayeredPacket),
+    // sent to the portal instead
+    Redirected(LayeredPacket),
+    Dropped,
+}
+
+#[derive(Debug)]
+pub struct CaptivePortal {
+    pub portal : (Ipv4Addr, u16),
+    pub login_url : String,
+    // the path of login_url, "/login" of "http://192.168.1.1:8080/login", where the form is posted
+    pub login_path : String,
+    pub authenticated : Vec<(MacAddr, Ipv4Addr)>,
+    // what a host that hasn't logged in may reach: DNS and the portal, nothing else
+    pub firewall : HostFirewall,
+    // where the redirected connections were going
+    pub nat : NatTableV4,
+}
+
+impl CaptivePortal {
+    pub fn new(portal: (Ipv4Addr, u16), login_url: &str) -> Self {
+        let mut firewall = HostFirewall::new(0);
+        firewall.default_outbound = Action::Deny;
+        firewall.add_rule(FirewallRule { remote_port : Some(53), ..FirewallRule::new(Direction::Outbound, Action::Allow) });
+        firewall.add_rule(FirewallRule {
+            remote_ip : Some(portal.0),
+            remote_port : Some(portal.1),
+            ..FirewallRule::new(Direction::Outbound, Action::Allow)
+        });
+        let without_scheme = login_url.split_once("://").map_or(login_url, |(_, rest)| rest);
+        let login_path = without_scheme.find('/').map_or("/", |start| &without_scheme[start..]);
+        CaptivePortal { portal, login_url : login_url.to_string(), login_path : login_path.to_string(), authenticated : vec![], firewall, nat : NatTableV4::new("portal", portal.0) }
+    }
+
+    pub fn is_authenticated(&self, mac: MacAddr, ip: Ipv4Addr) -> bool {
+        self.authenticated.contains(&(mac, ip))
+    }
+
+    // the host filled in the form; the same IP from another MAC is still not let through
+    pub fn login(&mut self, mac: MacAddr, ip: Ipv4Addr) {
+        if !self.is_authenticated(mac, ip) {
+            self.authenticated.push((mac, ip));
+        }
+    }
+
+    // a packet from a host on the LAN, with the MAC it came from
+    pub fn outbound(&mut self, mac: MacAddr, packet: LayeredPacket, now: Instant) -> GatewayVerdict {
+        if self.is_authenticated(mac, packet.source_ip()) || self.firewall.check_outbound(&packet) == Action::Allow {
+            return GatewayVerdict::Forward(packet);
+        }
+        if packet.destination_port() != 80 {
+            return GatewayVerdict::Dropped;
+        }
+        GatewayVerdict::Redirected(self.nat.redirect_at(packet, self.portal, now))
+    }
+
+    // the portal's answer to a redirected connection gets its source put back (the reverse DNAT)
+    pub fn inbound(&mut self, packet: LayeredPacket, now: Instant) -> LayeredPacket {
+        match self.nat.unredirect_at(packet.clone(), now) {
+            Ok(answer) => answer,
+            Err(_) => packet,
+        }
+    }
+
+    // What the portal web server says: filling in the form logs the host in, whatever else it
+    // asks for gets it sent to the form. The MAC is the one the request came from.
+    pub fn serve(&mut self, mac: MacAddr, request: &LayeredPacket) -> LayeredPacket {
+        let response = match HttpRequest::parse(&request.payload) {
+            Some(http) if http.method == "POST" && http.path == self.login_path => {
+                self.login(mac, request.source_ip());
+                HttpResponse::new(200, "OK").with_body(b"Welcome!")
+            }
+            Some(_) => HttpResponse::redirect(&self.login_url),
+            None => HttpResponse::new(400, "Bad Request"),
+        };
+        let mut answer = LayeredPacket { payload : response.to_bytes(), ..request.clone() };
+        answer.set_source(request.destination_ip(), request.destination_port());
+        answer.set_destination(request.source_ip(), request.source_port());
+        answer
+    }
+}
+
+#[test]
+fn unauthenticated_http_goes_to_the_portal() {
+    use crate::packet::builder::PacketBuilder;
+
+    let laptop_mac = MacAddr([0x02, 0, 0, 0, 0, 0x10]);
+    let laptop: Ipv4Addr = "192.168.1.10".parse().unwrap();
+    let example: Ipv4Addr = "93.184.216.34".parse().unwrap();
+    let now = Instant::now();
+    let mut gateway = CaptivePortal::new(("192.168.1.1".parse().unwrap(), 8080), "http://192.168.1.1:8080/login");
+    let request_to = |to: Ipv4Addr, port: u16, request: HttpRequest| {
+        PacketBuilder::ipv4().src(laptop).dst(to).tcp().sport(40000).dport(port).payload(request.to_bytes()).build()
+    };
+    let home_page = |port: u16| request_to(example, port, HttpRequest::get("example.com", "/"));
+
+    // https can't be redirected without a certificate error, so it is just dropped
+    assert_eq!(gateway.outbound(laptop_mac, home_page(443), now), GatewayVerdict::Dropped);
+    let GatewayVerdict::Redirected(redirected) = gateway.outbound(laptop_mac, home_page(80), now) else {
+        panic!("http should go to the portal");
+    };
+    assert_eq!((redirected.destination_ip(), redirected.destination_port()), gateway.portal);
+
+    // the laptop sees the redirect coming from example.com, as it expected
+    let answer = gateway.serve(laptop_mac, &redirected);
+    let answer = gateway.inbound(answer, now);
+    assert_eq!((answer.source_ip(), answer.source_port(), answer.destination_port()), (example, 80, 40000));
+    let redirect = HttpResponse::parse(&answer.payload).unwrap();
+    assert_eq!((redirect.status, redirect.header("Location")), (302, Some("http://192.168.1.1:8080/login")));
+
+    // posting anywhere but the login page logs nobody in, even where the login URL ends the same
+    let elsewhere = request_to(gateway.portal.0, 8080, HttpRequest::post("192.168.1.1:8080", "in", b"room=214"));
+    let GatewayVerdict::Forward(elsewhere) = gateway.outbound(laptop_mac, elsewhere, now) else {
+        panic!("the portal should be reachable");
+    };
+    assert_eq!(HttpResponse::parse(&gateway.serve(laptop_mac, &elsewhere).payload).unwrap().status, 302);
+    assert!(gateway.authenticated.is_empty());
+
+    // the browser follows it; the portal itself is let through, and the form logs the laptop in
+    let form = request_to(gateway.portal.0, 8080, HttpRequest::post("192.168.1.1:8080", "/login", b"room=214"));
+    let GatewayVerdict::Forward(form) = gateway.outbound(laptop_mac, form, now) else {
+        panic!("the portal should be reachable");
+    };
+    assert_eq!(HttpResponse::parse(&gateway.serve(laptop_mac, &form).payload).unwrap().status, 200);
+
+    assert_eq!(gateway.outbound(laptop_mac, home_page(80), now), GatewayVerdict::Forward(home_page(80)));
+    // someone else who took the laptop's IP is not logged in
+    assert_eq!(gateway.outbound(MacAddr([0x02, 0, 0, 0, 0, 0x66]), home_page(443), now), GatewayVerdict::Dropped);
+}
@@ -0,0 +1,78 @@
+//! Double NAT: the home router does NAT, and the ISP puts all its customers behind one more,
+//! a carrier-grade NAT (CGNAT), because it doesn't have a public address for each of them.
+//! The home router's "public" address is then only a 100.64.0.0/10 one, and the CGNAT sees
+//! the home router as just another computer. A packet going out is translated twice, and the
+//! reply has to be translated back by both, outer one first.
+use crate::nat_v4::{NatError, NatTable};
+use crate::packet::layered::LayeredPacket;
+
+#[derive(Debug)]
+pub struct DoubleNat {
+    pub home : NatTable,
+    pub cgnat : NatTable,
+    // which computer the home router is, for the CGNAT
+    pub home_router : u16,
+}
+
+impl DoubleNat {
+    pub fn new(home: NatTable, cgnat: NatTable, home_router: u16) -> Self {
+        DoubleNat { home, cgnat, home_router }
+    }
+
+    // from a computer at home out to the internet
+    pub fn outbound(&mut self, packet: LayeredPacket, computer: u16) -> Result<LayeredPacket, NatError> {
+        let packet = self.home.translate_outgoing(packet, computer)?;
+        self.cgnat.translate_outgoing(packet, self.home_router)
+    }
+
+    // from the internet back to a computer at home
+    pub fn inbound(&mut self, packet: LayeredPacket) -> Result<(LayeredPacket, u16), NatError> {
+        let (packet, computer) = self.cgnat.translate_incoming(packet)?;
+        if computer != self.home_router {
+            return Err(NatError::NoMapping);
+        }
+        self.home.translate_incoming(packet)
+    }
+}
+
+#[test]
+fn both_layers_translate_and_untranslate() {
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+    use std::net::Ipv4Addr;
+
+    let cgnat_public: Ipv4Addr = "203.0.113.7".parse().unwrap();
+    let mut double_nat = DoubleNat::new(
+        NatTable::new("home router", "100.64.0.5".parse().unwrap()),
+        NatTable::new("ISP CGNAT", cgnat_public),
+        1,
+    );
+    let packet = PacketBuilder::ipv4()
+        .src("192.168.1.10".parse().unwrap())
+        .dst("8.8.8.8".parse().unwrap())
+        .hop_limit(30)
+        .udp()
+        .sport(5000)
+        .dport(53)
+        .payload(b"where is example.com")
+        .build();
+
+    let out = double_nat.outbound(packet.clone(), 3).unwrap();
+    // the internet only ever sees the CGNAT address
+    assert_eq!(out.source_ip(), cgnat_public);
+    // and the home router's mapping is in the CGNAT under the home router's address
+    let inner = double_nat.home.found_on_nat_towards(&packet, 3).unwrap();
+    let outer = double_nat.cgnat.found_on_nat(double_nat.home.translated_addr, inner.mangled_port).unwrap();
+    assert_eq!((outer.computer, outer.mangled_port), (1, out.source_port()));
+
+    let mut reply = LayeredPacket { payload : b"example.com is at 93.184.216.34".to_vec(), ..out.clone() };
+    reply.set_source(out.destination_ip(), out.destination_port());
+    reply.set_destination(out.source_ip(), out.source_port());
+    let (back, computer) = double_nat.inbound(reply.clone()).unwrap();
+    assert_eq!((back.destination_ip(), back.destination_port(), computer), (packet.source_ip(), packet.source_port(), 3));
+
+    // nothing was mapped on the port next to it, in either NAT
+    let mut unsolicited = reply.clone();
+    unsolicited.set_destination(reply.destination_ip(), reply.destination_port() + 1);
+    assert_eq!(double_nat.inbound(unsolicited).unwrap_err(), NatError::NoMapping);
+}
@@ -0,0 +1,116 @@
+//! GeoDNS: the same name answered with a different server depending on where the user is.
+//! A site with servers in Europe, America and Asia, and users in all three. With one answer for
+//! everybody, all of them go to the same server, and most cross an ocean for every request.
+//! With answers per client subnet, each user is sent to the server in their own region.
+//! The round trip of a request is measured on `traffic::Link`s with the distance as their
+//! propagation delay.
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use crate::dns::{Record, Zone};
+use crate::traffic::Link;
+
+pub const SITE: &str = "www.shop.example";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name : String,
+    // where its users are
+    pub clients : (Ipv4Addr, u8),
+    pub server : Ipv4Addr,
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoDnsScenario {
+    pub regions : Vec<Region>,
+    // one way delay from the users of region i to the server of region j
+    pub delays : Vec<Vec<Duration>>,
+}
+
+// (region, average round trip of its users)
+pub type LatencyReport = Vec<(String, Duration)>;
+
+impl GeoDnsScenario {
+    pub fn three_regions() -> Self {
+        let region = |name: &str, clients: [u8; 4], server: [u8; 4]| Region {
+            name : name.to_string(),
+            clients : (Ipv4Addr::from(clients), 16),
+            server : Ipv4Addr::from(server),
+        };
+        let ms = Duration::from_millis;
+        GeoDnsScenario {
+            regions : vec![
+                region("europe", [10, 1, 0, 0], [192, 0, 2, 10]),
+                region("america", [10, 2, 0, 0], [198, 51, 100, 10]),
+                region("asia", [10, 3, 0, 0], [203, 0, 113, 10]),
+            ],
+            delays : vec![
+                vec![ms(10), ms(40), ms(120)],
+                vec![ms(40), ms(10), ms(90)],
+                vec![ms(120), ms(90), ms(10)],
+            ],
+        }
+    }
+
+    // the first server is the answer for everyone; with geo, each region gets its own
+    pub fn zone(&self, geo: bool) -> Zone {
+        let mut zone = Zone::new().with_record(SITE, Record::A(self.regions[0].server));
+        if geo {
+            for region in &self.regions {
+                zone = zone.with_client_subnet_record(region.clients.0.into(), region.clients.1, SITE, Record::A(region.server)).expect("a subnet");
+            }
+        }
+        zone
+    }
+
+    // users resolve the site and send a request of this size, the answer is 10 times bigger
+    pub fn measure(&self, zone: &Zone, users_per_region: u32, request_size: usize) -> LatencyReport {
+        self.regions.iter().enumerate().map(|(from, region)| {
+            let mut total = Duration::ZERO;
+            for user in 0..users_per_region {
+                let client = Ipv4Addr::from(u32::from(region.clients.0) + user + 1);
+                let server = zone.resolve_a_for_client(SITE, client.into()).expect("the site always resolves");
+                let to = self.regions.iter().position(|region| region.server == server).expect("one of the servers");
+                // 100 Mbps both ways, plenty of queue
+                let mut there = Link::new(100_000_000, self.delays[from][to], 1 << 20).expect("a bandwidth");
+                let mut back = Link::new(100_000_000, self.delays[to][from], 1 << 20).expect("a bandwidth");
+                let arrived = there.send(request_size, Duration::ZERO).expect("empty queue");
+                total += back.send(request_size * 10, arrived).expect("empty queue");
+            }
+            (region.name.clone(), total / users_per_region)
+        }).collect()
+    }
+}
+
+#[test]
+fn users_get_the_closest_server() {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    let scenario = GeoDnsScenario::three_regions();
+    let without = scenario.measure(&scenario.zone(false), 20, 500);
+    let with = scenario.measure(&scenario.zone(true), 20, 500);
+
+    // the European server was already the closest for Europe
+    assert_eq!(without[0], with[0]);
+    for (region, (without, with)) in scenario.regions.iter().zip(without.iter().zip(&with)).skip(1) {
+        assert!(with.1 < without.1, "{} should be faster with GeoDNS", region.name);
+    }
+    // Asia: 2 x 10ms instead of 2 x 120ms, plus sending 500 and 5000 bytes at 100 Mbps
+    assert_eq!(with[2].1, Duration::from_millis(20) + Duration::from_micros(40 + 400));
+    assert_eq!(without[2].1, Duration::from_millis(240) + Duration::from_micros(40 + 400));
+
+    // a client outside every subnet gets the plain answer
+    let zone = scenario.zone(true);
+    assert_eq!(zone.resolve_a_for_client(SITE, Ipv4Addr::new(172, 16, 0, 1).into()), Some(scenario.regions[0].server));
+
+    // the same for AAAA records, and for clients coming over IPv6
+    let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let zone = Zone::new()
+        .with_record(SITE, Record::Aaaa(v6("2001:db8:e0::80")))
+        .with_client_subnet_record(IpAddr::V6(v6("2001:db8:a5::")), 48, SITE, Record::Aaaa(v6("2001:db8:a5::80"))).unwrap()
+        .with_client_subnet_record(IpAddr::V4(Ipv4Addr::new(10, 3, 0, 0)), 16, SITE, Record::Aaaa(v6("2001:db8:a5::80"))).unwrap();
+    assert_eq!(zone.resolve_aaaa_for_client(SITE, IpAddr::V6(v6("2001:db8:a5:1::10"))), Some(v6("2001:db8:a5::80")));
+    assert_eq!(zone.resolve_aaaa_for_client(SITE, IpAddr::V4(Ipv4Addr::new(10, 3, 2, 1))), Some(v6("2001:db8:a5::80")));
+    assert_eq!(zone.resolve_aaaa_for_client(SITE, IpAddr::V6(v6("2001:db8:77::10"))), Some(v6("2001:db8:e0::80")));
+    assert_eq!(zone.resolve_a_for_client(SITE, IpAddr::V6(v6("2001:db8:a5:1::10"))), None);
+}
@@ -0,0 +1,149 @@
+//! Two branch offices, 10.1.0.0/24 and 10.2.0.0/24, joined by a GRE tunnel across an ISP that
+//! only knows about public addresses. Each branch router has a tunnel interface, gre0, with a
+//! little /30 of its own, and a route to the other branch through it. A packet from one branch
+//! to the other is routed into gre0, wrapped in a packet between the two routers' public
+//! addresses, carried by the ISP like any other, and unwrapped and routed again by the router at
+//! the other end. Without the tunnel the ISP has no idea where 10.2.0.20 is.
+//!
+//! ```text
+//! 10.1.0.0/24 ── branch-a ══════ gre0 (172.16.0.0/30) ══════ branch-b ── 10.2.0.0/24
+//!                198.51.100.2 ── isp ── 203.0.113.2
+//! ```
+use std::net::Ipv4Addr;
+
+use crate::packet::layered::{LayeredPacket, Transport};
+use crate::router::{Forwarded, Router, RouterInterface};
+use crate::routing::Route;
+use crate::tunnel::GreTunnel;
+
+// A router with a tunnel interface, and the tunnel behind it
+#[derive(Debug)]
+pub struct TunnelEnd {
+    pub router : Router,
+    pub tunnel : GreTunnel,
+}
+
+impl TunnelEnd {
+    pub fn new(router: Router, tunnel: GreTunnel) -> Self {
+        TunnelEnd { router, tunnel }
+    }
+
+    // What the router does with a packet that came in on that interface. Routed into the
+    // tunnel, it goes out again wrapped; a GRE packet for the router from the other end is
+    // unwrapped and forwarded as having come in on the tunnel interface.
+    pub fn receive(&mut self, packet: LayeredPacket, ingress: &str) -> Forwarded {
+        match self.router.forward(packet, ingress) {
+            Forwarded::Out { interface, packet, .. } if interface == self.tunnel.name => self.router.send(self.tunnel.encapsulate(&packet)),
+            Forwarded::Local(packet) if matches!(packet.transport, Transport::Gre(_)) => match self.tunnel.decapsulate(&packet) {
+                Ok(inner) => {
+                    let tunnel = self.tunnel.name.clone();
+                    self.receive(inner, &tunnel)
+                }
+                // not from the other end, so only for the router itself
+                Err(_) => Forwarded::Local(packet),
+            },
+            other => other,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GreVpn {
+    pub branch_a : TunnelEnd,
+    pub isp : Router,
+    pub branch_b : TunnelEnd,
+}
+
+fn v4(text: &str) -> Ipv4Addr {
+    text.parse().unwrap()
+}
+
+// a branch router: its LAN, its link to the ISP, and its end of the tunnel to the other branch
+fn branch(name: &str, lan: &str, wan: &str, isp: &str, tunnel: (&str, &str), other_lan: &str, remote: &str) -> TunnelEnd {
+    let mut router = Router::new(name);
+    router.add_interface(RouterInterface::new("lan").with_v4(v4(lan), 24));
+    router.add_interface(RouterInterface::new("wan").with_v4(v4(wan), 30));
+    router.add_interface(RouterInterface::new("gre0").with_v4(v4(tunnel.0), 30));
+    router.routes_v4.add_route(Route::default_route(v4(isp))).unwrap();
+    router.routes_v4.add_route(Route::with_prefix(v4(other_lan), 24, v4(tunnel.1)).unwrap()).unwrap();
+    TunnelEnd::new(router, GreTunnel::new("gre0", v4(wan), v4(remote)))
+}
+
+impl Default for GreVpn {
+    fn default() -> Self {
+        let mut isp = Router::new("isp");
+        isp.add_interface(RouterInterface::new("branch-a").with_v4(v4("198.51.100.1"), 30));
+        isp.add_interface(RouterInterface::new("branch-b").with_v4(v4("203.0.113.1"), 30));
+        GreVpn {
+            branch_a : branch("branch-a", "10.1.0.1", "198.51.100.2", "198.51.100.1", ("172.16.0.1", "172.16.0.2"), "10.2.0.0", "203.0.113.2"),
+            isp,
+            branch_b : branch("branch-b", "10.2.0.1", "203.0.113.2", "203.0.113.1", ("172.16.0.2", "172.16.0.1"), "10.1.0.0", "198.51.100.2"),
+        }
+    }
+}
+
+impl GreVpn {
+    // From a host on branch A's LAN to one on branch B's: what the ISP carried, and what came
+    // out on B's LAN. None if it didn't get there.
+    pub fn a_to_b(&mut self, packet: LayeredPacket) -> Option<(LayeredPacket, LayeredPacket)> {
+        carry(&mut self.branch_a, &mut self.isp, "branch-a", &mut self.branch_b, packet)
+    }
+
+    pub fn b_to_a(&mut self, packet: LayeredPacket) -> Option<(LayeredPacket, LayeredPacket)> {
+        carry(&mut self.branch_b, &mut self.isp, "branch-b", &mut self.branch_a, packet)
+    }
+}
+
+fn carry(from: &mut TunnelEnd, isp: &mut Router, isp_ingress: &str, to: &mut TunnelEnd, packet: LayeredPacket) -> Option<(LayeredPacket, LayeredPacket)> {
+    let Forwarded::Out { interface, packet, .. } = from.receive(packet, "lan") else {
+        return None;
+    };
+    if interface != "wan" {
+        return None;
+    }
+    let Forwarded::Out { packet : carried, .. } = isp.forward(packet, isp_ingress) else {
+        return None;
+    };
+    match to.receive(carried.clone(), "wan") {
+        Forwarded::Out { interface, packet, .. } if interface == "lan" => Some((carried, packet)),
+        _ => None,
+    }
+}
+
+#[test]
+fn branches_talk_across_a_core_that_doesnt_know_them() {
+    use crate::nat_v4::Protocol;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+    use crate::router::DropReason;
+
+    let mut vpn = GreVpn::default();
+    let ssh = PacketBuilder::ipv4().src(v4("10.1.0.10")).dst(v4("10.2.0.20")).tcp().sport(40000).dport(22).seq(1000).payload(b"SSH-2.0-OpenSSH_9.6").build();
+    // on its own, the ISP drops it
+    assert_eq!(vpn.isp.forward(ssh.clone(), "branch-a"), Forwarded::Dropped(DropReason::NoRoute));
+
+    let (carried, delivered) = vpn.a_to_b(ssh.clone()).unwrap();
+    // the ISP only saw the two branch routers talking GRE, one hop older
+    assert_eq!((carried.protocol(), carried.source_ip(), carried.destination_ip(), carried.hop_limit()), (Protocol::Gre, v4("198.51.100.2"), v4("203.0.113.2"), 63));
+    // and the packet came out as it went in, a hop older at each branch router: the ISP in
+    // between is one hop for the outer packet, not for the inner one
+    let mut expected = ssh.clone();
+    expected.set_hop_limit(62);
+    assert_eq!(delivered.to_bytes(), expected.to_bytes());
+
+    // and the answer goes back the same way
+    let mut reply = ssh.clone();
+    reply.set_source(ssh.destination_ip(), 22);
+    reply.set_destination(ssh.source_ip(), 40000);
+    let (carried, delivered) = vpn.b_to_a(reply.clone()).unwrap();
+    assert_eq!((carried.source_ip(), carried.destination_ip()), (v4("203.0.113.2"), v4("198.51.100.2")));
+    assert_eq!(delivered.payload, reply.payload);
+
+    // the internet is still reached directly, untunneled
+    let mut web = ssh;
+    web.set_destination(v4("203.0.113.1"), 80);
+    let Forwarded::Out { interface, packet, .. } = vpn.branch_a.receive(web, "lan") else {
+        panic!("should have been routed to the ISP");
+    };
+    assert_eq!((interface.as_str(), packet.protocol()), ("wan", Protocol::Tcp));
+}
@@ -0,0 +1,150 @@
+//! UDP hole punching: two computers, each behind its own NAT, want to talk directly.
+//! Neither can be reached from outside, so both first register with a rendezvous server
+//! (which sees their public mapping, like STUN), learn each other's public (ip, port)
+//! from it, and then send packets to each other at the same time. Each outgoing packet
+//! opens a "hole" in the sender's NAT through which the other side's packets can come in.
+//!
+//! It only works if the mapping the server saw is the one used towards the peer,
+//! so a symmetric NAT on both sides makes it fail.
+use std::net::Ipv4Addr;
+
+use crate::nat_v4::NatTable;
+use crate::packet::builder::PacketBuilder;
+use crate::packet::layered::LayeredPacket;
+use crate::packet::Packet;
+
+#[derive(Debug)]
+pub struct Peer {
+    pub name : String,
+    pub ip : Ipv4Addr,
+    pub port : u16,
+    pub computer : u16,
+    pub nat : NatTable,
+}
+
+impl Peer {
+    pub fn new(name: &str, ip: Ipv4Addr, port: u16, computer: u16, nat: NatTable) -> Self {
+        Peer { name : name.to_string(), ip, port, computer, nat }
+    }
+
+    // sends a packet out through our NAT, giving back what appears on the internet
+    pub fn send(&mut self, destination_ip: Ipv4Addr, destination_port: u16, data: &[u8]) -> Option<LayeredPacket> {
+        let packet = PacketBuilder::ipv4()
+            .src(self.ip)
+            .dst(destination_ip)
+            .hop_limit(30)
+            .udp()
+            .sport(self.port)
+            .dport(destination_port)
+            .payload(data)
+            .build();
+        self.nat.translate_outgoing(packet, self.computer).ok()
+    }
+
+    // a packet from the internet reaches our NAT; did it make it to us?
+    pub fn receive(&mut self, packet: LayeredPacket) -> Option<LayeredPacket> {
+        if packet.destination_ip() != self.nat.translated_addr {
+            return None;
+        }
+        let (packet, computer) = self.nat.translate_incoming(packet).ok()?;
+        (computer == self.computer && packet.destination_port() == self.port).then_some(packet)
+    }
+}
+
+#[derive(Debug)]
+pub struct RendezvousServer {
+    pub ip : Ipv4Addr,
+    pub port : u16,
+    pub registered : Vec<(String, Ipv4Addr, u16)>,
+}
+
+impl RendezvousServer {
+    pub fn new(ip: Ipv4Addr, port: u16) -> Self {
+        RendezvousServer { ip, port, registered : vec![] }
+    }
+
+    // remembers the public (ip, port) the registration came from
+    pub fn register(&mut self, name: &str, packet: &LayeredPacket) {
+        self.registered.retain(|(registered, _, _)| registered != name);
+        self.registered.push((name.to_string(), packet.source_ip(), packet.source_port()));
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<(Ipv4Addr, u16)> {
+        self.registered
+            .iter()
+            .find(|(registered, _, _)| registered == name)
+            .map(|&(_, ip, port)| (ip, port))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HolePunchResult {
+    pub first_heard_second : bool,
+    pub second_heard_first : bool,
+}
+
+impl HolePunchResult {
+    pub fn succeeded(&self) -> bool {
+        self.first_heard_second && self.second_heard_first
+    }
+}
+
+// one punch: `from` sends to where the server said `to` is; if it gets through, `to` answers back
+// to wherever the packet came from. Returns who heard whom (from heard to, to heard from).
+fn punch(from: &mut Peer, to: &mut Peer, target: (Ipv4Addr, u16)) -> (bool, bool) {
+    let Some(packet) = from.send(target.0, target.1, b"punch") else {
+        return (false, false);
+    };
+    let Some(packet) = to.receive(packet) else {
+        return (false, false);
+    };
+    let reply = to.send(packet.source_ip(), packet.source_port(), b"punch back");
+    let heard_reply = reply.and_then(|reply| from.receive(reply)).is_some();
+    (heard_reply, true)
+}
+
+pub fn hole_punch(first: &mut Peer, second: &mut Peer, server: &mut RendezvousServer) -> Option<HolePunchResult> {
+    // both register, so the server learns their public mappings
+    let registration = first.send(server.ip, server.port, b"register")?;
+    server.register(&first.name, &registration);
+    let registration = second.send(server.ip, server.port, b"register")?;
+    server.register(&second.name, &registration);
+
+    // the server tells each of them about the other one
+    let second_public = server.lookup(&second.name)?;
+    let first_public = server.lookup(&first.name)?;
+
+    // both punch at "the same time"
+    let (first_heard, second_heard) = punch(first, second, second_public);
+    let (second_heard_back, first_heard_back) = punch(second, first, first_public);
+
+    Some(HolePunchResult {
+        first_heard_second : first_heard || first_heard_back,
+        second_heard_first : second_heard || second_heard_back,
+    })
+}
+
+#[cfg(test)]
+fn peers(first_type: crate::nat_v4::NatType, second_type: crate::nat_v4::NatType) -> (Peer, Peer, RendezvousServer) {
+    let first = Peer::new("alice", "192.168.1.10".parse().unwrap(), 4000, 1,
+        NatTable::new("Alice's router", "103.5.150.9".parse().unwrap()).with_nat_type(first_type));
+    let second = Peer::new("bob", "10.0.0.20".parse().unwrap(), 5000, 2,
+        NatTable::new("Bob's router", "27.34.1.7".parse().unwrap()).with_nat_type(second_type));
+    (first, second, RendezvousServer::new("8.8.4.4".parse().unwrap(), 3478))
+}
+
+#[test]
+fn hole_punching_depends_on_nat_type() {
+    use crate::nat_v4::NatType::{Cone, Symmetric};
+
+    for (first_type, second_type, works) in [
+        (Cone, Cone, true),
+        (Cone, Symmetric, true),
+        (Symmetric, Cone, true),
+        (Symmetric, Symmetric, false),
+    ] {
+        let (mut first, mut second, mut server) = peers(first_type, second_type);
+        let result = hole_punch(&mut first, &mut second, &mut server).unwrap();
+        assert_eq!(result.succeeded(), works, "{first_type:?} <-> {second_type:?}: {result:?}");
+    }
+}
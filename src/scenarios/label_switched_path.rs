@@ -0,0 +1,114 @@
+//! A label switched path through a provider's network: two edge routers (PE) with customers
+//! behind them, and two core routers (P) in between that only know the provider's own links.
+//! Each PE pushes a label for the prefix behind the other one, each P swaps it for the label
+//! the next router asked for, and the last P pops it (the next router asked for implicit null),
+//! so the far PE gets a plain IP packet it can route. The core never has a route to either
+//! customer, and doesn't need one.
+//!
+//! ```text
+//! 10.0.0.0/24 ── pe1 ──[100]── p1 ──[200]── p2 ──────── pe2 ── 192.0.2.0/24
+//!                    ──────── p1 ──[400]── p2 ──[300]── pe2      (the way back)
+//! ```
+use std::net::Ipv4Addr;
+
+use crate::mpls::{LabelOperation, Switched, IMPLICIT_NULL};
+use crate::packet::layered::LayeredPacket;
+use crate::router::{Forwarded, Router, RouterInterface};
+
+// pe1, p1, p2, pe2; each has "up" towards pe1 and "down" towards pe2, except at the ends
+#[derive(Debug)]
+pub struct LabelSwitchedPath {
+    pub routers : Vec<Router>,
+}
+
+fn v4(text: &str) -> Ipv4Addr {
+    text.parse().unwrap()
+}
+
+impl Default for LabelSwitchedPath {
+    fn default() -> Self {
+        let mut pe1 = Router::new("pe1");
+        pe1.add_interface(RouterInterface::new("customer").with_v4(v4("10.0.0.1"), 24));
+        pe1.add_interface(RouterInterface::new("down").with_v4(v4("172.16.1.1"), 30));
+        pe1.labels.bind(v4("192.0.2.0"), 24, 100, "down", v4("172.16.1.2"));
+
+        let mut p1 = Router::new("p1");
+        p1.add_interface(RouterInterface::new("up").with_v4(v4("172.16.1.2"), 30));
+        p1.add_interface(RouterInterface::new("down").with_v4(v4("172.16.2.1"), 30));
+        p1.labels.add_entry(100, LabelOperation::Swap(200), "down", v4("172.16.2.2"));
+        p1.labels.add_entry(400, LabelOperation::Swap(IMPLICIT_NULL), "up", v4("172.16.1.1"));
+
+        let mut p2 = Router::new("p2");
+        p2.add_interface(RouterInterface::new("up").with_v4(v4("172.16.2.2"), 30));
+        p2.add_interface(RouterInterface::new("down").with_v4(v4("172.16.3.1"), 30));
+        p2.labels.add_entry(200, LabelOperation::Swap(IMPLICIT_NULL), "down", v4("172.16.3.2"));
+        p2.labels.add_entry(300, LabelOperation::Swap(400), "up", v4("172.16.2.1"));
+
+        let mut pe2 = Router::new("pe2");
+        pe2.add_interface(RouterInterface::new("up").with_v4(v4("172.16.3.2"), 30));
+        pe2.add_interface(RouterInterface::new("servers").with_v4(v4("192.0.2.1"), 24));
+        pe2.labels.bind(v4("10.0.0.0"), 24, 300, "up", v4("172.16.3.1"));
+
+        LabelSwitchedPath { routers : vec![pe1, p1, p2, pe2] }
+    }
+}
+
+impl LabelSwitchedPath {
+    // From a customer of pe1 to a server behind pe2 (downstream), or back: the labels on each
+    // link it crossed, and the packet that came out at the other end. None if it got lost.
+    pub fn carry(&mut self, packet: LayeredPacket, downstream: bool) -> Option<(Vec<Vec<u32>>, LayeredPacket)> {
+        let (mut router, ingress, arrives_on) = if downstream { (0, "customer", "up") } else { (self.routers.len() - 1, "servers", "down") };
+        let mut links = vec![];
+        let mut switched = self.routers[router].label_push(packet, ingress);
+        loop {
+            if let Switched::Routed(Forwarded::Out { interface, packet, .. }) = &switched {
+                if interface == "customer" || interface == "servers" {
+                    return Some((links, packet.clone()));
+                }
+            }
+            router = if downstream { router + 1 } else { router.checked_sub(1)? };
+            switched = match switched {
+                Switched::Labeled { packet, .. } => {
+                    links.push(packet.labels.iter().map(|label| label.label).collect());
+                    self.routers.get_mut(router)?.switch(packet, arrives_on)
+                }
+                Switched::Routed(Forwarded::Out { packet, .. }) => {
+                    links.push(vec![]);
+                    self.routers.get_mut(router)?.label_push(packet, arrives_on)
+                }
+                Switched::Routed(_) => return None,
+            };
+        }
+    }
+}
+
+#[test]
+fn labels_carry_packets_the_core_cant_route() {
+    use crate::mpls::LabeledPacket;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+    use crate::router::DropReason;
+
+    let mut path = LabelSwitchedPath::default();
+    let request = |hop_limit: u8| PacketBuilder::ipv4().src(v4("10.0.0.10")).dst(v4("192.0.2.80")).hop_limit(hop_limit).tcp().sport(40000).dport(80).payload(b"GET /").build();
+    // the core has no idea where the servers are
+    assert_eq!(path.routers[1].forward(request(64), "up"), Forwarded::Dropped(DropReason::NoRoute));
+
+    let (links, delivered) = path.carry(request(64), true).unwrap();
+    assert_eq!(links, [vec![100], vec![200], vec![]]);
+    // four routers, four hops, whether counted on the label or on the IP header
+    assert_eq!(delivered.to_bytes(), request(60).to_bytes());
+
+    let mut reply = request(64);
+    reply.set_source(v4("192.0.2.80"), 80);
+    reply.set_destination(v4("10.0.0.10"), 40000);
+    let (links, delivered) = path.carry(reply, false).unwrap();
+    assert_eq!(links, [vec![300], vec![400], vec![]]);
+    assert_eq!(delivered.destination_ip(), v4("10.0.0.10"));
+
+    // a label nobody handed out, and a TTL running out in the middle
+    let mut stray = LabeledPacket::new(request(64));
+    stray.push(999);
+    assert_eq!(path.routers[2].switch(stray, "up"), Switched::Routed(Forwarded::Dropped(DropReason::UnknownLabel(999))));
+    assert_eq!(path.carry(request(3), true), None);
+}
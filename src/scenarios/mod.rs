@@ -0,0 +1,12 @@
+//! Small stories built out of the other modules, each one showing a single idea end to end.
+pub mod anycast;
+pub mod captive_portal;
+pub mod double_nat;
+pub mod geo_dns;
+pub mod gre_vpn;
+pub mod hole_punch;
+pub mod label_switched_path;
+pub mod nat64;
+pub mod ping;
+pub mod six_in_four;
+pub mod traceroute;
@@ -0,0 +1,157 @@
+//! An IPv6-only network reaching an IPv4-only server, with DNS64 and NAT64.
+//! The client asks for the server's AAAA record; there is none, so the DNS64 server makes one
+//! up by putting the IPv4 address in the last 32 bits of the well-known prefix 64:ff9b::/96
+//! (RFC 6052). The client sends to that address like to any other, the route for the prefix
+//! leads to the NAT64 box, which takes the IPv4 address back out, gives the flow a port on its
+//! public IPv4 address and sends it on as an IPv4 packet. Replies go the opposite way.
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
+
+use crate::nat_v4::{NatError, NatTableV4};
+use crate::packet::ipv4::Ipv4Header;
+use crate::packet::ipv6::Ipv6Header;
+use crate::packet::layered::{IpHeader, LayeredPacket};
+use crate::packet::Packet;
+
+pub const WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+pub fn synthesize(v4: Ipv4Addr) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(WELL_KNOWN_PREFIX) | u32::from(v4) as u128)
+}
+
+// the IPv4 address inside a synthesized one, None if it is not in the prefix
+pub fn extract(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v6 = u128::from(v6);
+    (v6 >> 32 == u128::from(WELL_KNOWN_PREFIX) >> 32).then_some(Ipv4Addr::from(v6 as u32))
+}
+
+#[derive(Debug, Default)]
+pub struct Dns64 {
+    pub a_records : Vec<(String, Ipv4Addr)>,
+    pub aaaa_records : Vec<(String, Ipv6Addr)>,
+}
+
+impl Dns64 {
+    // a real AAAA record if there is one, otherwise one made from the A record
+    pub fn resolve_aaaa(&self, name: &str) -> Option<Ipv6Addr> {
+        let native = self.aaaa_records.iter().find(|(record, _)| record == name).map(|&(_, ip)| ip);
+        native.or_else(|| self.a_records
+            .iter()
+            .find(|(record, _)| record == name)
+            .map(|&(_, ip)| synthesize(ip)))
+    }
+}
+
+// The same transport header and payload under an IP header of the other version; the
+// checksums are worked out again when it is written out, with the new addresses in them
+fn readdress<F: IpHeader, T: IpHeader>(packet: LayeredPacket<F>, source: T::Address, destination: T::Address) -> LayeredPacket<T> {
+    let mut network = T::between(source, destination);
+    network.set_hop_limit(packet.hop_limit());
+    network.set_traffic_class(packet.traffic_class());
+    LayeredPacket::new(network, packet.transport, packet.payload)
+}
+
+// The NAT64 box is two things glued together, like TAYGA with the kernel's NAT: every IPv6
+// client gets a stand-in IPv4 address of its own (numbered from STAND_IN_POOL, the client's index
+// is the "computer" of the NAT), which makes its packets plain IPv4 ones, and then an ordinary
+// NAT table puts them behind the public address, ports, timeouts and all.
+pub const STAND_IN_POOL: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 0);
+
+#[derive(Debug)]
+pub struct Nat64 {
+    pub nat : NatTableV4,
+    pub clients : Vec<Ipv6Addr>,
+}
+
+impl Nat64 {
+    pub fn new(public: Ipv4Addr) -> Self {
+        let mut nat = NatTableV4::new("NAT64", public);
+        nat.port_range = 1024..=u16::MAX;
+        Nat64 { nat, clients : vec![] }
+    }
+
+    // the client's index, and the stand-in address that goes with it
+    fn stand_in(&mut self, client: Ipv6Addr) -> Result<(u16, Ipv4Addr), NatError> {
+        let index = match self.clients.iter().position(|&known| known == client) {
+            Some(index) => index,
+            None => {
+                self.clients.push(client);
+                self.clients.len() - 1
+            }
+        };
+        let computer = u16::try_from(index).map_err(|_| NatError::QuotaExceeded)?;
+        Ok((computer, Ipv4Addr::from(u32::from(STAND_IN_POOL) + index as u32)))
+    }
+
+    // IPv6 in, IPv4 out
+    pub fn outbound(&mut self, packet: LayeredPacket<Ipv6Header>, now: Instant) -> Result<LayeredPacket<Ipv4Header>, NatError> {
+        let destination_ip = extract(packet.destination_ip()).ok_or(NatError::NoMapping)?;
+        let (computer, stand_in) = self.stand_in(packet.source_ip())?;
+        self.nat.translate_outgoing_at(readdress(packet, stand_in, destination_ip), computer, now)
+    }
+
+    // IPv4 in, IPv6 out; the IPv4 source becomes a synthesized address again
+    pub fn inbound(&mut self, packet: LayeredPacket<Ipv4Header>, now: Instant) -> Result<LayeredPacket<Ipv6Header>, NatError> {
+        let (packet, computer) = self.nat.translate_incoming_at(packet, now)?;
+        let client = *self.clients.get(computer as usize).ok_or(NatError::NoMapping)?;
+        let source = synthesize(packet.source_ip());
+        Ok(readdress(packet, source, client))
+    }
+}
+
+#[test]
+fn v6_only_client_reaches_v4_only_server() {
+    use std::time::Duration;
+
+    use crate::packet::builder::PacketBuilder;
+    use crate::trace::PacketTrace;
+
+    let server: Ipv4Addr = "93.184.216.34".parse().unwrap();
+    let client: Ipv6Addr = "2001:db8:1::10".parse().unwrap();
+    let mut dns = Dns64::default();
+    dns.a_records.push(("legacy.example".to_string(), server));
+    dns.aaaa_records.push(("modern.example".to_string(), "2001:db8:2::80".parse().unwrap()));
+    let mut nat64 = Nat64::new("203.0.113.64".parse().unwrap());
+    let mut trace = PacketTrace::new();
+    let now = Instant::now();
+
+    // only an A record, so the AAAA is synthesized; a real AAAA is given as it is
+    let synthesized = dns.resolve_aaaa("legacy.example").unwrap();
+    assert_eq!(synthesized, "64:ff9b::5db8:d822".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(dns.resolve_aaaa("modern.example"), Some("2001:db8:2::80".parse().unwrap()));
+
+    let request_from = |client: Ipv6Addr| {
+        PacketBuilder::ipv6().src(client).dst(synthesized).tcp().sport(50000).dport(80).payload(b"GET / HTTP/1.1\r\nHost: legacy.example\r\n\r\n").build()
+    };
+    let request = request_from(client);
+    let translated = nat64.outbound(request.clone(), now).unwrap();
+    trace.record("client", "NAT64", &request);
+    trace.record("NAT64", "server", &request).rewritten = Some((translated.source(), translated.destination()));
+    assert_eq!((translated.source_ip(), translated.source_port(), translated.destination_ip()), (nat64.nat.translated_addr, 1024, server));
+    // it is a real IPv4 packet now, and the TCP checksum was redone over the new addresses
+    assert_eq!(LayeredPacket::<Ipv4Header>::from_bytes(&translated.to_bytes()).map(|read| read.payload), Ok(request.payload.clone()));
+
+    let mut reply = translated.clone();
+    reply.set_source(translated.destination_ip(), translated.destination_port());
+    reply.set_destination(translated.source_ip(), translated.source_port());
+    reply.payload = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+    let back = nat64.inbound(reply.clone(), now).unwrap();
+    trace.record("server", "NAT64", &reply).rewritten = Some((back.source(), back.destination()));
+    trace.record("NAT64", "client", &back);
+    assert_eq!((back.source_ip(), back.destination_ip(), back.destination_port()), (synthesized, client, 50000));
+
+    // a session that timed out (even an established TCP one lasts only days) gives its port
+    // back, and the next one gets it again
+    let later = now + nat64.nat.timeouts.tcp_established + Duration::from_secs(1);
+    nat64.nat.expire_at(later);
+    let again = request_from("2001:db8:1::11".parse().unwrap());
+    assert_eq!(nat64.outbound(again, later).unwrap().source_port(), 1024);
+    // and the last port of all is handed out like any other
+    nat64.nat.port_range = u16::MAX..=u16::MAX;
+    assert_eq!(nat64.outbound(request.clone(), later).unwrap().source_port(), u16::MAX);
+
+    let diagram = trace.to_mermaid(&["client", "NAT64", "server"]);
+    println!("{diagram}");
+    assert!(diagram.contains("[NAT 203.0.113.64:1024 → 93.184.216.34:80]"));
+    assert!(diagram.contains("[NAT [64:ff9b::5db8:d822]:80 → [2001:db8:1::10]:50000]"));
+}
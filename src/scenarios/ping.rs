@@ -0,0 +1,263 @@
+//! Ping: an ICMP echo request to an address, and the echo reply it sends back, timed. The
+//! routers on the way forward both like any other packet; the time it took is the time on the
+//! links both ways, a little for putting the bytes on each wire and the rest for the signal to
+//! get to the other end. It runs on the virtual clock, one probe a second like ping does, so
+//! the times come out the same every run.
+//!
+//! The routers are attached to the nodes of a topology, and the packets go between them over
+//! its links; each of those has a queue both ways here, to time what crosses it.
+use std::fmt::{self, Display, Write};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use crate::clock::VirtualClock;
+use crate::packet::builder::PacketBuilder;
+use crate::packet::icmp::IcmpMessage;
+use crate::packet::layered::{IpHeader, LayeredPacket, Transport};
+use crate::packet::Packet;
+use crate::router::{Forwarded, Router, RouterAddress, RouterInterface, DEFAULT_HOP_LIMIT};
+use crate::routing::Route;
+use crate::topology::{LinkId, NodeId, Topology};
+use crate::traffic::Link;
+
+// what ping sends by default: 56 bytes of data, and a probe every second
+pub const PAYLOAD_LEN: usize = 56;
+pub const INTERVAL: Duration = Duration::from_secs(1);
+// 100 Mbps everywhere, with room for plenty of pings
+const BANDWIDTH: u64 = 100_000_000;
+const QUEUE: usize = 1 << 20;
+
+// A host on a router's interface, with its own link to it: up towards the router, and down
+#[derive(Debug, Clone)]
+pub struct PingHost<A> {
+    pub name : String,
+    pub address : A,
+    pub router : NodeId,
+    pub interface : String,
+    pub up : Link,
+    pub down : Link,
+}
+
+// where a packet ended up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Endpoint {
+    Host(usize),
+    Router(NodeId),
+}
+
+#[derive(Debug)]
+pub struct PingNetwork<A = Ipv4Addr> {
+    pub topology : Topology,
+    // both directions of every link between two routers: a to b, then b to a
+    pub links : Vec<(LinkId, Link, Link)>,
+    pub hosts : Vec<PingHost<A>>,
+    pub clock : VirtualClock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoReply<A> {
+    pub from : A,
+    pub sequence : u16,
+    pub bytes : usize,
+    // of the reply when it got back
+    pub ttl : u8,
+    pub rtt : Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingReport<A> {
+    pub destination : A,
+    // one for every probe, None for the ones that got no answer
+    pub replies : Vec<Option<EchoReply<A>>>,
+}
+
+impl<A> PingReport<A> {
+    pub fn received(&self) -> usize {
+        self.replies.iter().flatten().count()
+    }
+
+    pub fn loss_percent(&self) -> usize {
+        (self.replies.len() - self.received()) * 100 / self.replies.len().max(1)
+    }
+
+    // min, average, max, of the probes that got an answer
+    pub fn rtt(&self) -> Option<(Duration, Duration, Duration)> {
+        let rtts = self.replies.iter().flatten().map(|reply| reply.rtt);
+        let received = self.received() as u32;
+        (received > 0).then(|| (rtts.clone().min().unwrap(), rtts.clone().sum::<Duration>() / received, rtts.max().unwrap()))
+    }
+}
+
+// what ping prints, in milliseconds with 3 decimals
+impl<A: Display> Display for PingReport<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |rtt: Duration| format!("{:.3}", rtt.as_secs_f64() * 1000.0);
+        let mut out = String::new();
+        for reply in self.replies.iter().flatten() {
+            let _ = writeln!(out, "{} bytes from {}: icmp_seq={} ttl={} time={} ms", reply.bytes, reply.from, reply.sequence, reply.ttl, ms(reply.rtt));
+        }
+        let _ = writeln!(out, "--- {} ping statistics ---", self.destination);
+        let _ = writeln!(out, "{} packets transmitted, {} received, {}% packet loss", self.replies.len(), self.received(), self.loss_percent());
+        if let Some((min, avg, max)) = self.rtt() {
+            let _ = writeln!(out, "rtt min/avg/max = {}/{}/{} ms", ms(min), ms(avg), ms(max));
+        }
+        write!(f, "{out}")
+    }
+}
+
+impl<A: RouterAddress> PingNetwork<A> {
+    pub fn new(topology: Topology) -> Self {
+        PingNetwork { topology, links : vec![], hosts : vec![], clock : VirtualClock::new() }
+    }
+
+    fn link(delay: Duration) -> Link {
+        Link::new(BANDWIDTH, delay, QUEUE).expect("BANDWIDTH isn't zero")
+    }
+
+    // a link between two routers of the topology, this long one way; None if the topology
+    // wouldn't have it
+    pub fn connect(&mut self, a: NodeId, a_interface: &str, b: NodeId, b_interface: &str, delay: Duration) -> Option<LinkId> {
+        let link = self.topology.connect(a, a_interface, b, b_interface)?;
+        self.topology.link_mut(link)?.delay = delay;
+        self.links.push((link, Self::link(delay), Self::link(delay)));
+        Some(link)
+    }
+
+    pub fn add_host(&mut self, name: &str, address: A, router: NodeId, interface: &str, delay: Duration) {
+        let link = Self::link(delay);
+        self.hosts.push(PingHost { name : name.to_string(), address, router, interface : interface.to_string(), up : link.clone(), down : link });
+    }
+
+    // From what a router did with a packet at this time, on from router to router until it
+    // gets to a host or to a router it is for. When it got there, or None if it got lost.
+    fn follow(&mut self, mut forwarded: Forwarded<LayeredPacket<A::Header>>, mut router: NodeId, mut now: Duration) -> Option<(Endpoint, LayeredPacket<A::Header>, Duration)> {
+        loop {
+            let (interface, packet) = match forwarded {
+                Forwarded::Out { interface, packet, .. } | Forwarded::Icmp { interface, packet, .. } => (interface, packet),
+                Forwarded::Local(packet) => return Some((Endpoint::Router(router), packet, now)),
+                Forwarded::Replicated(_) | Forwarded::Dropped(_) => return None,
+            };
+            // the whole packet on the wire, headers and all, is what takes time to send
+            let size = packet.to_bytes().len();
+            let to_router = self.topology.peer_of(router, &interface).filter(|(next, _)| self.topology.router(*next).is_some());
+            let Some((next, ingress)) = to_router else {
+                let host = self.hosts.iter().position(|host| (host.router, host.interface.as_str(), host.address) == (router, &interface, packet.destination_ip()))?;
+                now = self.hosts[host].down.send(size, now).ok()?;
+                return Some((Endpoint::Host(host), packet, now));
+            };
+            let link = self.topology.link_at(router, &interface)?;
+            let a_to_b = self.topology.link(link)?.a == (router, interface);
+            let (_, forward, backward) = self.links.iter_mut().find(|(timed, ..)| *timed == link)?;
+            now = if a_to_b { forward } else { backward }.send(size, now).ok()?;
+            router = next;
+            forwarded = self.topology.router_mut(router)?.forward(packet, &ingress);
+        }
+    }
+
+    fn send_from(&mut self, host: usize, packet: LayeredPacket<A::Header>, now: Duration) -> Option<(Endpoint, LayeredPacket<A::Header>, Duration)> {
+        let now = self.hosts[host].up.send(packet.to_bytes().len(), now).ok()?;
+        let (router, interface) = (self.hosts[host].router, self.hosts[host].interface.clone());
+        let forwarded = self.topology.router_mut(router)?.forward(packet, &interface);
+        self.follow(forwarded, router, now)
+    }
+
+    // One echo request out and, with luck, its reply back. Whoever it is for, host or router,
+    // answers with the same identifier and data.
+    fn probe(&mut self, source: usize, request: LayeredPacket<A::Header>, now: Duration) -> Option<(LayeredPacket<A::Header>, Duration)> {
+        let (endpoint, arrived, at) = self.send_from(source, request, now)?;
+        let Transport::Icmp(IcmpMessage::EchoRequest { identifier, sequence, data }) = arrived.transport else {
+            return None;
+        };
+        let mut network = A::Header::between(arrived.network.destination(), arrived.network.source());
+        network.set_hop_limit(DEFAULT_HOP_LIMIT);
+        let reply = LayeredPacket::new(network, Transport::Icmp(IcmpMessage::EchoReply { identifier, sequence, data }), vec![]);
+        let (back_at, reply, at) = match endpoint {
+            Endpoint::Host(host) => self.send_from(host, reply, at)?,
+            Endpoint::Router(router) => {
+                let forwarded = self.topology.router_mut(router)?.send(reply);
+                self.follow(forwarded, router, at)?
+            }
+        };
+        (back_at == Endpoint::Host(source)).then_some((reply, at))
+    }
+
+    // `ping -c count destination` from the host with that name
+    pub fn ping(&mut self, source: &str, destination: A, count: u16) -> PingReport<A> {
+        let host = self.hosts.iter().position(|host| host.name == source).expect("no host with that name");
+        // like ping, something that tells this run's replies apart from others
+        let identifier = 0x1c00 | host as u16;
+        let mut replies = vec![];
+        for sequence in 1..=count {
+            let data = vec![0x5a; PAYLOAD_LEN];
+            let request = PacketBuilder::<A::Header>::between(self.hosts[host].address, destination)
+                .hop_limit(DEFAULT_HOP_LIMIT)
+                .ping(identifier, sequence)
+                .payload(data.clone())
+                .build();
+            let sent_at = self.clock.elapsed();
+            let echo = IcmpMessage::EchoReply { identifier, sequence, data : data.clone() };
+            let reply = self.probe(host, request, sent_at).filter(|(reply, _)| reply.source_ip() == destination && reply.transport == Transport::Icmp(echo.clone()));
+            replies.push(reply.map(|(reply, at)| EchoReply { from : reply.source_ip(), sequence, bytes : data.len() + 8, ttl : reply.hop_limit(), rtt : at - sent_at }));
+            self.clock.advance(INTERVAL);
+        }
+        PingReport { destination, replies }
+    }
+}
+
+fn v4(text: &str) -> Ipv4Addr {
+    text.parse().unwrap()
+}
+
+// laptop ── 1ms ── R1 ── 10ms ── R2 ── 20ms ── R3 ── 1ms ── server
+impl Default for PingNetwork<Ipv4Addr> {
+    fn default() -> Self {
+        let router = |name: &str, interfaces: [(&str, &str, u8); 2]| {
+            let mut router = Router::new(name);
+            for (interface, address, prefix_len) in interfaces {
+                router.add_interface(RouterInterface::new(interface).with_v4(v4(address), prefix_len));
+            }
+            router
+        };
+        let mut r1 = router("R1", [("lan", "10.0.1.1", 24), ("east", "10.0.12.1", 30)]);
+        let mut r2 = router("R2", [("west", "10.0.12.2", 30), ("east", "10.0.23.1", 30)]);
+        let mut r3 = router("R3", [("west", "10.0.23.2", 30), ("lan", "10.0.3.1", 24)]);
+        r1.routes_v4.add_route(Route::default_route(v4("10.0.12.2"))).unwrap();
+        r2.routes_v4.add_route(Route::with_prefix(v4("10.0.1.0"), 24, v4("10.0.12.1")).unwrap()).unwrap();
+        r2.routes_v4.add_route(Route::with_prefix(v4("10.0.3.0"), 24, v4("10.0.23.2")).unwrap()).unwrap();
+        r3.routes_v4.add_route(Route::default_route(v4("10.0.23.1"))).unwrap();
+
+        let mut topology = Topology::new();
+        let (r1, r2, r3) = (topology.add_router(r1), topology.add_router(r2), topology.add_router(r3));
+        let mut network = PingNetwork::new(topology);
+        network.connect(r1, "east", r2, "west", Duration::from_millis(10));
+        network.connect(r2, "east", r3, "west", Duration::from_millis(20));
+        network.add_host("laptop", v4("10.0.1.10"), r1, "lan", Duration::from_millis(1));
+        network.add_host("server", v4("10.0.3.10"), r3, "lan", Duration::from_millis(1));
+        network
+    }
+}
+
+#[test]
+fn ping_times_the_links_both_ways() {
+    let mut network = PingNetwork::default();
+    let report = network.ping("laptop", v4("10.0.3.10"), 3);
+    assert_eq!(report.received(), 3);
+    // 32ms there and 32ms back, plus 84 bytes (IPv4, ICMP and 56 of data) at 100 Mbps on 8 links
+    let rtt = Duration::from_millis(64) + Duration::from_nanos(8 * 6720);
+    assert_eq!(report.rtt(), Some((rtt, rtt, rtt)));
+    let first = report.replies[0].unwrap();
+    // the server's reply crossed three routers
+    assert_eq!((first.from, first.sequence, first.ttl, first.bytes), (v4("10.0.3.10"), 1, 61, 64));
+    assert_eq!(network.clock.elapsed(), 3 * INTERVAL);
+    assert_eq!(report.to_string().lines().last(), Some("rtt min/avg/max = 64.054/64.054/64.054 ms"));
+    assert!(report.to_string().starts_with("64 bytes from 10.0.3.10: icmp_seq=1 ttl=61 time=64.054 ms\n"));
+
+    // a router answers for its own addresses, after only the first two links
+    let report = network.ping("laptop", v4("10.0.12.2"), 1);
+    assert_eq!(report.replies[0].map(|reply| (reply.rtt, reply.ttl)), Some((Duration::from_millis(22) + Duration::from_nanos(4 * 6720), 63)));
+
+    // nobody has this address: every probe is lost
+    let report = network.ping("server", v4("10.0.1.99"), 2);
+    assert_eq!((report.received(), report.loss_percent(), report.rtt()), (0, 100, None));
+    assert!(report.to_string().ends_with("2 packets transmitted, 0 received, 100% packet loss\n"));
+}
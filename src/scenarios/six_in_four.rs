@@ -0,0 +1,158 @@
+//! Two IPv6 networks, 2001:db8:a::/64 and 2001:db8:b::/64, with an ISP between them that only
+//! has IPv4. Their edge routers have both: an IPv4 address towards the ISP, and a 6in4 tunnel
+//! interface, sit1, with an IPv6 /64 of its own, which the IPv6 route to the other network goes
+//! through. So one packet uses both routing tables of both edges: the IPv6 one sends it into
+//! the tunnel, the IPv4 one sends the wrapped packet to the ISP, and at the other end the IPv4
+//! packet is for the router itself, and the IPv6 packet inside is routed on to its network.
+//!
+//! ```text
+//! 2001:db8:a::/64 ── edge-a ══════ sit1 (2001:db8:ffff::/64) ══════ edge-b ── 2001:db8:b::/64
+//!                    198.51.100.2 ── isp (IPv4 only) ── 203.0.113.2
+//! ```
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::packet::ipv4::Ipv4Header;
+use crate::packet::ipv6::Ipv6Header;
+use crate::packet::layered::{LayeredPacket, Transport};
+use crate::router::{Forwarded, Router, RouterInterface};
+use crate::routing::{Interface, Route};
+use crate::tunnel::SixInFour;
+
+// what a dual-stack router sent on, in whichever version it went out
+#[derive(Debug, Clone, PartialEq)]
+pub enum Routed {
+    V4(Forwarded<LayeredPacket<Ipv4Header>>),
+    V6(Forwarded<LayeredPacket<Ipv6Header>>),
+}
+
+#[derive(Debug)]
+pub struct SixInFourEnd {
+    pub router : Router,
+    pub tunnel : SixInFour,
+}
+
+impl SixInFourEnd {
+    pub fn new(router: Router, tunnel: SixInFour) -> Self {
+        SixInFourEnd { router, tunnel }
+    }
+
+    // an IPv6 packet routed into the tunnel comes out as an IPv4 one to the other end
+    pub fn receive_v6(&mut self, packet: LayeredPacket<Ipv6Header>, ingress: &str) -> Routed {
+        match self.router.forward(packet, ingress) {
+            Forwarded::Out { interface, packet, .. } if interface == self.tunnel.name => Routed::V4(self.router.send(self.tunnel.encapsulate(&packet))),
+            other => Routed::V6(other),
+        }
+    }
+
+    // and the IPv6 packet in one from the other end is routed as if it came in on the tunnel
+    pub fn receive_v4(&mut self, packet: LayeredPacket<Ipv4Header>, ingress: &str) -> Routed {
+        match self.router.forward(packet, ingress) {
+            Forwarded::Local(packet) if packet.transport == Transport::Ipv6 => match self.tunnel.decapsulate(&packet) {
+                Ok(inner) => {
+                    let tunnel = self.tunnel.name.clone();
+                    self.receive_v6(inner, &tunnel)
+                }
+                Err(_) => Routed::V4(Forwarded::Local(packet)),
+            },
+            other => Routed::V4(other),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SixInFourPath {
+    pub edge_a : SixInFourEnd,
+    pub isp : Router,
+    pub edge_b : SixInFourEnd,
+}
+
+fn v4(text: &str) -> Ipv4Addr {
+    text.parse().unwrap()
+}
+
+fn v6(text: &str) -> Ipv6Addr {
+    text.parse().unwrap()
+}
+
+// an edge router: its IPv6 network, its IPv4 link to the ISP, and its end of the tunnel
+fn edge(name: &str, lan: &str, wan: &str, isp: &str, tunnel: (&str, &str), other_lan: &str, remote: &str) -> SixInFourEnd {
+    let mut router = Router::new(name);
+    router.add_interface(RouterInterface::new("lan").with_v6(v6(lan), 64));
+    router.add_interface(RouterInterface::new("wan").with_v4(v4(wan), 30));
+    router.add_interface(RouterInterface::new("sit1").with_v6(v6(tunnel.0), 64));
+    router.routes_v4.add_route(Route::default_route(v4(isp))).unwrap();
+    router.routes_v6.add_route(Route::with_prefix(v6(other_lan), 64, Interface::IpAddr(v6(tunnel.1))).unwrap()).unwrap();
+    SixInFourEnd::new(router, SixInFour::new("sit1", v4(wan), v4(remote)))
+}
+
+impl Default for SixInFourPath {
+    fn default() -> Self {
+        let mut isp = Router::new("isp");
+        isp.add_interface(RouterInterface::new("edge-a").with_v4(v4("198.51.100.1"), 30));
+        isp.add_interface(RouterInterface::new("edge-b").with_v4(v4("203.0.113.1"), 30));
+        SixInFourPath {
+            edge_a : edge("edge-a", "2001:db8:a::1", "198.51.100.2", "198.51.100.1", ("2001:db8:ffff::1", "2001:db8:ffff::2"), "2001:db8:b::", "203.0.113.2"),
+            isp,
+            edge_b : edge("edge-b", "2001:db8:b::1", "203.0.113.2", "203.0.113.1", ("2001:db8:ffff::2", "2001:db8:ffff::1"), "2001:db8:a::", "198.51.100.2"),
+        }
+    }
+}
+
+impl SixInFourPath {
+    // From a host on A's network to one on B's: the IPv4 packet the ISP carried, and the IPv6
+    // packet that came out on B's network. None if it didn't get there.
+    pub fn a_to_b(&mut self, packet: LayeredPacket<Ipv6Header>) -> Option<(LayeredPacket<Ipv4Header>, LayeredPacket<Ipv6Header>)> {
+        carry(&mut self.edge_a, &mut self.isp, "edge-a", &mut self.edge_b, packet)
+    }
+
+    pub fn b_to_a(&mut self, packet: LayeredPacket<Ipv6Header>) -> Option<(LayeredPacket<Ipv4Header>, LayeredPacket<Ipv6Header>)> {
+        carry(&mut self.edge_b, &mut self.isp, "edge-b", &mut self.edge_a, packet)
+    }
+}
+
+fn carry(from: &mut SixInFourEnd, isp: &mut Router, isp_ingress: &str, to: &mut SixInFourEnd, packet: LayeredPacket<Ipv6Header>) -> Option<(LayeredPacket<Ipv4Header>, LayeredPacket<Ipv6Header>)> {
+    let Routed::V4(Forwarded::Out { packet, .. }) = from.receive_v6(packet, "lan") else {
+        return None;
+    };
+    let Forwarded::Out { packet : carried, .. } = isp.forward(packet, isp_ingress) else {
+        return None;
+    };
+    match to.receive_v4(carried.clone(), "wan") {
+        Routed::V6(Forwarded::Out { interface, packet, .. }) if interface == "lan" => Some((carried, packet)),
+        _ => None,
+    }
+}
+
+#[test]
+fn ipv6_crosses_an_ipv4_only_core() {
+    use crate::nat_v4::Protocol;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::Packet;
+    use crate::router::DropReason;
+
+    let mut path = SixInFourPath::default();
+    let request = PacketBuilder::ipv6().src(v6("2001:db8:a::10")).dst(v6("2001:db8:b::20")).tcp().sport(50000).dport(443).payload(b"GET / HTTP/1.1").build();
+    // the ISP has no IPv6 routes at all
+    assert_eq!(path.isp.forward(request.clone(), "edge-a"), Forwarded::Dropped(DropReason::NoRoute));
+
+    let (carried, delivered) = path.a_to_b(request.clone()).unwrap();
+    // to the ISP it was IPv4 between the two edges, protocol 41, with the IPv6 packet inside
+    assert_eq!((carried.protocol(), carried.source_ip(), carried.destination_ip(), carried.hop_limit()), (Protocol::Ipv6, v4("198.51.100.2"), v4("203.0.113.2"), 63));
+    assert_eq!(carried.payload[0] >> 4, 6);
+    assert_eq!(carried.payload[24..40], v6("2001:db8:b::20").octets());
+    let mut expected = request.clone();
+    expected.set_hop_limit(62);
+    assert_eq!(delivered.to_bytes(), expected.to_bytes());
+
+    let mut reply = request.clone();
+    reply.set_source(request.destination_ip(), 443);
+    reply.set_destination(request.source_ip(), 50000);
+    reply.payload = b"HTTP/1.1 200 OK".to_vec();
+    let (carried, delivered) = path.b_to_a(reply.clone()).unwrap();
+    assert_eq!(carried.source_ip(), v4("203.0.113.2"));
+    assert_eq!((delivered.destination_ip(), delivered.payload), (reply.destination_ip(), reply.payload));
+
+    // plain IPv4 for the edge router itself isn't taken for the tunnel
+    let hello = PacketBuilder::ipv4().src(v4("198.51.100.1")).dst(v4("198.51.100.2")).udp().sport(1).dport(2).build();
+    assert!(matches!(path.edge_a.receive_v4(hello, "wan"), Routed::V4(Forwarded::Local(_))));
+}
@@ -0,0 +1,104 @@
+//! Traceroute: a router sends nothing back when it forwards a packet, but it does when it drops
+//! one for running out of hops. So the source sends a probe with a hop limit of 1, and the first
+//! router answers with a Time Exceeded; a probe with 2 gets one from the second router, and so
+//! on, until a probe gets all the way to the destination. Each answer comes from the interface
+//! the probe came in on, and travels back through the same routers like any other packet.
+//! A router that answers nothing (or whose answer gets lost) is a "*".
+//!
+//! The routers are the ones attached to the nodes of a topology, and the probes go from router
+//! to router over its links (see Topology::carry).
+use crate::packet::builder::PacketBuilder;
+use crate::packet::icmp::IcmpMessage;
+use crate::packet::layered::{IpHeader, LayeredPacket, Transport};
+use crate::packet::Packet;
+use crate::router::RouterAddress;
+use crate::topology::{NodeId, Topology};
+
+// where traceroute's UDP probes start, one port higher for each of them
+pub const FIRST_PROBE_PORT: u16 = 33434;
+
+// An ICMP error quotes the start of the packet it is about, which for a probe ends with its UDP
+// header; the ports in it say which probe it was
+fn is_about<H: IpHeader>(error: &LayeredPacket<H>, probe: &LayeredPacket<H>) -> bool {
+    let Transport::Icmp(IcmpMessage::TimeExceeded { original, .. }) = &error.transport else {
+        return false;
+    };
+    let ports = [probe.source_port(), probe.destination_port()].map(u16::to_be_bytes).concat();
+    original.len() >= 8 && original[original.len() - 8..].starts_with(&ports)
+}
+
+// The address that answered each probe, in order; None for a probe nobody answered.
+// The source is a host on the ingress interface of the router at the first node.
+pub fn traceroute<A: RouterAddress>(topology: &mut Topology, first: NodeId, ingress: &str, source: A, destination: A, max_hops: u8) -> Vec<Option<A>> {
+    let mut hops = vec![];
+    for hop_limit in 1..=max_hops {
+        let probe = PacketBuilder::<A::Header>::between(source, destination)
+            .hop_limit(hop_limit)
+            .udp()
+            .sport(FIRST_PROBE_PORT)
+            .dport(FIRST_PROBE_PORT + hop_limit as u16)
+            .build();
+        match topology.carry(probe.clone(), first, ingress) {
+            Some((_, _, packet)) if packet.destination_ip() == destination => {
+                hops.push(Some(destination));
+                break;
+            }
+            // an ICMP error for this very probe, on its way back to the source
+            Some((_, _, packet)) if packet.destination_ip() == source && is_about(&packet, &probe) => {
+                hops.push(Some(packet.source_ip()));
+            }
+            _ => hops.push(None),
+        }
+    }
+    hops
+}
+
+#[test]
+fn every_router_on_the_way_shows_up_once() {
+    use crate::router::{Router, RouterInterface};
+    use crate::routing::{Interface, Route};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // host -- lan R1 east -- west R2 east -- west R3 lan -- server, with both IP versions
+    let v4 = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let v6 = |text: &str| text.parse::<Ipv6Addr>().unwrap();
+    let interface = |name: &str, net: &str, last: u8| RouterInterface::new(name)
+        .with_v4(v4(&format!("10.0.{net}.{last}")), 24)
+        .with_v6(v6(&format!("2001:db8:{net}::{last}")), 64);
+    let router = |name: &str, interfaces: [RouterInterface; 2]| {
+        let mut router = Router::new(name);
+        interfaces.into_iter().for_each(|interface| router.add_interface(interface));
+        router
+    };
+    let mut topology = Topology::new();
+    let r1 = topology.add_router(router("R1", [interface("lan", "1", 1), interface("east", "12", 1)]));
+    let r2 = topology.add_router(router("R2", [interface("west", "12", 2), interface("east", "23", 1)]));
+    let r3 = topology.add_router(router("R3", [interface("west", "23", 2), interface("lan", "3", 1)]));
+    topology.connect(r1, "east", r2, "west").unwrap();
+    topology.connect(r2, "east", r3, "west").unwrap();
+    // to 10.0.{net}.0/24 and 2001:db8:{net}::/64, or everywhere without a net
+    let mut route = |router: NodeId, net: Option<&str>, via: &str| {
+        let (gateway_v4, gateway_v6) = (v4(&format!("10.0.{via}")), Interface::IpAddr(v6(&format!("2001:db8:{}", via.replace('.', "::")))));
+        let router = topology.router_mut(router).unwrap();
+        let (route_v4, route_v6) = match net {
+            Some(net) => (Route::with_prefix(v4(&format!("10.0.{net}.0")), 24, gateway_v4).unwrap(), Route::with_prefix(v6(&format!("2001:db8:{net}::")), 64, gateway_v6).unwrap()),
+            None => (Route::default_route(gateway_v4), Route::default_route(gateway_v6)),
+        };
+        router.routes_v4.add_route(route_v4).unwrap();
+        router.routes_v6.add_route(route_v6).unwrap();
+    };
+    route(r1, None, "12.2");
+    route(r2, Some("1"), "12.1");
+    route(r2, Some("3"), "23.2");
+    route(r3, None, "23.1");
+
+    let hops = traceroute(&mut topology, r1, "lan", v4("10.0.1.10"), v4("10.0.3.10"), 30);
+    assert_eq!(hops, ["10.0.1.1", "10.0.12.2", "10.0.23.2", "10.0.3.10"].map(|hop| Some(v4(hop))));
+    let hops = traceroute(&mut topology, r1, "lan", v6("2001:db8:1::10"), v6("2001:db8:3::10"), 30);
+    assert_eq!(hops, ["2001:db8:1::1", "2001:db8:12::2", "2001:db8:23::2", "2001:db8:3::10"].map(|hop| Some(v6(hop))));
+
+    // R2 forgets the way back: its Time Exceeded never reaches the source, but the others do
+    topology.router_mut(r2).unwrap().routes_v4.remove_route(v4("10.0.1.0"), 24).unwrap();
+    let hops = traceroute(&mut topology, r1, "lan", v4("10.0.1.10"), v4("10.0.3.10"), 3);
+    assert_eq!(hops, vec![Some(v4("10.0.1.1")), None, None]);
+}
@@ -0,0 +1,91 @@
+//! A NAT table that several threads can use at once, like the ingress and egress paths of a
+//! router running on different cores. It is just the NatTable behind an RwLock: translating
+//! changes the table (new mappings, refreshed timers), so it takes the write lock, while
+//! looking at the table only needs the read lock.
+use std::net::Ipv4Addr;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+
+use crate::nat_v4::{NatAddress, NatError, NatTable};
+use crate::packet::Packet;
+
+// Clones share the same table
+#[derive(Debug, Clone)]
+pub struct SharedNatTable<A = Ipv4Addr> {
+    inner : Arc<RwLock<NatTable<A>>>,
+}
+
+impl<A: NatAddress> SharedNatTable<A> {
+    pub fn new(table: NatTable<A>) -> Self {
+        SharedNatTable { inner : Arc::new(RwLock::new(table)) }
+    }
+
+    // a panic in another thread while it held the lock doesn't make the table unusable
+    pub fn read(&self) -> RwLockReadGuard<'_, NatTable<A>> {
+        self.inner.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, NatTable<A>> {
+        self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn translate_outgoing<P: Packet<Address = A>>(&self, packet: P, computer: u16) -> Result<P, NatError> {
+        self.write().translate_outgoing(packet, computer)
+    }
+
+    pub fn translate_incoming<P: Packet<Address = A>>(&self, packet: P) -> Result<(P, u16), NatError> {
+        self.write().translate_incoming(packet)
+    }
+
+    pub fn prune_unnecessary_ports_at(&self, now: Instant) {
+        self.write().prune_unnecessary_ports_at(now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().table.is_empty()
+    }
+}
+
+#[test]
+fn many_threads_translating() {
+    use crate::packet::builder::PacketBuilder;
+
+    let nat = SharedNatTable::new(NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap()));
+    let threads: Vec<_> = (0..4u16)
+        .map(|computer| {
+            let nat = nat.clone();
+            std::thread::spawn(move || {
+                for socket in 0..100u16 {
+                    let packet = PacketBuilder::ipv4()
+                        .src(Ipv4Addr::new(10, 0, 0, computer as u8 + 1))
+                        .dst("192.168.1.1".parse().unwrap())
+                        .udp()
+                        .sport(10000 + socket)
+                        .dport(80)
+                        .payload(format!("computer {computer}, socket {socket}"))
+                        .build();
+                    let outgoing = nat.translate_outgoing(packet.clone(), computer).unwrap();
+                    let mut reply = outgoing.clone();
+                    reply.set_source(outgoing.destination_ip(), outgoing.destination_port());
+                    reply.set_destination(outgoing.source_ip(), outgoing.source_port());
+                    // whatever the other threads did in between, the reply finds its way back
+                    let (incoming, to) = nat.translate_incoming(reply).unwrap();
+                    assert_eq!((incoming.destination_ip(), incoming.destination_port(), to), (packet.source_ip(), packet.source_port(), computer));
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(nat.len(), 400);
+    let mut ports: Vec<u16> = nat.read().table.iter().map(|entry| entry.mangled_port).collect();
+    ports.sort_unstable();
+    ports.dedup();
+    assert_eq!(ports.len(), 400, "two mappings got the same port");
+}
@@ -0,0 +1,406 @@
+//! Stateless address autoconfiguration (SLAAC, RFC 4862): an IPv6 host makes up its own
+//! addresses, without a DHCP server. Routers send Router Advertisements (RFC 4861) to all nodes
+//! now and then, or right away to a host that asks with a Router Solicitation; the RA lists the
+//! /64 prefixes of the link, and the host puts its interface id after each one it may use.
+//!
+//! The interface id comes from the MAC address (modified EUI-64): ff:fe goes in the middle and
+//! the universal/local bit is flipped, so 02:00:00:00:00:0a is ::ff:fe00:a. The same host then
+//! has the same address wherever it goes, which makes it easy to follow around, so with privacy
+//! extensions (RFC 8981) it also makes random temporary addresses that only live a day or two,
+//! and uses those for the connections it starts.
+//!
+//! Each prefix comes with two lifetimes: after the preferred one the address is deprecated (kept
+//! for the connections already using it, but no new ones), after the valid one it is gone.
+use std::net::Ipv6Addr;
+use std::time::{Duration, Instant};
+
+use crate::interface::NetInterface;
+use crate::link::{EthernetFrame, MacAddr, ETHERTYPE_IPV6};
+use crate::ndp::{multicast_mac, NDP_HOP_LIMIT};
+use crate::packet::checksum::PseudoHeader;
+use crate::packet::ipv6::{Ipv6Header, HEADER_LEN};
+use crate::packet::{need, PacketError, IPPROTO_ICMPV6};
+use crate::traffic::SimpleRng;
+
+pub const ROUTER_SOLICITATION: u8 = 133;
+pub const ROUTER_ADVERTISEMENT: u8 = 134;
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+pub const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+const SOURCE_LINK_ADDRESS: u8 = 1;
+const PREFIX_INFORMATION: u8 = 3;
+const MTU: u8 = 5;
+
+// An RA can shorten the valid lifetime of an address to no less than this, unless it ran
+// out anyway, so a stray RA on the link can't take everybody's addresses away at once
+pub const MIN_VALID_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+// how long temporary addresses live at most
+pub const TEMP_PREFERRED_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+pub const TEMP_VALID_LIFETIME: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixInformation {
+    pub prefix : Ipv6Addr,
+    pub prefix_len : u8,
+    // hosts in it are on this link, no need to go through the router
+    pub on_link : bool,
+    // hosts may make addresses in it
+    pub autonomous : bool,
+    // in seconds, u32::MAX for forever
+    pub valid_lifetime : u32,
+    pub preferred_lifetime : u32,
+}
+
+impl PrefixInformation {
+    // a /64 to make addresses in, with the lifetimes radvd uses by default
+    pub fn new(prefix: Ipv6Addr) -> Self {
+        PrefixInformation { prefix, prefix_len : 64, on_link : true, autonomous : true, valid_lifetime : 86400, preferred_lifetime : 14400 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RouterAdvertisement {
+    // what hosts should use for their packets, 0 for no advice
+    pub hop_limit : u8,
+    // the M and O flags: addresses, or only other settings like DNS, come from DHCPv6
+    pub managed : bool,
+    pub other : bool,
+    // how long the router can be the default one, in seconds; 0 for not at all
+    pub router_lifetime : u16,
+    pub source_mac : Option<MacAddr>,
+    pub mtu : Option<u32>,
+    pub prefixes : Vec<PrefixInformation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouterMessage {
+    Solicitation { source_mac : Option<MacAddr> },
+    Advertisement(RouterAdvertisement),
+}
+
+impl RouterMessage {
+    // with a zero checksum, which to_frame() fills in, like NdpMessage
+    pub fn to_icmpv6(&self) -> Vec<u8> {
+        let (mut bytes, source_mac) = match self {
+            RouterMessage::Solicitation { source_mac } => (vec![ROUTER_SOLICITATION, 0, 0, 0, 0, 0, 0, 0], *source_mac),
+            RouterMessage::Advertisement(advertisement) => {
+                let flags = (advertisement.managed as u8) << 7 | (advertisement.other as u8) << 6;
+                let mut bytes = vec![ROUTER_ADVERTISEMENT, 0, 0, 0, advertisement.hop_limit, flags];
+                bytes.extend(advertisement.router_lifetime.to_be_bytes());
+                // reachable time and retransmission timer, left to the hosts
+                bytes.extend([0; 8]);
+                (bytes, advertisement.source_mac)
+            }
+        };
+        if let Some(mac) = source_mac {
+            bytes.extend([SOURCE_LINK_ADDRESS, 1]);
+            bytes.extend(mac.0);
+        }
+        if let RouterMessage::Advertisement(advertisement) = self {
+            if let Some(mtu) = advertisement.mtu {
+                bytes.extend([MTU, 1, 0, 0]);
+                bytes.extend(mtu.to_be_bytes());
+            }
+            for prefix in &advertisement.prefixes {
+                let flags = (prefix.on_link as u8) << 7 | (prefix.autonomous as u8) << 6;
+                bytes.extend([PREFIX_INFORMATION, 4, prefix.prefix_len, flags]);
+                bytes.extend(prefix.valid_lifetime.to_be_bytes());
+                bytes.extend(prefix.preferred_lifetime.to_be_bytes());
+                bytes.extend([0; 4]);
+                bytes.extend(prefix.prefix.octets());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_icmpv6(bytes: &[u8]) -> Result<Self, PacketError> {
+        need(bytes, 8)?;
+        let (mut message, options_at) = match bytes[0] {
+            ROUTER_SOLICITATION => (RouterMessage::Solicitation { source_mac : None }, 8),
+            ROUTER_ADVERTISEMENT => {
+                need(bytes, 16)?;
+                let advertisement = RouterAdvertisement {
+                    hop_limit : bytes[4],
+                    managed : bytes[5] & 0x80 != 0,
+                    other : bytes[5] & 0x40 != 0,
+                    router_lifetime : u16::from_be_bytes([bytes[6], bytes[7]]),
+                    ..RouterAdvertisement::default()
+                };
+                (RouterMessage::Advertisement(advertisement), 16)
+            }
+            other => return Err(PacketError::UnknownType(other)),
+        };
+        let mut options = &bytes[options_at..];
+        while !options.is_empty() {
+            need(options, 2)?;
+            let len = options[1] as usize * 8;
+            if len == 0 {
+                return Err(PacketError::BadLength(0));
+            }
+            need(options, len)?;
+            let word = |at: usize| u32::from_be_bytes(options[at..at + 4].try_into().unwrap());
+            match (&mut message, options[0], len) {
+                (RouterMessage::Solicitation { source_mac }, SOURCE_LINK_ADDRESS, 8) => *source_mac = Some(MacAddr(options[2..8].try_into().unwrap())),
+                (RouterMessage::Advertisement(advertisement), SOURCE_LINK_ADDRESS, 8) => advertisement.source_mac = Some(MacAddr(options[2..8].try_into().unwrap())),
+                (RouterMessage::Advertisement(advertisement), MTU, 8) => advertisement.mtu = Some(word(4)),
+                (RouterMessage::Advertisement(advertisement), PREFIX_INFORMATION, 32) => advertisement.prefixes.push(PrefixInformation {
+                    prefix : Ipv6Addr::from(<[u8; 16]>::try_from(&options[16..32]).unwrap()),
+                    prefix_len : options[2],
+                    on_link : options[3] & 0x80 != 0,
+                    autonomous : options[3] & 0x40 != 0,
+                    valid_lifetime : word(4),
+                    preferred_lifetime : word(8),
+                }),
+                // the ones this crate doesn't know about
+                _ => {}
+            }
+            options = &options[len..];
+        }
+        Ok(message)
+    }
+
+    // A solicitation goes to all routers, an advertisement to all nodes, or back to the one
+    // who asked; `from` is the sender's (MAC, link-local address).
+    pub fn to_frame(&self, from: (MacAddr, Ipv6Addr), to: Ipv6Addr, to_mac: MacAddr) -> EthernetFrame {
+        let mut message = self.to_icmpv6();
+        let _ = PseudoHeader::v6(from.1, to, IPPROTO_ICMPV6, message.len()).fill(&mut message);
+        let mut header = Ipv6Header::new(from.1, to, IPPROTO_ICMPV6, message.len() as u16);
+        header.hop_limit = NDP_HOP_LIMIT;
+        let mut payload = header.to_bytes();
+        payload.extend(message);
+        EthernetFrame::new(to_mac, from.0, ETHERTYPE_IPV6, payload)
+    }
+
+    // An RA from anywhere but a link-local address of a router on this link, or one that
+    // was forwarded here (hop limit below 255), is an attack and not taken.
+    pub fn from_frame(frame: &EthernetFrame) -> Result<(Ipv6Header, Self), PacketError> {
+        let header = Ipv6Header::from_bytes(&frame.payload)?;
+        if header.next_header != IPPROTO_ICMPV6 {
+            return Err(PacketError::UnknownType(header.next_header));
+        }
+        let end = HEADER_LEN + header.payload_length as usize;
+        need(&frame.payload, end)?;
+        let message = &frame.payload[HEADER_LEN..end];
+        PseudoHeader::v6(header.source, header.destination, IPPROTO_ICMPV6, message.len()).verify(message)?;
+        let message = RouterMessage::from_icmpv6(message)?;
+        if matches!(message, RouterMessage::Advertisement(_)) && (header.hop_limit != NDP_HOP_LIMIT || !is_link_local(header.source)) {
+            return Err(PacketError::UnknownType(ROUTER_ADVERTISEMENT));
+        }
+        Ok((header, message))
+    }
+}
+
+pub fn is_link_local(address: Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
+// the modified EUI-64 interface id of a MAC address
+pub fn interface_id(mac: MacAddr) -> [u8; 8] {
+    let [a, b, c, d, e, f] = mac.0;
+    [a ^ 0x02, b, c, 0xff, 0xfe, d, e, f]
+}
+
+// the first 64 bits of the prefix, the interface id after them
+pub fn with_interface_id(prefix: Ipv6Addr, id: [u8; 8]) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&id);
+    Ipv6Addr::from(octets)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlaacAddress {
+    pub address : Ipv6Addr,
+    pub prefix_len : u8,
+    // made up for privacy, rather than from the MAC address
+    pub temporary : bool,
+    pub preferred_until : Instant,
+    pub valid_until : Instant,
+}
+
+impl SlaacAddress {
+    // not deprecated yet, so good for new connections
+    pub fn is_preferred(&self, now: Instant) -> bool {
+        now < self.preferred_until
+    }
+}
+
+// The host side, for one interface
+#[derive(Debug, Clone)]
+pub struct Slaac {
+    pub mac : MacAddr,
+    // Some with privacy extensions on, for the random interface ids
+    pub privacy : Option<SimpleRng>,
+    pub addresses : Vec<SlaacAddress>,
+    // the router to send everything off the link to, and until when
+    pub default_router : Option<(Ipv6Addr, Instant)>,
+    pub mtu : Option<u32>,
+    pub hop_limit : Option<u8>,
+}
+
+fn lifetime(seconds: u32) -> Duration {
+    // forever, or as good as
+    if seconds == u32::MAX { Duration::from_secs(100 * 365 * 24 * 60 * 60) } else { Duration::from_secs(seconds as u64) }
+}
+
+impl Slaac {
+    pub fn new(mac: MacAddr) -> Self {
+        Slaac { mac, privacy : None, addresses : vec![], default_router : None, mtu : None, hop_limit : None }
+    }
+
+    // temporary addresses too, with interface ids from this seed
+    pub fn with_privacy(mut self, seed: u64) -> Self {
+        self.privacy = Some(SimpleRng::new(seed));
+        self
+    }
+
+    // fe80::/64 and the interface id, which every IPv6 interface has before anything else
+    pub fn link_local(&self) -> Ipv6Addr {
+        with_interface_id(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), interface_id(self.mac))
+    }
+
+    // what a host sends when it comes up, instead of waiting for the next RA
+    pub fn solicit(&self) -> EthernetFrame {
+        let message = RouterMessage::Solicitation { source_mac : Some(self.mac) };
+        message.to_frame((self.mac, self.link_local()), ALL_ROUTERS, multicast_mac(ALL_ROUTERS))
+    }
+
+    // Takes in an RA from this router, and gives back the addresses it made new
+    pub fn hear(&mut self, router: Ipv6Addr, advertisement: &RouterAdvertisement, now: Instant) -> Vec<Ipv6Addr> {
+        self.default_router = match advertisement.router_lifetime {
+            0 => self.default_router.filter(|(current, _)| *current != router),
+            seconds => Some((router, now + Duration::from_secs(seconds as u64))),
+        };
+        self.mtu = advertisement.mtu.or(self.mtu);
+        self.hop_limit = Some(advertisement.hop_limit).filter(|&hop_limit| hop_limit != 0).or(self.hop_limit);
+
+        let mut made = vec![];
+        for prefix in &advertisement.prefixes {
+            // only /64s make room for an interface id, and the link-local prefix is never taken
+            if !prefix.autonomous || prefix.prefix_len != 64 || is_link_local(prefix.prefix) || prefix.preferred_lifetime > prefix.valid_lifetime {
+                continue;
+            }
+            let preferred_until = now + lifetime(prefix.preferred_lifetime);
+            let valid_until = now + lifetime(prefix.valid_lifetime);
+            let stable = with_interface_id(prefix.prefix, interface_id(self.mac));
+            match self.addresses.iter_mut().find(|address| address.address == stable) {
+                Some(address) => {
+                    address.preferred_until = preferred_until;
+                    // the two hour rule
+                    let remaining = address.valid_until.saturating_duration_since(now);
+                    if valid_until > now + MIN_VALID_LIFETIME || valid_until > address.valid_until {
+                        address.valid_until = valid_until;
+                    } else if remaining > MIN_VALID_LIFETIME {
+                        address.valid_until = now + MIN_VALID_LIFETIME;
+                    }
+                }
+                None => {
+                    self.addresses.push(SlaacAddress { address : stable, prefix_len : 64, temporary : false, preferred_until, valid_until });
+                    made.push(stable);
+                }
+            }
+            let Some(rng) = self.privacy.as_mut() else {
+                continue;
+            };
+            // a temporary address lives as long as the prefix does, but no longer than its own limits
+            let preferred_until = preferred_until.min(now + TEMP_PREFERRED_LIFETIME);
+            let valid_until = valid_until.min(now + TEMP_VALID_LIFETIME);
+            let in_prefix = |address: &SlaacAddress| address.temporary && address.address.octets()[..8] == prefix.prefix.octets()[..8];
+            match self.addresses.iter_mut().find(|address| in_prefix(address) && address.is_preferred(now)) {
+                Some(address) => {
+                    address.preferred_until = address.preferred_until.min(preferred_until);
+                    address.valid_until = address.valid_until.min(valid_until);
+                }
+                None => {
+                    let address = with_interface_id(prefix.prefix, rng.next_u64().max(1).to_be_bytes());
+                    self.addresses.push(SlaacAddress { address, prefix_len : 64, temporary : true, preferred_until, valid_until });
+                    made.push(address);
+                }
+            }
+        }
+        made
+    }
+
+    // drops the addresses and the router whose time is up
+    pub fn expire(&mut self, now: Instant) -> Vec<SlaacAddress> {
+        let (expired, kept) = self.addresses.iter().partition(|address| address.valid_until <= now);
+        self.addresses = kept;
+        self.default_router = self.default_router.filter(|&(_, until)| until > now);
+        expired
+    }
+
+    // The address new connections to the world go out from: a temporary one when there is
+    // one, like RFC 6724 says, or else the stable one. Never a deprecated one.
+    pub fn source_address(&self, now: Instant) -> Option<Ipv6Addr> {
+        let preferred = self.addresses.iter().filter(|address| address.is_preferred(now));
+        preferred.clone().find(|address| address.temporary).or(preferred.clone().next()).map(|address| address.address)
+    }
+
+    // Puts the addresses on the interface, like the kernel does: the link-local one first,
+    // then the ones from the RAs, in place of the IPv6 addresses it had. IPv4 ones stay.
+    pub fn configure(&self, interface: &mut NetInterface) {
+        interface.addresses.retain(|(address, _)| address.is_ipv4());
+        interface.addresses.push((self.link_local().into(), 64));
+        interface.addresses.extend(self.addresses.iter().map(|address| (address.address.into(), address.prefix_len)));
+    }
+}
+
+#[test]
+fn hosts_make_their_own_addresses() {
+    let router = ("02:00:00:00:00:01".parse().unwrap(), "fe80::1".parse::<Ipv6Addr>().unwrap());
+    let laptop: MacAddr = "02:00:00:00:00:0a".parse().unwrap();
+    let mut host = Slaac::new(laptop).with_privacy(7);
+    let now = Instant::now();
+    assert_eq!(host.link_local(), "fe80::ff:fe00:a".parse::<Ipv6Addr>().unwrap());
+
+    // the host asks, and the router answers everybody
+    let solicitation = host.solicit();
+    assert_eq!(solicitation.destination.to_string(), "33:33:00:00:00:02");
+    let (header, asked) = RouterMessage::from_frame(&solicitation).unwrap();
+    assert_eq!((header.source, asked), (host.link_local(), RouterMessage::Solicitation { source_mac : Some(laptop) }));
+    let advertisement = RouterAdvertisement {
+        hop_limit : 64,
+        router_lifetime : 1800,
+        source_mac : Some(router.0),
+        mtu : Some(1480),
+        prefixes : vec![PrefixInformation::new("2001:db8:1::".parse().unwrap()), PrefixInformation { autonomous : false, ..PrefixInformation::new("2001:db8:2::".parse().unwrap()) }],
+        ..RouterAdvertisement::default()
+    };
+    let frame = RouterMessage::Advertisement(advertisement.clone()).to_frame(router, ALL_NODES, multicast_mac(ALL_NODES));
+    let (header, RouterMessage::Advertisement(heard)) = RouterMessage::from_frame(&frame).unwrap() else {
+        panic!("an advertisement was sent");
+    };
+    assert_eq!(heard, advertisement);
+
+    // a stable address from the MAC, and a random temporary one, only in the prefix that allows it
+    let made = host.hear(header.source, &heard, now);
+    assert_eq!(made[0], "2001:db8:1::ff:fe00:a".parse::<Ipv6Addr>().unwrap());
+    assert_eq!((made.len(), made[1].segments()[..4] == made[0].segments()[..4], made[1] != made[0]), (2, true, true));
+    assert_eq!((host.default_router, host.mtu, host.hop_limit), (Some((router.1, now + Duration::from_secs(1800))), Some(1480), Some(64)));
+    assert_eq!(host.source_address(now), Some(made[1]));
+    let mut eth0 = NetInterface::named("eth0").with_address("192.168.1.10".parse().unwrap(), 24);
+    host.configure(&mut eth0);
+    assert_eq!(eth0.addresses.len(), 4);
+    assert!(eth0.has_address(made[0].into()) && eth0.has_address(host.link_local().into()));
+    // hearing the same RA again makes nothing new
+    assert!(host.hear(header.source, &heard, now).is_empty());
+
+    // a few hours on both are deprecated, and the next RA makes a new temporary address
+    let later = now + Duration::from_secs(14400);
+    assert_eq!(host.source_address(later), None);
+    let renewed = host.hear(header.source, &heard, later);
+    assert_eq!((renewed.len(), host.source_address(later)), (1, Some(renewed[0])));
+    // an RA trying to end the prefix right away only gets it down to two hours
+    let mut short = heard.clone();
+    short.prefixes[0].valid_lifetime = 60;
+    short.prefixes[0].preferred_lifetime = 0;
+    host.hear(header.source, &short, later);
+    assert_eq!(host.addresses[0].valid_until, later + MIN_VALID_LIFETIME);
+    assert_eq!(host.expire(later + MIN_VALID_LIFETIME).len(), 2);
+    assert_eq!(host.expire(now + TEMP_VALID_LIFETIME).len(), 1);
+    assert_eq!((host.addresses.len(), host.default_router), (0, None));
+
+    // a forwarded RA, with its hop limit down by one, isn't believed
+    let mut forwarded = frame;
+    forwarded.payload[7] = NDP_HOP_LIMIT - 1;
+    assert!(RouterMessage::from_frame(&forwarded).is_err());
+}
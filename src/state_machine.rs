@@ -0,0 +1,224 @@
+//! Protocols are mostly state machines, and the picture of the machine is what we draw in class.
+//! A `StateMachine` knows all its transitions and also counts which ones were actually taken while
+//! running, so `to_dot()` draws the full diagram with the paths our traffic used highlighted.
+//!
+//! The TCP, DHCP client, OSPF neighbor, BGP session and RIP route machines are described here;
+//! the protocols drive them with `fire()` as they change state: a tcp_endpoint::TcpEndpoint and
+//! a dhcp::DhcpClient have one each, OSPF and BGP routers one per neighbor, and a RIP router one
+//! per route it learned.
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from : &'static str,
+    pub to : &'static str,
+    pub event : &'static str,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+    pub name : &'static str,
+    pub initial : &'static str,
+    pub current : &'static str,
+    pub transitions : Vec<Transition>,
+    // how many times each transition (same index) was taken
+    pub taken : Vec<usize>,
+}
+
+impl StateMachine {
+    pub fn new(name: &'static str, initial: &'static str, transitions: &[(&'static str, &'static str, &'static str)]) -> Self {
+        let transitions: Vec<Transition> = transitions
+            .iter()
+            .map(|&(from, event, to)| Transition { from, to, event })
+            .collect();
+        StateMachine { name, initial, current : initial, taken : vec![0; transitions.len()], transitions }
+    }
+
+    // moves along the transition for this event, if there is one from the current state
+    pub fn fire(&mut self, event: &str) -> Option<&'static str> {
+        let index = self.transitions
+            .iter()
+            .position(|transition| transition.from == self.current && transition.event == event)?;
+        self.taken[index] += 1;
+        self.current = self.transitions[index].to;
+        Some(self.current)
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    pub fn states(&self) -> Vec<&'static str> {
+        let mut states = vec![self.initial];
+        for transition in &self.transitions {
+            for state in [transition.from, transition.to] {
+                if !states.contains(&state) {
+                    states.push(state);
+                }
+            }
+        }
+        states
+    }
+
+    fn visited(&self, state: &str) -> bool {
+        state == self.initial || self.transitions
+            .iter()
+            .zip(&self.taken)
+            .any(|(transition, &count)| count > 0 && transition.to == state)
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = format!("digraph \"{}\" {{\n    rankdir=LR;\n", self.name);
+        for state in self.states() {
+            let shape = if state == self.initial { "doublecircle" } else { "circle" };
+            let style = if self.visited(state) { ", style=filled, fillcolor=lightblue" } else { "" };
+            let _ = writeln!(out, "    \"{state}\" [shape={shape}{style}];");
+        }
+        for (transition, &count) in self.transitions.iter().zip(&self.taken) {
+            if count > 0 {
+                let _ = writeln!(out, "    \"{}\" -> \"{}\" [label=\"{} ({count})\", color=red, penwidth=2];", transition.from, transition.to, transition.event);
+            } else {
+                let _ = writeln!(out, "    \"{}\" -> \"{}\" [label=\"{}\", color=gray];", transition.from, transition.to, transition.event);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // the connection states from RFC 793
+    pub fn tcp() -> Self {
+        StateMachine::new("tcp", "CLOSED", &[
+            ("CLOSED", "passive open", "LISTEN"),
+            ("CLOSED", "send SYN", "SYN-SENT"),
+            ("LISTEN", "recv SYN, send SYN+ACK", "SYN-RECEIVED"),
+            ("LISTEN", "close", "CLOSED"),
+            ("SYN-SENT", "recv SYN+ACK, send ACK", "ESTABLISHED"),
+            ("SYN-SENT", "recv SYN, send SYN+ACK", "SYN-RECEIVED"),
+            ("SYN-SENT", "close", "CLOSED"),
+            ("SYN-RECEIVED", "recv ACK", "ESTABLISHED"),
+            ("SYN-RECEIVED", "recv RST", "LISTEN"),
+            ("ESTABLISHED", "close, send FIN", "FIN-WAIT-1"),
+            ("ESTABLISHED", "recv FIN, send ACK", "CLOSE-WAIT"),
+            ("FIN-WAIT-1", "recv ACK", "FIN-WAIT-2"),
+            ("FIN-WAIT-1", "recv FIN, send ACK", "CLOSING"),
+            ("FIN-WAIT-2", "recv FIN, send ACK", "TIME-WAIT"),
+            ("CLOSING", "recv ACK", "TIME-WAIT"),
+            ("CLOSE-WAIT", "close, send FIN", "LAST-ACK"),
+            ("LAST-ACK", "recv ACK", "CLOSED"),
+            ("TIME-WAIT", "2MSL timeout", "CLOSED"),
+        ])
+    }
+
+    // the client side of RFC 2131
+    pub fn dhcp_client() -> Self {
+        StateMachine::new("dhcp client", "INIT", &[
+            ("INIT", "send DISCOVER", "SELECTING"),
+            ("SELECTING", "recv OFFER, send REQUEST", "REQUESTING"),
+            ("REQUESTING", "recv ACK", "BOUND"),
+            ("REQUESTING", "recv NAK", "INIT"),
+            ("BOUND", "T1 expires, send REQUEST", "RENEWING"),
+            ("RENEWING", "recv ACK", "BOUND"),
+            ("RENEWING", "T2 expires, broadcast REQUEST", "REBINDING"),
+            ("REBINDING", "recv ACK", "BOUND"),
+            ("REBINDING", "lease expires", "INIT"),
+        ])
+    }
+
+    // what a router thinks of one neighbor, RFC 2328 section 10.3; Full is when their link-state
+    // databases are the same
+    pub fn ospf_neighbor() -> Self {
+        StateMachine::new("ospf neighbor", "Down", &[
+            ("Down", "Start", "Attempt"),
+            ("Down", "HelloReceived", "Init"),
+            ("Attempt", "HelloReceived", "Init"),
+            ("Init", "2-WayReceived", "ExStart"),
+            ("Init", "2-WayReceived, no adjacency", "2-Way"),
+            ("2-Way", "AdjOK", "ExStart"),
+            ("ExStart", "NegotiationDone", "Exchange"),
+            ("Exchange", "ExchangeDone", "Loading"),
+            ("Exchange", "SeqNumberMismatch", "ExStart"),
+            ("Loading", "LoadingDone", "Full"),
+            ("Full", "SeqNumberMismatch", "ExStart"),
+            ("Full", "1-WayReceived", "Init"),
+            ("Init", "InactivityTimer", "Down"),
+            ("2-Way", "InactivityTimer", "Down"),
+            ("ExStart", "InactivityTimer", "Down"),
+            ("Exchange", "InactivityTimer", "Down"),
+            ("Loading", "InactivityTimer", "Down"),
+            ("Full", "InactivityTimer", "Down"),
+        ])
+    }
+
+    // one BGP session, RFC 4271 section 8
+    pub fn bgp() -> Self {
+        StateMachine::new("bgp", "Idle", &[
+            ("Idle", "ManualStart", "Connect"),
+            ("Connect", "TCP connection fails", "Active"),
+            ("Connect", "TCP connected, send OPEN", "OpenSent"),
+            ("Active", "ConnectRetryTimer expires", "Connect"),
+            ("Active", "TCP connected, send OPEN", "OpenSent"),
+            ("OpenSent", "recv OPEN, send KEEPALIVE", "OpenConfirm"),
+            ("OpenSent", "TCP connection fails", "Active"),
+            ("OpenConfirm", "recv KEEPALIVE", "Established"),
+            ("OpenConfirm", "HoldTimer expires", "Idle"),
+            ("OpenConfirm", "recv NOTIFICATION", "Idle"),
+            ("Established", "recv UPDATE or KEEPALIVE", "Established"),
+            ("Established", "HoldTimer expires", "Idle"),
+            ("Established", "recv NOTIFICATION", "Idle"),
+            ("Established", "ManualStop", "Idle"),
+        ])
+    }
+
+    // RIP has no sessions, only the timers of each learned route, RFC 2453 section 3.8
+    pub fn rip_route() -> Self {
+        StateMachine::new("rip route", "Unknown", &[
+            ("Unknown", "recv route", "Valid"),
+            ("Valid", "recv route", "Valid"),
+            ("Valid", "recv metric 16", "Garbage collection"),
+            ("Valid", "timeout", "Garbage collection"),
+            ("Garbage collection", "recv route", "Valid"),
+            ("Garbage collection", "garbage-collection timer", "Unknown"),
+        ])
+    }
+}
+
+#[test]
+fn tcp_handshake_is_highlighted() {
+    let mut tcp = StateMachine::tcp();
+    for event in ["send SYN", "recv SYN+ACK, send ACK", "close, send FIN", "recv ACK"] {
+        assert!(tcp.fire(event).is_some(), "{event} from {}", tcp.current);
+    }
+    assert_eq!(tcp.current, "FIN-WAIT-2");
+    // no such transition from here, so nothing changes
+    assert_eq!(tcp.fire("passive open"), None);
+    assert_eq!(tcp.current, "FIN-WAIT-2");
+
+    let dot = tcp.to_dot();
+    println!("{dot}");
+    assert!(dot.starts_with("digraph \"tcp\" {"));
+    assert!(dot.contains("\"CLOSED\" -> \"SYN-SENT\" [label=\"send SYN (1)\", color=red, penwidth=2];"));
+    assert!(dot.contains("\"CLOSED\" -> \"LISTEN\" [label=\"passive open\", color=gray];"));
+    assert!(dot.contains("\"ESTABLISHED\" [shape=circle, style=filled, fillcolor=lightblue];"));
+    assert!(dot.contains("\"LISTEN\" [shape=circle];"));
+}
+
+#[test]
+fn ospf_adjacencies_are_drawn() {
+    use crate::protocols::ospf::{OspfNetwork, OspfRouter};
+    use std::net::Ipv4Addr;
+
+    let mut network = OspfNetwork::new(vec![
+        OspfRouter::new("R1", Ipv4Addr::new(1, 1, 1, 1)),
+        OspfRouter::new("R2", Ipv4Addr::new(2, 2, 2, 2)),
+    ]);
+    network.connect(0, 1, 10);
+    let r2 = Ipv4Addr::new(2, 2, 2, 2);
+    assert_eq!(network.routers[0].adjacency(r2).map(|machine| machine.current), Some("Full"));
+    network.disconnect(0, 1);
+    let machine = network.routers[0].adjacency(r2).unwrap();
+    assert_eq!(machine.current, "Down");
+    let dot = machine.to_dot();
+    assert!(dot.contains("\"Loading\" -> \"Full\" [label=\"LoadingDone (1)\", color=red, penwidth=2];"));
+    assert!(dot.contains("\"Full\" -> \"Down\" [label=\"InactivityTimer (1)\", color=red, penwidth=2];"));
+    assert!(dot.contains("\"Attempt\" [shape=circle];"));
+}
@@ -0,0 +1,163 @@
+//! Storm control, as found on switch ports.
+//! Broadcast, unknown-unicast and multicast (BUM) frames are flooded out of every port, so a
+//! loop or a misbehaving host can fill the whole LAN with them. Each port gets a rate limit per
+//! kind of traffic; what goes above is dropped and counted, or with err-disable the port is
+//! shut down until someone recovers it, like `storm-control action shutdown` on a Cisco switch.
+use std::time::Instant;
+
+use crate::token_bucket::TokenBucket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficClass {
+    Broadcast,
+    Multicast,
+    // unicast to a destination the switch hasn't learned, so it gets flooded too
+    UnknownUnicast,
+    KnownUnicast,
+}
+
+impl TrafficClass {
+    fn index(self) -> Option<usize> {
+        match self {
+            TrafficClass::Broadcast => Some(0),
+            TrafficClass::Multicast => Some(1),
+            TrafficClass::UnknownUnicast => Some(2),
+            TrafficClass::KnownUnicast => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StormAction {
+    #[default]
+    Drop,
+    ErrDisable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Forward,
+    Dropped,
+    // the port is err-disabled, nothing goes through
+    PortDisabled,
+}
+
+#[derive(Debug, Clone)]
+pub struct PortStormControl {
+    // broadcast, multicast, unknown unicast
+    limits : [Option<TokenBucket>; 3],
+    pub action : StormAction,
+    pub err_disabled : bool,
+    pub dropped : [u64; 3],
+}
+
+impl Default for PortStormControl {
+    fn default() -> Self {
+        PortStormControl { limits : [None, None, None], action : StormAction::Drop, err_disabled : false, dropped : [0; 3] }
+    }
+}
+
+impl PortStormControl {
+    // limit one class of traffic to this many frames per second
+    pub fn limit(&mut self, class: TrafficClass, frames_per_second: f64) {
+        if let Some(index) = class.index() {
+            self.limits[index] = Some(TokenBucket::new(frames_per_second, frames_per_second.max(1.0)));
+        }
+    }
+
+    pub fn dropped(&self, class: TrafficClass) -> u64 {
+        class.index().map_or(0, |index| self.dropped[index])
+    }
+
+    pub fn check(&mut self, class: TrafficClass, now: Instant) -> Verdict {
+        if self.err_disabled {
+            return Verdict::PortDisabled;
+        }
+        let Some(index) = class.index() else {
+            return Verdict::Forward;
+        };
+        let within_limit = self.limits[index]
+            .as_mut()
+            .is_none_or(|bucket| bucket.try_take(now));
+        if within_limit {
+            return Verdict::Forward;
+        }
+        self.dropped[index] += 1;
+        if self.action == StormAction::ErrDisable {
+            self.err_disabled = true;
+            Verdict::PortDisabled
+        } else {
+            Verdict::Dropped
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StormControl {
+    pub ports : Vec<PortStormControl>,
+}
+
+impl StormControl {
+    pub fn new(ports: usize) -> Self {
+        StormControl { ports : vec![PortStormControl::default(); ports] }
+    }
+
+    // None for a port the switch doesn't have
+    pub fn port(&mut self, port: usize) -> Option<&mut PortStormControl> {
+        self.ports.get_mut(port)
+    }
+
+    // a frame of this class came in on this port: can it be forwarded? Not from a port that
+    // doesn't exist
+    pub fn check(&mut self, port: usize, class: TrafficClass, now: Instant) -> Verdict {
+        self.ports.get_mut(port).map_or(Verdict::Dropped, |port| port.check(class, now))
+    }
+
+    // bring an err-disabled port back, like `shutdown` / `no shutdown`
+    pub fn recover(&mut self, port: usize) {
+        if let Some(port) = self.ports.get_mut(port) {
+            port.err_disabled = false;
+        }
+    }
+
+    pub fn disabled_ports(&self) -> Vec<usize> {
+        (0..self.ports.len()).filter(|&port| self.ports[port].err_disabled).collect()
+    }
+}
+
+#[test]
+fn broadcast_storm_is_limited() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let mut storm_control = StormControl::new(4);
+    storm_control.port(1).unwrap().limit(TrafficClass::Broadcast, 100.0);
+    storm_control.port(2).unwrap().limit(TrafficClass::Broadcast, 100.0);
+    storm_control.port(2).unwrap().action = StormAction::ErrDisable;
+    // there are only four
+    assert!(storm_control.port(4).is_none());
+    assert_eq!(storm_control.check(4, TrafficClass::KnownUnicast, start), Verdict::Dropped);
+    storm_control.recover(4);
+
+    // a host sending 1000 broadcasts per second for one second, on ports 0, 1 and 2
+    let mut forwarded = [0; 3];
+    for frame in 0..1000 {
+        let now = start + Duration::from_millis(frame);
+        for (port, count) in forwarded.iter_mut().enumerate() {
+            if storm_control.check(port, TrafficClass::Broadcast, now) == Verdict::Forward {
+                *count += 1;
+            }
+        }
+    }
+    // no limit on port 0; about a 100 go through on port 1
+    assert_eq!(forwarded[0], 1000);
+    assert!((100..=201).contains(&forwarded[1]), "{}", forwarded[1]);
+    assert_eq!(storm_control.ports[1].dropped(TrafficClass::Broadcast), 1000 - forwarded[1] as u64);
+
+    // port 2 shut itself down, so even normal traffic stops until it is recovered
+    assert_eq!(storm_control.disabled_ports(), vec![2]);
+    let later = start + Duration::from_secs(2);
+    assert_eq!(storm_control.check(2, TrafficClass::KnownUnicast, later), Verdict::PortDisabled);
+    storm_control.recover(2);
+    assert_eq!(storm_control.check(2, TrafficClass::KnownUnicast, later), Verdict::Forward);
+}
@@ -0,0 +1,89 @@
+//! STUN, the tiny version: a computer behind a NAT cannot know its public (ip, port) by itself,
+//! so it sends a binding request to a server on the internet, and the server just tells
+//! it back the source it saw. Whether that answer is useful depends on the type of NAT:
+//! behind a symmetric NAT, every server sees a different port.
+use std::net::{Ipv4Addr, SocketAddr};
+
+use crate::nat_v4::{NatTable, NatType};
+use crate::packet::builder::PacketBuilder;
+use crate::packet::layered::LayeredPacket;
+use crate::packet::Packet;
+
+#[derive(Debug, Clone)]
+pub struct StunServer {
+    pub ip : Ipv4Addr,
+    pub port : u16,
+}
+
+impl StunServer {
+    pub fn new(ip: Ipv4Addr, port: u16) -> Self {
+        StunServer { ip, port }
+    }
+
+    pub fn binding_request(&self, client_ip: Ipv4Addr, client_port: u16) -> LayeredPacket {
+        PacketBuilder::ipv4().src(client_ip).dst(self.ip).hop_limit(30).udp().sport(client_port).dport(self.port).payload(b"BINDING-REQUEST").build()
+    }
+
+    // the answer carries the source (ip, port) the server observed, back to that same address
+    pub fn respond(&self, request: &LayeredPacket) -> LayeredPacket {
+        PacketBuilder::ipv4()
+            .src(self.ip)
+            .dst(request.source_ip())
+            .hop_limit(request.hop_limit())
+            .udp()
+            .sport(self.port)
+            .dport(request.source_port())
+            .payload(request.source().to_string())
+            .build()
+    }
+}
+
+// sends a binding request through the NAT and returns the public address the server saw
+pub fn discover_mapping(nat: &mut NatTable, computer: u16, client_ip: Ipv4Addr, client_port: u16, server: &StunServer) -> Option<SocketAddr> {
+    let request = nat.translate_outgoing(server.binding_request(client_ip, client_port), computer).ok()?;
+    let response = server.respond(&request);
+    let (response, reached) = nat.translate_incoming(response).ok()?;
+    if reached != computer || response.destination_port() != client_port {
+        return None;
+    }
+    std::str::from_utf8(&response.payload).ok()?.parse().ok()
+}
+
+// asks two different servers: if both saw the same mapping, it doesn't depend on the destination
+pub fn detect_nat_type(nat: &mut NatTable, computer: u16, client_ip: Ipv4Addr, client_port: u16, first: &StunServer, second: &StunServer) -> Option<NatType> {
+    let first_mapping = discover_mapping(nat, computer, client_ip, client_port, first)?;
+    let second_mapping = discover_mapping(nat, computer, client_ip, client_port, second)?;
+    if first_mapping == second_mapping {
+        Some(NatType::Cone)
+    } else {
+        Some(NatType::Symmetric)
+    }
+}
+
+#[test]
+fn stun_behind_cone_nat() {
+    let mut nat = NatTable::new("Home router", "103.5.150.9".parse().unwrap());
+    let first = StunServer::new("74.125.1.1".parse().unwrap(), 3478);
+    let second = StunServer::new("74.125.2.2".parse().unwrap(), 3478);
+    let client = "10.100.1.1".parse().unwrap();
+
+    let mapping = discover_mapping(&mut nat, 12, client, 5000, &first).unwrap();
+    assert_eq!(mapping.ip(), std::net::IpAddr::V4(nat.translated_addr));
+    assert_eq!(discover_mapping(&mut nat, 12, client, 5000, &second), Some(mapping));
+    assert_eq!(detect_nat_type(&mut nat, 12, client, 5000, &first, &second), Some(NatType::Cone));
+}
+
+#[test]
+fn stun_behind_symmetric_nat() {
+    let mut nat = NatTable::new("Carrier NAT", "103.5.150.9".parse().unwrap())
+        .with_nat_type(NatType::Symmetric);
+    let first = StunServer::new("74.125.1.1".parse().unwrap(), 3478);
+    let second = StunServer::new("74.125.2.2".parse().unwrap(), 3478);
+    let client = "10.100.1.1".parse().unwrap();
+
+    let from_first = discover_mapping(&mut nat, 12, client, 5000, &first).unwrap();
+    let from_second = discover_mapping(&mut nat, 12, client, 5000, &second).unwrap();
+    // what the client learned from the first server is useless for talking to anyone else
+    assert_ne!(from_first, from_second);
+    assert_eq!(detect_nat_type(&mut nat, 12, client, 5000, &first, &second), Some(NatType::Symmetric));
+}
@@ -0,0 +1,159 @@
+//! A learning switch (a bridge, IEEE 802.1D without the spanning tree). It knows nothing when it
+//! is switched on; every frame that comes in tells it where its source MAC address is, and it
+//! writes that down in its MAC address table: that MAC is behind that port. A frame to a MAC in
+//! the table goes out of that one port only. A frame to a MAC it hasn't seen yet (or to
+//! broadcast or multicast) is flooded out of every port except the one it came in on, like a hub
+//! would. Once everybody has said something, the flooding stops.
+//!
+//! Entries age: one not refreshed for a while (five minutes by default, like most switches) is
+//! forgotten, in case the machine was unplugged or moved to another port.
+use std::time::{Duration, Instant};
+
+use crate::link::{EthernetFrame, MacAddr};
+use crate::storm_control::{StormControl, TrafficClass, Verdict};
+
+// how long a MAC is remembered without being heard from
+pub const DEFAULT_AGING: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacEntry {
+    pub mac : MacAddr,
+    pub port : usize,
+    pub learned_at : Instant,
+}
+
+#[derive(Debug)]
+pub struct Switch {
+    pub name : String,
+    pub ports : usize,
+    pub table : Vec<MacEntry>,
+    pub aging : Duration,
+    pub storm_control : Option<StormControl>,
+    // frames sent out of more than one port because of where they were going
+    pub flooded : u64,
+}
+
+// the names of the ports, for the links of a topology
+pub fn port_name(port: usize) -> String {
+    format!("port{port}")
+}
+
+pub fn port_of(name: &str) -> Option<usize> {
+    name.strip_prefix("port")?.parse().ok()
+}
+
+impl Switch {
+    pub fn new(name: &str, ports: usize) -> Self {
+        Switch { name : name.to_string(), ports, table : vec![], aging : DEFAULT_AGING, storm_control : None, flooded : 0 }
+    }
+
+    pub fn with_aging(mut self, aging: Duration) -> Self {
+        self.aging = aging;
+        self
+    }
+
+    pub fn with_storm_control(mut self, storm_control: StormControl) -> Self {
+        self.storm_control = Some(storm_control);
+        self
+    }
+
+    pub fn lookup(&self, mac: MacAddr) -> Option<usize> {
+        self.table.iter().find(|entry| entry.mac == mac).map(|entry| entry.port)
+    }
+
+    // forgets the entries older than the aging time, and gives them back
+    pub fn expire(&mut self, now: Instant) -> Vec<MacEntry> {
+        let aging = self.aging;
+        let (expired, kept) = self.table.iter().partition(|entry| now.saturating_duration_since(entry.learned_at) > aging);
+        self.table = kept;
+        expired
+    }
+
+    // a MAC heard on another port than before has moved there
+    pub fn learn(&mut self, mac: MacAddr, port: usize, now: Instant) {
+        match self.table.iter_mut().find(|entry| entry.mac == mac) {
+            Some(entry) => (entry.port, entry.learned_at) = (port, now),
+            None => self.table.push(MacEntry { mac, port, learned_at : now }),
+        }
+    }
+
+    // what storm control calls the frame, which is also what decides if it gets flooded
+    pub fn classify(&self, frame: &EthernetFrame) -> TrafficClass {
+        if frame.destination.is_broadcast() {
+            TrafficClass::Broadcast
+        } else if frame.destination.is_multicast() {
+            TrafficClass::Multicast
+        } else if self.lookup(frame.destination).is_some() {
+            TrafficClass::KnownUnicast
+        } else {
+            TrafficClass::UnknownUnicast
+        }
+    }
+
+    // A frame in on this port: the ports it goes out of, with the frame for each. Nothing for
+    // a frame to a MAC behind the port it came in on, since it is already there.
+    pub fn receive(&mut self, port: usize, frame: &EthernetFrame, now: Instant) -> Vec<(usize, EthernetFrame)> {
+        if port >= self.ports {
+            return vec![];
+        }
+        self.expire(now);
+        // a broadcast or multicast source is a lie; nobody is behind it
+        if !frame.source.is_multicast() {
+            self.learn(frame.source, port, now);
+        }
+        let class = self.classify(frame);
+        if let Some(storm_control) = &mut self.storm_control {
+            if storm_control.check(port, class, now) != Verdict::Forward {
+                return vec![];
+            }
+        }
+        match self.lookup(frame.destination).filter(|_| class == TrafficClass::KnownUnicast) {
+            Some(out) if out == port => vec![],
+            Some(out) => vec![(out, frame.clone())],
+            None => {
+                self.flooded += 1;
+                (0..self.ports).filter(|&out| out != port).map(|out| (out, frame.clone())).collect()
+            }
+        }
+    }
+}
+
+#[test]
+fn flooding_stops_once_the_table_is_learned() {
+    use crate::link::ETHERTYPE_IPV4;
+
+    let mac = |last: u8| MacAddr([2, 0, 0, 0, 0, last]);
+    let frame = |from: u8, to: u8| EthernetFrame::new(mac(to), mac(from), ETHERTYPE_IPV4, vec![0; 46]);
+    let ports = |out: Vec<(usize, EthernetFrame)>| out.into_iter().map(|(port, _)| port).collect::<Vec<_>>();
+    // hosts 1, 2 and 3 on ports 0, 1 and 2 of a four-port switch
+    let mut switch = Switch::new("sw1", 4);
+    let start = Instant::now();
+
+    // nobody is known yet: 1 talking to 2 goes everywhere, and 1 is learned
+    assert_eq!(ports(switch.receive(0, &frame(1, 2), start)), vec![1, 2, 3]);
+    assert_eq!(switch.lookup(mac(1)), Some(0));
+    // 2 answering goes only to 1, and now 2 is known too
+    assert_eq!(ports(switch.receive(1, &frame(2, 1), start)), vec![0]);
+    assert_eq!(switch.flooded, 1);
+
+    // from now on the conversation doesn't bother anybody else
+    for _ in 0..10 {
+        assert_eq!(ports(switch.receive(0, &frame(1, 2), start)), vec![1]);
+        assert_eq!(ports(switch.receive(1, &frame(2, 1), start)), vec![0]);
+    }
+    assert_eq!(switch.flooded, 1);
+    // but broadcast still goes everywhere, and 3 hasn't said anything yet
+    assert_eq!(ports(switch.receive(1, &EthernetFrame::new(MacAddr::BROADCAST, mac(2), ETHERTYPE_IPV4, vec![]), start)), vec![0, 2, 3]);
+    assert_eq!(ports(switch.receive(0, &frame(1, 3), start)), vec![1, 2, 3]);
+    assert_eq!(switch.flooded, 3);
+
+    // 1 moves to port 3
+    assert_eq!(ports(switch.receive(3, &frame(1, 2), start)), vec![1]);
+    assert_eq!(switch.lookup(mac(1)), Some(3));
+
+    // nothing heard from 2 for more than the aging time: it is forgotten, and flooded to again
+    let later = start + DEFAULT_AGING + Duration::from_secs(1);
+    assert_eq!(ports(switch.receive(3, &frame(1, 2), later)), vec![0, 1, 2]);
+    assert_eq!(switch.lookup(mac(2)), None);
+    assert_eq!(switch.flooded, 4);
+}
@@ -0,0 +1,118 @@
+//! What a table does when it is full. Routers have only so much memory for routes, neighbors
+//! and mappings, and what happens at the limit (refuse the new entry, or throw out an old one)
+//! is a choice worth seeing instead of the table just growing forever.
+//! Every refusal or eviction is written down as an event, for whoever watches the table.
+use std::fmt::{self, Debug, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FullPolicy {
+    // the new entry is not added
+    #[default]
+    Refuse,
+    // the oldest entry makes room for the new one
+    EvictOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableLimit {
+    pub max_entries : usize,
+    pub policy : FullPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableEvent {
+    Refused { table : String, entry : String },
+    Evicted { table : String, entry : String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableFull;
+
+impl Display for TableFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the table is full")
+    }
+}
+
+impl std::error::Error for TableFull {}
+
+impl TableLimit {
+    pub fn new(max_entries: usize, policy: FullPolicy) -> Self {
+        TableLimit { max_entries, policy }
+    }
+
+    // Makes room for one more entry, if the policy allows. `oldest` picks the entry to evict.
+    pub fn make_room<T: Debug>(&self, table: &str, entries: &mut Vec<T>, oldest: impl Fn(&[T]) -> Option<usize>,
+        new_entry: &impl Debug, events: &mut Vec<TableEvent>) -> Result<(), TableFull> {
+        while entries.len() >= self.max_entries {
+            let index = match self.policy {
+                FullPolicy::EvictOldest => oldest(entries),
+                FullPolicy::Refuse => None,
+            };
+            let Some(index) = index else {
+                events.push(TableEvent::Refused { table : table.to_string(), entry : format!("{new_entry:?}") });
+                return Err(TableFull);
+            };
+            let evicted = entries.remove(index);
+            events.push(TableEvent::Evicted { table : table.to_string(), entry : format!("{evicted:?}") });
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn full_tables_refuse_or_evict() {
+    use crate::nat_v4::{NatError, NatTableV4, Protocol};
+    use crate::packet::builder::PacketBuilder;
+    use crate::neighbor::{ArpCache, MacAddr};
+    use crate::routing::{Interface, Route, RouteError, RoutingTable};
+    use std::time::{Duration, Instant};
+
+    let route = |destination: &str, port: u64| Route {
+        destination : destination.parse::<std::net::Ipv6Addr>().unwrap(),
+        prefix_len : 48,
+        next_hop : Interface::dev(&format!("eth{port}")),
+        equal_cost : vec![],
+        distance : 1,
+        metric : 0,
+        expires_at : None,
+    };
+    let mut routes = RoutingTable::new("small router").with_limit(TableLimit::new(2, FullPolicy::Refuse));
+    routes.add_route(route("2001:db8::", 1)).unwrap();
+    routes.add_route(route("2001:db8:1::", 2)).unwrap();
+    assert_eq!(routes.add_route(route("2001:db8:2::", 3)), Err(RouteError::TableFull));
+    assert_eq!(routes.routes().len(), 2);
+    assert!(matches!(&routes.take_events()[..], [TableEvent::Refused { table, .. }] if table == "small router"));
+
+    // the ARP cache keeps the neighbors heard from most recently
+    let start = Instant::now();
+    let mut arp = ArpCache::new("eth0").with_limit(TableLimit::new(2, FullPolicy::EvictOldest));
+    arp.learn("192.168.1.1".parse().unwrap(), MacAddr([0x02, 0, 0, 0, 0, 1]), start).unwrap();
+    arp.learn("192.168.1.2".parse().unwrap(), MacAddr([0x02, 0, 0, 0, 0, 2]), start + Duration::from_secs(1)).unwrap();
+    // hearing from .1 again makes .2 the oldest
+    arp.learn("192.168.1.1".parse().unwrap(), MacAddr([0x02, 0, 0, 0, 0, 1]), start + Duration::from_secs(2)).unwrap();
+    arp.learn("192.168.1.3".parse().unwrap(), MacAddr([0x02, 0, 0, 0, 0, 3]), start + Duration::from_secs(3)).unwrap();
+    assert_eq!(arp.lookup("192.168.1.2".parse().unwrap()), None);
+    assert_eq!(arp.lookup("192.168.1.1".parse().unwrap()), Some(MacAddr([0x02, 0, 0, 0, 0, 1])));
+    let events = arp.take_events();
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], TableEvent::Evicted { entry, .. } if entry.contains("192.168.1.2")));
+
+    // the NAT refuses the third mapping, or throws out the one used longest ago
+    let mut nat = NatTableV4::new("home NAT", "203.0.113.1".parse().unwrap()).with_limit(TableLimit::new(2, FullPolicy::Refuse));
+    let internal = |last: u8| std::net::Ipv4Addr::new(192, 168, 1, last);
+    for last in [1, 2] {
+        nat.request_mapping(u16::from(last), Protocol::Udp, (internal(last), 5000), 0, Duration::from_secs(60), start).unwrap();
+    }
+    assert_eq!(nat.request_mapping(3, Protocol::Udp, (internal(3), 5000), 0, Duration::from_secs(60), start), Err(NatError::TableFull));
+    assert!(matches!(&nat.take_events()[..], [TableEvent::Refused { table, .. }] if table == "home NAT"));
+    nat.limit = Some(TableLimit::new(2, FullPolicy::EvictOldest));
+    let packet = |last: u8| PacketBuilder::ipv4().src(internal(last)).dst("198.51.100.7".parse().unwrap()).udp().sport(5000).dport(53).build();
+    nat.table.clear();
+    nat.translate_outgoing_at(packet(1), 1, start).unwrap();
+    nat.translate_outgoing_at(packet(2), 2, start + Duration::from_secs(1)).unwrap();
+    nat.translate_outgoing_at(packet(3), 3, start + Duration::from_secs(2)).unwrap();
+    assert!(nat.found_on_nat(internal(1), 5000).is_none());
+    assert_eq!(nat.forced_evictions, 1);
+    assert!(matches!(&nat.take_events()[..], [TableEvent::Evicted { entry, .. }] if entry.contains("192.168.1.1")));
+}
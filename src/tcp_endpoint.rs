@@ -0,0 +1,188 @@
+//! One end of a TCP connection, only as far as its state goes: the handshake, the closing and
+//! TIME-WAIT. No data, no retransmissions and no windows; every segment is a TcpHeader that
+//! arrives once and in order. `state` is state_machine::StateMachine::tcp(), fired at every
+//! change, so to_dot() shows the way this connection went.
+//!
+//! The side that closes first waits in TIME-WAIT for twice the maximum segment lifetime (2MSL)
+//! before it is CLOSED, so that a late segment of this connection can't end up in the next one
+//! between the same ports. That is a timer like the NAT's, and a device clock jumping breaks it
+//! the same way (see clock).
+use std::time::{Duration, Instant};
+
+use crate::packet::tcp::{TcpHeader, FIN, SYN};
+use crate::state_machine::StateMachine;
+
+// 2MSL, with the maximum segment lifetime of 2 minutes from RFC 793
+pub const TIME_WAIT: Duration = Duration::from_secs(240);
+
+#[derive(Debug, Clone)]
+pub struct TcpEndpoint {
+    pub port : u16,
+    pub remote_port : Option<u16>,
+    pub state : StateMachine,
+    // the next sequence number to send, and the next one expected from the other side
+    pub sequence : u32,
+    pub acknowledgment : u32,
+    // when TIME-WAIT is over
+    pub time_wait_until : Option<Instant>,
+}
+
+impl TcpEndpoint {
+    pub fn new(port: u16, initial_sequence: u32) -> Self {
+        TcpEndpoint { port, remote_port : None, state : StateMachine::tcp(), sequence : initial_sequence, acknowledgment : 0, time_wait_until : None }
+    }
+
+    // a server waiting for connections
+    pub fn listen(&mut self) {
+        self.state.fire("passive open");
+    }
+
+    // the SYN that opens a connection to that port
+    pub fn connect(&mut self, remote_port: u16) -> Option<TcpHeader> {
+        self.state.fire("send SYN")?;
+        self.remote_port = Some(remote_port);
+        Some(self.segment(SYN))
+    }
+
+    // the FIN, if there is a connection to close
+    pub fn close(&mut self) -> Option<TcpHeader> {
+        match self.state.current {
+            "ESTABLISHED" | "CLOSE-WAIT" => {
+                self.state.fire("close, send FIN")?;
+                Some(self.segment(FIN))
+            }
+            _ => {
+                self.state.fire("close");
+                None
+            }
+        }
+    }
+
+    // Takes a segment from the other side, and gives back the answer to send, if any.
+    // Segments for other ports, or that make no sense in the current state, are ignored.
+    pub fn receive(&mut self, segment: &TcpHeader, now: Instant) -> Option<TcpHeader> {
+        if segment.destination_port != self.port || self.remote_port.is_some_and(|port| port != segment.source_port) {
+            return None;
+        }
+        // the ACK is for everything sent so far (the SYN or the FIN)
+        let acked = segment.ack() && segment.acknowledgment == self.sequence;
+        match self.state.current {
+            "LISTEN" if segment.syn() => {
+                self.remote_port = Some(segment.source_port);
+                self.acknowledgment = segment.sequence.wrapping_add(1);
+                self.state.fire("recv SYN, send SYN+ACK")?;
+                Some(self.segment(SYN))
+            }
+            "SYN-SENT" if segment.syn() && acked => {
+                self.acknowledgment = segment.sequence.wrapping_add(1);
+                self.state.fire("recv SYN+ACK, send ACK")?;
+                Some(self.segment(0))
+            }
+            "SYN-SENT" if segment.syn() => {
+                self.acknowledgment = segment.sequence.wrapping_add(1);
+                self.state.fire("recv SYN, send SYN+ACK")?;
+                // both opened at once; our SYN went already, with the sequence number before this one
+                let answer = TcpHeader::new(self.port, segment.source_port, self.sequence.wrapping_sub(1))
+                    .with_acknowledgment(self.acknowledgment)
+                    .with_flags(SYN);
+                Some(answer)
+            }
+            "SYN-RECEIVED" if segment.rst() => {
+                self.remote_port = None;
+                self.state.fire("recv RST");
+                None
+            }
+            "SYN-RECEIVED" if acked => {
+                self.state.fire("recv ACK");
+                None
+            }
+            "ESTABLISHED" if segment.fin() => {
+                self.acknowledgment = segment.sequence.wrapping_add(1);
+                self.state.fire("recv FIN, send ACK")?;
+                Some(self.segment(0))
+            }
+            "FIN-WAIT-1" | "FIN-WAIT-2" | "CLOSING" | "LAST-ACK" => {
+                // the ACK of our FIN and their own FIN may come in the same segment
+                if acked {
+                    self.state.fire("recv ACK");
+                }
+                let answer = if segment.fin() && self.state.current.starts_with("FIN-WAIT") {
+                    self.acknowledgment = segment.sequence.wrapping_add(1);
+                    self.state.fire("recv FIN, send ACK");
+                    Some(self.segment(0))
+                } else {
+                    None
+                };
+                if self.state.current == "TIME-WAIT" {
+                    self.time_wait_until = Some(now + TIME_WAIT);
+                }
+                answer
+            }
+            _ => None,
+        }
+    }
+
+    // CLOSED once TIME-WAIT is over; gives back whether that happened now
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.time_wait_until.is_none_or(|until| now < until) {
+            return false;
+        }
+        self.time_wait_until = None;
+        self.remote_port = None;
+        self.state.fire("2MSL timeout").is_some()
+    }
+
+    // the next segment to the other side with these flags, acknowledging what came so far
+    // (nothing yet for the first SYN); SYN and FIN take up a sequence number
+    fn segment(&mut self, flags: u8) -> TcpHeader {
+        let mut segment = TcpHeader::new(self.port, self.remote_port.unwrap_or(0), self.sequence).with_flags(flags);
+        if self.state.current != "SYN-SENT" {
+            segment = segment.with_acknowledgment(self.acknowledgment);
+        }
+        if flags & (SYN | FIN) != 0 {
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        segment
+    }
+}
+
+#[test]
+fn a_connection_opens_closes_and_waits() {
+    let start = Instant::now();
+    let mut client = TcpEndpoint::new(40000, 1000);
+    let mut server = TcpEndpoint::new(80, 5000);
+    server.listen();
+
+    // the three-way handshake
+    let syn = client.connect(80).unwrap();
+    assert!(syn.syn() && !syn.ack());
+    let syn_ack = server.receive(&syn, start).unwrap();
+    assert!(syn_ack.syn() && syn_ack.ack() && syn_ack.acknowledgment == 1001);
+    let ack = client.receive(&syn_ack, start).unwrap();
+    assert_eq!(server.receive(&ack, start), None);
+    assert_eq!((client.state.current, server.state.current), ("ESTABLISHED", "ESTABLISHED"));
+
+    // the client closes first, so the client is the one that waits
+    let fin = client.close().unwrap();
+    let ack = server.receive(&fin, start).unwrap();
+    assert_eq!(client.receive(&ack, start), None);
+    assert_eq!((client.state.current, server.state.current), ("FIN-WAIT-2", "CLOSE-WAIT"));
+    let fin = server.close().unwrap();
+    let last_ack = client.receive(&fin, start).unwrap();
+    assert_eq!(server.receive(&last_ack, start), None);
+    assert_eq!((client.state.current, server.state.current), ("TIME-WAIT", "CLOSED"));
+
+    assert!(!client.expire(start + TIME_WAIT - Duration::from_secs(1)));
+    assert!(client.expire(start + TIME_WAIT));
+    assert_eq!(client.state.current, "CLOSED");
+    let dot = client.state.to_dot();
+    assert!(dot.contains("\"TIME-WAIT\" -> \"CLOSED\" [label=\"2MSL timeout (1)\", color=red, penwidth=2];"));
+    assert!(dot.contains("\"CLOSED\" -> \"LISTEN\" [label=\"passive open\", color=gray];"));
+    assert!(server.state.to_dot().contains("\"LISTEN\" -> \"SYN-RECEIVED\" [label=\"recv SYN, send SYN+ACK (1)\", color=red, penwidth=2];"));
+
+    // a segment for another port is none of its business
+    let mut other = TcpEndpoint::new(443, 0);
+    other.listen();
+    assert_eq!(other.receive(&syn, start), None);
+    assert_eq!(other.state.current, "LISTEN");
+}
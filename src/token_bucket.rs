@@ -0,0 +1,56 @@
+//! The usual rate limiter: tokens drip into a bucket at a fixed rate, each packet takes one,
+//! and the bucket can only hold so many, which is the largest burst allowed.
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    pub rate_per_second : f64,
+    pub burst : f64,
+    tokens : f64,
+    last_refill : Option<Instant>,
+}
+
+impl TokenBucket {
+    // starts full
+    pub fn new(rate_per_second: f64, burst: f64) -> Self {
+        TokenBucket { rate_per_second, burst, tokens : burst, last_refill : None }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(last) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate_per_second).min(self.burst);
+        }
+        self.last_refill = Some(self.last_refill.map_or(now, |last| last.max(now)));
+    }
+
+    pub fn tokens(&mut self, now: Instant) -> f64 {
+        self.refill(now);
+        self.tokens
+    }
+
+    // takes a token if there is one
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[test]
+fn bucket_allows_burst_then_rate() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(10.0, 5.0);
+    let allowed = (0..20).filter(|_| bucket.try_take(start)).count();
+    assert_eq!(allowed, 5);
+    // half a second later, 5 more tokens dripped in
+    let later = start + Duration::from_millis(500);
+    let allowed = (0..20).filter(|_| bucket.try_take(later)).count();
+    assert_eq!(allowed, 5);
+}
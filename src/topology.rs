@@ -0,0 +1,529 @@
+//! The network as a graph: the nodes are the boxes (hosts, routers, switches), and the links are
+//! the cables between an interface of one and an interface of another. Nodes and links come and
+//! go while the simulation runs, like somebody walking around the room with a cable.
+//!
+//! A node is only a name and a kind until a device is attached to it; a router attached to a node
+//! gets the packets that arrive over the node's links, so forwarding follows the graph. The
+//! routing protocols take their neighbors from it too (see `OspfNetwork::connect_over`,
+//! `RipNetwork::connect_over` and `BgpNetwork::connect_over`), and ping and traceroute send
+//! their probes over it. Hosts, switches and hubs pass Ethernet frames over the links
+//! instead, and routers take those frames too, see run_frames(). Nothing stops two switches
+//! joined by two cables from flooding a broadcast around forever (there is no spanning tree),
+//! so a run gives up after a budget of frames.
+//!
+//! Ids stay the same for as long as the node (or link) exists; removing one leaves a hole instead
+//! of moving the others down, so nobody holding an id ends up pointing at the wrong box.
+use std::collections::{BinaryHeap, VecDeque};
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+
+use crate::host::Host;
+use crate::hub::Hub;
+use crate::link::EthernetFrame;
+use crate::packet::Packet;
+use crate::router::{Forwarded, Router, RouterAddress};
+use crate::switch::{self, Switch};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LinkId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Host,
+    Router,
+    Switch,
+    Hub,
+}
+
+// what actually does the work at a node, boxed since they are all big and of different sizes
+#[derive(Debug)]
+pub enum Device {
+    Host(Box<Host>),
+    Router(Box<Router>),
+    Switch(Box<Switch>),
+    Hub(Box<Hub>),
+}
+
+impl Device {
+    pub fn kind(&self) -> NodeKind {
+        match self {
+            Device::Host(_) => NodeKind::Host,
+            Device::Router(_) => NodeKind::Router,
+            Device::Switch(_) => NodeKind::Switch,
+            Device::Hub(_) => NodeKind::Hub,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub name : String,
+    pub kind : NodeKind,
+    pub device : Option<Device>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    // (node, interface) at each end
+    pub a : (NodeId, String),
+    pub b : (NodeId, String),
+    // what the routing protocols think of it, like an OSPF cost
+    pub cost : u32,
+    pub delay : Duration,
+    // a link that is down stays in the graph, it just carries nothing
+    pub up : bool,
+    // what run_frames() put on it so far
+    pub frames : u64,
+    pub bytes : u64,
+}
+
+impl Link {
+    // the other end, seen from this node
+    pub fn other(&self, node: NodeId) -> Option<&(NodeId, String)> {
+        if self.a.0 == node {
+            Some(&self.b)
+        } else if self.b.0 == node {
+            Some(&self.a)
+        } else {
+            None
+        }
+    }
+}
+
+// how many frames one run_frames() may put on the links before it stops
+pub const DEFAULT_FRAME_BUDGET: usize = 100_000;
+
+#[derive(Debug)]
+pub struct Topology {
+    nodes : Vec<Option<Node>>,
+    links : Vec<Option<Link>>,
+    pub frame_budget : usize,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology { nodes : vec![], links : vec![], frame_budget : DEFAULT_FRAME_BUDGET }
+    }
+}
+
+// what one run_frames() did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRun {
+    // frames that crossed a link
+    pub crossed : usize,
+    // still on their way when the budget ran out, going round a loop most likely
+    pub dropped : usize,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_frame_budget(mut self, frame_budget: usize) -> Self {
+        self.frame_budget = frame_budget;
+        self
+    }
+
+    pub fn add_node(&mut self, name: &str, kind: NodeKind) -> NodeId {
+        self.nodes.push(Some(Node { name : name.to_string(), kind, device : None }));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    // a node named after the router, with the router already attached
+    pub fn add_router(&mut self, router: Router) -> NodeId {
+        let node = self.add_node(&router.name, NodeKind::Router);
+        self.attach(node, Device::Router(Box::new(router)));
+        node
+    }
+
+    // unplugs every cable of the node too
+    pub fn remove_node(&mut self, node: NodeId) -> Option<Node> {
+        let removed = self.nodes.get_mut(node.0)?.take()?;
+        for slot in &mut self.links {
+            if slot.as_ref().is_some_and(|link| link.a.0 == node || link.b.0 == node) {
+                *slot = None;
+            }
+        }
+        Some(removed)
+    }
+
+    // gives back the device that was there before
+    pub fn attach(&mut self, node: NodeId, device: Device) -> Option<Device> {
+        let node = self.node_mut(node)?;
+        node.kind = device.kind();
+        node.device.replace(device)
+    }
+
+    pub fn node(&self, node: NodeId) -> Option<&Node> {
+        self.nodes.get(node.0)?.as_ref()
+    }
+
+    pub fn node_mut(&mut self, node: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(node.0)?.as_mut()
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes().find(|(_, node)| node.name == name).map(|(id, _)| id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes.iter().enumerate().filter_map(|(i, node)| Some((NodeId(i), node.as_ref()?)))
+    }
+
+    pub fn host(&self, node: NodeId) -> Option<&Host> {
+        match self.node(node)?.device.as_ref()? {
+            Device::Host(host) => Some(host.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn host_mut(&mut self, node: NodeId) -> Option<&mut Host> {
+        match self.node_mut(node)?.device.as_mut()? {
+            Device::Host(host) => Some(host.as_mut()),
+            _ => None,
+        }
+    }
+
+    pub fn switch(&self, node: NodeId) -> Option<&Switch> {
+        match self.node(node)?.device.as_ref()? {
+            Device::Switch(switch) => Some(switch.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn switch_mut(&mut self, node: NodeId) -> Option<&mut Switch> {
+        match self.node_mut(node)?.device.as_mut()? {
+            Device::Switch(switch) => Some(switch.as_mut()),
+            _ => None,
+        }
+    }
+
+    pub fn hub(&self, node: NodeId) -> Option<&Hub> {
+        match self.node(node)?.device.as_ref()? {
+            Device::Hub(hub) => Some(hub.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn router(&self, node: NodeId) -> Option<&Router> {
+        match self.node(node)?.device.as_ref()? {
+            Device::Router(router) => Some(router.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn router_mut(&mut self, node: NodeId) -> Option<&mut Router> {
+        match self.node_mut(node)?.device.as_mut()? {
+            Device::Router(router) => Some(router.as_mut()),
+            _ => None,
+        }
+    }
+
+    // A cable with a cost of 1 and no delay; change them through `link_mut`. None when either
+    // node is gone or one of the interfaces already has a cable in it.
+    pub fn connect(&mut self, a: NodeId, a_interface: &str, b: NodeId, b_interface: &str) -> Option<LinkId> {
+        if self.node(a).is_none() || self.node(b).is_none() || self.link_at(a, a_interface).is_some() || self.link_at(b, b_interface).is_some() {
+            return None;
+        }
+        self.links.push(Some(Link {
+            a : (a, a_interface.to_string()),
+            b : (b, b_interface.to_string()),
+            cost : 1,
+            delay : Duration::ZERO,
+            up : true,
+            frames : 0,
+            bytes : 0,
+        }));
+        Some(LinkId(self.links.len() - 1))
+    }
+
+    pub fn disconnect(&mut self, link: LinkId) -> Option<Link> {
+        self.links.get_mut(link.0)?.take()
+    }
+
+    pub fn link(&self, link: LinkId) -> Option<&Link> {
+        self.links.get(link.0)?.as_ref()
+    }
+
+    pub fn link_mut(&mut self, link: LinkId) -> Option<&mut Link> {
+        self.links.get_mut(link.0)?.as_mut()
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = (LinkId, &Link)> {
+        self.links.iter().enumerate().filter_map(|(i, link)| Some((LinkId(i), link.as_ref()?)))
+    }
+
+    // the link plugged into that interface, up or not
+    pub fn link_at(&self, node: NodeId, interface: &str) -> Option<LinkId> {
+        self.links()
+            .find(|(_, link)| [&link.a, &link.b].iter().any(|end| end.0 == node && end.1 == interface))
+            .map(|(id, _)| id)
+    }
+
+    // who is on the other end of each working cable, with the cable
+    pub fn neighbors(&self, node: NodeId) -> Vec<(NodeId, LinkId)> {
+        self.links()
+            .filter(|(_, link)| link.up)
+            .filter_map(|(id, link)| Some((link.other(node)?.0, id)))
+            .collect()
+    }
+
+    // where a packet sent out of this interface arrives: the node and its interface
+    pub fn peer_of(&self, node: NodeId, interface: &str) -> Option<(NodeId, String)> {
+        let link = self.link(self.link_at(node, interface)?)?;
+        if !link.up {
+            return None;
+        }
+        if link.a == (node, interface.to_string()) {
+            Some(link.b.clone())
+        } else {
+            Some(link.a.clone())
+        }
+    }
+
+    // Dijkstra over the link costs, only through working links; the nodes along the way,
+    // both ends included
+    pub fn shortest_path(&self, from: NodeId, to: NodeId) -> Option<Vec<NodeId>> {
+        self.node(from)?;
+        self.node(to)?;
+        let mut distance = vec![u32::MAX; self.nodes.len()];
+        let mut previous: Vec<Option<NodeId>> = vec![None; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        distance[from.0] = 0;
+        queue.push(Reverse((0, from)));
+        while let Some(Reverse((cost, node))) = queue.pop() {
+            if cost > distance[node.0] {
+                continue;
+            }
+            if node == to {
+                break;
+            }
+            for (neighbor, link) in self.neighbors(node) {
+                let next = cost.saturating_add(self.links[link.0].as_ref()?.cost);
+                if next < distance[neighbor.0] {
+                    distance[neighbor.0] = next;
+                    previous[neighbor.0] = Some(node);
+                    queue.push(Reverse((next, neighbor)));
+                }
+            }
+        }
+        if distance[to.0] == u32::MAX {
+            return None;
+        }
+        let mut path = vec![to];
+        while let Some(before) = previous[path[path.len() - 1].0] {
+            path.push(before);
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    // The packet goes into the router at that node from the interface, and from router to
+    // router over the links until it comes out of an interface with no router on the other side.
+    // Gives back that node and interface, and what came out; None if it got dropped (or ran
+    // into a node with no router attached).
+    pub fn carry<P: Packet>(&mut self, mut packet: P, mut node: NodeId, ingress: &str) -> Option<(NodeId, String, P)>
+    where
+        P::Address: RouterAddress,
+    {
+        let mut ingress = ingress.to_string();
+        loop {
+            let (interface, out) = match self.router_mut(node)?.forward(packet, &ingress) {
+                Forwarded::Out { interface, packet, .. } | Forwarded::Icmp { interface, packet, .. } => (interface, packet),
+                Forwarded::Local(_) | Forwarded::Replicated(_) | Forwarded::Dropped(_) => return None,
+            };
+            match self.peer_of(node, &interface) {
+                Some((next, next_ingress)) if self.router(next).is_some() => (packet, node, ingress) = (out, next, next_ingress),
+                _ => return Some((node, interface, out)),
+            }
+        }
+    }
+
+    // Moves frames over the links until nobody has anything left to send: what the hosts
+    // transmit goes out over their cables, and what arrives at a switch or a hub goes on out of
+    // the ports it sends it to (named like switch::port_name), and what arrives at a router is
+    // forwarded out of its interfaces (see Router::receive_frame). Gives back how many frames
+    // crossed a link (each link counts its own too), and how many were dropped when more than
+    // frame_budget of them had.
+    pub fn run_frames(&mut self, now: Instant) -> FrameRun {
+        let mut arriving = VecDeque::new();
+        let mut crossed = 0;
+        let by_name = |out: Vec<(usize, EthernetFrame)>| out.into_iter().map(|(port, frame)| (switch::port_name(port), frame)).collect();
+        loop {
+            let hosts: Vec<NodeId> = self.nodes().filter(|(_, node)| matches!(node.device, Some(Device::Host(_)))).map(|(id, _)| id).collect();
+            for node in hosts {
+                let frames = self.host_mut(node).map(|host| host.transmit()).unwrap_or_default();
+                let cables: Vec<String> = self.links().filter_map(|(_, link)| [&link.a, &link.b].into_iter().find(|end| end.0 == node)).map(|end| end.1.clone()).collect();
+                for frame in frames {
+                    for cable in &cables {
+                        crossed += self.send_frame(node, cable, frame.clone(), &mut arriving);
+                    }
+                }
+            }
+            if arriving.is_empty() {
+                return FrameRun { crossed, dropped : 0 };
+            }
+            while let Some((node, interface, frame)) = arriving.pop_front() {
+                if crossed >= self.frame_budget {
+                    return FrameRun { crossed, dropped : arriving.len() + 1 };
+                }
+                let out = match self.node_mut(node).and_then(|node| node.device.as_mut()) {
+                    Some(Device::Host(host)) => {
+                        host.deliver(&frame, now);
+                        vec![]
+                    }
+                    Some(Device::Switch(switch)) => switch::port_of(&interface).map_or(vec![], |port| by_name(switch.receive(port, &frame, now))),
+                    Some(Device::Hub(hub)) => switch::port_of(&interface).map_or(vec![], |port| by_name(hub.receive(port, &frame))),
+                    Some(Device::Router(router)) => router.receive_frame(&interface, &frame, now),
+                    None => vec![],
+                };
+                for (out, frame) in out {
+                    crossed += self.send_frame(node, &out, frame, &mut arriving);
+                }
+            }
+        }
+    }
+
+    // puts the frame on the cable of that interface, if there is one that works; how many
+    // frames that made cross a link (0 or 1)
+    fn send_frame(&mut self, node: NodeId, interface: &str, frame: EthernetFrame, arriving: &mut VecDeque<(NodeId, String, EthernetFrame)>) -> usize {
+        let Some(id) = self.link_at(node, interface) else { return 0 };
+        let Some(link) = self.links[id.0].as_mut().filter(|link| link.up) else { return 0 };
+        link.frames += 1;
+        link.bytes += frame.to_bytes().len() as u64;
+        let Some((peer, peer_interface)) = link.other(node).cloned() else { return 0 };
+        arriving.push_back((peer, peer_interface, frame));
+        1
+    }
+}
+
+#[test]
+fn nodes_and_links_come_and_go() {
+    use crate::networkingv4::Route;
+    use crate::packet::builder::PacketBuilder;
+    use crate::link::MacAddr;
+    use crate::router::RouterInterface;
+    use std::net::Ipv4Addr;
+
+    let router = |name: &str, interfaces: &[(&str, [u8; 4], u8)]| {
+        let mut router = Router::new(name);
+        for &(interface, address, prefix_len) in interfaces {
+            router.add_interface(RouterInterface::new(interface).with_v4(Ipv4Addr::from(address), prefix_len));
+        }
+        router
+    };
+    let mut left = router("left", &[("eth0", [192, 168, 1, 1], 24), ("eth1", [10, 0, 0, 1], 30), ("eth2", [10, 0, 1, 1], 30)]);
+    left.routes_v4.add_route(Route::default_route(Ipv4Addr::new(10, 0, 0, 2))).unwrap();
+    let right = router("right", &[("eth0", [10, 0, 0, 2], 30), ("eth1", [172, 16, 0, 1], 24)]);
+
+    let mut topology = Topology::new();
+    let (left, right) = (topology.add_router(left), topology.add_router(right));
+    let backup = topology.add_node("backup", NodeKind::Router);
+    let server = topology.add_node("server", NodeKind::Host);
+    let direct = topology.connect(left, "eth1", right, "eth0").unwrap();
+    topology.connect(left, "eth2", backup, "eth0").unwrap();
+    topology.connect(backup, "eth1", right, "eth2").unwrap();
+    topology.connect(right, "eth1", server, "eth0").unwrap();
+    // the cable is already plugged in there
+    assert_eq!(topology.connect(left, "eth1", server, "eth1"), None);
+    assert_eq!(topology.find("backup"), Some(backup));
+    assert_eq!(topology.neighbors(left).len(), 2);
+    assert_eq!(topology.peer_of(left, "eth1"), Some((right, "eth0".to_string())));
+
+    assert_eq!(topology.shortest_path(left, server), Some(vec![left, right, server]));
+    topology.link_mut(direct).unwrap().cost = 10;
+    assert_eq!(topology.shortest_path(left, server), Some(vec![left, backup, right, server]));
+
+    // the routers forward over the graph, and the packet comes out next to the server
+    let packet = PacketBuilder::ipv4().src(Ipv4Addr::new(192, 168, 1, 10)).dst(Ipv4Addr::new(172, 16, 0, 20)).udp().sport(5000).dport(53).build();
+    let (node, interface, out) = topology.carry(packet.clone(), left, "eth0").unwrap();
+    assert_eq!((node, interface.as_str(), out.hop_limit()), (right, "eth1", 62));
+
+    // OSPF takes its adjacencies, and their costs, from the same graph
+    let mut ospf = crate::protocols::ospf::OspfNetwork::new(vec![
+        crate::protocols::ospf::OspfRouter::new("left", Ipv4Addr::new(1, 1, 1, 1)),
+        crate::protocols::ospf::OspfRouter::new("right", Ipv4Addr::new(2, 2, 2, 2)),
+    ]);
+    ospf.connect_over(&topology);
+    assert_eq!(ospf.routers[0].neighbors, vec![(Ipv4Addr::new(2, 2, 2, 2), 10)]);
+    // and BGP its sessions
+    let mut bgp = crate::protocols::bgp::BgpNetwork::new(vec![
+        crate::protocols::bgp::BgpRouter::new("left", 100, Ipv4Addr::new(192, 0, 2, 1)),
+        crate::protocols::bgp::BgpRouter::new("backup", 200, Ipv4Addr::new(192, 0, 2, 2)),
+        crate::protocols::bgp::BgpRouter::new("right", 300, Ipv4Addr::new(192, 0, 2, 3)),
+    ]);
+    bgp.connect_over(&topology);
+    assert_eq!(bgp.sessions, vec![(0, 2), (0, 1), (1, 2)]);
+    assert_eq!(bgp.routers[0].session(Ipv4Addr::new(192, 0, 2, 3)).map(|machine| machine.current), Some("Established"));
+
+    topology.link_mut(direct).unwrap().up = false;
+    assert_eq!(topology.peer_of(left, "eth1"), None);
+    assert_eq!(topology.neighbors(left), vec![(backup, LinkId(1))]);
+
+    // pulling the backup router out takes its cables with it, and the ids of the others stay
+    assert_eq!(topology.remove_node(backup).map(|node| node.name), Some("backup".to_string()));
+    assert_eq!(topology.links().count(), 2);
+    assert_eq!(topology.shortest_path(left, server), None);
+    assert_eq!(topology.shortest_path(left, NodeId(99)), None);
+    assert_eq!(topology.find("server"), Some(server));
+    assert!(topology.disconnect(direct).is_some());
+    assert_eq!(topology.neighbors(left), vec![]);
+
+    // two hosts behind a switch talk in frames over the same kind of graph
+    let mut lan = Topology::new();
+    let host = |name: &str, last: u8| Host::new(name, MacAddr([2, 0, 0, 0, 0, last])).with_address(Ipv4Addr::new(192, 168, 1, last).into(), 24);
+    let laptop = lan.add_node("laptop", NodeKind::Host);
+    let printer = lan.add_node("printer", NodeKind::Host);
+    let switch = lan.add_node("sw1", NodeKind::Switch);
+    lan.attach(laptop, Device::Host(Box::new(host("laptop", 10))));
+    lan.attach(printer, Device::Host(Box::new(host("printer", 20))));
+    lan.attach(switch, Device::Switch(Box::new(Switch::new("sw1", 8))));
+    lan.connect(laptop, "eth0", switch, &switch::port_name(0)).unwrap();
+    lan.connect(printer, "eth0", switch, &switch::port_name(5)).unwrap();
+    let now = Instant::now();
+    lan.host_mut(laptop).unwrap().send_to("192.168.1.20:9100".parse().unwrap(), b"page", now).unwrap();
+    // the ARP request, its reply and the datagram, each over two cables
+    assert_eq!(lan.run_frames(now), FrameRun { crossed : 6, dropped : 0 });
+    assert_eq!(lan.host_mut(printer).unwrap().receive().map(|datagram| datagram.data), Some(b"page".to_vec()));
+    assert_eq!(lan.switch(switch).unwrap().lookup(MacAddr([2, 0, 0, 0, 0, 20])), Some(5));
+
+    // and a router between that LAN and another one forwards the frames it gets
+    let mut gateway = Router::new("gateway");
+    gateway.add_interface(RouterInterface::new("eth0").with_mac(MacAddr([2, 0, 0, 0, 1, 1])).with_v4(Ipv4Addr::new(192, 168, 1, 1), 24));
+    gateway.add_interface(RouterInterface::new("eth1").with_mac(MacAddr([2, 0, 0, 0, 2, 1])).with_v4(Ipv4Addr::new(192, 168, 2, 1), 24));
+    let gateway = lan.add_router(gateway);
+    let nas = lan.add_node("nas", NodeKind::Host);
+    lan.attach(nas, Device::Host(Box::new(Host::new("nas", MacAddr([2, 0, 0, 0, 2, 50])).with_address(Ipv4Addr::new(192, 168, 2, 50).into(), 24))));
+    lan.connect(gateway, "eth0", switch, &switch::port_name(1)).unwrap();
+    lan.connect(gateway, "eth1", nas, "eth0").unwrap();
+    lan.host_mut(laptop).unwrap().gateway_v4 = Some(Ipv4Addr::new(192, 168, 1, 1));
+    let backup = |lan: &mut Topology, data: &[u8]| {
+        lan.host_mut(laptop).unwrap().send_to("192.168.2.50:873".parse().unwrap(), data, now).unwrap();
+        lan.run_frames(now);
+        lan.host_mut(nas).unwrap().receive().map(|datagram| datagram.data)
+    };
+    // the first one is lost while the router asks where the NAS is, the next one gets there
+    assert_eq!(backup(&mut lan, b"first"), None);
+    assert_eq!(backup(&mut lan, b"second"), Some(b"second".to_vec()));
+
+    // two switches with two cables between them send a broadcast round and round, until the
+    // budget runs out
+    let mut looped = Topology::new().with_frame_budget(1000);
+    let [first, second] = ["sw1", "sw2"].map(|name| {
+        let node = looped.add_node(name, NodeKind::Switch);
+        looped.attach(node, Device::Switch(Box::new(Switch::new(name, 4))));
+        node
+    });
+    looped.connect(first, &switch::port_name(1), second, &switch::port_name(1)).unwrap();
+    looped.connect(first, &switch::port_name(2), second, &switch::port_name(2)).unwrap();
+    let laptop = looped.add_node("laptop", NodeKind::Host);
+    looped.attach(laptop, Device::Host(Box::new(host("laptop", 10))));
+    looped.connect(laptop, "eth0", first, &switch::port_name(0)).unwrap();
+    looped.host_mut(laptop).unwrap().send_to("192.168.1.20:9100".parse().unwrap(), b"page", now).unwrap();
+    let run = looped.run_frames(now);
+    assert!(run.crossed >= 1000 && run.dropped > 0, "{run:?}");
+}
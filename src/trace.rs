@@ -0,0 +1,139 @@
+//! A packet trace is just the list of hops a packet took between named hosts.
+//! It can be exported as a sequence diagram (Mermaid or PlantUML), which is
+//! handy to paste into notes while learning what NAT does to the addresses.
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use crate::packet::Packet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub from : String,
+    pub to : String,
+    pub source : SocketAddr,
+    pub destination : SocketAddr,
+    pub seq : Option<u32>,
+    // the (source, destination) after a NAT rewrote the packet, if it did
+    pub rewritten : Option<(SocketAddr, SocketAddr)>,
+}
+
+impl TraceEvent {
+    // text written on the arrow of the diagram
+    pub fn label(&self) -> String {
+        let mut label = format!("{} → {}", self.source, self.destination);
+        if let Some(seq) = self.seq {
+            let _ = write!(label, " seq={seq}");
+        }
+        if let Some((source, destination)) = self.rewritten {
+            let _ = write!(label, " [NAT {source} → {destination}]");
+        }
+        label
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PacketTrace {
+    pub events : Vec<TraceEvent>,
+}
+
+impl PacketTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // records a packet going from one host to the other, untouched
+    pub fn record<P: Packet>(&mut self, from: &str, to: &str, packet: &P) -> &mut TraceEvent {
+        self.events.push(TraceEvent {
+            from : from.to_string(),
+            to : to.to_string(),
+            source : packet.source(),
+            destination : packet.destination(),
+            seq : None,
+            rewritten : None,
+        });
+        self.events.last_mut().unwrap()
+    }
+
+    // records a packet that got translated on its way (before -> after)
+    pub fn record_translated<P: Packet>(&mut self, from: &str, to: &str, before: &P, after: &P) -> &mut TraceEvent {
+        let event = self.record(from, to, before);
+        if before.source() != after.source() || before.destination() != after.destination() {
+            event.rewritten = Some((after.source(), after.destination()));
+        }
+        event
+    }
+
+    // only the events where both ends are among the selected hosts
+    pub fn between<'a>(&'a self, hosts: &'a [&str]) -> impl Iterator<Item = &'a TraceEvent> {
+        self.events
+            .iter()
+            .filter(|event| hosts.contains(&event.from.as_str()) && hosts.contains(&event.to.as_str()))
+    }
+
+    pub fn to_mermaid(&self, hosts: &[&str]) -> String {
+        let mut out = String::from("sequenceDiagram\n");
+        for (i, host) in hosts.iter().enumerate() {
+            let _ = writeln!(out, "    participant P{i} as {host}");
+        }
+        for event in self.between(hosts) {
+            let _ = writeln!(out, "    P{}->>P{}: {}", participant(hosts, &event.from), participant(hosts, &event.to), event.label());
+        }
+        out
+    }
+
+    pub fn to_plantuml(&self, hosts: &[&str]) -> String {
+        let mut out = String::from("@startuml\n");
+        for (i, host) in hosts.iter().enumerate() {
+            let _ = writeln!(out, "participant \"{host}\" as P{i}");
+        }
+        for event in self.between(hosts) {
+            let _ = writeln!(out, "P{} -> P{} : {}", participant(hosts, &event.from), participant(hosts, &event.to), event.label());
+        }
+        out.push_str("@enduml\n");
+        out
+    }
+}
+
+fn participant(hosts: &[&str], host: &str) -> usize {
+    hosts.iter().position(|&h| h == host).unwrap()
+}
+
+#[cfg(test)]
+fn sample_trace() -> PacketTrace {
+    use crate::nat_v4::NatTable;
+    use crate::packet::builder::PacketBuilder;
+
+    let packet = PacketBuilder::ipv4().src("10.100.1.1".parse().unwrap()).dst("192.168.1.1".parse().unwrap()).udp().sport(8090).dport(80).payload(b"K xa bro, haal khabar?").build();
+    let mut nat = NatTable::new("Krischal's NAT", "103.5.150.9".parse().unwrap());
+    let translated = nat.translate_outgoing(packet.clone(), 12).unwrap();
+
+    let mut trace = PacketTrace::new();
+    trace.record("laptop", "router", &packet).seq = Some(1);
+    trace.record_translated("router", "server", &packet, &translated).seq = Some(1);
+    trace.record("laptop", "printer", &packet);
+    trace
+}
+
+#[test]
+fn mermaid_export() {
+    let diagram = sample_trace().to_mermaid(&["laptop", "router", "server"]);
+    println!("{diagram}");
+    assert_eq!(diagram, "sequenceDiagram
+    participant P0 as laptop
+    participant P1 as router
+    participant P2 as server
+    P0->>P1: 10.100.1.1:8090 → 192.168.1.1:80 seq=1
+    P1->>P2: 10.100.1.1:8090 → 192.168.1.1:80 seq=1 [NAT 103.5.150.9:0 → 192.168.1.1:80]
+");
+}
+
+#[test]
+fn plantuml_export() {
+    let diagram = sample_trace().to_plantuml(&["router", "server"]);
+    assert_eq!(diagram, "@startuml
+participant \"router\" as P0
+participant \"server\" as P1
+P0 -> P1 : 10.100.1.1:8090 → 192.168.1.1:80 seq=1 [NAT 103.5.150.9:0 → 192.168.1.1:80]
+@enduml
+");
+}
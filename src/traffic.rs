@@ -0,0 +1,260 @@
+//! Traffic for throughput experiments.
+//! Real traffic is not made of equal packets: the classic "simple IMIX" mixes 40, 576 and
+//! 1500 byte packets in a 7:4:1 ratio. Packet size matters on a link: a big packet takes
+//! longer to put on the wire (serialization delay = bits / bandwidth), and a queue limited
+//! in bytes fills up with a few big packets but many small ones.
+//!
+//! Times here are `Duration`s since the start of the simulation, like `VirtualClock::elapsed()`.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// xorshift64, enough randomness for traffic, and the same seed always gives the same run
+#[derive(Debug, Clone)]
+pub struct SimpleRng {
+    state : u64,
+}
+
+impl SimpleRng {
+    pub fn new(seed: u64) -> Self {
+        SimpleRng { state : seed.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // uniform in min..=max; just min if max is below it
+    pub fn between(&mut self, min: u64, max: u64) -> u64 {
+        match max.saturating_sub(min).checked_add(1) {
+            Some(span) => min + self.next_u64() % span,
+            None => self.next_u64(),
+        }
+    }
+}
+
+// what the constructors refuse, since nothing sensible can be sent with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficError {
+    // weighted sizes whose weights add up to nothing
+    NoWeight,
+    // a uniform range with min above max
+    EmptyRange,
+    ZeroRate,
+    ZeroBandwidth,
+    // a heatmap bucket of no time at all
+    ZeroBucket,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketSizes {
+    Fixed(usize),
+    Uniform { min : usize, max : usize },
+    // (size, weight) pairs, picked proportionally to the weight
+    Weighted(Vec<(usize, u32)>),
+}
+
+impl PacketSizes {
+    // simple IMIX: 7 x 40 bytes, 4 x 576 bytes, 1 x 1500 bytes
+    pub fn imix() -> Self {
+        PacketSizes::Weighted(vec![(40, 7), (576, 4), (1500, 1)])
+    }
+
+    pub fn check(&self) -> Result<(), TrafficError> {
+        match self {
+            PacketSizes::Fixed(_) => Ok(()),
+            PacketSizes::Uniform { min, max } if min > max => Err(TrafficError::EmptyRange),
+            PacketSizes::Uniform { .. } => Ok(()),
+            PacketSizes::Weighted(sizes) if sizes.iter().all(|&(_, weight)| weight == 0) => Err(TrafficError::NoWeight),
+            PacketSizes::Weighted(_) => Ok(()),
+        }
+    }
+
+    // sizes that don't pass check() give the smallest they can (0 with no weight at all)
+    pub fn sample(&self, rng: &mut SimpleRng) -> usize {
+        match self {
+            PacketSizes::Fixed(size) => *size,
+            PacketSizes::Uniform { min, max } => rng.between(*min as u64, *max as u64) as usize,
+            PacketSizes::Weighted(sizes) => {
+                let total: u64 = sizes.iter().map(|&(_, weight)| weight as u64).sum();
+                if total == 0 {
+                    return 0;
+                }
+                let mut pick = rng.between(0, total - 1) as u32;
+                for &(size, weight) in sizes {
+                    if pick < weight {
+                        return size;
+                    }
+                    pick -= weight;
+                }
+                unreachable!("the pick is always below the total weight")
+            }
+        }
+    }
+
+    // none for sizes that don't pass check(), they have no average
+    pub fn average(&self) -> Option<f64> {
+        self.check().ok()?;
+        let average = match self {
+            PacketSizes::Fixed(size) => *size as f64,
+            PacketSizes::Uniform { min, max } => (*min as f64 + *max as f64) / 2.0,
+            PacketSizes::Weighted(sizes) => {
+                let total: u64 = sizes.iter().map(|&(_, weight)| weight as u64).sum();
+                let bytes: f64 = sizes.iter().map(|&(size, weight)| size as f64 * weight as f64).sum();
+                bytes / total as f64
+            }
+        };
+        Some(average)
+    }
+}
+
+// sends packets of the given sizes at a constant rate
+#[derive(Debug, Clone)]
+pub struct TrafficSource {
+    pub sizes : PacketSizes,
+    pub interval : Duration,
+    pub next_at : Duration,
+    rng : SimpleRng,
+}
+
+impl TrafficSource {
+    pub fn new(sizes: PacketSizes, packets_per_second: u32, seed: u64) -> Result<Self, TrafficError> {
+        sizes.check()?;
+        if packets_per_second == 0 {
+            return Err(TrafficError::ZeroRate);
+        }
+        Ok(TrafficSource {
+            sizes,
+            interval : Duration::from_secs(1) / packets_per_second,
+            next_at : Duration::ZERO,
+            rng : SimpleRng::new(seed),
+        })
+    }
+
+    // the next packet: when it is sent and how big it is
+    pub fn next_packet(&mut self) -> (Duration, usize) {
+        let at = self.next_at;
+        self.next_at += self.interval;
+        (at, self.sizes.sample(&mut self.rng))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkError {
+    // tail drop: the queue has no room left for this many bytes
+    QueueFull,
+}
+
+// A point to point link with a FIFO queue limited in bytes
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub bandwidth_bps : u64,
+    pub propagation_delay : Duration,
+    pub queue_limit_bytes : usize,
+    // (size, time it has fully left the link) for every packet still waiting or being sent
+    queue : VecDeque<(usize, Duration)>,
+    pub sent_packets : u64,
+    pub sent_bytes : u64,
+    pub dropped_packets : u64,
+    pub dropped_bytes : u64,
+    // (start, end) of every transmission, for utilization over time (see heatmap)
+    pub busy_periods : Vec<(Duration, Duration)>,
+}
+
+impl Link {
+    pub fn new(bandwidth_bps: u64, propagation_delay: Duration, queue_limit_bytes: usize) -> Result<Self, TrafficError> {
+        if bandwidth_bps == 0 {
+            return Err(TrafficError::ZeroBandwidth);
+        }
+        Ok(Link {
+            bandwidth_bps,
+            propagation_delay,
+            queue_limit_bytes,
+            queue : VecDeque::new(),
+            sent_packets : 0,
+            sent_bytes : 0,
+            dropped_packets : 0,
+            dropped_bytes : 0,
+            busy_periods : vec![],
+        })
+    }
+
+    pub fn serialization_delay(&self, size: usize) -> Duration {
+        Duration::from_nanos((size as u64 * 8 * 1_000_000_000) / self.bandwidth_bps)
+    }
+
+    // bytes still in the queue (including the one on the wire) at this time
+    pub fn queued_bytes(&mut self, now: Duration) -> usize {
+        while self.queue.front().is_some_and(|&(_, done)| done <= now) {
+            self.queue.pop_front();
+        }
+        self.queue.iter().map(|&(size, _)| size).sum()
+    }
+
+    // puts a packet in the queue, giving back when it arrives at the other end
+    pub fn send(&mut self, size: usize, now: Duration) -> Result<Duration, LinkError> {
+        if self.queued_bytes(now) + size > self.queue_limit_bytes {
+            self.dropped_packets += 1;
+            self.dropped_bytes += size as u64;
+            return Err(LinkError::QueueFull);
+        }
+        let starts = self.queue.back().map_or(now, |&(_, done)| done.max(now));
+        let done = starts + self.serialization_delay(size);
+        self.queue.push_back((size, done));
+        self.busy_periods.push((starts, done));
+        self.sent_packets += 1;
+        self.sent_bytes += size as u64;
+        Ok(done + self.propagation_delay)
+    }
+}
+
+#[test]
+fn imix_sizes_follow_the_ratio() {
+    let imix = PacketSizes::imix();
+    assert!((imix.average().unwrap() - 4084.0 / 12.0).abs() < 1e-9);
+    assert_eq!(PacketSizes::Weighted(vec![]).average(), None);
+    assert_eq!(PacketSizes::Weighted(vec![(1500, 0)]).average(), None);
+
+    let mut rng = SimpleRng::new(42);
+    let samples: Vec<usize> = (0..12_000).map(|_| imix.sample(&mut rng)).collect();
+    let count = |size| samples.iter().filter(|&&s| s == size).count();
+    // 7000, 4000 and 1000 expected
+    assert!((6500..7500).contains(&count(40)));
+    assert!((3500..4500).contains(&count(576)));
+    assert!((700..1300).contains(&count(1500)));
+}
+
+#[test]
+fn packet_size_changes_delay_and_drops() {
+    // 1 Mbps: a 1500 byte packet takes 12ms to send, a 40 byte one 0.32ms
+    let mut link = Link::new(1_000_000, Duration::from_millis(5), 3000).unwrap();
+    assert_eq!(link.serialization_delay(1500), Duration::from_millis(12));
+    assert_eq!(link.send(1500, Duration::ZERO), Ok(Duration::from_millis(17)));
+    assert_eq!(link.send(1500, Duration::ZERO), Ok(Duration::from_millis(29)));
+    // 3000 bytes already queued, no room for even a small one
+    assert_eq!(link.send(40, Duration::ZERO), Err(LinkError::QueueFull));
+    // once the first one is gone, there is room again
+    assert!(link.send(1500, Duration::from_millis(12)).is_ok());
+
+    // the same burst of 20 packets: full size packets mostly get dropped, IMIX mostly fits
+    let burst = |sizes: PacketSizes| {
+        let mut link = Link::new(1_000_000, Duration::ZERO, 6000).unwrap();
+        let mut source = TrafficSource::new(sizes, 10_000, 7).unwrap();
+        for _ in 0..20 {
+            let (at, size) = source.next_packet();
+            let _ = link.send(size, at);
+        }
+        link.dropped_packets
+    };
+    assert!(burst(PacketSizes::imix()) < burst(PacketSizes::Fixed(1500)));
+
+    // nothing can be sent with these, so they aren't made at all
+    assert_eq!(Link::new(0, Duration::ZERO, 6000).err(), Some(TrafficError::ZeroBandwidth));
+    assert_eq!(TrafficSource::new(PacketSizes::imix(), 0, 7).err(), Some(TrafficError::ZeroRate));
+    assert_eq!(TrafficSource::new(PacketSizes::Weighted(vec![(40, 0)]), 10, 7).err(), Some(TrafficError::NoWeight));
+    assert_eq!(TrafficSource::new(PacketSizes::Weighted(vec![]), 10, 7).err(), Some(TrafficError::NoWeight));
+    assert_eq!(TrafficSource::new(PacketSizes::Uniform { min : 1500, max : 40 }, 10, 7).err(), Some(TrafficError::EmptyRange));
+    assert_eq!(PacketSizes::Weighted(vec![]).sample(&mut SimpleRng::new(1)), 0);
+}
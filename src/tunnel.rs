@@ -0,0 +1,183 @@
+//! A GRE tunnel, like `ip tunnel add gre0 mode gre local A remote B key K`: a packet the routing
+//! sends into the tunnel is wrapped, whole, in a GRE header and a new IP header from this end
+//! (local) to the other one (remote). On the way that is the only header anybody looks at, so
+//! the inner packet can have addresses the network in between has never heard of, like private
+//! ones. The other end takes the outer headers off and routes what was inside again, as if it
+//! had come in on its own tunnel interface.
+//!
+//! 6in4 (RFC 4213, `mode sit`) is the same without the GRE header: an IPv6 packet right inside an
+//! IPv4 one, protocol 41, so IPv6 can get across a part of the network that only has IPv4.
+//!
+//! The inner packet is written out with its real headers (see packet::layered), so the outer
+//! one carries exactly the bytes a capture on the tunnel interface would show.
+use std::fmt::{self, Display};
+use std::net::Ipv4Addr;
+
+use crate::packet::gre::GreHeader;
+use crate::packet::ipv4::Ipv4Header;
+use crate::packet::ipv6::Ipv6Header;
+use crate::packet::layered::{IpHeader, LayeredPacket, Transport};
+use crate::packet::PacketError;
+use crate::router::{RouterAddress, DEFAULT_HOP_LIMIT};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreTunnel<A = Ipv4Addr> {
+    // the name of the router's interface for it, which routes point through
+    pub name : String,
+    pub local : A,
+    pub remote : A,
+    // both ends have to agree on it
+    pub key : Option<u32>,
+    // of the outer packets; the inner ones keep theirs
+    pub hop_limit : u8,
+}
+
+// Why a packet didn't come out of the tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelError {
+    // not GRE (or 6in4), or not between the two ends of this tunnel
+    NotThisTunnel,
+    // a key other than ours, or one missing
+    WrongKey(Option<u32>),
+    // what is inside is not a packet of this IP version
+    WrongProtocol(u16),
+    Packet(PacketError),
+}
+
+impl Display for TunnelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TunnelError::NotThisTunnel => write!(f, "not a packet of this tunnel"),
+            TunnelError::WrongKey(Some(key)) => write!(f, "wrong tunnel key {key}"),
+            TunnelError::WrongKey(None) => write!(f, "tunnel key missing"),
+            TunnelError::WrongProtocol(protocol_type) => write!(f, "unexpected protocol type 0x{protocol_type:04x} inside"),
+            TunnelError::Packet(error) => write!(f, "bad inner packet: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for TunnelError {}
+
+impl From<PacketError> for TunnelError {
+    fn from(error: PacketError) -> Self {
+        TunnelError::Packet(error)
+    }
+}
+
+impl<A: RouterAddress> GreTunnel<A> {
+    pub fn new(name: &str, local: A, remote: A) -> Self {
+        GreTunnel { name : name.to_string(), local, remote, key : None, hop_limit : DEFAULT_HOP_LIMIT }
+    }
+
+    pub fn with_key(mut self, key: u32) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    fn header(&self) -> GreHeader {
+        GreHeader { key : self.key, ..GreHeader::new(A::Header::ETHERTYPE) }
+    }
+
+    // the packet as the payload of a GRE packet to the other end
+    pub fn encapsulate(&self, inner: &LayeredPacket<A::Header>) -> LayeredPacket<A::Header> {
+        let mut network = A::Header::between(self.local, self.remote);
+        network.set_hop_limit(self.hop_limit);
+        LayeredPacket::new(network, Transport::Gre(self.header()), inner.to_bytes())
+    }
+
+    // what a GRE packet from the other end had inside
+    pub fn decapsulate(&self, outer: &LayeredPacket<A::Header>) -> Result<LayeredPacket<A::Header>, TunnelError> {
+        let Transport::Gre(header) = &outer.transport else {
+            return Err(TunnelError::NotThisTunnel);
+        };
+        if (outer.network.source(), outer.network.destination()) != (self.remote, self.local) {
+            return Err(TunnelError::NotThisTunnel);
+        }
+        if header.key != self.key {
+            return Err(TunnelError::WrongKey(header.key));
+        }
+        if header.protocol_type != A::Header::ETHERTYPE {
+            return Err(TunnelError::WrongProtocol(header.protocol_type));
+        }
+        Ok(LayeredPacket::from_bytes(&outer.payload)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SixInFour {
+    pub name : String,
+    pub local : Ipv4Addr,
+    pub remote : Ipv4Addr,
+    pub hop_limit : u8,
+}
+
+impl SixInFour {
+    pub fn new(name: &str, local: Ipv4Addr, remote: Ipv4Addr) -> Self {
+        SixInFour { name : name.to_string(), local, remote, hop_limit : DEFAULT_HOP_LIMIT }
+    }
+
+    pub fn encapsulate(&self, inner: &LayeredPacket<Ipv6Header>) -> LayeredPacket<Ipv4Header> {
+        let mut network = Ipv4Header::between(self.local, self.remote);
+        network.set_hop_limit(self.hop_limit);
+        LayeredPacket::new(network, Transport::Ipv6, inner.to_bytes())
+    }
+
+    pub fn decapsulate(&self, outer: &LayeredPacket<Ipv4Header>) -> Result<LayeredPacket<Ipv6Header>, TunnelError> {
+        if outer.transport != Transport::Ipv6 || (outer.network.source, outer.network.destination) != (self.remote, self.local) {
+            return Err(TunnelError::NotThisTunnel);
+        }
+        Ok(LayeredPacket::from_bytes(&outer.payload)?)
+    }
+}
+
+#[test]
+fn packets_go_through_whole() {
+    use crate::link::ETHERTYPE_IPV6;
+    use crate::nat_v4::Protocol;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::tcp::{ACK, PSH};
+    use crate::packet::Packet;
+
+    let tunnel = GreTunnel::new("gre0", Ipv4Addr::new(198, 51, 100, 2), Ipv4Addr::new(203, 0, 113, 2)).with_key(42);
+    let inner = PacketBuilder::ipv4()
+        .src(Ipv4Addr::new(10, 1, 0, 10))
+        .dst(Ipv4Addr::new(10, 2, 0, 20))
+        .hop_limit(63)
+        .tcp()
+        .sport(40000)
+        .dport(22)
+        .seq(1000)
+        .ack(2000)
+        .flags(PSH | ACK)
+        .payload(b"SSH-2.0-OpenSSH_9.6")
+        .build();
+    let outer = tunnel.encapsulate(&inner);
+    assert_eq!((outer.protocol(), outer.source_ip(), outer.destination_ip(), outer.hop_limit()), (Protocol::Gre, tunnel.local, tunnel.remote, 64));
+    // the outer IPv4 header, the GRE header with the key, then the inner IPv4 header, then the TCP one
+    let bytes = outer.to_bytes();
+    assert_eq!(bytes[20..28], [0x20, 0, 0x08, 0x00, 0, 0, 0, 42]);
+    assert_eq!(bytes.len(), 20 + 8 + 20 + 20 + inner.payload.len());
+
+    // the other end sees the same tunnel from its side, and gets all of the TCP header back
+    let far_end = GreTunnel::new("gre0", tunnel.remote, tunnel.local).with_key(42);
+    assert_eq!(far_end.decapsulate(&outer), Ok(inner.clone()));
+    assert_eq!(tunnel.decapsulate(&outer), Err(TunnelError::NotThisTunnel));
+    assert_eq!(GreTunnel { key : None, ..far_end.clone() }.decapsulate(&outer), Err(TunnelError::WrongKey(Some(42))));
+    let mut v6_inside = outer.clone();
+    if let Transport::Gre(header) = &mut v6_inside.transport {
+        header.protocol_type = ETHERTYPE_IPV6;
+    }
+    assert_eq!(far_end.decapsulate(&v6_inside), Err(TunnelError::WrongProtocol(ETHERTYPE_IPV6)));
+    // damaged on the way, and the inner checksum catches it
+    let mut damaged = outer;
+    *damaged.payload.last_mut().unwrap() ^= 1;
+    assert_eq!(far_end.decapsulate(&damaged), Err(TunnelError::Packet(PacketError::BadChecksum)));
+
+    // 6in4 has nothing between the two IP headers, and a ping stays a ping
+    let sit = SixInFour::new("sit1", tunnel.local, tunnel.remote);
+    let ping = PacketBuilder::ipv6().src("2001:db8:a::10".parse().unwrap()).dst("2001:db8:b::20".parse().unwrap()).ping(7, 1).payload(b"abcd").build();
+    let outer = sit.encapsulate(&ping);
+    assert_eq!((outer.protocol(), outer.payload[0] >> 4, outer.payload.len()), (Protocol::Ipv6, 6, 40 + 8 + 4));
+    assert_eq!(SixInFour::new("sit1", sit.remote, sit.local).decapsulate(&outer), Ok(ping));
+    assert_eq!(sit.decapsulate(&outer), Err(TunnelError::NotThisTunnel));
+}
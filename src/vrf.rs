@@ -0,0 +1,163 @@
+//! VRFs (virtual routing and forwarding): one router holding several routing tables that know
+//! nothing of each other, each interface belonging to one of them. A packet is routed in the
+//! table of the VRF it came in on, so two customers can both use 10.0.0.0/8 on the same router
+//! without ever reaching each other. The VRF ends up in the packet's metadata too.
+//!
+//! Sometimes a VRF should see a few routes of another, like a shared services network every
+//! customer may reach. Leaking copies the route over, but its next hop stays in the VRF it came
+//! from, which is why every next hop here says which VRF it is in.
+use std::net::Ipv4Addr;
+
+use crate::metadata::Tagged;
+use crate::packet::Packet;
+use crate::routing::{Route, RouteError, RoutingTable};
+
+pub const DEFAULT_VRF: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VrfNextHop {
+    pub vrf : u32,
+    pub address : Ipv4Addr,
+}
+
+pub type VrfTable = RoutingTable<Ipv4Addr, VrfNextHop>;
+
+#[derive(Debug)]
+pub struct Vrf {
+    pub id : u32,
+    pub table : VrfTable,
+}
+
+#[derive(Debug)]
+pub struct VrfRouter {
+    pub vrfs : Vec<Vrf>,
+    // (ifindex, VRF); interfaces not in the list are in the default VRF
+    pub interfaces : Vec<(u32, u32)>,
+}
+
+impl Default for VrfRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VrfRouter {
+    // with only the default VRF
+    pub fn new() -> Self {
+        let default = Vrf { id : DEFAULT_VRF, table : RoutingTable::new("default") };
+        VrfRouter { vrfs : vec![default], interfaces : vec![] }
+    }
+
+    // gives back the new VRF's id
+    pub fn add_vrf(&mut self, name: &str) -> u32 {
+        let id = self.vrfs.iter().map(|vrf| vrf.id).max().unwrap_or(DEFAULT_VRF) + 1;
+        self.vrfs.push(Vrf { id, table : RoutingTable::new(name) });
+        id
+    }
+
+    pub fn vrf(&self, id: u32) -> Option<&Vrf> {
+        self.vrfs.iter().find(|vrf| vrf.id == id)
+    }
+
+    fn vrf_mut(&mut self, id: u32) -> Result<&mut Vrf, RouteError> {
+        self.vrfs.iter_mut().find(|vrf| vrf.id == id).ok_or(RouteError::NotFound)
+    }
+
+    // like `ip link set eth1 master red`
+    pub fn bind_interface(&mut self, ifindex: u32, vrf: u32) {
+        self.interfaces.retain(|&(bound, _)| bound != ifindex);
+        self.interfaces.push((ifindex, vrf));
+    }
+
+    pub fn vrf_of_interface(&self, ifindex: u32) -> u32 {
+        self.interfaces
+            .iter()
+            .find(|&&(bound, _)| bound == ifindex)
+            .map_or(DEFAULT_VRF, |&(_, vrf)| vrf)
+    }
+
+    // a route in the VRF, through a next hop in the same VRF
+    pub fn add_route(&mut self, vrf: u32, destination: Ipv4Addr, prefix_len: u8, next_hop: Ipv4Addr) -> Result<(), RouteError> {
+        let next_hop = VrfNextHop { vrf, address : next_hop };
+        self.vrf_mut(vrf)?.table.add_route(Route::with_prefix(destination, prefix_len, next_hop)?)
+    }
+
+    // copies the routes for destination/prefix_len from one VRF to another, their next hops
+    // still in the VRF they came from
+    pub fn leak_route(&mut self, from: u32, to: u32, destination: Ipv4Addr, prefix_len: u8) -> Result<(), RouteError> {
+        let leaked: Vec<_> = self.vrf(from)
+            .ok_or(RouteError::NotFound)?
+            .table
+            .routes()
+            .iter()
+            .filter(|route| (route.destination, route.prefix_len) == (destination, prefix_len))
+            .cloned()
+            .collect();
+        if leaked.is_empty() {
+            return Err(RouteError::NotFound);
+        }
+        let table = &mut self.vrf_mut(to)?.table;
+        for route in leaked {
+            table.add_route(route)?;
+        }
+        Ok(())
+    }
+
+    // Puts the packet in the VRF of the interface it came in on; a packet made on the router
+    // keeps the VRF it already has
+    pub fn classify<P>(&self, packet: &mut Tagged<P>) {
+        if let Some(ifindex) = packet.meta.ingress_ifindex {
+            packet.meta.vrf = self.vrf_of_interface(ifindex);
+        }
+    }
+
+    // looked up in the packet's VRF only
+    pub fn find_next_hop<P: Packet<Address = Ipv4Addr>>(&self, packet: &Tagged<P>) -> Option<VrfNextHop> {
+        let vrf = match packet.meta.ingress_ifindex {
+            Some(ifindex) => self.vrf_of_interface(ifindex),
+            None => packet.meta.vrf,
+        };
+        self.vrf(vrf)?.table.find_next_hop(packet.packet.destination_ip())
+    }
+}
+
+#[test]
+fn customers_stay_apart_except_for_leaked_routes() {
+    use crate::packet::builder::PacketBuilder;
+    use std::time::Instant;
+
+    let address = |text: &str| text.parse::<Ipv4Addr>().unwrap();
+    let mut router = VrfRouter::new();
+    let red = router.add_vrf("red");
+    let blue = router.add_vrf("blue");
+    router.bind_interface(1, red);
+    router.bind_interface(2, blue);
+    // both customers use 10.0.0.0/8
+    router.add_route(red, address("10.0.0.0"), 8, address("192.168.1.2")).unwrap();
+    router.add_route(blue, address("10.0.0.0"), 8, address("192.168.2.2")).unwrap();
+    router.add_route(red, address("10.200.0.0"), 16, address("192.168.1.3")).unwrap();
+    // shared services, for both of them
+    let services = router.add_vrf("services");
+    router.add_route(services, address("172.20.0.0"), 16, address("192.168.9.2")).unwrap();
+    router.leak_route(services, red, address("172.20.0.0"), 16).unwrap();
+    router.leak_route(services, blue, address("172.20.0.0"), 16).unwrap();
+    assert_eq!(router.leak_route(services, red, address("172.21.0.0"), 16), Err(RouteError::NotFound));
+
+    let from_interface = |ifindex: u32, destination: &str| {
+        let packet = PacketBuilder::ipv4().src(address("10.0.0.5")).dst(address(destination)).tcp().sport(40000).dport(443).build();
+        Tagged::received(packet, ifindex, Instant::now())
+    };
+    let mut packet = from_interface(1, "10.1.2.3");
+    router.classify(&mut packet);
+    assert_eq!(packet.meta.vrf, red);
+    assert_eq!(router.find_next_hop(&packet), Some(VrfNextHop { vrf : red, address : address("192.168.1.2") }));
+    assert_eq!(router.find_next_hop(&from_interface(2, "10.1.2.3")), Some(VrfNextHop { vrf : blue, address : address("192.168.2.2") }));
+    // red's /16 is red's alone
+    assert_eq!(router.find_next_hop(&from_interface(2, "10.200.0.1")).unwrap().vrf, blue);
+    // the leaked route is used from both, and forwards in the services VRF
+    for ifindex in [1, 2] {
+        assert_eq!(router.find_next_hop(&from_interface(ifindex, "172.20.1.1")), Some(VrfNextHop { vrf : services, address : address("192.168.9.2") }));
+    }
+    // an interface in no VRF is in the default one, which has no routes at all
+    assert_eq!(router.find_next_hop(&from_interface(3, "10.1.2.3")), None);
+}